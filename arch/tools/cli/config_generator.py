@@ -14,6 +14,10 @@ SUPPORTED_PROVIDERS = [
     "mistral",
     "openai",
     "gemini",
+    "azure_openai",
+    "bedrock",
+    "ollama",
+    "cohere",
 ]
 
 
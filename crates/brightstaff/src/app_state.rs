@@ -0,0 +1,92 @@
+use std::sync::{Arc, RwLock};
+
+use common::configuration::{Configuration, LlmProvider};
+
+use crate::metrics::Metrics;
+use crate::router::llm_router::RouterService;
+use crate::utils::circuit_breaker::CircuitBreakers;
+use crate::utils::concurrency::ConcurrencyLimiter;
+use crate::utils::credentials::ProviderCredentials;
+use crate::utils::idempotency::IdempotencyKeys;
+use crate::utils::model_allowlist::ModelAllowlist;
+use crate::utils::provider_headers::ProviderHeaders;
+use crate::utils::rate_limit::RateLimiter;
+use crate::utils::response_cache::CompletionCache;
+use crate::utils::shutdown::InFlight;
+use crate::utils::transform::Transforms;
+use crate::utils::upstream_endpoints::UpstreamEndpoints;
+
+/// What a reload of the routes replaces, swapped as one so that the models listed match the
+/// routes requests are routed with.
+pub struct Routing {
+    pub router_service: Arc<RouterService>,
+    /// Providers listed by `/v1/models`, with their routes.
+    pub llm_providers: Arc<Vec<LlmProvider>>,
+}
+
+impl Routing {
+    pub fn new(router_service: RouterService, llm_providers: Vec<LlmProvider>) -> Self {
+        Routing {
+            router_service: Arc::new(router_service),
+            llm_providers: Arc::new(llm_providers),
+        }
+    }
+}
+
+/// State shared by all request handlers.
+pub struct AppState {
+    /// Router and providers of new requests, replaced when the routes are reloaded. Requests
+    /// keep the router they started with.
+    pub routing: RwLock<Routing>,
+    /// Config file the routes are reloaded from.
+    pub config_path: Option<String>,
+    /// The llm gateway, where requests of providers without an upstream endpoint of their
+    /// own are sent.
+    pub llm_provider_endpoint: String,
+    /// Where the requests of each provider are sent.
+    pub upstream_endpoints: UpstreamEndpoints,
+    pub http_client: reqwest::Client,
+    pub arch_config: Arc<Configuration>,
+    pub metrics: Arc<Metrics>,
+    /// Auth headers injected for the provider a request is routed to.
+    pub credentials: ProviderCredentials,
+    /// Static headers injected for the provider a request is routed to.
+    pub provider_headers: ProviderHeaders,
+    /// Connections and response streams that shutdown waits for.
+    pub in_flight: InFlight,
+    pub model_allowlist: ModelAllowlist,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub circuit_breakers: CircuitBreakers,
+    /// Slots for requests in flight to the providers.
+    pub concurrency: ConcurrencyLimiter,
+    /// Responses of deterministic requests, served again for identical requests.
+    pub response_cache: CompletionCache,
+    /// Hooks run on every request before it is forwarded and on its response.
+    pub transforms: Transforms,
+    /// Responses replayed for requests sent again with the same idempotency key.
+    pub idempotency_keys: IdempotencyKeys,
+}
+
+impl AppState {
+    pub fn router_service(&self) -> Arc<RouterService> {
+        Arc::clone(&self.routing.read().unwrap().router_service)
+    }
+
+    pub fn llm_providers(&self) -> Arc<Vec<LlmProvider>> {
+        Arc::clone(&self.routing.read().unwrap().llm_providers)
+    }
+
+    /// Routes new requests with `router_service` and lists `llm_providers` as the models,
+    /// returns the router it replaced.
+    pub fn swap_router_service(
+        &self,
+        router_service: RouterService,
+        llm_providers: Vec<LlmProvider>,
+    ) -> Arc<RouterService> {
+        std::mem::replace(
+            &mut *self.routing.write().unwrap(),
+            Routing::new(router_service, llm_providers),
+        )
+        .router_service
+    }
+}
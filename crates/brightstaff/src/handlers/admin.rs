@@ -0,0 +1,311 @@
+use std::fs;
+
+use bytes::Bytes;
+use common::configuration::Configuration;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::header::{self, HeaderMap};
+use hyper::{Response, StatusCode};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::app_state::AppState;
+use crate::handlers::errors::{error_response, ErrorClass};
+use crate::router::router_config::{router_service_from_config, RouterConfigError};
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error("no config file to reload the routes from")]
+    NoConfigFile,
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("provider {0} is not configured, providers are only added by a restart")]
+    UnknownProvider(String),
+    #[error("invalid routing config: {0}")]
+    Router(#[from] RouterConfigError),
+}
+
+impl ReloadError {
+    fn class(&self) -> ErrorClass {
+        match self {
+            ReloadError::NoConfigFile | ReloadError::Read { .. } => ErrorClass::InternalError,
+            ReloadError::Parse(_) | ReloadError::UnknownProvider(_) | ReloadError::Router(_) => {
+                ErrorClass::BadRequest
+            }
+        }
+    }
+}
+
+/// Reloads the routes from the config file, `POST /admin/reload`. Authorized with the admin
+/// token of the config the gateway was started with.
+pub async fn reload(
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let admin_token = match state.arch_config.admin.as_ref() {
+        Some(admin) => admin.token.as_str(),
+        None => {
+            return error_response(
+                ErrorClass::Forbidden,
+                "Admin endpoints are disabled, no admin token is configured",
+            )
+        }
+    };
+    if !authorized(headers, admin_token) {
+        warn!("rejecting reload without a valid admin token");
+        return error_response(ErrorClass::Unauthorized, "Invalid admin token");
+    }
+
+    match reload_router_service(state).await {
+        Ok(()) => {
+            info!("reloaded the routes");
+            let body = Full::new(Bytes::from(r#"{"status":"reloaded"}"#))
+                .map_err(|never| match never {})
+                .boxed();
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .unwrap()
+        }
+        Err(err) => {
+            warn!("failed to reload the routes: {}", err);
+            error_response(err.class(), format!("Failed to reload the routes: {}", err))
+        }
+    }
+}
+
+fn authorized(headers: &HeaderMap, admin_token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token.trim() == admin_token)
+}
+
+/// Builds a router from the config file and swaps it in for new requests, requests in flight
+/// finish with the router they started with. The running router is kept when the config
+/// can't be read, parsed or built into a router.
+///
+/// Only the routing is reloaded: routes, their descriptions, keywords, weights, splits and
+/// fallbacks, the routing settings, and the models listed by `/v1/models`. Providers, their
+/// endpoints and credentials, and the limits of the routes take a restart.
+pub async fn reload_router_service(state: &AppState) -> Result<(), ReloadError> {
+    let path = state
+        .config_path
+        .as_ref()
+        .ok_or(ReloadError::NoConfigFile)?;
+    let config_contents = fs::read_to_string(path).map_err(|source| ReloadError::Read {
+        path: path.clone(),
+        source,
+    })?;
+    let arch_config: Configuration = serde_yaml::from_str(&config_contents)?;
+
+    // requests are sent with the providers the gateway was started with
+    if let Some(provider) = arch_config.llm_providers.iter().find(|provider| {
+        !state
+            .arch_config
+            .llm_providers
+            .iter()
+            .any(|running| running.name == provider.name)
+    }) {
+        return Err(ReloadError::UnknownProvider(provider.name.clone()));
+    }
+
+    let router_service = router_service_from_config(
+        &arch_config,
+        state.llm_provider_endpoint.clone(),
+        state.http_client.clone(),
+        state.metrics.clone(),
+    )
+    .await?;
+    state.swap_router_service(router_service, arch_config.llm_providers);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::{Arc, RwLock};
+
+    use hermesllm::providers::openai::types::Message;
+    use hyper::header::HeaderValue;
+
+    use crate::app_state::Routing;
+    use crate::metrics::Metrics;
+    use crate::router::llm_router::RouterService;
+    use crate::utils::circuit_breaker::CircuitBreakers;
+    use crate::utils::concurrency::ConcurrencyLimiter;
+    use crate::utils::credentials::ProviderCredentials;
+    use crate::utils::idempotency::IdempotencyKeys;
+    use crate::utils::model_allowlist::ModelAllowlist;
+    use crate::utils::provider_headers::ProviderHeaders;
+    use crate::utils::rate_limit::RateLimiter;
+    use crate::utils::response_cache::CompletionCache;
+    use crate::utils::shutdown::InFlight;
+    use crate::utils::transform::Transforms;
+    use crate::utils::upstream_endpoints::UpstreamEndpoints;
+
+    const ROUTES: &str = r#"
+version: v0.1
+admin:
+  token: admin-secret
+llm_providers:
+  - name: gpt-4o
+    provider_interface: openai
+    routing_preferences:
+      - name: image-generation
+        description: generating image
+        keywords: ["draw"]
+  - name: claude-3-7-sonnet
+    provider_interface: claude
+    routing_preferences:
+      - name: code-generation
+        description: generating new code snippets
+        keywords: ["python"]
+"#;
+
+    fn app_state(config_path: &Path) -> AppState {
+        let arch_config: Configuration = serde_yaml::from_str(ROUTES).unwrap();
+        let router_url = "http://localhost:12001/v1/chat/completions".to_string();
+        let http_client = reqwest::Client::new();
+        let router_service = RouterService::new(
+            arch_config.llm_providers.clone(),
+            router_url.clone(),
+            "Arch-Router".to_string(),
+            "arch-router".to_string(),
+            http_client.clone(),
+        )
+        .unwrap();
+        AppState {
+            routing: RwLock::new(Routing::new(
+                router_service,
+                arch_config.llm_providers.clone(),
+            )),
+            config_path: Some(config_path.to_string_lossy().to_string()),
+            llm_provider_endpoint: router_url,
+            upstream_endpoints: UpstreamEndpoints::from_providers(&arch_config.llm_providers),
+            http_client,
+            credentials: ProviderCredentials::from_providers(&arch_config.llm_providers),
+            provider_headers: ProviderHeaders::from_providers(&arch_config.llm_providers),
+            rate_limiter: Arc::new(RateLimiter::from_config(&arch_config)),
+            response_cache: CompletionCache::default(),
+            idempotency_keys: IdempotencyKeys::default(),
+            arch_config: Arc::new(arch_config),
+            metrics: Arc::new(Metrics::new()),
+            in_flight: InFlight::new(),
+            model_allowlist: ModelAllowlist::default(),
+            circuit_breakers: CircuitBreakers::default(),
+            concurrency: ConcurrencyLimiter::default(),
+            transforms: Transforms::default(),
+        }
+    }
+
+    fn admin_headers(token: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        headers
+    }
+
+    async fn routed_model(router_service: &RouterService) -> Option<String> {
+        router_service
+            .determine_route(
+                &[Message::new("write a python script".to_string())],
+                &HeaderMap::new(),
+                None,
+            )
+            .await
+            .unwrap()
+            .model_name()
+            .map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn test_reload_changes_routing() {
+        let config_path =
+            std::env::temp_dir().join(format!("archgw-reload-{}.yaml", std::process::id()));
+        fs::write(&config_path, ROUTES).unwrap();
+        let state = app_state(&config_path);
+        let running = state.router_service();
+        assert_eq!(
+            routed_model(&running).await.as_deref(),
+            Some("claude-3-7-sonnet")
+        );
+
+        // python moves to gpt-4o
+        fs::write(
+            &config_path,
+            ROUTES
+                .replace(r#"keywords: ["python"]"#, r#"keywords: ["rust"]"#)
+                .replace(r#"keywords: ["draw"]"#, r#"keywords: ["draw", "python"]"#),
+        )
+        .unwrap();
+        assert_eq!(
+            reload(&admin_headers("not-the-token"), &state)
+                .await
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            routed_model(&state.router_service()).await.as_deref(),
+            Some("claude-3-7-sonnet")
+        );
+
+        assert_eq!(
+            reload(&admin_headers("admin-secret"), &state)
+                .await
+                .status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            routed_model(&state.router_service()).await.as_deref(),
+            Some("gpt-4o")
+        );
+        // the models are listed with the reloaded routes
+        assert_eq!(
+            state.llm_providers()[0]
+                .routing_preferences
+                .as_ref()
+                .unwrap()[0]
+                .keywords,
+            Some(vec!["draw".to_string(), "python".to_string()])
+        );
+        // requests that started before the reload keep their router
+        assert_eq!(
+            routed_model(&running).await.as_deref(),
+            Some("claude-3-7-sonnet")
+        );
+
+        // invalid configs leave the running router in place
+        fs::write(&config_path, "llm_providers: [").unwrap();
+        assert_eq!(
+            reload(&admin_headers("admin-secret"), &state)
+                .await
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+        fs::write(
+            &config_path,
+            ROUTES.replace("claude-3-7-sonnet", "mistral-large"),
+        )
+        .unwrap();
+        assert!(matches!(
+            reload_router_service(&state).await,
+            Err(ReloadError::UnknownProvider(provider)) if provider == "mistral-large"
+        ));
+        assert_eq!(
+            routed_model(&state.router_service()).await.as_deref(),
+            Some("gpt-4o")
+        );
+
+        fs::remove_file(config_path).unwrap();
+    }
+}
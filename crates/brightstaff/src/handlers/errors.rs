@@ -0,0 +1,180 @@
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::header;
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+
+/// Failure classes surfaced to clients, each maps to an OpenAI style error `type` and `code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    BadRequest,
+    /// The request carries no valid credentials.
+    Unauthorized,
+    Forbidden,
+    /// The content of the request was blocked by the guard.
+    ContentBlocked,
+    InternalError,
+    /// A request with the same idempotency key is still being served.
+    Conflict,
+    PayloadTooLarge,
+    TooManyRequests,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+}
+
+impl ErrorClass {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorClass::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorClass::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorClass::Forbidden | ErrorClass::ContentBlocked => StatusCode::FORBIDDEN,
+            ErrorClass::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorClass::Conflict => StatusCode::CONFLICT,
+            ErrorClass::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorClass::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorClass::BadGateway => StatusCode::BAD_GATEWAY,
+            ErrorClass::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorClass::GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            ErrorClass::BadRequest
+            | ErrorClass::PayloadTooLarge
+            | ErrorClass::ContentBlocked
+            | ErrorClass::Conflict => "invalid_request_error",
+            ErrorClass::Unauthorized => "authentication_error",
+            ErrorClass::Forbidden => "permission_error",
+            ErrorClass::TooManyRequests => "rate_limit_error",
+            ErrorClass::InternalError
+            | ErrorClass::BadGateway
+            | ErrorClass::ServiceUnavailable
+            | ErrorClass::GatewayTimeout => "server_error",
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorClass::BadRequest => "bad_request",
+            ErrorClass::Unauthorized => "unauthorized",
+            ErrorClass::Forbidden => "forbidden",
+            ErrorClass::ContentBlocked => "content_blocked",
+            ErrorClass::InternalError => "internal_error",
+            ErrorClass::Conflict => "conflict",
+            ErrorClass::PayloadTooLarge => "payload_too_large",
+            ErrorClass::TooManyRequests => "rate_limit_exceeded",
+            ErrorClass::BadGateway => "bad_gateway",
+            ErrorClass::ServiceUnavailable => "service_unavailable",
+            ErrorClass::GatewayTimeout => "gateway_timeout",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    message: &'a str,
+    #[serde(rename = "type")]
+    error_type: &'a str,
+    code: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorEnvelope<'a> {
+    error: ErrorBody<'a>,
+}
+
+/// Builds an error response using the OpenAI error envelope
+/// `{"error": {"message": ..., "type": ..., "code": ...}}` so that OpenAI SDK clients can
+/// surface the failure.
+pub fn error_response<T: AsRef<str>>(
+    class: ErrorClass,
+    message: T,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let envelope = ErrorEnvelope {
+        error: ErrorBody {
+            message: message.as_ref(),
+            error_type: class.error_type(),
+            code: class.code(),
+        },
+    };
+    let body = serde_json::to_string(&envelope).unwrap_or_default();
+
+    let mut response = Response::new(
+        Full::new(Bytes::from(body))
+            .map_err(|never| match never {})
+            .boxed(),
+    );
+    *response.status_mut() = class.status();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response_envelope() {
+        let response = error_response(ErrorClass::BadRequest, "Request body is not valid JSON");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "message": "Request body is not valid JSON",
+                    "type": "invalid_request_error",
+                    "code": "bad_request",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_class_status_codes() {
+        assert_eq!(
+            ErrorClass::InternalError.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            ErrorClass::PayloadTooLarge.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(ErrorClass::BadGateway.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            ErrorClass::ServiceUnavailable.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(ErrorClass::Unauthorized.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            ErrorClass::Unauthorized.error_type(),
+            "authentication_error"
+        );
+        assert_eq!(ErrorClass::Forbidden.status(), StatusCode::FORBIDDEN);
+        assert_eq!(ErrorClass::Forbidden.error_type(), "permission_error");
+        assert_eq!(ErrorClass::ContentBlocked.status(), StatusCode::FORBIDDEN);
+        assert_eq!(ErrorClass::ContentBlocked.code(), "content_blocked");
+        assert_eq!(ErrorClass::Conflict.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            ErrorClass::TooManyRequests.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            ErrorClass::GatewayTimeout.status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+    }
+}
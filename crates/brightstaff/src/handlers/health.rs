@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::consts::DEFAULT_HEALTH_PROBE_TIMEOUT_MS;
+use futures::future::join_all;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+
+use crate::app_state::AppState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Ready,
+    Degraded,
+    Unreachable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyStatus {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadinessBody {
+    status: Status,
+    dependencies: BTreeMap<String, DependencyStatus>,
+}
+
+fn json_response<T: Serialize>(
+    status: StatusCode,
+    body: &T,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let body = Full::new(Bytes::from(serde_json::to_string(body).unwrap_or_default()))
+        .map_err(|never| match never {})
+        .boxed();
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// Liveness, the process is up and serving requests.
+pub fn healthz() -> Response<BoxBody<Bytes, hyper::Error>> {
+    json_response(StatusCode::OK, &serde_json::json!({ "status": Status::Ok }))
+}
+
+/// Readiness, probes the llm gateway (which also serves the routing model), the embeddings
+/// endpoint and the openai compatible providers that are called directly. Responds 503 when any
+/// of them is unreachable so that the pod is taken out of rotation.
+pub async fn readyz(state: &AppState) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let health = state.arch_config.health.as_ref();
+    if !health
+        .and_then(|health| health.probe_upstreams)
+        .unwrap_or(true)
+    {
+        return readiness_response(BTreeMap::new());
+    }
+    let timeout = Duration::from_millis(
+        health
+            .and_then(|health| health.probe_timeout_ms)
+            .unwrap_or(DEFAULT_HEALTH_PROBE_TIMEOUT_MS),
+    );
+
+    let mut targets = vec![(
+        "llm_gateway".to_string(),
+        state.llm_provider_endpoint.clone(),
+    )];
+    if let Some(embedding) = state
+        .arch_config
+        .routing
+        .as_ref()
+        .and_then(|routing| routing.embedding.as_ref())
+    {
+        targets.push(("embedding".to_string(), embedding.url.clone()));
+    }
+    for provider in &state.arch_config.llm_providers {
+        if let Some(compatible_provider) = provider.openai_compatible_provider() {
+            targets.push((
+                provider.name.clone(),
+                format!(
+                    "{}/models",
+                    compatible_provider.base_url.trim_end_matches('/')
+                ),
+            ));
+        }
+    }
+
+    let statuses =
+        join_all(targets.into_iter().map(|(name, url)| async move {
+            (name, probe(&state.http_client, &url, timeout).await)
+        }))
+        .await;
+    readiness_response(statuses.into_iter().collect())
+}
+
+/// A cheap HEAD request, any http response means the dependency is reachable.
+pub async fn probe(
+    http_client: &reqwest::Client,
+    url: &str,
+    timeout: Duration,
+) -> DependencyStatus {
+    match http_client.head(url).timeout(timeout).send().await {
+        Ok(_) => DependencyStatus {
+            status: Status::Ok,
+            error: None,
+        },
+        Err(err) => DependencyStatus {
+            status: Status::Unreachable,
+            error: Some(if err.is_timeout() {
+                "timeout".to_string()
+            } else {
+                err.without_url().to_string()
+            }),
+        },
+    }
+}
+
+fn readiness_response(
+    dependencies: BTreeMap<String, DependencyStatus>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let ready = dependencies
+        .values()
+        .all(|dependency| dependency.status == Status::Ok);
+    let (status_code, status) = if ready {
+        (StatusCode::OK, Status::Ready)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Status::Degraded)
+    };
+    json_response(
+        status_code,
+        &ReadinessBody {
+            status,
+            dependencies,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Request;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(|_req: Request<Incoming>| async {
+                    let mut response = Response::new(Full::new(Bytes::new()));
+                    // the status doesn't matter, the dependency answered
+                    *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                    Ok::<_, Infallible>(response)
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        format!("http://{}/v1/models", addr)
+    }
+
+    async fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        format!("http://{}/v1/models", addr)
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let http_client = reqwest::Client::new();
+        let timeout = Duration::from_secs(1);
+
+        let status = probe(&http_client, &serve().await, timeout).await;
+        assert_eq!(status.status, Status::Ok);
+        assert!(status.error.is_none());
+
+        let status = probe(&http_client, &closed_port().await, timeout).await;
+        assert_eq!(status.status, Status::Unreachable);
+        assert!(status.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_readiness_response() {
+        let response = readiness_response(BTreeMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let http_client = reqwest::Client::new();
+        let timeout = Duration::from_secs(1);
+        let dependencies = BTreeMap::from([
+            (
+                "llm_gateway".to_string(),
+                probe(&http_client, &serve().await, timeout).await,
+            ),
+            (
+                "mistral".to_string(),
+                probe(&http_client, &closed_port().await, timeout).await,
+            ),
+        ]);
+        let response = readiness_response(dependencies);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["dependencies"]["llm_gateway"]["status"], "ok");
+        assert_eq!(body["dependencies"]["mistral"]["status"], "unreachable");
+    }
+}
@@ -0,0 +1,16 @@
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{Response, StatusCode};
+
+use crate::metrics::Metrics;
+
+pub fn scrape_metrics(metrics: &Metrics) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let body = Full::new(Bytes::from(metrics.render()))
+        .map_err(|never| match never {})
+        .boxed();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(body)
+        .unwrap()
+}
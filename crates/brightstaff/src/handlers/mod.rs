@@ -1,2 +1,8 @@
+pub mod admin;
 pub mod chat_completions;
+pub mod errors;
+pub mod health;
+pub mod metrics;
 pub mod models;
+pub mod request_log;
+pub mod route;
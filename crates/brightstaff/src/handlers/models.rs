@@ -4,14 +4,9 @@ use hermesllm::providers::openai::types::Models;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::{Response, StatusCode};
 use serde_json;
-use std::sync::Arc;
 
-pub async fn list_models(
-    llm_providers: Arc<tokio::sync::RwLock<Vec<LlmProvider>>>,
-) -> Response<BoxBody<Bytes, hyper::Error>> {
-    let prov = llm_providers.read().await;
-    let providers = prov.clone();
-    let openai_models: Models = providers.into_models();
+pub fn list_models(llm_providers: &[LlmProvider]) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let openai_models: Models = llm_providers.to_vec().into_models();
 
     match serde_json::to_string(&openai_models) {
         Ok(json) => {
@@ -0,0 +1,109 @@
+use hermesllm::providers::openai::types::ChatCompletionsRequest;
+use serde_json::Value;
+
+/// Metadata of a chat completions request that is safe to log, no message content.
+pub fn request_summary(request: &ChatCompletionsRequest, body_len: usize) -> String {
+    format!(
+        "model: {}, messages: {}, stream: {}, size: {} bytes",
+        request.model,
+        request.messages.len(),
+        request.stream.unwrap_or(false),
+        body_len
+    )
+}
+
+/// Serialized request with the content of every message cut to `max_chars` characters.
+pub fn redacted_request(request: &ChatCompletionsRequest, max_chars: usize) -> String {
+    let mut request = match serde_json::to_value(request) {
+        Ok(request) => request,
+        Err(_) => return String::new(),
+    };
+
+    if let Some(messages) = request.get_mut("messages").and_then(Value::as_array_mut) {
+        for message in messages {
+            match message.get_mut("content") {
+                Some(Value::String(text)) => *text = truncate(text, max_chars),
+                Some(Value::Array(parts)) => {
+                    for part in parts {
+                        if let Some(Value::String(text)) = part.get_mut("text") {
+                            *text = truncate(text, max_chars);
+                        }
+                        // image urls may carry the whole image as a data url
+                        if let Some(Value::String(url)) = part
+                            .get_mut("image_url")
+                            .and_then(|image_url| image_url.get_mut("url"))
+                        {
+                            *url = truncate(url, max_chars);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    request.to_string()
+}
+
+/// Cuts the text to `max_chars` characters, marking that it was cut.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "my social security number is 078-05-1120 and my address is 1 Main St";
+
+    fn request() -> ChatCompletionsRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "stream": true,
+            "messages": [
+                { "role": "system", "content": "You are a helpful assistant" },
+                { "role": "user", "content": SECRET },
+                { "role": "user", "content": [
+                    { "type": "text", "text": SECRET },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAAB" } }
+                ]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_summary_has_no_content() {
+        let summary = request_summary(&request(), 512);
+        assert_eq!(
+            summary,
+            "model: gpt-4o, messages: 3, stream: true, size: 512 bytes"
+        );
+    }
+
+    #[test]
+    fn test_redacted_request_truncates_content() {
+        let redacted = redacted_request(&request(), 10);
+        assert!(!redacted.contains(SECRET));
+        assert!(!redacted.contains("078-05-1120"));
+        assert!(!redacted.contains("iVBORw0KGgo"));
+        assert!(redacted.contains("my social ..."));
+        assert!(redacted.contains("You are a ..."));
+
+        // the request is still valid json
+        let redacted: Value = serde_json::from_str(&redacted).unwrap();
+        assert_eq!(redacted["model"], "gpt-4o");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello world", 5), "hello...");
+        // cut on character boundaries
+        assert_eq!(truncate("héllo wörld", 7), "héllo w...");
+    }
+}
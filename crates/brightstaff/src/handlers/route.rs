@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::consts::DEFAULT_MAX_REQUEST_BODY_BYTES;
+use hermesllm::providers::openai::types::ChatCompletionsRequest;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{Request, Response, StatusCode};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::app_state::AppState;
+use crate::handlers::chat_completions::{
+    payload_too_large, read_body_with_limit, routing_error_class, usage_preferences_from_metadata,
+    ReadBodyError,
+};
+use crate::handlers::errors::{error_response, ErrorClass};
+use crate::router::llm_router::RoutingPrompt;
+use crate::router::router_model::RouteDecision;
+use crate::utils::tracing::trace_context_headers;
+
+/// Response of the dry-run routing endpoint.
+#[derive(Debug, Serialize)]
+struct RouteResponse {
+    route: Option<String>,
+    model: Option<String>,
+    confidence: Option<f32>,
+    routing_model: Option<String>,
+    prompt: Option<String>,
+    estimated_tokens: Option<usize>,
+    messages_considered: Option<usize>,
+    truncated: Option<bool>,
+}
+
+impl RouteResponse {
+    fn new(route_decision: &RouteDecision, routing_prompt: Option<RoutingPrompt>) -> Self {
+        let routing_prompt = routing_prompt.as_ref();
+        RouteResponse {
+            route: route_decision.route_name().map(str::to_string),
+            model: route_decision.model_name().map(str::to_string),
+            confidence: route_decision.confidence,
+            routing_model: routing_prompt
+                .map(|routing_prompt| routing_prompt.routing_model.clone()),
+            prompt: routing_prompt.map(|routing_prompt| routing_prompt.prompt.clone()),
+            estimated_tokens: routing_prompt.map(|routing_prompt| routing_prompt.estimated_tokens),
+            messages_considered: routing_prompt
+                .map(|routing_prompt| routing_prompt.messages_considered),
+            truncated: routing_prompt.map(|routing_prompt| routing_prompt.truncated),
+        }
+    }
+}
+
+/// Dry run of routing for a chat completions request, `POST /v1/route`. Returns the route the
+/// routing model picks together with the prompt it was sent, the request is never forwarded
+/// to a provider.
+pub async fn route(
+    request: Request<hyper::body::Incoming>,
+    state: Arc<AppState>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let max_request_body_bytes = state
+        .arch_config
+        .overrides
+        .as_ref()
+        .and_then(|overrides| overrides.max_request_body_bytes)
+        .unwrap_or(DEFAULT_MAX_REQUEST_BODY_BYTES);
+
+    let trace_context = trace_context_headers(request.headers());
+    let body = match read_body_with_limit(request.into_body(), max_request_body_bytes).await {
+        Ok(body) => body,
+        Err(ReadBodyError::TooLarge { size, limit }) => return Ok(payload_too_large(size, limit)),
+        Err(ReadBodyError::Body(err)) => return Err(err),
+    };
+
+    let chat_completion_request: ChatCompletionsRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => {
+            warn!("Failed to parse chat completions request: {}", err);
+            return Ok(error_response(
+                ErrorClass::BadRequest,
+                format!("Invalid chat completions request: {}", err),
+            ));
+        }
+    };
+    let usage_preferences = usage_preferences_from_metadata(&chat_completion_request);
+
+    let router_service = state.router_service();
+    let routing_prompt =
+        router_service.routing_prompt(&chat_completion_request.messages, &usage_preferences);
+    let route_decision = match router_service
+        .determine_route(
+            &chat_completion_request.messages,
+            &trace_context,
+            usage_preferences,
+        )
+        .await
+    {
+        Ok(route_decision) => route_decision,
+        Err(err) => {
+            warn!("failed to determine route: {}", err);
+            return Ok(error_response(
+                routing_error_class(&err),
+                format!("Failed to determine route: {}", err),
+            ));
+        }
+    };
+    info!(
+        "dry-run routing, selected route: {:?}",
+        route_decision.route
+    );
+
+    let response = RouteResponse::new(&route_decision, routing_prompt);
+    let body = Full::new(Bytes::from(
+        serde_json::to_string(&response).unwrap_or_default(),
+    ))
+    .map_err(|never| match never {})
+    .boxed();
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_response() {
+        let route_decision = RouteDecision {
+            route: Some(("image-generation".to_string(), "gpt-4o".to_string())),
+            confidence: Some(0.9),
+        };
+        let routing_prompt = RoutingPrompt {
+            routing_model: "Arch-Router".to_string(),
+            prompt: "<routes></routes>".to_string(),
+            estimated_tokens: 12,
+            messages_considered: 2,
+            truncated: false,
+        };
+        let response =
+            serde_json::to_value(RouteResponse::new(&route_decision, Some(routing_prompt)))
+                .unwrap();
+        assert_eq!(response["route"], "image-generation");
+        assert_eq!(response["model"], "gpt-4o");
+        assert_eq!(response["routing_model"], "Arch-Router");
+        assert_eq!(response["prompt"], "<routes></routes>");
+        assert_eq!(response["estimated_tokens"], 12);
+        assert_eq!(response["truncated"], false);
+
+        // without configured routes the routing model is never asked
+        let response =
+            serde_json::to_value(RouteResponse::new(&RouteDecision::default(), None)).unwrap();
+        assert!(response["route"].is_null());
+        assert!(response["prompt"].is_null());
+        assert!(response["routing_model"].is_null());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod app_state;
 pub mod handlers;
+pub mod metrics;
 pub mod router;
 pub mod utils;
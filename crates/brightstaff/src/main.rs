@@ -1,9 +1,28 @@
+use brightstaff::app_state::{AppState, Routing};
+use brightstaff::handlers::admin::reload;
 use brightstaff::handlers::chat_completions::chat_completions;
+use brightstaff::handlers::health::{healthz, readyz};
+use brightstaff::handlers::metrics::scrape_metrics;
 use brightstaff::handlers::models::list_models;
-use brightstaff::router::llm_router::RouterService;
-use brightstaff::utils::tracing::init_tracer;
+use brightstaff::handlers::route::route;
+use brightstaff::metrics::Metrics;
+use brightstaff::router::router_config::router_service_from_config;
+use brightstaff::utils::circuit_breaker::CircuitBreakers;
+use brightstaff::utils::concurrency::ConcurrencyLimiter;
+use brightstaff::utils::credentials::{redacted, ProviderCredentials};
+use brightstaff::utils::http_client::build_http_client;
+use brightstaff::utils::idempotency::IdempotencyKeys;
+use brightstaff::utils::model_allowlist::ModelAllowlist;
+use brightstaff::utils::provider_headers::ProviderHeaders;
+use brightstaff::utils::rate_limit::RateLimiter;
+use brightstaff::utils::response_cache::CompletionCache;
+use brightstaff::utils::shutdown::{shutdown_signal, InFlight};
+use brightstaff::utils::tracing::{init_tracer, TracerOptions};
+use brightstaff::utils::transform::Transforms;
+use brightstaff::utils::upstream_endpoints::UpstreamEndpoints;
 use bytes::Bytes;
 use common::configuration::Configuration;
+use common::consts::DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_MS;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
@@ -14,16 +33,13 @@ use opentelemetry::trace::FutureExt;
 use opentelemetry::{global, Context};
 use opentelemetry_http::HeaderExtractor;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env, fs};
 use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
-pub mod router;
-
 const BIND_ADDRESS: &str = "0.0.0.0:9091";
-const DEFAULT_ROUTING_LLM_PROVIDER: &str = "arch-router";
-const DEFAULT_ROUTING_MODEL_NAME: &str = "Arch-Router";
 
 // Utility function to extract the context from the incoming request headers
 fn extract_context_from_request(req: &Request<Incoming>) -> Context {
@@ -40,31 +56,30 @@ fn empty() -> BoxBody<Bytes, hyper::Error> {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let _tracer_provider = init_tracer();
     let bind_address = env::var("BIND_ADDRESS").unwrap_or_else(|_| BIND_ADDRESS.to_string());
 
-    info!(
-        "current working directory: {}",
-        env::current_dir().unwrap().display()
-    );
-    // loading arch_config.yaml file
+    // loading arch_config.yaml file, the logger is configured from it
     let arch_config_path = env::var("ARCH_CONFIG_PATH_RENDERED")
         .unwrap_or_else(|_| "./arch_config_rendered.yaml".to_string());
-    info!("Loading arch_config.yaml from {}", arch_config_path);
-
     let config_contents =
         fs::read_to_string(&arch_config_path).expect("Failed to read arch_config.yaml");
 
     let config: Configuration =
         serde_yaml::from_str(&config_contents).expect("Failed to parse arch_config.yaml");
 
-    let arch_config = Arc::new(config);
+    let _tracer_provider = init_tracer(&TracerOptions::from_config(config.tracing.as_ref()));
 
-    let llm_providers = Arc::new(RwLock::new(arch_config.llm_providers.clone()));
+    info!(
+        "current working directory: {}",
+        env::current_dir().unwrap().display()
+    );
+    info!("Loaded arch_config.yaml from {}", arch_config_path);
+
+    let arch_config = Arc::new(config);
 
     debug!(
         "arch_config: {:?}",
-        &serde_json::to_string(arch_config.as_ref()).unwrap()
+        &serde_json::to_string(&redacted(arch_config.as_ref())).unwrap()
     );
 
     let llm_provider_endpoint = env::var("LLM_PROVIDER_ENDPOINT")
@@ -74,48 +89,106 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("listening on http://{}", bind_address);
     let listener = TcpListener::bind(bind_address).await?;
 
-    let routing_model_name: String = arch_config
-        .routing
-        .as_ref()
-        .and_then(|r| r.model.clone())
-        .unwrap_or_else(|| DEFAULT_ROUTING_MODEL_NAME.to_string());
+    // shared by all requests so that upstream connections are pooled and kept alive
+    let http_client =
+        build_http_client(arch_config.upstream.as_ref()).expect("Failed to build http client");
 
-    let routing_llm_provider = arch_config
-        .routing
-        .as_ref()
-        .and_then(|r| r.llm_provider.clone())
-        .unwrap_or_else(|| DEFAULT_ROUTING_LLM_PROVIDER.to_string());
+    let metrics = Arc::new(Metrics::new());
 
-    let router_service: Arc<RouterService> = Arc::new(RouterService::new(
-        arch_config.llm_providers.clone(),
+    let router_service = router_service_from_config(
+        &arch_config,
         llm_provider_endpoint.clone(),
-        routing_model_name,
-        routing_llm_provider,
-    ));
+        http_client.clone(),
+        metrics.clone(),
+    )
+    .await
+    .expect("Failed to build router service");
+
+    let shutdown_drain_timeout = Duration::from_millis(
+        arch_config
+            .overrides
+            .as_ref()
+            .and_then(|overrides| overrides.shutdown_drain_timeout_ms)
+            .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_MS),
+    );
+
+    let credentials = ProviderCredentials::from_providers(&arch_config.llm_providers);
+    let provider_headers = ProviderHeaders::from_providers(&arch_config.llm_providers);
+    let upstream_endpoints = UpstreamEndpoints::from_providers(&arch_config.llm_providers);
+    let model_allowlist = ModelAllowlist::from_config(&arch_config);
+    let rate_limiter = Arc::new(RateLimiter::from_config(&arch_config));
+    let circuit_breakers = CircuitBreakers::from_config(
+        arch_config
+            .upstream
+            .as_ref()
+            .and_then(|upstream| upstream.circuit_breaker.as_ref()),
+    );
+    let concurrency = ConcurrencyLimiter::from_config(
+        arch_config
+            .upstream
+            .as_ref()
+            .and_then(|upstream| upstream.concurrency.as_ref()),
+    );
+    let response_cache = CompletionCache::from_config(arch_config.response_cache.as_ref());
+    let idempotency_keys = IdempotencyKeys::from_config(arch_config.idempotency.as_ref());
+    let in_flight = InFlight::new();
+    let app_state = Arc::new(AppState {
+        routing: std::sync::RwLock::new(Routing::new(
+            router_service,
+            arch_config.llm_providers.clone(),
+        )),
+        config_path: Some(arch_config_path),
+        llm_provider_endpoint,
+        upstream_endpoints,
+        http_client,
+        arch_config,
+        metrics,
+        credentials,
+        provider_headers,
+        in_flight: in_flight.clone(),
+        model_allowlist,
+        rate_limiter,
+        circuit_breakers,
+        concurrency,
+        response_cache,
+        transforms: Transforms::default(),
+        idempotency_keys,
+    });
+
+    // connections finish their in-flight requests once shutdown is signaled
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
         let peer_addr = stream.peer_addr()?;
         let io = TokioIo::new(stream);
 
-        let router_service = Arc::clone(&router_service);
-        let llm_provider_endpoint = llm_provider_endpoint.clone();
+        let app_state = Arc::clone(&app_state);
 
-        let llm_providers = llm_providers.clone();
         let service = service_fn(move |req| {
-            let router_service = Arc::clone(&router_service);
+            let app_state = Arc::clone(&app_state);
             let parent_cx = extract_context_from_request(&req);
-            let llm_provider_endpoint = llm_provider_endpoint.clone();
-            let llm_providers = llm_providers.clone();
 
             async move {
                 match (req.method(), req.uri().path()) {
                     (&Method::POST, "/v1/chat/completions") => {
-                        chat_completions(req, router_service, llm_provider_endpoint)
+                        chat_completions(req, app_state)
                             .with_context(parent_cx)
                             .await
                     }
-                    (&Method::GET, "/v1/models") => Ok(list_models(llm_providers).await),
+                    (&Method::POST, "/v1/route") => {
+                        route(req, app_state).with_context(parent_cx).await
+                    }
+                    (&Method::GET, "/v1/models") => Ok(list_models(&app_state.llm_providers())),
+                    (&Method::GET, "/metrics") => Ok(scrape_metrics(&app_state.metrics)),
+                    (&Method::GET, "/healthz") => Ok(healthz()),
+                    (&Method::GET, "/readyz") => Ok(readyz(&app_state).await),
+                    (&Method::POST, "/admin/reload") => Ok(reload(req.headers(), &app_state).await),
                     (&Method::OPTIONS, "/v1/models") => {
                         let mut response = Response::new(empty());
                         *response.status_mut() = StatusCode::NO_CONTENT;
@@ -148,15 +221,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             }
         });
 
+        let connection_in_flight = in_flight.track();
+        let mut shutdown_rx = shutdown_rx.clone();
         tokio::task::spawn(async move {
+            let _connection_in_flight = connection_in_flight;
             debug!("Accepted connection from {:?}", peer_addr);
-            if let Err(err) = http1::Builder::new()
-                // .serve_connection(io, service_fn(chat_completion))
-                .serve_connection(io, service)
-                .await
-            {
+            let connection = http1::Builder::new().serve_connection(io, service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown_rx.changed() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(err) = result {
                 warn!("Error serving connection: {:?}", err);
             }
         });
     }
+
+    // stop accepting new connections and let the in-flight ones finish
+    drop(listener);
+    info!(
+        "shutting down, waiting up to {}ms for {} in-flight connections and streams",
+        shutdown_drain_timeout.as_millis(),
+        in_flight.count()
+    );
+    let _ = shutdown_tx.send(true);
+    if !in_flight.drain(shutdown_drain_timeout).await {
+        warn!(
+            "drain timeout elapsed, closing {} connections and streams",
+            in_flight.count()
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const LATENCY_BUCKETS_SECONDS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Counter partitioned by a fixed set of labels, values must be passed in the same order as
+/// `label_names`.
+pub struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> Self {
+        CounterVec {
+            name,
+            help,
+            label_names,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, label_values: &[&str]) {
+        self.inc_by(label_values, 1);
+    }
+
+    pub fn inc_by(&self, label_values: &[&str], value: u64) {
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+        let key = label_values.iter().map(|v| v.to_string()).collect();
+        let mut values = self.values.lock().unwrap();
+        *values.entry(key).or_insert(0) += value;
+    }
+
+    pub fn get(&self, label_values: &[&str]) -> u64 {
+        let key: Vec<String> = label_values.iter().map(|v| v.to_string()).collect();
+        self.values
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (label_values, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                render_labels(self.label_names, label_values, None),
+                value
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct HistogramValue {
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Histogram partitioned by a fixed set of labels, values must be passed in the same order as
+/// `label_names`.
+pub struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValue>>,
+}
+
+impl HistogramVec {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        HistogramVec {
+            name,
+            help,
+            label_names,
+            buckets,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, label_values: &[&str], value: f64) {
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+        let key = label_values.iter().map(|v| v.to_string()).collect();
+        let mut values = self.values.lock().unwrap();
+        let histogram = values.entry(key).or_insert_with(|| HistogramValue {
+            bucket_counts: vec![0; self.buckets.len()],
+            ..Default::default()
+        });
+        for (bucket, count) in self.buckets.iter().zip(histogram.bucket_counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    pub fn observe_duration(&self, label_values: &[&str], duration: Duration) {
+        self.observe(label_values, duration.as_secs_f64());
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (label_values, histogram) in self.values.lock().unwrap().iter() {
+            for (bucket, count) in self.buckets.iter().zip(histogram.bucket_counts.iter()) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    self.name,
+                    render_labels(self.label_names, label_values, Some(&bucket.to_string())),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                self.name,
+                render_labels(self.label_names, label_values, Some("+Inf")),
+                histogram.count
+            );
+            let labels = render_labels(self.label_names, label_values, None);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, histogram.count);
+        }
+    }
+}
+
+fn render_labels(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut labels: Vec<String> = names
+        .iter()
+        .zip(values.iter())
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+    if let Some(le) = le {
+        labels.push(format!("le=\"{}\"", le));
+    }
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn streaming_label(is_streaming: bool) -> &'static str {
+    if is_streaming {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+/// Metrics registry shared by all requests, rendered in the Prometheus text format on
+/// `/metrics`.
+pub struct Metrics {
+    pub route_selections: CounterVec,
+    pub routing_latency: HistogramVec,
+    pub routing_conversations: CounterVec,
+    pub route_splits: CounterVec,
+    pub upstream_latency: HistogramVec,
+    pub upstream_responses: CounterVec,
+    pub upstream_retries: CounterVec,
+    pub upstream_fallbacks: CounterVec,
+    pub payload_too_large: CounterVec,
+    pub prompt_tokens: CounterVec,
+    pub completion_tokens: CounterVec,
+    pub stream_errors: CounterVec,
+    pub rate_limited: CounterVec,
+    pub circuit_open: CounterVec,
+    pub concurrency_limited: CounterVec,
+    pub response_cache_hits: CounterVec,
+    pub guard_blocked: CounterVec,
+    pub idempotent_replays: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            route_selections: CounterVec::new(
+                "brightstaff_route_selections_total",
+                "Requests routed, by selected route.",
+                &["route", "streaming"],
+            ),
+            routing_latency: HistogramVec::new(
+                "brightstaff_routing_latency_seconds",
+                "Time spent determining the route with the routing model.",
+                &["streaming"],
+                &LATENCY_BUCKETS_SECONDS,
+            ),
+            routing_conversations: CounterVec::new(
+                "brightstaff_routing_conversations_total",
+                "Conversations sent to the routing model, by whether older messages were left out to fit its token budget.",
+                &["truncated"],
+            ),
+            route_splits: CounterVec::new(
+                "brightstaff_route_splits_total",
+                "Requests of routes split across providers, by route and the provider drawn.",
+                &["route", "provider"],
+            ),
+            upstream_latency: HistogramVec::new(
+                "brightstaff_upstream_latency_seconds",
+                "Time until the upstream provider responded with headers.",
+                &["provider", "streaming"],
+                &LATENCY_BUCKETS_SECONDS,
+            ),
+            upstream_responses: CounterVec::new(
+                "brightstaff_upstream_responses_total",
+                "Upstream responses, by provider and status code.",
+                &["provider", "status", "streaming"],
+            ),
+            upstream_retries: CounterVec::new(
+                "brightstaff_upstream_retries_total",
+                "Upstream requests that were retried.",
+                &["provider", "streaming"],
+            ),
+            upstream_fallbacks: CounterVec::new(
+                "brightstaff_upstream_fallbacks_total",
+                "Requests sent to the next provider of their route after a provider failed.",
+                &["provider", "fallback"],
+            ),
+            payload_too_large: CounterVec::new(
+                "brightstaff_payload_too_large_total",
+                "Requests rejected because the body exceeded the size limit.",
+                &[],
+            ),
+            prompt_tokens: CounterVec::new(
+                "brightstaff_prompt_tokens_total",
+                "Prompt tokens reported by the upstream provider.",
+                &["provider", "streaming"],
+            ),
+            completion_tokens: CounterVec::new(
+                "brightstaff_completion_tokens_total",
+                "Completion tokens reported by the upstream provider.",
+                &["provider", "streaming"],
+            ),
+            stream_errors: CounterVec::new(
+                "brightstaff_stream_errors_total",
+                "Error events sent by the upstream provider in the middle of a stream.",
+                &["provider"],
+            ),
+            rate_limited: CounterVec::new(
+                "brightstaff_rate_limited_total",
+                "Requests rejected by the rate limit of their route.",
+                &["route"],
+            ),
+            circuit_open: CounterVec::new(
+                "brightstaff_circuit_open_total",
+                "Requests rejected because the circuit of their provider was open.",
+                &["provider"],
+            ),
+            concurrency_limited: CounterVec::new(
+                "brightstaff_concurrency_limited_total",
+                "Requests rejected because no upstream slot of their provider became free.",
+                &["provider"],
+            ),
+            response_cache_hits: CounterVec::new(
+                "brightstaff_response_cache_hits_total",
+                "Requests answered from the response cache without calling the upstream.",
+                &["provider"],
+            ),
+            guard_blocked: CounterVec::new(
+                "brightstaff_guard_blocked_total",
+                "Requests rejected because the guard blocked their content.",
+                &[],
+            ),
+            idempotent_replays: CounterVec::new(
+                "brightstaff_idempotent_replays_total",
+                "Requests answered with the stored response of their idempotency key.",
+                &[],
+            ),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.route_selections.render(&mut out);
+        self.routing_latency.render(&mut out);
+        self.routing_conversations.render(&mut out);
+        self.route_splits.render(&mut out);
+        self.upstream_latency.render(&mut out);
+        self.upstream_responses.render(&mut out);
+        self.upstream_retries.render(&mut out);
+        self.upstream_fallbacks.render(&mut out);
+        self.payload_too_large.render(&mut out);
+        self.prompt_tokens.render(&mut out);
+        self.completion_tokens.render(&mut out);
+        self.stream_errors.render(&mut out);
+        self.rate_limited.render(&mut out);
+        self.circuit_open.render(&mut out);
+        self.concurrency_limited.render(&mut out);
+        self.response_cache_hits.render(&mut out);
+        self.guard_blocked.render(&mut out);
+        self.idempotent_replays.render(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_render() {
+        let metrics = Metrics::new();
+        metrics.route_selections.inc(&["code-generation", "false"]);
+        metrics.route_selections.inc(&["code-generation", "false"]);
+        metrics.route_selections.inc(&["image\"generation", "true"]);
+        metrics.payload_too_large.inc(&[]);
+
+        assert_eq!(
+            metrics.route_selections.get(&["code-generation", "false"]),
+            2
+        );
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE brightstaff_route_selections_total counter"));
+        assert!(rendered.contains(
+            "brightstaff_route_selections_total{route=\"code-generation\",streaming=\"false\"} 2"
+        ));
+        assert!(rendered.contains(
+            "brightstaff_route_selections_total{route=\"image\\\"generation\",streaming=\"true\"} 1"
+        ));
+        assert!(rendered.contains("brightstaff_payload_too_large_total 1"));
+    }
+
+    #[test]
+    fn test_histogram_render() {
+        let histogram = HistogramVec::new("latency_seconds", "latency", &["provider"], &[0.1, 1.0]);
+        histogram.observe(&["gpt-4o"], 0.0625);
+        histogram.observe(&["gpt-4o"], 0.5);
+        histogram.observe(&["gpt-4o"], 4.0);
+
+        let mut rendered = String::new();
+        histogram.render(&mut rendered);
+
+        assert!(rendered.contains("latency_seconds_bucket{provider=\"gpt-4o\",le=\"0.1\"} 1"));
+        assert!(rendered.contains("latency_seconds_bucket{provider=\"gpt-4o\",le=\"1\"} 2"));
+        assert!(rendered.contains("latency_seconds_bucket{provider=\"gpt-4o\",le=\"+Inf\"} 3"));
+        assert!(rendered.contains("latency_seconds_sum{provider=\"gpt-4o\"} 4.5625"));
+        assert!(rendered.contains("latency_seconds_count{provider=\"gpt-4o\"} 3"));
+    }
+}
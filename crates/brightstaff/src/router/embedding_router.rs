@@ -0,0 +1,405 @@
+use std::sync::Arc;
+
+use common::configuration::{LlmProvider, ModelUsagePreference};
+use futures::future::BoxFuture;
+use hermesllm::providers::openai::types::{ChatCompletionsRequest, Message};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::router_model::{RouteDecision, RouterModel, RoutingModelError};
+use super::router_model_v1::latest_user_text;
+
+pub type Result<T> = std::result::Result<T, RoutingModelError>;
+
+/// Turns texts into embedding vectors, one per input and in the same order.
+pub trait Embedder: Send + Sync {
+    fn embed<'a>(&'a self, input: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>>;
+    fn model_name(&self) -> String;
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Embedder calling an OpenAI compatible `/v1/embeddings` endpoint.
+pub struct HttpEmbedder {
+    client: reqwest::Client,
+    url: String,
+    model: String,
+}
+
+impl HttpEmbedder {
+    pub fn new(client: reqwest::Client, url: String, model: String) -> Self {
+        HttpEmbedder { client, url, model }
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn embed<'a>(&'a self, input: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let request = EmbeddingsRequest {
+                model: &self.model,
+                input,
+            };
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&request)?)
+                .send()
+                .await?
+                .error_for_status()?;
+            let body = response.bytes().await?;
+
+            let mut response: EmbeddingsResponse = serde_json::from_slice(&body)?;
+            if response.data.len() != input.len() {
+                return Err(RoutingModelError::UpstreamError(format!(
+                    "expected {} embeddings, got {}",
+                    input.len(),
+                    response.data.len()
+                )));
+            }
+            response.data.sort_by_key(|data| data.index);
+            Ok(response
+                .data
+                .into_iter()
+                .map(|data| data.embedding)
+                .collect())
+        })
+    }
+
+    fn model_name(&self) -> String {
+        self.model.clone()
+    }
+}
+
+struct RouteEmbedding {
+    route: String,
+    model: String,
+    embedding: Vec<f32>,
+}
+
+/// Routes on the cosine similarity between the latest user message and the route descriptions.
+/// The decision is made in [`RouterModel::match_route`], no chat model is asked. Descriptions of
+/// the configured routes are embedded once at construction, routes sent with the request are
+/// embedded together with the user message.
+pub struct EmbeddingRouterModel {
+    routes: Vec<RouteEmbedding>,
+    embedder: Arc<dyn Embedder>,
+    threshold: f32,
+}
+
+impl EmbeddingRouterModel {
+    pub async fn new(
+        providers: &[LlmProvider],
+        embedder: Arc<dyn Embedder>,
+        threshold: f32,
+    ) -> Result<Self> {
+        let routes: Vec<(String, String, String)> = providers
+            .iter()
+            .flat_map(|provider| {
+                provider.routing_preferences.iter().flatten().map(|pref| {
+                    (
+                        pref.name.clone(),
+                        provider.name.clone(),
+                        pref.description.clone(),
+                    )
+                })
+            })
+            .collect();
+
+        let routes = embed_routes(embedder.as_ref(), routes).await?;
+        Ok(EmbeddingRouterModel {
+            routes,
+            embedder,
+            threshold,
+        })
+    }
+
+    fn best_route(&self, embedding: &[f32], routes: &[RouteEmbedding]) -> RouteDecision {
+        let best = routes
+            .iter()
+            .map(|route| (route, cosine_similarity(embedding, &route.embedding)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best {
+            Some((route, similarity)) if similarity >= self.threshold => RouteDecision {
+                route: Some((route.route.clone(), route.model.clone())),
+                confidence: Some(similarity),
+            },
+            Some((route, similarity)) => {
+                debug!(
+                    "closest route {} with similarity {} is below threshold {}",
+                    route.route, similarity, self.threshold
+                );
+                RouteDecision::default()
+            }
+            None => RouteDecision::default(),
+        }
+    }
+
+    async fn route(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<RouteDecision> {
+        let text = match latest_user_text(messages) {
+            Some(text) if !text.trim().is_empty() => text,
+            _ => return Ok(RouteDecision::default()),
+        };
+
+        let usage_preferences = match usage_preferences {
+            Some(usage_preferences) => usage_preferences,
+            None => {
+                let embedding = self.embed_one(text).await?;
+                return Ok(self.best_route(&embedding, &self.routes));
+            }
+        };
+
+        let mut input = vec![text];
+        let mut routes = vec![];
+        for pref in usage_preferences {
+            for routing_pref in &pref.routing_preferences {
+                input.push(routing_pref.description.clone());
+                routes.push((routing_pref.name.clone(), pref.model.clone()));
+            }
+        }
+        let mut embeddings = self.embedder.embed(&input).await?.into_iter();
+        let embedding = embeddings.next().unwrap_or_default();
+        let routes: Vec<RouteEmbedding> = routes
+            .into_iter()
+            .zip(embeddings)
+            .map(|((route, model), embedding)| RouteEmbedding {
+                route,
+                model,
+                embedding,
+            })
+            .collect();
+        Ok(self.best_route(&embedding, &routes))
+    }
+
+    async fn embed_one(&self, text: String) -> Result<Vec<f32>> {
+        Ok(self
+            .embedder
+            .embed(&[text])
+            .await?
+            .into_iter()
+            .next()
+            .unwrap_or_default())
+    }
+}
+
+async fn embed_routes(
+    embedder: &dyn Embedder,
+    routes: Vec<(String, String, String)>,
+) -> Result<Vec<RouteEmbedding>> {
+    if routes.is_empty() {
+        return Ok(vec![]);
+    }
+    let descriptions: Vec<String> = routes
+        .iter()
+        .map(|(_, _, description)| description.clone())
+        .collect();
+    let embeddings = embedder.embed(&descriptions).await?;
+
+    Ok(routes
+        .into_iter()
+        .zip(embeddings)
+        .map(|((route, model, _), embedding)| RouteEmbedding {
+            route,
+            model,
+            embedding,
+        })
+        .collect())
+}
+
+/// Cosine similarity of two vectors, 0 if either is empty or zero or their lengths differ.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+impl RouterModel for EmbeddingRouterModel {
+    fn generate_request(
+        &self,
+        _messages: &[Message],
+        _usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> ChatCompletionsRequest {
+        ChatCompletionsRequest {
+            model: self.embedder.model_name(),
+            ..Default::default()
+        }
+    }
+
+    fn select_conversation(
+        &self,
+        messages: &[Message],
+        _usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<Message> {
+        latest_user_text(messages)
+            .map(|text| vec![Message::new(text)])
+            .unwrap_or_default()
+    }
+
+    fn parse_response(
+        &self,
+        _content: &str,
+        _usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<RouteDecision> {
+        Ok(RouteDecision::default())
+    }
+
+    fn match_route<'a>(
+        &'a self,
+        messages: &'a [Message],
+        usage_preferences: &'a Option<Vec<ModelUsagePreference>>,
+    ) -> BoxFuture<'a, Result<Option<RouteDecision>>> {
+        Box::pin(async move { self.route(messages, usage_preferences).await.map(Some) })
+    }
+
+    fn get_model_name(&self) -> String {
+        self.embedder.model_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use common::configuration::RoutingPreference;
+
+    /// Embeds texts by counting a few topic words, enough to tell the test routes apart.
+    #[derive(Default)]
+    struct StubEmbedder {
+        calls: AtomicUsize,
+    }
+
+    impl Embedder for StubEmbedder {
+        fn embed<'a>(&'a self, input: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let embeddings: Vec<Vec<f32>> = input
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    ["image", "code", "summar"]
+                        .iter()
+                        .map(|topic| text.matches(topic).count() as f32)
+                        .collect()
+                })
+                .collect();
+            Box::pin(async { Ok(embeddings) })
+        }
+
+        fn model_name(&self) -> String {
+            "stub-embedder".to_string()
+        }
+    }
+
+    fn providers() -> Vec<LlmProvider> {
+        serde_yaml::from_str(
+            r#"
+- name: gpt-4o
+  provider_interface: openai
+  model: gpt-4o
+  routing_preferences:
+    - name: image-generation
+      description: generating image
+- name: claude-3-7-sonnet
+  provider_interface: claude
+  model: claude-3-7-sonnet
+  routing_preferences:
+    - name: code-generation
+      description: generating new code snippets
+"#,
+        )
+        .unwrap()
+    }
+
+    async fn router(embedder: Arc<StubEmbedder>) -> EmbeddingRouterModel {
+        EmbeddingRouterModel::new(&providers(), embedder, 0.5)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_route_above_threshold() {
+        let embedder = Arc::new(StubEmbedder::default());
+        let router = router(embedder.clone()).await;
+        // route descriptions are embedded once
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), 1);
+
+        let messages = [Message::new("generate an image of a cat".to_string())];
+        let decision = router.match_route(&messages, &None).await.unwrap().unwrap();
+        assert_eq!(decision.model_name(), Some("gpt-4o"));
+
+        let messages = [Message::new("fix the code in this module".to_string())];
+        let decision = router.match_route(&messages, &None).await.unwrap().unwrap();
+        assert_eq!(
+            decision.route,
+            Some((
+                "code-generation".to_string(),
+                "claude-3-7-sonnet".to_string()
+            ))
+        );
+        assert_eq!(decision.confidence, Some(1.0));
+        assert_eq!(embedder.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_nothing_clears_threshold() {
+        let router = router(Arc::new(StubEmbedder::default())).await;
+        let messages = [Message::new("tell me a joke".to_string())];
+        let decision = router.match_route(&messages, &None).await.unwrap().unwrap();
+        assert_eq!(decision.route, None);
+    }
+
+    #[tokio::test]
+    async fn test_usage_preferences_from_request() {
+        let router = router(Arc::new(StubEmbedder::default())).await;
+        let usage_preferences = Some(vec![ModelUsagePreference {
+            model: "gpt-4o-mini".to_string(),
+            routing_preferences: vec![RoutingPreference {
+                name: "summarization".to_string(),
+                description: "summarizing documents".to_string(),
+                ..Default::default()
+            }],
+        }]);
+        let messages = [Message::new("summarize this article".to_string())];
+        let decision = router
+            .match_route(&messages, &usage_preferences)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(decision.model_name(), Some("gpt-4o-mini"));
+    }
+}
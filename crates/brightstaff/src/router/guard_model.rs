@@ -0,0 +1,116 @@
+use common::api::prompt_guard::{PromptGuardRequest, PromptGuardResponse, PromptGuardTask};
+use futures::future::BoxFuture;
+
+use super::router_model::{Result, RoutingModelError};
+
+/// Outcome of a guard check of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuardVerdict {
+    Allow,
+    /// The message must not reach the model it was routed to, `reason` is returned to the
+    /// client.
+    Block {
+        reason: String,
+    },
+}
+
+/// Moderation check of the latest user message, run while the request is routed.
+pub trait GuardModel: Send + Sync {
+    fn check<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<GuardVerdict>>;
+    fn name(&self) -> String;
+}
+
+/// Guard calling a prompt guard endpoint that scores messages for jailbreaks and toxicity.
+pub struct HttpGuardModel {
+    client: reqwest::Client,
+    url: String,
+    threshold: f64,
+}
+
+impl HttpGuardModel {
+    pub fn new(client: reqwest::Client, url: String, threshold: f64) -> Self {
+        HttpGuardModel {
+            client,
+            url,
+            threshold,
+        }
+    }
+
+    fn verdict(&self, response: &PromptGuardResponse) -> GuardVerdict {
+        let flagged = |verdict: Option<bool>, probability: Option<f64>| {
+            verdict.unwrap_or(false) || probability.is_some_and(|p| p >= self.threshold)
+        };
+        if flagged(response.jailbreak_verdict, response.jailbreak_prob) {
+            GuardVerdict::Block {
+                reason: "jailbreak".to_string(),
+            }
+        } else if flagged(response.toxic_verdict, response.toxic_prob) {
+            GuardVerdict::Block {
+                reason: "toxicity".to_string(),
+            }
+        } else {
+            GuardVerdict::Allow
+        }
+    }
+}
+
+impl GuardModel for HttpGuardModel {
+    fn check<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<GuardVerdict>> {
+        Box::pin(async move {
+            let request = PromptGuardRequest {
+                input: text.to_string(),
+                task: PromptGuardTask::Both,
+            };
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&request)?)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                return Err(RoutingModelError::UpstreamError(format!(
+                    "guard responded with status {}",
+                    response.status()
+                )));
+            }
+            let body = response.bytes().await?;
+            let response: PromptGuardResponse = serde_json::from_slice(&body)?;
+            Ok(self.verdict(&response))
+        })
+    }
+
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict() {
+        let guard = HttpGuardModel::new(reqwest::Client::new(), String::new(), 0.8);
+        let response = |jailbreak_prob: f64, toxic_verdict: bool| PromptGuardResponse {
+            toxic_prob: None,
+            jailbreak_prob: Some(jailbreak_prob),
+            toxic_verdict: Some(toxic_verdict),
+            jailbreak_verdict: None,
+        };
+
+        assert_eq!(guard.verdict(&response(0.2, false)), GuardVerdict::Allow);
+        assert_eq!(
+            guard.verdict(&response(0.9, false)),
+            GuardVerdict::Block {
+                reason: "jailbreak".to_string()
+            }
+        );
+        assert_eq!(
+            guard.verdict(&response(0.2, true)),
+            GuardVerdict::Block {
+                reason: "toxicity".to_string()
+            }
+        );
+    }
+}
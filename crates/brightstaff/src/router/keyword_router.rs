@@ -0,0 +1,273 @@
+use std::sync::Arc;
+
+use common::configuration::{LlmProvider, ModelUsagePreference};
+use futures::future::BoxFuture;
+use hermesllm::providers::openai::types::{ChatCompletionsRequest, Message};
+use regex::{Regex, RegexBuilder};
+use tracing::debug;
+
+use super::router_model::{RouteDecision, RouterModel, RoutingModelError, TruncationStats};
+use super::router_model_v1::latest_user_text;
+
+pub type Result<T> = std::result::Result<T, RoutingModelError>;
+
+/// Keywords and patterns of a single route.
+struct KeywordRule {
+    route: String,
+    model: String,
+    regexes: Vec<Regex>,
+}
+
+/// Routes requests whose latest user message obviously matches a route by keyword or regular
+/// expression, without calling the routing model. Everything else is handed to the wrapped
+/// router model.
+pub struct KeywordRouterModel {
+    rules: Vec<KeywordRule>,
+    fallback: Arc<dyn RouterModel>,
+}
+
+impl KeywordRouterModel {
+    /// Builds the rules from the `keywords` and `patterns` of the routing preferences. Rules are
+    /// tried in the order the providers and their preferences are configured and the first
+    /// match wins. Matching ignores case, keywords must match whole words.
+    pub fn new(providers: &[LlmProvider], fallback: Arc<dyn RouterModel>) -> Result<Self> {
+        let mut rules = vec![];
+        for provider in providers {
+            for pref in provider.routing_preferences.iter().flatten() {
+                let keywords =
+                    pref.keywords.iter().flatten().map(|keyword| {
+                        format!(r"(?:^|\W){}(?:$|\W)", regex::escape(keyword.trim()))
+                    });
+                let patterns = pref.patterns.iter().flatten().cloned();
+
+                let regexes = keywords
+                    .chain(patterns)
+                    .map(|pattern| RegexBuilder::new(&pattern).case_insensitive(true).build())
+                    .collect::<std::result::Result<Vec<Regex>, regex::Error>>()?;
+                if regexes.is_empty() {
+                    continue;
+                }
+
+                rules.push(KeywordRule {
+                    route: pref.name.clone(),
+                    model: provider.name.clone(),
+                    regexes,
+                });
+            }
+        }
+
+        Ok(KeywordRouterModel { rules, fallback })
+    }
+
+    pub fn has_rules(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    fn match_keyword_rule(&self, messages: &[Message]) -> Option<&KeywordRule> {
+        let text = latest_user_text(messages)?;
+        self.rules
+            .iter()
+            .find(|rule| rule.regexes.iter().any(|regex| regex.is_match(&text)))
+    }
+}
+
+impl RouterModel for KeywordRouterModel {
+    fn generate_request(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> ChatCompletionsRequest {
+        self.fallback.generate_request(messages, usage_preferences)
+    }
+
+    fn generate_request_with_stats(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> (ChatCompletionsRequest, TruncationStats) {
+        self.fallback
+            .generate_request_with_stats(messages, usage_preferences)
+    }
+
+    fn select_conversation(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<Message> {
+        self.fallback
+            .select_conversation(messages, usage_preferences)
+    }
+
+    fn parse_response(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<RouteDecision> {
+        self.fallback.parse_response(content, usage_preferences)
+    }
+
+    fn parse_response_ranked(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Vec<(String, String)>> {
+        self.fallback
+            .parse_response_ranked(content, usage_preferences)
+    }
+
+    fn answered_routes(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<String> {
+        self.fallback.answered_routes(content, usage_preferences)
+    }
+
+    fn match_route<'a>(
+        &'a self,
+        messages: &'a [Message],
+        usage_preferences: &'a Option<Vec<ModelUsagePreference>>,
+    ) -> BoxFuture<'a, Result<Option<RouteDecision>>> {
+        // usage preferences sent with the request replace the configured routes and with them
+        // the keyword rules
+        if usage_preferences.is_none() {
+            if let Some(rule) = self.match_keyword_rule(messages) {
+                debug!("keyword rule matched route: {}", rule.route);
+                let route_decision = RouteDecision {
+                    route: Some((rule.route.clone(), rule.model.clone())),
+                    confidence: None,
+                };
+                return Box::pin(async { Ok(Some(route_decision)) });
+            }
+        }
+
+        self.fallback.match_route(messages, usage_preferences)
+    }
+
+    fn get_model_name(&self) -> String {
+        self.fallback.get_model_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::router::router_model_v1::RouterModelV1;
+    use common::configuration::RoutingPreference;
+
+    fn providers() -> Vec<LlmProvider> {
+        serde_yaml::from_str(
+            r#"
+- name: gpt-4o
+  provider_interface: openai
+  model: gpt-4o
+  routing_preferences:
+    - name: image-generation
+      description: generating image
+      keywords: ["draw", "picture"]
+- name: claude-3-7-sonnet
+  provider_interface: claude
+  model: claude-3-7-sonnet
+  routing_preferences:
+    - name: code-generation
+      description: generating new code snippets
+      keywords: ["rust", "python"]
+      patterns: ['\bfn\s+\w+\(']
+"#,
+        )
+        .unwrap()
+    }
+
+    fn keyword_router() -> KeywordRouterModel {
+        let fallback = Arc::new(RouterModelV1::new(
+            HashMap::new(),
+            "Arch-Router".to_string(),
+            usize::MAX,
+        ));
+        KeywordRouterModel::new(&providers(), fallback).unwrap()
+    }
+
+    async fn route_name(router: &KeywordRouterModel, text: &str) -> Option<String> {
+        router
+            .match_route(&[Message::new(text.to_string())], &None)
+            .await
+            .unwrap()
+            .and_then(|decision| decision.route_name().map(|route| route.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_keyword_match() {
+        let router = keyword_router();
+        assert!(router.has_rules());
+
+        let decision = router
+            .match_route(&[Message::new("Please DRAW me a cat".to_string())], &None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decision.route,
+            Some(("image-generation".to_string(), "gpt-4o".to_string()))
+        );
+        assert_eq!(
+            route_name(&router, "what does fn main() do").await,
+            Some("code-generation".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_first_match_wins() {
+        let router = keyword_router();
+        assert_eq!(
+            route_name(&router, "draw a diagram of this python module").await,
+            Some("image-generation".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_match_falls_through() {
+        let router = keyword_router();
+        // keywords only match whole words
+        assert_eq!(
+            route_name(&router, "withdrawal limits of my account").await,
+            None
+        );
+        assert_eq!(route_name(&router, "tell me a joke").await, None);
+
+        // routes from the request replace the keyword rules
+        let usage_preferences = Some(vec![ModelUsagePreference {
+            model: "gpt-4o-mini".to_string(),
+            routing_preferences: vec![RoutingPreference {
+                name: "summarization".to_string(),
+                description: "summarizing documents".to_string(),
+                ..Default::default()
+            }],
+        }]);
+        assert!(router
+            .match_route(
+                &[Message::new("draw a cat".to_string())],
+                &usage_preferences
+            )
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut providers = providers();
+        providers[0].routing_preferences.as_mut().unwrap()[0].patterns =
+            Some(vec!["(unclosed".to_string()]);
+        let fallback = Arc::new(RouterModelV1::new(
+            HashMap::new(),
+            "Arch-Router".to_string(),
+            usize::MAX,
+        ));
+        assert!(matches!(
+            KeywordRouterModel::new(&providers, fallback),
+            Err(RoutingModelError::InvalidPattern(_))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use super::router_model_v1::TOKEN_LENGTH_DIVISOR;
+
+/// Scripts told apart by the language detection of the routing prompt. Languages that share a
+/// script tokenize alike, so the script is all the token estimate needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    /// Chinese, Japanese and Korean.
+    Cjk,
+}
+
+/// Average bytes per token of text in each script. Latin letters are a byte and tokens span a
+/// few of them, the letters of most other scripts take two or three bytes and tokens span fewer
+/// letters. The values lean low so that the routing prompt is rather cut than overflowing.
+pub const SCRIPT_TOKEN_LENGTH_DIVISORS: [(Script, usize); 8] = [
+    (Script::Latin, 4),
+    (Script::Greek, 3),
+    (Script::Cyrillic, 3),
+    (Script::Hebrew, 3),
+    (Script::Arabic, 3),
+    (Script::Devanagari, 2),
+    (Script::Thai, 2),
+    (Script::Cjk, 2),
+];
+
+impl Script {
+    pub fn token_length_divisor(&self) -> usize {
+        SCRIPT_TOKEN_LENGTH_DIVISORS
+            .iter()
+            .find(|(script, _)| script == self)
+            .map(|(_, divisor)| *divisor)
+            .unwrap_or(TOKEN_LENGTH_DIVISOR)
+    }
+
+    fn of(c: char) -> Option<Script> {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+            '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+            '\u{0400}'..='\u{04FF}' => Some(Script::Cyrillic),
+            '\u{0590}'..='\u{05FF}' => Some(Script::Hebrew),
+            '\u{0600}'..='\u{06FF}' => Some(Script::Arabic),
+            '\u{0900}'..='\u{097F}' => Some(Script::Devanagari),
+            '\u{0E00}'..='\u{0E7F}' => Some(Script::Thai),
+            '\u{1100}'..='\u{11FF}'
+            | '\u{3040}'..='\u{30FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{F900}'..='\u{FAFF}' => Some(Script::Cjk),
+            _ => None,
+        }
+    }
+}
+
+/// Dominant script of the texts, weighted by the bytes of the letters in each script. Digits,
+/// punctuation and symbols don't count, None when there are no letters at all.
+pub fn detect_script<'a>(texts: impl IntoIterator<Item = &'a str>) -> Option<Script> {
+    let mut bytes = [0usize; SCRIPT_TOKEN_LENGTH_DIVISORS.len()];
+    for c in texts.into_iter().flat_map(str::chars) {
+        if let Some(script) = Script::of(c) {
+            let position = SCRIPT_TOKEN_LENGTH_DIVISORS
+                .iter()
+                .position(|(known, _)| *known == script)
+                .unwrap();
+            bytes[position] += c.len_utf8();
+        }
+    }
+    let (position, count) = bytes
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, count)| **count)
+        .unwrap();
+    (*count > 0).then(|| SCRIPT_TOKEN_LENGTH_DIVISORS[position].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_script() {
+        assert_eq!(
+            detect_script(["Draw me a cat, please!"]),
+            Some(Script::Latin)
+        );
+        assert_eq!(detect_script(["请帮我画一只猫"]), Some(Script::Cjk));
+        assert_eq!(detect_script(["猫の絵を描いて"]), Some(Script::Cjk));
+        assert_eq!(detect_script(["Нарисуй кота"]), Some(Script::Cyrillic));
+        // the script with the most text wins
+        assert_eq!(
+            detect_script([
+                "write some python",
+                "用 Python 写一个排序函数，并解释它的复杂度"
+            ]),
+            Some(Script::Cjk)
+        );
+        assert_eq!(detect_script(["1234 !?", ""]), None);
+
+        assert!(Script::Cjk.token_length_divisor() < Script::Latin.token_length_divisor());
+    }
+}
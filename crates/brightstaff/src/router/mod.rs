@@ -1,3 +1,11 @@
+pub mod embedding_router;
+pub mod guard_model;
+pub mod keyword_router;
+pub mod language;
 pub mod llm_router;
+pub mod route_hierarchy;
+pub mod router_config;
 pub mod router_model;
 pub mod router_model_v1;
+pub mod router_model_v2;
+pub mod routing_log;
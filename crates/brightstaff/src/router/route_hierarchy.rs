@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use common::configuration;
+
+const DEFAULT_ROUTE_SEPARATOR: &str = ".";
+
+/// Resolves route names the routing model answered with that are not configured, when routes
+/// are named hierarchically, e.g. `billing.refund` and `billing.status`. A parent such as
+/// `billing` resolves to its configured default child, or to its only child when prefix
+/// matching is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteHierarchy {
+    separator: String,
+    default_children: HashMap<String, String>,
+    prefix_match: bool,
+}
+
+/// A parent route with several children and no default child.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbiguousRoute {
+    pub route: String,
+    pub children: Vec<String>,
+}
+
+impl Default for RouteHierarchy {
+    fn default() -> Self {
+        RouteHierarchy::new(DEFAULT_ROUTE_SEPARATOR)
+    }
+}
+
+impl RouteHierarchy {
+    pub fn new(separator: impl Into<String>) -> Self {
+        RouteHierarchy {
+            separator: separator.into(),
+            default_children: HashMap::new(),
+            prefix_match: false,
+        }
+    }
+
+    pub fn from_config(route_hierarchy: &configuration::RouteHierarchy) -> Self {
+        RouteHierarchy::new(
+            route_hierarchy
+                .separator
+                .as_deref()
+                .unwrap_or(DEFAULT_ROUTE_SEPARATOR),
+        )
+        .with_default_children(route_hierarchy.default_children.clone().unwrap_or_default())
+        .with_prefix_match(route_hierarchy.prefix_match.unwrap_or(false))
+    }
+
+    /// Child route each parent resolves to.
+    pub fn with_default_children(mut self, default_children: HashMap<String, String>) -> Self {
+        self.default_children = default_children;
+        self
+    }
+
+    /// Resolves a parent without a default child to the route it prefixes, several such routes
+    /// are ambiguous.
+    pub fn with_prefix_match(mut self, prefix_match: bool) -> Self {
+        self.prefix_match = prefix_match;
+        self
+    }
+
+    /// The route of `routes` that `route` stands for: the route itself when it is one of
+    /// `routes`, its default child or, with prefix matching, its only child. `None` when none of
+    /// them is a route.
+    pub fn resolve<'a>(
+        &self,
+        route: &str,
+        routes: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Option<String>, AmbiguousRoute> {
+        let routes: Vec<&str> = routes.into_iter().collect();
+        if routes.contains(&route) {
+            return Ok(Some(route.to_string()));
+        }
+        if let Some(child) = self.default_children.get(route) {
+            if routes.contains(&child.as_str()) {
+                return Ok(Some(child.clone()));
+            }
+        }
+        if !self.prefix_match {
+            return Ok(None);
+        }
+
+        let prefix = format!("{}{}", route, self.separator);
+        let mut children: Vec<String> = routes
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&prefix))
+            .map(str::to_string)
+            .collect();
+        match children.len() {
+            0 => Ok(None),
+            1 => Ok(children.pop()),
+            _ => {
+                children.sort();
+                Err(AmbiguousRoute {
+                    route: route.to_string(),
+                    children,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTES: [&str; 4] = [
+        "billing.refund",
+        "billing.status",
+        "support.account",
+        "code-generation",
+    ];
+
+    #[test]
+    fn test_exact_route() {
+        let hierarchy = RouteHierarchy::default().with_prefix_match(true);
+        assert_eq!(
+            hierarchy.resolve("billing.refund", ROUTES),
+            Ok(Some("billing.refund".to_string()))
+        );
+        assert_eq!(
+            hierarchy.resolve("code-generation", ROUTES),
+            Ok(Some("code-generation".to_string()))
+        );
+        assert_eq!(hierarchy.resolve("shipping", ROUTES), Ok(None));
+    }
+
+    #[test]
+    fn test_parent_with_default_child() {
+        let hierarchy = RouteHierarchy::default().with_default_children(HashMap::from([(
+            "billing".to_string(),
+            "billing.status".to_string(),
+        )]));
+        assert_eq!(
+            hierarchy.resolve("billing", ROUTES),
+            Ok(Some("billing.status".to_string()))
+        );
+        // parents without a default only resolve with prefix matching
+        assert_eq!(hierarchy.resolve("support", ROUTES), Ok(None));
+        let hierarchy = hierarchy.with_prefix_match(true);
+        assert_eq!(
+            hierarchy.resolve("support", ROUTES),
+            Ok(Some("support.account".to_string()))
+        );
+        // the default wins over the children
+        assert_eq!(
+            hierarchy.resolve("billing", ROUTES),
+            Ok(Some("billing.status".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_parent() {
+        let hierarchy = RouteHierarchy::new("/").with_prefix_match(true);
+        let routes = ["billing/status", "billing/refund", "billing-disputes"];
+        assert_eq!(
+            hierarchy.resolve("billing", routes),
+            Err(AmbiguousRoute {
+                route: "billing".to_string(),
+                children: vec!["billing/refund".to_string(), "billing/status".to_string()],
+            })
+        );
+    }
+}
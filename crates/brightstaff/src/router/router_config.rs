@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::configuration::Configuration;
+use common::consts::{
+    DEFAULT_EMBEDDING_ROUTING_THRESHOLD, DEFAULT_GUARD_THRESHOLD, DEFAULT_ROUTING_TIMEOUT_MS,
+};
+use thiserror::Error;
+use tracing::info;
+
+use crate::metrics::Metrics;
+
+use super::embedding_router::{EmbeddingRouterModel, HttpEmbedder};
+use super::guard_model::HttpGuardModel;
+use super::llm_router::{RouterService, RoutingError};
+use super::route_hierarchy::RouteHierarchy;
+use super::routing_log::{FileRoutingLogSink, RoutingLog};
+
+const DEFAULT_ROUTING_LLM_PROVIDER: &str = "arch-router";
+const DEFAULT_ROUTING_MODEL_NAME: &str = "Arch-Router";
+
+#[derive(Debug, Error)]
+pub enum RouterConfigError {
+    #[error("{0}")]
+    Routing(#[from] RoutingError),
+    #[error("failed to open the routing log {path}: {source}")]
+    RoutingLog {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+/// Builds the router of the routes and routing settings of `arch_config`, calling the routing
+/// model at `router_url`. Embedding routers embed the route descriptions before they are
+/// returned.
+pub async fn router_service_from_config(
+    arch_config: &Configuration,
+    router_url: String,
+    http_client: reqwest::Client,
+    metrics: Arc<Metrics>,
+) -> Result<RouterService, RouterConfigError> {
+    let routing = arch_config.routing.as_ref();
+
+    let routing_model_name: String = routing
+        .and_then(|r| r.model.clone())
+        .unwrap_or_else(|| DEFAULT_ROUTING_MODEL_NAME.to_string());
+
+    let routing_llm_provider = routing
+        .and_then(|r| r.llm_provider.clone())
+        .unwrap_or_else(|| DEFAULT_ROUTING_LLM_PROVIDER.to_string());
+
+    let mut router_service = RouterService::new(
+        arch_config.llm_providers.clone(),
+        router_url,
+        routing_model_name,
+        routing_llm_provider,
+        http_client.clone(),
+    )?
+    .with_metrics(metrics)
+    .with_default_route(routing.and_then(|r| r.default_route.clone()))
+    .with_timeout(Duration::from_millis(
+        routing
+            .and_then(|r| r.timeout_ms)
+            .unwrap_or(DEFAULT_ROUTING_TIMEOUT_MS),
+    ))
+    .with_fallback_on_timeout(routing.and_then(|r| r.fallback_on_timeout).unwrap_or(false))
+    .with_streaming(routing.and_then(|r| r.stream).unwrap_or(false))
+    .with_tie_break_confidence(routing.and_then(|r| r.tie_break_confidence))?
+    .with_min_confidence(routing.and_then(|r| r.min_confidence))
+    .with_route_hierarchy(
+        routing
+            .and_then(|r| r.route_hierarchy.as_ref())
+            .map(RouteHierarchy::from_config),
+    );
+
+    if let Some(guard) = routing.and_then(|r| r.guard.as_ref()) {
+        info!("checking requests with guard: {}", guard.url);
+        let guard_model = HttpGuardModel::new(
+            http_client.clone(),
+            guard.url.clone(),
+            guard.threshold.unwrap_or(DEFAULT_GUARD_THRESHOLD),
+        );
+        router_service = router_service
+            .with_guard(Arc::new(guard_model))
+            .with_guard_route(guard.route.clone())
+            .with_guard_fail_closed(guard.fail_closed.unwrap_or(false));
+    }
+
+    if let Some(routing_log) = routing.and_then(|r| r.log.as_ref()) {
+        info!(
+            "recording {}% of the routing calls to: {}",
+            routing_log.sample_rate * 100.0,
+            routing_log.path
+        );
+        let sink = FileRoutingLogSink::open(&routing_log.path).map_err(|source| {
+            RouterConfigError::RoutingLog {
+                path: routing_log.path.clone(),
+                source,
+            }
+        })?;
+        router_service = router_service.with_routing_log(Some(
+            RoutingLog::new(Arc::new(sink), routing_log.sample_rate)
+                .with_content_max_chars(routing_log.content_max_chars),
+        ));
+    }
+
+    if let Some(embedding) = routing.and_then(|r| r.embedding.as_ref()) {
+        info!(
+            "routing on embeddings, model: {}, endpoint: {}",
+            embedding.model, embedding.url
+        );
+        let embedder = HttpEmbedder::new(
+            http_client.clone(),
+            embedding.url.clone(),
+            embedding.model.clone(),
+        );
+        let embedding_router_model = EmbeddingRouterModel::new(
+            &arch_config.llm_providers,
+            Arc::new(embedder),
+            embedding
+                .threshold
+                .unwrap_or(DEFAULT_EMBEDDING_ROUTING_THRESHOLD),
+        )
+        .await
+        .map_err(RoutingError::from)?;
+        router_service = router_service.with_router_model(Arc::new(embedding_router_model))?;
+    }
+
+    Ok(router_service)
+}
@@ -1,25 +1,180 @@
 use common::configuration::ModelUsagePreference;
+use futures::future::BoxFuture;
 use hermesllm::providers::openai::types::{ChatCompletionsRequest, Message};
 use thiserror::Error;
 
+use super::router_model_v1::{conversation_messages, TOKEN_LENGTH_DIVISOR};
+
 #[derive(Debug, Error)]
 pub enum RoutingModelError {
+    /// The routing model could not be reached or answered with an error.
+    #[error("Routing model call failed: {0}")]
+    UpstreamError(String),
+    #[error("Routing model call timed out")]
+    Timeout,
+    /// The response of the routing model could not be parsed.
     #[error("Failed to parse JSON: {0}")]
     JsonError(#[from] serde_json::Error),
+    #[error("Invalid prompt template: {0}")]
+    InvalidPromptTemplate(String),
+    #[error("Invalid routing model option: {0}")]
+    InvalidOption(String),
+    #[error("Routing model selected unknown route: {0}")]
+    UnknownRoute(String),
+    #[error("Invalid routing pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    /// The JSON object in the response was cut off, e.g. by a dropped connection. Retrying may
+    /// succeed.
+    #[error("Incomplete routing model response: {0}")]
+    IncompleteResponse(String),
+}
+
+impl Clone for RoutingModelError {
+    // concurrent identical routing requests share one result, including its error
+    fn clone(&self) -> Self {
+        match self {
+            RoutingModelError::UpstreamError(message) => {
+                RoutingModelError::UpstreamError(message.clone())
+            }
+            RoutingModelError::Timeout => RoutingModelError::Timeout,
+            RoutingModelError::JsonError(err) => {
+                RoutingModelError::JsonError(clone_json_error(err))
+            }
+            RoutingModelError::InvalidPromptTemplate(message) => {
+                RoutingModelError::InvalidPromptTemplate(message.clone())
+            }
+            RoutingModelError::InvalidOption(message) => {
+                RoutingModelError::InvalidOption(message.clone())
+            }
+            RoutingModelError::UnknownRoute(route) => {
+                RoutingModelError::UnknownRoute(route.clone())
+            }
+            RoutingModelError::InvalidPattern(err) => {
+                RoutingModelError::InvalidPattern(err.clone())
+            }
+            RoutingModelError::IncompleteResponse(content) => {
+                RoutingModelError::IncompleteResponse(content.clone())
+            }
+        }
+    }
+}
+
+/// `serde_json::Error` is not `Clone`, the copy keeps the message including the position.
+pub(crate) fn clone_json_error(err: &serde_json::Error) -> serde_json::Error {
+    <serde_json::Error as serde::de::Error>::custom(err.to_string())
+}
+
+impl From<reqwest::Error> for RoutingModelError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            RoutingModelError::Timeout
+        } else {
+            RoutingModelError::UpstreamError(err.to_string())
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, RoutingModelError>;
 
+/// Outcome of parsing a routing model response.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteDecision {
+    /// Selected route name and the model it maps to.
+    pub route: Option<(String, String)>,
+    /// Confidence reported by the routing model, if it provided one.
+    pub confidence: Option<f32>,
+}
+
+impl RouteDecision {
+    pub fn route_name(&self) -> Option<&str> {
+        self.route.as_ref().map(|(route, _)| route.as_str())
+    }
+
+    pub fn model_name(&self) -> Option<&str> {
+        self.route.as_ref().map(|(_, model)| model.as_str())
+    }
+}
+
+/// How much of the conversation made it into a routing model request. Messages are counted
+/// among those the routing model may see, see [`conversation_messages`], so skipped system and
+/// tool messages don't count as truncated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TruncationStats {
+    pub total_messages: usize,
+    pub selected_messages: usize,
+    /// Estimated tokens of the rendered routing prompt.
+    pub estimated_tokens: usize,
+}
+
+impl TruncationStats {
+    /// Whether older messages were left out to fit the token budget.
+    pub fn truncated(&self) -> bool {
+        self.selected_messages < self.total_messages
+    }
+}
+
 pub trait RouterModel: Send + Sync {
     fn generate_request(
         &self,
         messages: &[Message],
         usage_preferences: &Option<Vec<ModelUsagePreference>>,
     ) -> ChatCompletionsRequest;
+    /// `generate_request` along with how the conversation was truncated to build it.
+    fn generate_request_with_stats(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> (ChatCompletionsRequest, TruncationStats) {
+        let request = self.generate_request(messages, usage_preferences);
+        let stats = TruncationStats {
+            total_messages: conversation_messages(messages, false).len(),
+            selected_messages: self.select_conversation(messages, usage_preferences).len(),
+            estimated_tokens: serde_json::to_string(&request)
+                .map_or(0, |body| body.len() / TOKEN_LENGTH_DIVISOR),
+        };
+        (request, stats)
+    }
+    /// Messages of the conversation that fit in the routing prompt, in conversation order.
+    fn select_conversation(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<Message>;
     fn parse_response(
         &self,
         content: &str,
         usage_preferences: &Option<Vec<ModelUsagePreference>>,
-    ) -> Result<Option<(String, String)>>;
+    ) -> Result<RouteDecision>;
+    /// Parse a response that ranks several candidate routes, best match first. Each entry is a
+    /// route name and the model it maps to.
+    fn parse_response_ranked(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .parse_response(content, usage_preferences)?
+            .route
+            .into_iter()
+            .collect())
+    }
+    /// Route names a response answers with, best match first, including routes that are not
+    /// configured. Aliases are resolved to their route.
+    fn answered_routes(
+        &self,
+        _content: &str,
+        _usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<String> {
+        vec![]
+    }
+    /// Routes the conversation without calling the routing model. `None` means the routing
+    /// model has to be asked.
+    fn match_route<'a>(
+        &'a self,
+        _messages: &'a [Message],
+        _usage_preferences: &'a Option<Vec<ModelUsagePreference>>,
+    ) -> BoxFuture<'a, Result<Option<RouteDecision>>> {
+        Box::pin(async { Ok(None) })
+    }
     fn get_model_name(&self) -> String;
 }
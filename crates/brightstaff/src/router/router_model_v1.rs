@@ -1,16 +1,53 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::{collections::HashMap, sync::Arc};
 
 use common::{
     configuration::{ModelUsagePreference, RoutingPreference},
     consts::{SYSTEM_ROLE, TOOL_ROLE, USER_ROLE},
+    tokenizer::Tokenizer,
+};
+use hermesllm::providers::openai::types::{
+    ChatCompletionsRequest, ContentType, Message, MultiPartContentType,
 };
-use hermesllm::providers::openai::types::{ChatCompletionsRequest, ContentType, Message};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
-use super::router_model::{RouterModel, RoutingModelError};
+use super::language::{detect_script, Script};
+use super::router_model::{RouteDecision, RouterModel, RoutingModelError, TruncationStats};
 
 pub const MAX_TOKEN_LEN: usize = 2048; // Default max token length for the routing model
+
+/// Context windows of the known routing models, matched on the start of the model name without
+/// its organization, case-insensitively.
+pub const ROUTING_MODEL_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("arch-router", 32_768),
+    ("qwen2.5", 32_768),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+];
+/// Tokens of the context window left for the routing model's JSON answer.
+pub const ROUTING_RESPONSE_TOKEN_RESERVE: usize = 256;
+
+/// Context window of a known routing model.
+pub fn context_window(routing_model: &str) -> Option<usize> {
+    let model = routing_model
+        .rsplit('/')
+        .next()
+        .unwrap_or(routing_model)
+        .to_lowercase();
+    ROUTING_MODEL_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, context_window)| *context_window)
+}
+
+/// Max token length of the routing prompt for `routing_model`: its context window less the
+/// reserve for the answer, or `MAX_TOKEN_LEN` for unknown models.
+pub fn default_max_token_length(routing_model: &str) -> usize {
+    context_window(routing_model)
+        .map(|context_window| context_window.saturating_sub(ROUTING_RESPONSE_TOKEN_RESERVE))
+        .unwrap_or(MAX_TOKEN_LEN)
+}
 pub const ARCH_ROUTER_V1_SYSTEM_PROMPT: &str = r#"
 You are a helpful assistant designed to find the best suited route.
 You are provided with route description within <routes></routes> XML tags:
@@ -31,208 +68,958 @@ Based on your analysis, provide your response in the following JSON formats if y
 {"route": "route_name"}
 "#;
 
+pub const ARCH_ROUTER_V1_RANKED_ROUTES_PROMPT: &str = r#"If more than one route matches the user's request, respond with all matching routes ranked from best to worst match instead:
+{"routes": ["best_route_name", "next_best_route_name"]}
+"#;
+
+pub const ARCH_ROUTER_V1_CONFIDENCE_PROMPT: &str = r#"Add your confidence that the route is the best match, a number between 0 and 1, to your response:
+{"route": "route_name", "confidence": 0.9}
+"#;
+
+/// Normalizations of the message content shown to the routing model. Whitespace and casing
+/// don't change the intent of a message but do change its token count.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ContentNormalization {
+    /// Removes leading and trailing whitespace.
+    pub trim: bool,
+    /// Replaces every run of whitespace, newlines included, with a single space.
+    pub collapse_whitespace: bool,
+    pub lowercase: bool,
+}
+
+impl ContentNormalization {
+    pub fn is_enabled(&self) -> bool {
+        self.trim || self.collapse_whitespace || self.lowercase
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = if self.collapse_whitespace {
+            let mut collapsed = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+            // the whitespace at the ends is kept unless trimmed as well
+            if !self.trim && !collapsed.is_empty() {
+                if text.starts_with(char::is_whitespace) {
+                    collapsed.insert(0, ' ');
+                }
+                if text.ends_with(char::is_whitespace) {
+                    collapsed.push(' ');
+                }
+            }
+            collapsed
+        } else if self.trim {
+            text.trim().to_string()
+        } else {
+            text.to_string()
+        };
+        if self.lowercase {
+            text = text.to_lowercase();
+        }
+        text
+    }
+
+    /// Copies of the messages with the text of their content normalized, images are kept.
+    pub(crate) fn normalize_messages(&self, messages: &[Message]) -> Vec<Message> {
+        messages
+            .iter()
+            .map(|message| {
+                let mut message = message.clone();
+                message.content = match message.content {
+                    Some(ContentType::Text(text)) => Some(ContentType::Text(self.normalize(&text))),
+                    Some(ContentType::MultiPart(mut parts)) => {
+                        for part in parts.iter_mut() {
+                            if let Some(text) = part.text.as_mut() {
+                                *text = self.normalize(text);
+                            }
+                        }
+                        Some(ContentType::MultiPart(parts))
+                    }
+                    None => None,
+                };
+                message
+            })
+            .collect()
+    }
+}
+
 pub type Result<T> = std::result::Result<T, RoutingModelError>;
 pub struct RouterModelV1 {
     llm_route_json_str: String,
     llm_route_to_model_map: HashMap<String, String>,
+    /// Alias to the name of the route it stands for.
+    route_aliases: HashMap<String, String>,
     routing_model: String,
     max_token_length: usize,
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    token_length_divisor: usize,
+    ranked_routes: bool,
+    confidence: bool,
+    start_at_user_turn: bool,
+    max_messages: Option<usize>,
+    system_message_max_chars: Option<usize>,
+    tool_messages: bool,
+    prompt_template: String,
+    reject_unknown_routes: bool,
+    content_normalization: ContentNormalization,
+    language_detection: bool,
+    localized_instructions: HashMap<Script, String>,
 }
 impl RouterModelV1 {
+    /// Router with the max token length of the routing model's known context window, see
+    /// `default_max_token_length`.
+    pub fn for_routing_model(
+        llm_routes: HashMap<String, Vec<RoutingPreference>>,
+        routing_model: String,
+    ) -> Self {
+        RouterModelV1Builder::new(llm_routes, routing_model)
+            .build()
+            .expect("the default options are valid")
+    }
+
     pub fn new(
         llm_routes: HashMap<String, Vec<RoutingPreference>>,
         routing_model: String,
         max_token_length: usize,
     ) -> Self {
-        let llm_route_values: Vec<RoutingPreference> =
-            llm_routes.values().flatten().cloned().collect();
+        RouterModelV1Builder::new(llm_routes, routing_model)
+            .max_token_length(max_token_length)
+            .build()
+            .expect("the default options are valid")
+    }
+
+    /// Router with options other than the defaults, the options are checked by
+    /// [`RouterModelV1Builder::build`].
+    pub fn builder(
+        llm_routes: HashMap<String, Vec<RoutingPreference>>,
+        routing_model: String,
+    ) -> RouterModelV1Builder {
+        RouterModelV1Builder::new(llm_routes, routing_model)
+    }
+
+    /// Parses the answer of the routing model. Smaller models sometimes answer with nothing but
+    /// the route name, an answer that is exactly a known route or alias is taken as that route.
+    fn parse_router_response(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Option<LlmRouterResponse>> {
+        match parse_llm_router_response(content) {
+            Ok(Some(router_response)) => Ok(Some(router_response)),
+            result => match self.bare_route(content, usage_preferences) {
+                Some(route) => Ok(Some(LlmRouterResponse {
+                    route: Some(route),
+                    routes: None,
+                    confidence: None,
+                })),
+                None => result,
+            },
+        }
+    }
+
+    /// The route an answer without JSON names, `None` unless it is a known route or alias.
+    fn bare_route(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Option<String> {
+        let route = strip_reasoning(content).trim();
+        let known = match usage_preferences {
+            Some(usage_preferences) => usage_preferences.iter().any(|pref| {
+                pref.routing_preferences.iter().any(|routing_pref| {
+                    routing_pref.name == route
+                        || routing_pref
+                            .aliases
+                            .as_ref()
+                            .is_some_and(|aliases| aliases.iter().any(|alias| alias == route))
+                })
+            }),
+            None => {
+                self.llm_route_to_model_map.contains_key(route)
+                    || self.route_aliases.contains_key(route)
+            }
+        };
+        if known {
+            debug!("routing model answered with a bare route name: {}", route);
+        }
+        known.then(|| route.to_string())
+    }
+
+    /// Maps the routes selected by the routing model to models, keeping the ranking order.
+    /// Routes that can't be mapped to a model are unknown and either dropped or rejected.
+    fn resolve_routes(
+        &self,
+        router_response: &LlmRouterResponse,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Vec<(String, String)>> {
+        let mut routes = vec![];
+        for route in ranked_route_names(router_response) {
+            let route = canonical_route(route, &self.route_aliases, usage_preferences);
+            match self.model_for_route(&route, usage_preferences) {
+                Some(model) => routes.push((route, model)),
+                None if self.reject_unknown_routes => {
+                    return Err(RoutingModelError::UnknownRoute(route))
+                }
+                None => {}
+            }
+        }
+        Ok(routes)
+    }
+
+    fn model_for_route(
+        &self,
+        selected_route: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Option<String> {
+        if let Some(usage_preferences) = usage_preferences {
+            // If usage preferences are defined, we need to find the model that matches the selected route
+            let model_name: Option<String> = usage_preferences
+                .iter()
+                .map(|pref| {
+                    pref.routing_preferences
+                        .iter()
+                        .find(|routing_pref| routing_pref.name == selected_route)
+                        .map(|_| pref.model.clone())
+                })
+                .find_map(|model| model);
+
+            if model_name.is_none() {
+                warn!(
+                    "No matching model found for route: {}, usage preferences: {:?}",
+                    selected_route, usage_preferences
+                );
+            }
+            return model_name;
+        }
+
+        // If no usage preferences are passed in request then use the default routing model preferences
+        if let Some(model) = self.llm_route_to_model_map.get(selected_route).cloned() {
+            return Some(model);
+        }
+
+        warn!(
+            "No model found for route: {}, router model preferences: {:?}",
+            selected_route, self.llm_route_to_model_map
+        );
+
+        None
+    }
+
+    /// Routes substituted into the prompt. If preferences are passed in the request they are
+    /// used, otherwise the configured routes.
+    fn routes_block(&self, usage_preferences: &Option<Vec<ModelUsagePreference>>) -> Cow<'_, str> {
+        match convert_to_router_preferences(usage_preferences) {
+            Some(prefs) => Cow::Owned(prefs),
+            None => Cow::Borrowed(&self.llm_route_json_str),
+        }
+    }
+
+    /// Dominant script of the most recent user messages, None unless language detection is on.
+    fn detected_script(&self, messages: &[Message]) -> Option<Script> {
+        if !self.language_detection {
+            return None;
+        }
+        let texts: Vec<String> = messages
+            .iter()
+            .rev()
+            .filter(|message| message.role == USER_ROLE)
+            .filter_map(|message| message.content.as_ref().map(routing_text))
+            .take(LANGUAGE_DETECTION_MESSAGES)
+            .collect();
+        detect_script(texts.iter().map(String::as_str))
+    }
+
+    fn localized_instruction(&self, script: Option<Script>) -> Option<&str> {
+        self.localized_instructions
+            .get(&script?)
+            .map(String::as_str)
+    }
+
+    /// Bytes per token of conversation content in `script`. The template, the routes and Latin
+    /// text are counted with the configured divisor.
+    fn content_token_length_divisor(&self, script: Option<Script>) -> usize {
+        match script {
+            Some(script) if script != Script::Latin => script.token_length_divisor(),
+            _ => self.token_length_divisor,
+        }
+    }
+
+    /// Tokens of `text`, conversation content when `script` is set.
+    fn token_count(&self, text: &str, script: Option<Script>) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count(text),
+            None => text.len() / self.content_token_length_divisor(script),
+        }
+    }
+
+    /// Tokens of the rendered routing prompt, the `content` it contains is counted as
+    /// conversation content in `script` and the rest with the configured divisor.
+    fn prompt_token_count(&self, prompt: &str, content: &[&str], script: Option<Script>) -> usize {
+        if let Some(tokenizer) = &self.tokenizer {
+            return tokenizer.count(prompt);
+        }
+        let content_len = content
+            .iter()
+            .map(|text| text.len())
+            .sum::<usize>()
+            .min(prompt.len());
+        (prompt.len() - content_len) / self.token_length_divisor
+            + content_len / self.content_token_length_divisor(script)
+    }
+
+    fn select_conversation_for_script(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+        script: Option<Script>,
+    ) -> Vec<Message> {
+        // normalized before the budget is counted, on a copy of the messages
+        let normalized_messages;
+        let messages: &[Message] = if self.content_normalization.is_enabled() {
+            normalized_messages = self.content_normalization.normalize_messages(messages);
+            &normalized_messages
+        } else {
+            messages
+        };
+
+        // the routes block can be large with many routes so it counts toward the budget too
+        let mut base_token_count = self.token_count(&self.prompt_template, None)
+            + self.token_count(&self.routes_block(usage_preferences), None);
+        if self.ranked_routes {
+            base_token_count += self.token_count(ARCH_ROUTER_V1_RANKED_ROUTES_PROMPT, None);
+        }
+        if self.confidence {
+            base_token_count += self.token_count(ARCH_ROUTER_V1_CONFIDENCE_PROMPT, None);
+        }
+        if let Some(instruction) = self.localized_instruction(script) {
+            base_token_count += self.token_count(instruction, script);
+        }
+        let system_message = self
+            .system_message_max_chars
+            .and_then(|max_chars| latest_system_message(messages, max_chars));
+        if let Some(content) = system_message.as_ref().and_then(|m| m.content.as_ref()) {
+            base_token_count += self.token_count(&content.to_string(), script);
+        }
+
+        // Following code is to ensure that the conversation does not exceed max token length
+        // Note: unless a tokenizer is configured we use a simple heuristic to estimate token count
+        // based on character length to optimize for performance
+        let mut conversation = trim_conversation(
+            messages,
+            self.max_token_length,
+            base_token_count,
+            self.start_at_user_turn,
+            self.max_messages,
+            self.tool_messages,
+            |text| self.token_count(text, script),
+        );
+        if let Some(system_message) = system_message {
+            conversation.insert(0, system_message);
+        }
+        conversation
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LlmRouterResponse {
+    pub route: Option<String>,
+    pub routes: Option<Vec<String>>,
+    #[serde(alias = "score")]
+    pub confidence: Option<f32>,
+}
+
+pub(crate) const TOKEN_LENGTH_DIVISOR: usize = 4; // Approximate token length divisor for UTF-8 characters
+/// Number of recent user messages the language detection looks at.
+const LANGUAGE_DETECTION_MESSAGES: usize = 3;
+const IMAGE_PART_LABEL: &str = "[image]";
+
+/// Builds a [`RouterModelV1`] from its options. Every router is built through `build`, which
+/// checks that the options are valid.
+pub struct RouterModelV1Builder {
+    llm_routes: HashMap<String, Vec<RoutingPreference>>,
+    routing_model: String,
+    max_token_length: Option<usize>,
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    token_length_divisor: usize,
+    ranked_routes: bool,
+    confidence: bool,
+    start_at_user_turn: bool,
+    max_messages: Option<usize>,
+    system_message_max_chars: Option<usize>,
+    tool_messages: bool,
+    prompt_template: Option<String>,
+    reject_unknown_routes: bool,
+    content_normalization: ContentNormalization,
+    language_detection: bool,
+    localized_instructions: HashMap<Script, String>,
+}
+
+impl RouterModelV1Builder {
+    pub fn new(
+        llm_routes: HashMap<String, Vec<RoutingPreference>>,
+        routing_model: impl Into<String>,
+    ) -> Self {
+        Self {
+            llm_routes,
+            routing_model: routing_model.into(),
+            max_token_length: None,
+            tokenizer: None,
+            token_length_divisor: TOKEN_LENGTH_DIVISOR,
+            ranked_routes: false,
+            confidence: false,
+            start_at_user_turn: false,
+            max_messages: None,
+            system_message_max_chars: None,
+            tool_messages: false,
+            prompt_template: None,
+            reject_unknown_routes: false,
+            content_normalization: ContentNormalization::default(),
+            language_detection: false,
+            localized_instructions: HashMap::new(),
+        }
+    }
+
+    /// Defaults to the length for the routing model, see `default_max_token_length`.
+    pub fn max_token_length(mut self, max_token_length: usize) -> Self {
+        self.max_token_length = Some(max_token_length);
+        self
+    }
+
+    /// Use a real tokenizer to budget the conversation instead of the
+    /// character length heuristic.
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Average bytes per token of the character length heuristic, defaults to
+    /// [`TOKEN_LENGTH_DIVISOR`]. Tokenizers of the routing model or CJK heavy traffic may need a
+    /// smaller divisor. Not used when a tokenizer is configured. Must be greater than 0.
+    pub fn token_length_divisor(mut self, token_length_divisor: usize) -> Self {
+        self.token_length_divisor = token_length_divisor;
+        self
+    }
+
+    /// Ask the routing model for a ranked list of candidate routes in addition to the single best route.
+    pub fn ranked_routes(mut self, ranked_routes: bool) -> Self {
+        self.ranked_routes = ranked_routes;
+        self
+    }
+
+    /// Ask the routing model for its confidence in the decision, see
+    /// [`ARCH_ROUTER_V1_CONFIDENCE_PROMPT`]. Routers without it leave the confidence unset.
+    pub fn confidence(mut self, confidence: bool) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// When the conversation is truncated, keep trimming the oldest messages until it starts at
+    /// a user turn instead of in the middle of an exchange.
+    pub fn start_at_user_turn(mut self, start_at_user_turn: bool) -> Self {
+        self.start_at_user_turn = start_at_user_turn;
+        self
+    }
+
+    /// Caps the number of most recent messages sent to the routing model, on top of the token
+    /// budget. No cap by default.
+    pub fn max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    /// Shows the latest system message, cut to `max_chars` characters, at the start of the
+    /// conversation sent to the routing model, e.g. when the system prompt names the tools
+    /// available or the tier of the user. System messages are left out by default.
+    pub fn system_message(mut self, max_chars: usize) -> Self {
+        self.system_message_max_chars = Some(max_chars);
+        self
+    }
+
+    /// Shows tool calls and tool results in the conversation sent to the routing model, e.g.
+    /// `[called tool: get_weather({"location":"Tokyo"})]`, instead of leaving them out. Useful
+    /// for routing agentic conversations.
+    pub fn tool_messages(mut self, tool_messages: bool) -> Self {
+        self.tool_messages = tool_messages;
+        self
+    }
+
+    /// Replaces the built-in routing prompt, [`ARCH_ROUTER_V1_SYSTEM_PROMPT`]. Must contain the
+    /// `{routes}` and `{conversation}` placeholders.
+    pub fn prompt_template(mut self, prompt_template: impl Into<String>) -> Self {
+        self.prompt_template = Some(prompt_template.into());
+        self
+    }
+
+    /// Routes returned by the routing model that are not configured are dropped by default. When
+    /// set, an unknown route fails the response with [`RoutingModelError::UnknownRoute`] instead.
+    pub fn reject_unknown_routes(mut self, reject_unknown_routes: bool) -> Self {
+        self.reject_unknown_routes = reject_unknown_routes;
+        self
+    }
+
+    /// Normalizes the message content shown to the routing model, the forwarded request is left
+    /// as it is. Nothing is normalized by default.
+    pub fn content_normalization(mut self, content_normalization: ContentNormalization) -> Self {
+        self.content_normalization = content_normalization;
+        self
+    }
+
+    /// Detects the script of the recent user messages to budget the conversation with the
+    /// divisor of that script, see [`SCRIPT_TOKEN_LENGTH_DIVISORS`]. The template, the routes
+    /// and Latin text keep the configured divisor. Not used when a tokenizer is configured.
+    ///
+    /// [`SCRIPT_TOKEN_LENGTH_DIVISORS`]: super::language::SCRIPT_TOKEN_LENGTH_DIVISORS
+    pub fn language_detection(mut self, language_detection: bool) -> Self {
+        self.language_detection = language_detection;
+        self
+    }
+
+    /// Instruction appended to the routing prompt when language detection finds `script`, e.g.
+    /// to tell the routing model that the conversation is in Japanese but route names must be
+    /// answered as they are.
+    pub fn localized_instruction(mut self, script: Script, instruction: impl Into<String>) -> Self {
+        self.localized_instructions
+            .insert(script, instruction.into());
+        self
+    }
+
+    pub fn build(self) -> Result<RouterModelV1> {
+        if let Some(prompt_template) = self.prompt_template.as_deref() {
+            check_prompt_template(prompt_template)?;
+        }
+        if self.token_length_divisor == 0 {
+            return Err(RoutingModelError::InvalidOption(
+                "token length divisor must be greater than 0".to_string(),
+            ));
+        }
+
+        // only the name, description and aliases are shown to the routing model, keyword rules
+        // and allowed models are applied by the gateway
+        let llm_route_values: Vec<RoutingPreference> = self
+            .llm_routes
+            .values()
+            .flatten()
+            .map(|pref| RoutingPreference {
+                name: pref.name.clone(),
+                description: pref.description.clone(),
+                aliases: pref.aliases.clone(),
+                ..Default::default()
+            })
+            .collect();
         let llm_route_json_str =
             serde_json::to_string(&llm_route_values).unwrap_or_else(|_| "[]".to_string());
-        let llm_route_to_model_map: HashMap<String, String> = llm_routes
+        let llm_route_to_model_map: HashMap<String, String> = self
+            .llm_routes
             .iter()
             .flat_map(|(model, prefs)| prefs.iter().map(|pref| (pref.name.clone(), model.clone())))
             .collect();
+        let route_aliases = route_aliases(self.llm_routes.values().flatten());
+        let max_token_length = self
+            .max_token_length
+            .unwrap_or_else(|| default_max_token_length(&self.routing_model));
 
-        RouterModelV1 {
-            routing_model,
+        Ok(RouterModelV1 {
+            routing_model: self.routing_model,
             max_token_length,
             llm_route_json_str,
             llm_route_to_model_map,
-        }
+            route_aliases,
+            tokenizer: self.tokenizer,
+            token_length_divisor: self.token_length_divisor,
+            ranked_routes: self.ranked_routes,
+            confidence: self.confidence,
+            start_at_user_turn: self.start_at_user_turn,
+            max_messages: self.max_messages,
+            system_message_max_chars: self.system_message_max_chars,
+            tool_messages: self.tool_messages,
+            prompt_template: self
+                .prompt_template
+                .unwrap_or_else(|| ARCH_ROUTER_V1_SYSTEM_PROMPT.to_string()),
+            reject_unknown_routes: self.reject_unknown_routes,
+            content_normalization: self.content_normalization,
+            language_detection: self.language_detection,
+            localized_instructions: self.localized_instructions,
+        })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LlmRouterResponse {
-    pub route: Option<String>,
+/// Fails unless the template contains the `{routes}` and `{conversation}` placeholders.
+fn check_prompt_template(prompt_template: &str) -> Result<()> {
+    for placeholder in ["{routes}", "{conversation}"] {
+        if !prompt_template.contains(placeholder) {
+            return Err(RoutingModelError::InvalidPromptTemplate(format!(
+                "missing {} placeholder",
+                placeholder
+            )));
+        }
+    }
+    Ok(())
 }
 
-const TOKEN_LENGTH_DIVISOR: usize = 4; // Approximate token length divisor for UTF-8 characters
-
 impl RouterModel for RouterModelV1 {
     fn generate_request(
         &self,
         messages: &[Message],
         usage_preferences_from_request: &Option<Vec<ModelUsagePreference>>,
     ) -> ChatCompletionsRequest {
-        // remove system prompt, tool calls, tool call response and messages without content
-        // if content is empty its likely a tool call
-        // when role == tool its tool call response
-        let messages_vec = messages
-            .iter()
-            .filter(|m| m.role != SYSTEM_ROLE && m.role != TOOL_ROLE && m.content.is_some())
-            .collect::<Vec<&Message>>();
-
-        // Following code is to ensure that the conversation does not exceed max token length
-        // Note: we use a simple heuristic to estimate token count based on character length to optimize for performance
-        let mut token_count = ARCH_ROUTER_V1_SYSTEM_PROMPT.len() / TOKEN_LENGTH_DIVISOR;
-        let mut selected_messages_list_reversed: Vec<&Message> = vec![];
-        for (selected_messsage_count, message) in messages_vec.iter().rev().enumerate() {
-            let message_token_count = message
-                .content
-                .as_ref()
-                .unwrap_or(&ContentType::Text("".to_string()))
-                .to_string()
-                .len()
-                / TOKEN_LENGTH_DIVISOR;
-            token_count += message_token_count;
-            if token_count > self.max_token_length {
-                debug!(
-                      "RouterModelV1: token count {} exceeds max token length {}, truncating conversation, selected message count {}, total message count: {}",
-                      token_count,
-                      self.max_token_length
-                      , selected_messsage_count,
-                      messages_vec.len()
-                  );
-                if message.role == USER_ROLE {
-                    // If message that exceeds max token length is from user, we need to keep it
-                    selected_messages_list_reversed.push(message);
-                }
-                break;
-            }
-            // If we are here, it means that the message is within the max token length
-            selected_messages_list_reversed.push(message);
-        }
+        self.generate_request_with_stats(messages, usage_preferences_from_request)
+            .0
+    }
 
-        if selected_messages_list_reversed.is_empty() {
-            debug!(
-                "RouterModelV1: no messages selected, using the last message in the conversation"
-            );
-            if let Some(last_message) = messages_vec.last() {
-                selected_messages_list_reversed.push(last_message);
-            }
-        }
+    fn generate_request_with_stats(
+        &self,
+        messages: &[Message],
+        usage_preferences_from_request: &Option<Vec<ModelUsagePreference>>,
+    ) -> (ChatCompletionsRequest, TruncationStats) {
+        let script = self.detected_script(messages);
+        let selected_conversation_list =
+            self.select_conversation_for_script(messages, usage_preferences_from_request, script);
+
+        let router_message = generate_router_message(
+            &self.prompt_template,
+            &self.routes_block(usage_preferences_from_request),
+            &selected_conversation_list,
+        );
 
-        // ensure that first and last selected message is from user
-        if let Some(first_message) = selected_messages_list_reversed.first() {
-            if first_message.role != USER_ROLE {
-                warn!("RouterModelV1: last message in the conversation is not from user, this may lead to incorrect routing");
-            }
+        let mut router_message = if self.ranked_routes {
+            router_message + ARCH_ROUTER_V1_RANKED_ROUTES_PROMPT
+        } else {
+            router_message
+        };
+        if self.confidence {
+            router_message.push_str(ARCH_ROUTER_V1_CONFIDENCE_PROMPT);
         }
-        if let Some(last_message) = selected_messages_list_reversed.last() {
-            if last_message.role != USER_ROLE {
-                warn!("RouterModelV1: first message in the conversation is not from user, this may lead to incorrect routing");
-            }
+        if let Some(instruction) = self.localized_instruction(script) {
+            router_message.push_str(instruction);
         }
-
-        // Reverse the selected messages to maintain the conversation order
-        let selected_conversation_list = selected_messages_list_reversed
+        let contents: Vec<String> = selected_conversation_list
             .iter()
-            .rev()
-            .map(|message| {
-                Message {
-                    role: message.role.clone(),
-                    // we can unwrap here because we have already filtered out messages without content
-                    content: Some(ContentType::Text(
-                        message.content.as_ref().unwrap().to_string(),
-                    )),
-                }
-            })
-            .collect::<Vec<Message>>();
+            .filter_map(|message| message.content.as_ref())
+            .map(|content| content.to_string())
+            .collect();
+        let content: Vec<&str> = contents
+            .iter()
+            .map(String::as_str)
+            .chain(self.localized_instruction(script))
+            .collect();
 
-        // Generate the router request message based on the usage preferences.
-        // If preferences are passed in request then we use them otherwise we use the default routing model preferences.
-        let router_message = match convert_to_router_preferences(usage_preferences_from_request) {
-            Some(prefs) => generate_router_message(&prefs, &selected_conversation_list),
-            None => generate_router_message(&self.llm_route_json_str, &selected_conversation_list),
+        // the latest system message is sent along when configured, it is not part of the
+        // conversation that is truncated
+        let stats = TruncationStats {
+            total_messages: conversation_messages(messages, self.tool_messages).len(),
+            selected_messages: selected_conversation_list
+                .iter()
+                .filter(|message| message.role != SYSTEM_ROLE)
+                .count(),
+            estimated_tokens: self.prompt_token_count(&router_message, &content, script),
         };
 
-        ChatCompletionsRequest {
+        let request = ChatCompletionsRequest {
             model: self.routing_model.clone(),
             messages: vec![Message {
                 content: Some(ContentType::Text(router_message)),
                 role: USER_ROLE.to_string(),
+                ..Default::default()
             }],
             temperature: Some(0.01),
             ..Default::default()
-        }
+        };
+        (request, stats)
+    }
+
+    fn select_conversation(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<Message> {
+        self.select_conversation_for_script(
+            messages,
+            usage_preferences,
+            self.detected_script(messages),
+        )
     }
 
     fn parse_response(
         &self,
         content: &str,
         usage_preferences: &Option<Vec<ModelUsagePreference>>,
-    ) -> Result<Option<(String, String)>> {
+    ) -> Result<RouteDecision> {
+        if content.is_empty() {
+            return Ok(RouteDecision::default());
+        }
+        let router_response = match self.parse_router_response(content, usage_preferences)? {
+            Some(router_response) => router_response,
+            None => return Ok(RouteDecision::default()),
+        };
+
+        Ok(RouteDecision {
+            route: self
+                .resolve_routes(&router_response, usage_preferences)?
+                .into_iter()
+                .next(),
+            confidence: router_response.confidence,
+        })
+    }
+
+    fn parse_response_ranked(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Vec<(String, String)>> {
         if content.is_empty() {
-            return Ok(None);
+            return Ok(vec![]);
         }
-        let router_resp_fixed = fix_json_response(content);
-        let router_response: LlmRouterResponse = serde_json::from_str(router_resp_fixed.as_str())?;
+        let router_response = match self.parse_router_response(content, usage_preferences)? {
+            Some(router_response) => router_response,
+            None => return Ok(vec![]),
+        };
 
-        let selected_route = router_response.route.unwrap_or_default().to_string();
+        self.resolve_routes(&router_response, usage_preferences)
+    }
 
-        if selected_route.is_empty() || selected_route == "other" {
-            return Ok(None);
+    fn answered_routes(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<String> {
+        if content.is_empty() {
+            return vec![];
+        }
+        match self.parse_router_response(content, usage_preferences) {
+            Ok(Some(router_response)) => ranked_route_names(&router_response)
+                .into_iter()
+                .map(|route| canonical_route(route, &self.route_aliases, usage_preferences))
+                .collect(),
+            _ => vec![],
         }
+    }
 
-        if let Some(usage_preferences) = usage_preferences {
-            // If usage preferences are defined, we need to find the model that matches the selected route
-            let model_name: Option<String> = usage_preferences
-                .iter()
-                .map(|pref| {
-                    pref.routing_preferences
-                        .iter()
-                        .find(|routing_pref| routing_pref.name == selected_route)
-                        .map(|_| pref.model.clone())
-                })
-                .find_map(|model| model);
+    fn get_model_name(&self) -> String {
+        self.routing_model.clone()
+    }
+}
 
-            if let Some(model_name) = model_name {
-                return Ok(Some((selected_route, model_name)));
+/// Messages of the conversation the routing model may see, the ones `trim_conversation` selects
+/// from.
+pub(crate) fn conversation_messages(messages: &[Message], tool_messages: bool) -> Vec<&Message> {
+    // remove system prompt, tool calls, tool call response and messages without content
+    // if content is empty its likely a tool call
+    // when role == tool its tool call response
+    messages
+        .iter()
+        .filter(|m| m.role != SYSTEM_ROLE)
+        .filter(|m| {
+            if tool_messages {
+                m.content.is_some() || m.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty())
             } else {
-                warn!(
-                    "No matching model found for route: {}, usage preferences: {:?}",
-                    selected_route, usage_preferences
-                );
-                return Ok(None);
+                m.role != TOOL_ROLE && m.content.is_some()
             }
+        })
+        .collect()
+}
+
+/// Selects the most recent messages of the conversation that fit in the token budget of the
+/// routing model. System messages are skipped, tool calls and tool call responses as well unless
+/// `tool_messages` is set.
+pub(crate) fn trim_conversation<F>(
+    messages: &[Message],
+    max_token_length: usize,
+    base_token_count: usize,
+    start_at_user_turn: bool,
+    max_messages: Option<usize>,
+    tool_messages: bool,
+    count_tokens: F,
+) -> Vec<Message>
+where
+    F: Fn(&str) -> usize,
+{
+    let messages_vec = conversation_messages(messages, tool_messages);
+
+    let mut token_count = base_token_count;
+    let mut truncated = false;
+    let mut selected_messages_list_reversed: Vec<&Message> = vec![];
+    for (selected_messsage_count, message) in messages_vec.iter().rev().enumerate() {
+        if max_messages.is_some_and(|max_messages| selected_messsage_count >= max_messages) {
+            debug!(
+                "RouterModel: selected message count {} reached max messages, truncating conversation, total message count: {}",
+                selected_messsage_count,
+                messages_vec.len()
+            );
+            truncated = true;
+            break;
         }
+        let message_token_count = count_tokens(&conversation_text(message, tool_messages));
+        token_count += message_token_count;
+        if token_count > max_token_length {
+            debug!(
+                "RouterModel: token count {} exceeds max token length {}, truncating conversation, selected message count {}, total message count: {}",
+                token_count,
+                max_token_length,
+                selected_messsage_count,
+                messages_vec.len()
+            );
+            if message.role == USER_ROLE {
+                // If message that exceeds max token length is from user, we need to keep it
+                selected_messages_list_reversed.push(message);
+            }
+            truncated = true;
+            break;
+        }
+        // If we are here, it means that the message is within the max token length
+        selected_messages_list_reversed.push(message);
+    }
 
-        // If no usage preferences are passed in request then use the default routing model preferences
-        if let Some(model) = self.llm_route_to_model_map.get(&selected_route).cloned() {
-            return Ok(Some((selected_route, model)));
+    if selected_messages_list_reversed.is_empty() {
+        debug!("RouterModel: no messages selected, using the last message in the conversation");
+        if let Some(last_message) = messages_vec.last() {
+            selected_messages_list_reversed.push(last_message);
         }
+    }
 
-        warn!(
-            "No model found for route: {}, router model preferences: {:?}",
-            selected_route, self.llm_route_to_model_map
-        );
+    if truncated && start_at_user_turn {
+        // the oldest selected message is last, drop messages until a user turn while keeping at
+        // least the latest message
+        while selected_messages_list_reversed.len() > 1
+            && selected_messages_list_reversed
+                .last()
+                .is_some_and(|message| message.role != USER_ROLE)
+        {
+            selected_messages_list_reversed.pop();
+        }
+    }
 
-        Ok(None)
+    // ensure that first and last selected message is from user
+    if let Some(first_message) = selected_messages_list_reversed.first() {
+        if first_message.role != USER_ROLE {
+            warn!("RouterModel: last message in the conversation is not from user, this may lead to incorrect routing");
+        }
+    }
+    if let Some(last_message) = selected_messages_list_reversed.last() {
+        if last_message.role != USER_ROLE {
+            warn!("RouterModel: first message in the conversation is not from user, this may lead to incorrect routing");
+        }
     }
 
-    fn get_model_name(&self) -> String {
-        self.routing_model.clone()
+    // Reverse the selected messages to maintain the conversation order
+    selected_messages_list_reversed
+        .iter()
+        .rev()
+        .map(|message| Message {
+            role: message.role.clone(),
+            content: Some(ContentType::Text(conversation_text(message, tool_messages))),
+            ..Default::default()
+        })
+        .collect::<Vec<Message>>()
+}
+
+/// Text of a message in the conversation shown to the routing model, followed by its tool calls
+/// if `tool_calls` is set.
+fn conversation_text(message: &Message, tool_calls: bool) -> String {
+    let mut text = message
+        .content
+        .as_ref()
+        .map(routing_text)
+        .unwrap_or_default();
+    if tool_calls {
+        for tool_call in message.tool_calls.iter().flatten() {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!(
+                "[called tool: {}({})]",
+                tool_call.function.name, tool_call.function.arguments
+            ));
+        }
+    }
+    text
+}
+
+/// Text of a message as the routing model sees it. Text parts of multi part content are
+/// concatenated and images are replaced by a label, so that vision requests are still routed on
+/// what the user asked without sending image data to the routing model.
+pub(crate) fn routing_text(content: &ContentType) -> String {
+    match content {
+        ContentType::Text(text) => text.clone(),
+        ContentType::MultiPart(parts) => parts
+            .iter()
+            .filter_map(|part| match part.content_type {
+                MultiPartContentType::Text => part.text.clone(),
+                MultiPartContentType::ImageUrl => Some(IMAGE_PART_LABEL.to_string()),
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
     }
 }
 
-fn generate_router_message(prefs: &str, selected_conversation_list: &Vec<Message>) -> String {
-    ARCH_ROUTER_V1_SYSTEM_PROMPT
-        .replace("{routes}", prefs)
-        .replace(
-            "{conversation}",
-            &serde_json::to_string(&selected_conversation_list).unwrap_or_default(),
+/// Latest system message with its routing text cut to `max_chars` characters.
+fn latest_system_message(messages: &[Message], max_chars: usize) -> Option<Message> {
+    let content = messages
+        .iter()
+        .rev()
+        .find(|message| message.role == SYSTEM_ROLE)?
+        .content
+        .as_ref()?;
+    let text: String = routing_text(content).chars().take(max_chars).collect();
+    if text.trim().is_empty() {
+        return None;
+    }
+    Some(Message {
+        role: SYSTEM_ROLE.to_string(),
+        content: Some(ContentType::Text(text)),
+        ..Default::default()
+    })
+}
+
+/// Routing text of the most recent user message.
+pub(crate) fn latest_user_text(messages: &[Message]) -> Option<String> {
+    messages
+        .iter()
+        .rev()
+        .find(|message| message.role == USER_ROLE)?
+        .content
+        .as_ref()
+        .map(routing_text)
+}
+
+fn generate_router_message(
+    prompt_template: &str,
+    prefs: &str,
+    selected_conversation_list: &Vec<Message>,
+) -> String {
+    prompt_template.replace("{routes}", prefs).replace(
+        "{conversation}",
+        &serde_json::to_string(&selected_conversation_list).unwrap_or_default(),
+    )
+}
+
+/// Maps every alias of the routes to the name of its route.
+pub(crate) fn route_aliases<'a>(
+    routes: impl Iterator<Item = &'a RoutingPreference>,
+) -> HashMap<String, String> {
+    routes
+        .flat_map(|pref| {
+            pref.aliases
+                .iter()
+                .flatten()
+                .map(|alias| (alias.clone(), pref.name.clone()))
+        })
+        .collect()
+}
+
+/// Name of the route `route` is an alias of, `route` itself when it is not an alias. Aliases of
+/// the preferences sent with the request replace the configured ones.
+pub(crate) fn canonical_route(
+    route: String,
+    configured_aliases: &HashMap<String, String>,
+    usage_preferences: &Option<Vec<ModelUsagePreference>>,
+) -> String {
+    let canonical = match usage_preferences {
+        Some(usage_preferences) => route_aliases(
+            usage_preferences
+                .iter()
+                .flat_map(|pref| pref.routing_preferences.iter()),
         )
+        .remove(&route),
+        None => configured_aliases.get(&route).cloned(),
+    };
+    canonical.unwrap_or(route)
 }
 
 fn convert_to_router_preferences(
@@ -247,6 +1034,8 @@ fn convert_to_router_preferences(
                     .map(|routing_pref| RoutingPreference {
                         name: routing_pref.name.clone(),
                         description: routing_pref.description.clone(),
+                        aliases: routing_pref.aliases.clone(),
+                        ..Default::default()
                     })
             })
             .collect::<Vec<RoutingPreference>>();
@@ -257,8 +1046,87 @@ fn convert_to_router_preferences(
     None
 }
 
+pub(crate) fn parse_llm_router_response(content: &str) -> Result<Option<LlmRouterResponse>> {
+    let content = strip_reasoning(content);
+    if !content.contains('{') {
+        warn!(
+            "No json object found in router response: {}",
+            content.replace("\n", "\\n")
+        );
+        return Ok(None);
+    }
+    let router_resp_fixed = fix_json_response(content);
+    match serde_json::from_str(router_resp_fixed.as_str()) {
+        Ok(router_response) => Ok(Some(router_response)),
+        // a truncated object is told apart from one that is malformed
+        Err(_) if !json_object_complete(&router_resp_fixed) => {
+            warn!(
+                "Incomplete json object in router response: {}",
+                content.replace("\n", "\\n")
+            );
+            Err(RoutingModelError::IncompleteResponse(content.to_string()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Whether the first JSON object in `content` is closed, reasoning blocks are skipped.
+pub(crate) fn json_object_complete(content: &str) -> bool {
+    let content = match content.rfind("</think>") {
+        Some(end) => &content[end + "</think>".len()..],
+        None if content.contains("<think>") => return false,
+        None => content,
+    };
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in content.chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' if depth > 0 => in_string = true,
+            '{' => depth += 1,
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Route names in the order the routing model ranked them. Duplicates, empty names and
+/// the catch all "other" route are removed.
+pub(crate) fn ranked_route_names(router_response: &LlmRouterResponse) -> Vec<String> {
+    let candidates: Vec<String> = match &router_response.routes {
+        Some(routes) => routes.clone(),
+        None => router_response.route.iter().cloned().collect(),
+    };
+
+    let mut route_names: Vec<String> = vec![];
+    for route in candidates {
+        if route.is_empty() || route == "other" || route_names.contains(&route) {
+            continue;
+        }
+        route_names.push(route);
+    }
+    route_names
+}
+
 fn fix_json_response(body: &str) -> String {
-    let mut updated_body = body.to_string();
+    let body = strip_emphasis(body);
+    let mut updated_body = decode_json_string(body).unwrap_or_else(|| body.to_string());
 
     updated_body = updated_body.replace("'", "\"");
 
@@ -280,7 +1148,192 @@ fn fix_json_response(body: &str) -> String {
             .to_string();
     }
 
-    updated_body
+    // drop any prose that the model emitted around the json object
+    if let Some(json_object) = extract_json_object(&updated_body) {
+        updated_body = json_object.to_string();
+    }
+
+    quote_unquoted_keys(&remove_trailing_commas(&updated_body))
+}
+
+/// Markdown emphasis models wrap their answer in, e.g. `**{"route": "x"}**`.
+const EMPHASIS_MARKERS: [&str; 4] = ["**", "__", "*", "_"];
+
+fn strip_emphasis(body: &str) -> &str {
+    let mut body = body.trim();
+    while let Some(stripped) = EMPHASIS_MARKERS
+        .iter()
+        .find_map(|marker| body.strip_prefix(marker)?.strip_suffix(marker))
+    {
+        body = stripped.trim();
+    }
+    body
+}
+
+/// Decodes one layer of a json object the model returned encoded as a json string, e.g.
+/// `"{\"route\": \"x\"}"`.
+fn decode_json_string(body: &str) -> Option<String> {
+    if body.len() < 2 || !body.starts_with('"') || !body.ends_with('"') {
+        return None;
+    }
+    let decoded: String = serde_json::from_str(body).ok()?;
+    decoded.trim_start().starts_with('{').then_some(decoded)
+}
+
+/// Returns the last balanced `{...}` object in the body, models that reason before answering
+/// put the final answer last. If no object is ever closed the remainder of the body from the
+/// first `{` is returned so that the json parser reports the error.
+fn extract_json_object(body: &str) -> Option<&str> {
+    let first_start = body.find('{')?;
+    let mut last_object = None;
+    let mut start = first_start;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in body.char_indices().skip_while(|(i, _)| *i < first_start) {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            // quotes in the prose between objects don't start a string
+            '"' if depth > 0 => in_string = true,
+            '{' => {
+                if depth == 0 {
+                    start = i;
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    last_object = Some(&body[start..=i]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    last_object.or(Some(&body[first_start..]))
+}
+
+/// Drops the chain of thought reasoning models emit before their answer, i.e. everything up to
+/// the last `</think>`. Some models omit the opening tag, so it is not required.
+fn strip_reasoning(content: &str) -> &str {
+    const THINK_END_TAG: &str = "</think>";
+    match content.rfind(THINK_END_TAG) {
+        Some(end) => &content[end + THINK_END_TAG.len()..],
+        None => content,
+    }
+}
+
+/// Removes commas that directly precede a closing brace or bracket, e.g. `{"route": "x",}`.
+fn remove_trailing_commas(body: &str) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut fixed = String::with_capacity(body.len());
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, &c) in chars.iter().enumerate() {
+        if in_string {
+            fixed.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        if c == '"' {
+            in_string = true;
+        }
+
+        if c == ',' {
+            let next = chars[i + 1..]
+                .iter()
+                .find(|next| !next.is_whitespace())
+                .copied();
+            if next == Some('}') || next == Some(']') {
+                continue;
+            }
+        }
+
+        fixed.push(c);
+    }
+
+    fixed
+}
+
+/// Adds quotes around bare object keys, e.g. `{route: "x"}`.
+fn quote_unquoted_keys(body: &str) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut fixed = String::with_capacity(body.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut expect_key = false;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+
+        if in_string {
+            fixed.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            i += 1;
+            continue;
+        }
+
+        if expect_key && (c.is_alphabetic() || c == '_') {
+            let key_end = chars[i..]
+                .iter()
+                .position(|k| !(k.is_alphanumeric() || *k == '_' || *k == '-'))
+                .map_or(chars.len(), |offset| i + offset);
+            let next = chars[key_end..]
+                .iter()
+                .find(|next| !next.is_whitespace())
+                .copied();
+            if next == Some(':') {
+                fixed.push('"');
+                fixed.extend(&chars[i..key_end]);
+                fixed.push('"');
+                expect_key = false;
+                i = key_end;
+                continue;
+            }
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                expect_key = false;
+            }
+            '{' | ',' => expect_key = true,
+            c if c.is_whitespace() => {}
+            _ => expect_key = false,
+        }
+
+        fixed.push(c);
+        i += 1;
+    }
+
+    fixed
 }
 
 impl std::fmt::Debug for dyn RouterModel {
@@ -292,6 +1345,7 @@ impl std::fmt::Debug for dyn RouterModel {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use common::consts::ASSISTANT_ROLE;
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -408,6 +1462,7 @@ Based on your analysis, provide your response in the following JSON formats if y
             routing_preferences: vec![RoutingPreference {
                 name: "code-generation".to_string(),
                 description: "generating new code snippets, functions, or boilerplate based on user prompts or requirements".to_string(),
+                ..Default::default()
             }],
         }]);
         let req = router.generate_request(&conversation, &usage_preferences);
@@ -477,6 +1532,89 @@ Based on your analysis, provide your response in the following JSON formats if y
         assert_eq!(expected_prompt, prompt.to_string());
     }
 
+    #[test]
+    fn test_generate_request_with_stats() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": "Hello! How can I assist you today?" },
+                { "role": "user", "content": "given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson" }
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let router = RouterModelV1::new(llm_routes.clone(), "test-model".to_string(), 235);
+        let (req, stats) = router.generate_request_with_stats(&conversation, &None);
+        assert_eq!(stats.total_messages, 3);
+        assert_eq!(stats.selected_messages, 1);
+        assert!(stats.truncated());
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+        assert_eq!(stats.estimated_tokens, prompt.len() / TOKEN_LENGTH_DIVISOR);
+        assert_eq!(
+            router.generate_request(&conversation, &None).messages[0].content,
+            req.messages[0].content
+        );
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), MAX_TOKEN_LEN);
+        let (_, stats) = router.generate_request_with_stats(&conversation, &None);
+        assert_eq!(stats.selected_messages, 3);
+        assert!(!stats.truncated());
+    }
+
+    #[test]
+    fn test_skipped_messages_are_not_truncated() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "system", "content": "You are a helpful assistant." },
+                { "role": "user", "content": "what's the weather in Paris?" },
+                { "role": "assistant", "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\": \"Paris\"}"}}] },
+                { "role": "tool", "tool_call_id": "call_1", "content": "sunny" },
+                { "role": "assistant", "content": "It is sunny in Paris." },
+                { "role": "user", "content": "draw it in the style of Andy Warhol" }
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let router =
+            RouterModelV1::new(llm_routes.clone(), "test-model".to_string(), MAX_TOKEN_LEN);
+        let (_, stats) = router.generate_request_with_stats(&conversation, &None);
+        assert_eq!(stats.total_messages, 3);
+        assert_eq!(stats.selected_messages, 3);
+        assert!(!stats.truncated());
+
+        // nor when the system message is sent along or tool messages are included
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(MAX_TOKEN_LEN)
+            .system_message(1000)
+            .tool_messages(true)
+            .build()
+            .unwrap();
+        let (_, stats) = router.generate_request_with_stats(&conversation, &None);
+        assert_eq!(stats.total_messages, 5);
+        assert_eq!(stats.selected_messages, 5);
+        assert!(!stats.truncated());
+    }
+
     #[test]
     fn test_conversation_exceed_token_count_large_single_message() {
         let expected_prompt = r#"
@@ -570,7 +1708,7 @@ Based on your analysis, provide your response in the following JSON formats if y
         let llm_routes =
             serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
         let routing_model = "test-model".to_string();
-        let router = RouterModelV1::new(llm_routes, routing_model.clone(), 230);
+        let router = RouterModelV1::new(llm_routes, routing_model.clone(), 245);
 
         let conversation_str = r#"
                     [
@@ -606,6 +1744,56 @@ Based on your analysis, provide your response in the following JSON formats if y
         assert_eq!(expected_prompt, prompt.to_string());
     }
 
+    #[test]
+    fn test_conversation_with_tokenizer() {
+        struct ZeroTokenizer;
+        impl Tokenizer for ZeroTokenizer {
+            fn count(&self, _text: &str) -> usize {
+                0
+            }
+        }
+
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+        // with the heuristic a budget of 10 tokens would drop everything but the last user message
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(10)
+            .tokenizer(Arc::new(ZeroTokenizer))
+            .build()
+            .unwrap();
+
+        let conversation_str = r#"
+                    [
+                        {
+                            "role": "user",
+                            "content": "hi"
+                        },
+                        {
+                            "role": "assistant",
+                            "content": "Hello! How can I assist you today?"
+                        },
+                        {
+                            "role": "user",
+                            "content": "given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"
+                        }
+                    ]
+        "#;
+        let conversation: Vec<Message> = serde_json::from_str(conversation_str).unwrap();
+
+        let req = router.generate_request(&conversation, &None);
+
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+
+        assert!(prompt.contains(r#"[{"role":"user","content":"hi"},{"role":"assistant","content":"Hello! How can I assist you today?"},{"role":"user","content":"given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"}]"#));
+    }
+
     #[test]
     fn test_non_text_input() {
         let expected_prompt = r#"
@@ -616,7 +1804,7 @@ You are provided with route description within <routes></routes> XML tags:
 </routes>
 
 <conversation>
-[{"role":"user","content":"hi"},{"role":"assistant","content":"Hello! How can I assist you today?"},{"role":"user","content":"given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"}]
+[{"role":"user","content":"hi\n[image]"},{"role":"assistant","content":"Hello! How can I assist you today?"},{"role":"user","content":"given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"}]
 </conversation>
 
 Your task is to decide which route is best suit with user intent on the conversation in <conversation></conversation> XML tags.  Follow the instruction:
@@ -633,46 +1821,532 @@ Based on your analysis, provide your response in the following JSON formats if y
               {"name": "Image generation", "description": "generating image"}
             ]
         }
-        "#;
-        let llm_routes =
-            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
-        let routing_model = "test-model".to_string();
-        let router = RouterModelV1::new(llm_routes, routing_model.clone(), usize::MAX);
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+        let routing_model = "test-model".to_string();
+        let router = RouterModelV1::new(llm_routes, routing_model.clone(), usize::MAX);
+
+        let conversation_str = r#"
+                    [
+                        {
+                            "role": "user",
+                            "content": [
+                              {
+                                "type": "text",
+                                "text": "hi"
+                              },
+                              {
+                                "type": "image_url",
+                                "image_url": {
+                                  "url": "https://example.com/image.png"
+                                }
+                              }
+                            ]
+                        },
+                        {
+                            "role": "assistant",
+                            "content": "Hello! How can I assist you today?"
+                        },
+                        {
+                            "role": "user",
+                            "content": "given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"
+                        }
+                    ]
+        "#;
+        let conversation: Vec<Message> = serde_json::from_str(conversation_str).unwrap();
+
+        let req = router.generate_request(&conversation, &None);
+
+        let prompt = req.messages[0].content.as_ref().unwrap();
+
+        assert_eq!(expected_prompt, prompt.to_string());
+    }
+
+    #[test]
+    fn test_conversation_truncated_to_user_turn() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+
+        // the assistant answered in two messages (e.g. around a tool call), the budget runs out
+        // in the middle of the first one
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": "Hello! Let me look up a few image styles for you, this will take a moment." },
+                { "role": "assistant", "content": "Here are some styles" },
+                { "role": "user", "content": "use the first style" }
+            ]
+            "#,
+        )
+        .unwrap();
+        let routes = r#"[{"name":"Image generation","description":"generating image"}]"#;
+        let base_token_count = ARCH_ROUTER_V1_SYSTEM_PROMPT.len() / TOKEN_LENGTH_DIVISOR
+            + routes.len() / TOKEN_LENGTH_DIVISOR;
+        let max_token_length = base_token_count + 15;
+
+        let router = RouterModelV1::new(
+            llm_routes.clone(),
+            "test-model".to_string(),
+            max_token_length,
+        );
+        let selected = router.select_conversation(&conversation, &None);
+        let roles: Vec<&str> = selected.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["assistant", "user"]);
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(max_token_length)
+            .start_at_user_turn(true)
+            .build()
+            .unwrap();
+        let selected = router.select_conversation(&conversation, &None);
+        let roles: Vec<&str> = selected.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user"]);
+        assert_eq!(
+            selected[0].content,
+            Some(ContentType::Text("use the first style".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_token_length_divisor() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": "Hello there, how can I help you today?" },
+                { "role": "user", "content": "draw a cat" }
+            ]
+            "#,
+        )
+        .unwrap();
+        // room for the prompt, the routes and a few tokens when counting two bytes per token
+        let routes = r#"[{"name":"Image generation","description":"generating image"}]"#;
+        let max_token_length = ARCH_ROUTER_V1_SYSTEM_PROMPT.len() / 2 + routes.len() / 2 + 12;
+
+        let router = RouterModelV1::new(
+            llm_routes.clone(),
+            "test-model".to_string(),
+            max_token_length,
+        );
+        assert_eq!(router.select_conversation(&conversation, &None).len(), 3);
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(max_token_length)
+            .token_length_divisor(2)
+            .build()
+            .unwrap();
+        let selected = router.select_conversation(&conversation, &None);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(
+            selected[0].content,
+            Some(ContentType::Text("draw a cat".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_routes_count_toward_token_budget() {
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": "Hello! How can I assist you today?" },
+                { "role": "user", "content": "draw a cat" }
+            ]
+            "#,
+        )
+        .unwrap();
+        let max_token_length = ARCH_ROUTER_V1_SYSTEM_PROMPT.len() / TOKEN_LENGTH_DIVISOR + 100;
+
+        let few_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let router = RouterModelV1::new(few_routes, "test-model".to_string(), max_token_length);
+        assert_eq!(router.select_conversation(&conversation, &None).len(), 3);
+
+        let many_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            (0..10)
+                .map(|i| RoutingPreference {
+                    name: format!("route-{}", i),
+                    description: "a long description of what the route is good at".to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        )]);
+        let router = RouterModelV1::new(many_routes, "test-model".to_string(), max_token_length);
+        let selected = router.select_conversation(&conversation, &None);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(
+            selected[0].content,
+            Some(ContentType::Text("draw a cat".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_system_message() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "system", "content": "You are a helpful assistant" },
+                { "role": "user", "content": "hi" },
+                { "role": "system", "content": "Tools available: calendar, email. User tier: premium." },
+                { "role": "user", "content": "book a meeting" }
+            ]
+            "#,
+        )
+        .unwrap();
+
+        // left out unless enabled
+        let router = RouterModelV1::new(llm_routes.clone(), "test-model".to_string(), usize::MAX);
+        let prompt = router.generate_request(&conversation, &None).messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .to_string();
+        assert!(prompt.contains(
+            r#"[{"role":"user","content":"hi"},{"role":"user","content":"book a meeting"}]"#
+        ));
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .system_message(32)
+            .build()
+            .unwrap();
+        let prompt = router.generate_request(&conversation, &None).messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .to_string();
+        assert!(prompt.contains(
+            r#"[{"role":"system","content":"Tools available: calendar, email"},{"role":"user","content":"hi"},{"role":"user","content":"book a meeting"}]"#
+        ));
+    }
+
+    #[test]
+    fn test_tool_messages() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "What's the weather like in Tokyo?" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "toolcall-abc123",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"location\":\"Tokyo\"}" }
+                    }]
+                },
+                { "role": "tool", "tool_call_id": "toolcall-abc123", "content": "22°C and sunny" },
+                { "role": "user", "content": "What about in New York?" }
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .tool_messages(true)
+            .build()
+            .unwrap();
+        let prompt = router.generate_request(&conversation, &None).messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .to_string();
+        assert!(prompt.contains(
+            r#"{"role":"assistant","content":"[called tool: get_weather({\"location\":\"Tokyo\"})]"}"#
+        ));
+        assert!(prompt.contains(r#"{"role":"tool","content":"22°C and sunny"}"#));
+        assert!(!prompt.contains("null"));
+    }
+
+    #[test]
+    fn test_unknown_route() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let router = RouterModelV1::new(llm_routes.clone(), "test-model".to_string(), usize::MAX);
+
+        let decision = router
+            .parse_response(r#"{"route": "Image generation"}"#, &None)
+            .unwrap();
+        assert_eq!(
+            decision.route,
+            Some(("Image generation".to_string(), "gpt-4o".to_string()))
+        );
+
+        // hallucinated routes are dropped by default
+        let decision = router
+            .parse_response(r#"{"route": "Video generation"}"#, &None)
+            .unwrap();
+        assert_eq!(decision.route, None);
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .reject_unknown_routes(true)
+            .build()
+            .unwrap();
+        let decision = router
+            .parse_response(r#"{"route": "Image generation"}"#, &None)
+            .unwrap();
+        assert_eq!(decision.model_name(), Some("gpt-4o"));
+
+        let err = router
+            .parse_response(r#"{"route": "Video generation"}"#, &None)
+            .unwrap_err();
+        assert!(
+            matches!(err, RoutingModelError::UnknownRoute(route) if route == "Video generation")
+        );
+
+        // "other" is not a route and never rejected
+        let decision = router
+            .parse_response(r#"{"route": "other"}"#, &None)
+            .unwrap();
+        assert_eq!(decision.route, None);
+    }
+
+    #[test]
+    fn test_incomplete_response() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), usize::MAX);
+
+        let err = router
+            .parse_response(r#"{"route": "rou"#, &None)
+            .unwrap_err();
+        assert!(
+            matches!(err, RoutingModelError::IncompleteResponse(content) if content == r#"{"route": "rou"#)
+        );
+        let err = router
+            .parse_response(r#"<think>{}</think>{"route": {"#, &None)
+            .unwrap_err();
+        assert!(matches!(err, RoutingModelError::IncompleteResponse(_)));
+
+        // a complete object that is not valid is still a json error
+        let err = router
+            .parse_response(r#"{"route": rou}"#, &None)
+            .unwrap_err();
+        assert!(matches!(err, RoutingModelError::JsonError(_)));
+    }
+
+    #[test]
+    fn test_custom_prompt_template() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .prompt_template(
+                "Pick a route for a travel agency.\nroutes: {routes}\nconversation: {conversation}\nAnswer as {\"route\": \"name\"}",
+            )
+            .build()
+            .unwrap();
+
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "draw a beach" }
+            ]
+            "#,
+        )
+        .unwrap();
+        let request = router.generate_request(&conversation, &None);
+        let prompt = request.messages[0].content.as_ref().unwrap().to_string();
+
+        let expected = r#"Pick a route for a travel agency.
+routes: [{"name":"Image generation","description":"generating image"}]
+conversation: [{"role":"user","content":"draw a beach"}]
+Answer as {"route": "name"}"#;
+        assert_eq!(prompt, expected);
+    }
+
+    #[test]
+    fn test_prompt_template_missing_placeholder() {
+        let err = RouterModelV1::builder(HashMap::new(), "test-model".to_string())
+            .prompt_template("routes: {routes}")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, RoutingModelError::InvalidPromptTemplate(_)));
+        assert!(err.to_string().contains("{conversation}"));
+    }
+
+    #[test]
+    fn test_max_messages() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = (0..100)
+            .map(|i| Message {
+                role: if i % 2 == 0 {
+                    USER_ROLE
+                } else {
+                    ASSISTANT_ROLE
+                }
+                .to_string(),
+                content: Some(ContentType::Text(format!("m{}", i))),
+                ..Default::default()
+            })
+            .collect();
+
+        // all messages fit in the token budget
+        let router = RouterModelV1::new(llm_routes.clone(), "test-model".to_string(), usize::MAX);
+        assert_eq!(router.select_conversation(&conversation, &None).len(), 100);
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .max_messages(5)
+            .build()
+            .unwrap();
+        let selected = router.select_conversation(&conversation, &None);
+        let contents: Vec<String> = selected
+            .iter()
+            .map(|m| routing_text(m.content.as_ref().unwrap()))
+            .collect();
+        assert_eq!(contents, vec!["m95", "m96", "m97", "m98", "m99"]);
+    }
+
+    #[test]
+    fn test_untruncated_conversation_keeps_leading_assistant_turn() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "assistant", "content": "Hello! How can I help?" },
+                { "role": "user", "content": "draw a cat" }
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .start_at_user_turn(true)
+            .build()
+            .unwrap();
+        let selected = router.select_conversation(&conversation, &None);
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn test_multi_part_content_token_count() {
+        struct RecordingTokenizer(std::sync::Mutex<Vec<String>>);
+        impl Tokenizer for RecordingTokenizer {
+            fn count(&self, text: &str) -> usize {
+                self.0.lock().unwrap().push(text.to_string());
+                text.len()
+            }
+        }
 
-        let conversation_str = r#"
-                    [
-                        {
-                            "role": "user",
-                            "content": [
-                              {
-                                "type": "text",
-                                "text": "hi"
-                              },
-                              {
-                                "type": "image_url",
-                                "image_url": {
-                                  "url": "https://example.com/image.png"
-                                }
-                              }
-                            ]
-                        },
-                        {
-                            "role": "assistant",
-                            "content": "Hello! How can I assist you today?"
-                        },
-                        {
-                            "role": "user",
-                            "content": "given the image In style of Andy Warhol, portrait of Bart and Lisa Simpson"
-                        }
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let tokenizer = Arc::new(RecordingTokenizer(std::sync::Mutex::new(vec![])));
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .tokenizer(tokenizer.clone())
+            .build()
+            .unwrap();
+
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "what is in this picture" },
+                        { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } },
+                        { "type": "text", "text": "make it brighter" }
                     ]
-        "#;
-        let conversation: Vec<Message> = serde_json::from_str(conversation_str).unwrap();
-
-        let req = router.generate_request(&conversation, &None);
+                }
+            ]
+            "#,
+        )
+        .unwrap();
 
-        let prompt = req.messages[0].content.as_ref().unwrap();
+        let selected = router.select_conversation(&conversation, &None);
 
-        assert_eq!(expected_prompt, prompt.to_string());
+        assert_eq!(
+            selected[0].content,
+            Some(ContentType::Text(
+                "what is in this picture\n[image]\nmake it brighter".to_string()
+            ))
+        );
+        // the image data is not counted, only the label
+        let counted = tokenizer.0.lock().unwrap();
+        assert!(counted.contains(&"what is in this picture\n[image]\nmake it brighter".to_string()));
+        assert!(!counted.iter().any(|text| text.contains("base64")));
     }
 
     #[test]
@@ -786,7 +2460,7 @@ Based on your analysis, provide your response in the following JSON formats if y
 
         // Case 1: Valid JSON with non-empty route
         let input = r#"{"route": "Image generation"}"#;
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(
             result,
             Some(("Image generation".to_string(), "gpt-4o".to_string()))
@@ -794,22 +2468,22 @@ Based on your analysis, provide your response in the following JSON formats if y
 
         // Case 2: Valid JSON with empty route
         let input = r#"{"route": ""}"#;
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(result, None);
 
         // Case 3: Valid JSON with null route
         let input = r#"{"route": null}"#;
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(result, None);
 
         // Case 4: JSON missing route field
         let input = r#"{}"#;
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(result, None);
 
         // Case 4.1: empty string
         let input = r#""#;
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(result, None);
 
         // Case 5: Malformed JSON
@@ -819,7 +2493,7 @@ Based on your analysis, provide your response in the following JSON formats if y
 
         // Case 6: Single quotes and \n in JSON
         let input = "{'route': 'Image generation'}\\n";
-        let result = router.parse_response(input, &None).unwrap();
+        let result = router.parse_response(input, &None).unwrap().route;
         assert_eq!(
             result,
             Some(("Image generation".to_string(), "gpt-4o".to_string()))
@@ -827,10 +2501,566 @@ Based on your analysis, provide your response in the following JSON formats if y
 
         // Case 7: Code block marker
         let input = "```json\n{\"route\": \"Image generation\"}\n```";
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(
+            result,
+            Some(("Image generation".to_string(), "gpt-4o".to_string()))
+        );
+
+        // Case 8: Confidence reported by the model
+        let input = r#"{"route": "Image generation", "confidence": 0.87}"#;
+        let result = router.parse_response(input, &None).unwrap();
+        assert_eq!(result.route_name(), Some("Image generation"));
+        assert_eq!(result.model_name(), Some("gpt-4o"));
+        assert_eq!(result.confidence, Some(0.87));
+
+        // Case 9: Score is accepted as an alias for confidence
+        let input = r#"{"route": "other", "score": 0.2}"#;
         let result = router.parse_response(input, &None).unwrap();
+        assert_eq!(result.route, None);
+        assert_eq!(result.confidence, Some(0.2));
+    }
+
+    #[test]
+    fn test_route_aliases() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "support", "description": "account questions", "aliases": ["helpdesk"]}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), 2000);
+
+        let req = router.generate_request(&[Message::new("reset my password".to_string())], &None);
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+        assert!(prompt.contains(
+            r#"[{"name":"support","description":"account questions","aliases":["helpdesk"]}]"#
+        ));
+
+        let expected = Some(("support".to_string(), "gpt-4o".to_string()));
+        let result = router.parse_response(r#"{"route": "helpdesk"}"#, &None);
+        assert_eq!(result.unwrap().route, expected);
+        let result = router.parse_response(r#"{"route": "support"}"#, &None);
+        assert_eq!(result.unwrap().route, expected);
+
+        // aliases of the preferences sent with the request
+        let usage_preferences = Some(vec![ModelUsagePreference {
+            model: "claude-3-7-sonnet".to_string(),
+            routing_preferences: vec![RoutingPreference {
+                name: "coding".to_string(),
+                description: "writing code".to_string(),
+                aliases: Some(vec!["programming".to_string()]),
+                ..Default::default()
+            }],
+        }]);
+        let result = router.parse_response(r#"{"route": "programming"}"#, &usage_preferences);
+        assert_eq!(
+            result.unwrap().route,
+            Some(("coding".to_string(), "claude-3-7-sonnet".to_string()))
+        );
+        let result = router.parse_response(r#"{"route": "helpdesk"}"#, &usage_preferences);
+        assert_eq!(result.unwrap().route, None);
+    }
+
+    #[test]
+    fn test_parse_response_malformed_json() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), 2000);
+        let expected = Some(("Image generation".to_string(), "gpt-4o".to_string()));
+
+        // trailing comma
+        let input = r#"{"route": "Image generation",}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // unquoted key
+        let input = r#"{route: "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // leading prose before the json object
+        let input = r#"The best route is {"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // commas and colons inside strings are left alone
+        assert_eq!(
+            fix_json_response(r#"{"route": "a, }", key: "b:c",}"#),
+            r#"{"route": "a, }", "key": "b:c"}"#
+        );
+
+        // wrapped in markdown bold or italics
+        let input = r#"**{"route": "Image generation"}**"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+        assert_eq!(
+            fix_json_response(r#" *__{"route": "x"}__* "#),
+            r#"{"route": "x"}"#
+        );
+
+        // doubly encoded, one layer is decoded
+        let input = r#""{\"route\": \"Image generation\"}""#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+        let input =
+            r#"**"{\"route\":\"Image generation\", \"reason\": \"user wants a picture\"}"**"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // other strings are left to the parser
+        assert_eq!(fix_json_response(r#""other""#), r#""other""#);
+    }
+
+    #[test]
+    fn test_parse_response_embedded_in_prose() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), 2000);
+        let expected = Some(("Image generation".to_string(), "gpt-4o".to_string()));
+
+        // prose before the json object
+        let input = r#"Sure! Here's the route: {"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // prose after the json object
+        let input = r#"{"route": "Image generation"} — hope that helps"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // the last object is the final answer
+        let input = r#"{"route": "other"} or rather {"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // braces inside strings don't end the object
+        let input = r#"route: {"route": "Image generation", "reason": "user wants a {picture}"}."#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // no json object at all
+        let input = "I am not sure which route to pick";
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_content_normalization() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "Image generation".to_string(),
+                description: "generating image".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let conversation: Vec<Message> = serde_json::from_str(
+            r#"
+            [
+                { "role": "user", "content": "  Please DRAW\n\n   a   Cat  " },
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": " What IS\tthis? " },
+                        { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } }
+                    ]
+                }
+            ]
+            "#,
+        )
+        .unwrap();
+        let original = serde_json::to_string(&conversation).unwrap();
+
+        let router = RouterModelV1::builder(llm_routes.clone(), "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .content_normalization(ContentNormalization {
+                trim: true,
+                collapse_whitespace: true,
+                lowercase: true,
+            })
+            .build()
+            .unwrap();
+        let prompt = router.generate_request(&conversation, &None).messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .to_string();
+        assert!(prompt.contains(r#"{"role":"user","content":"please draw a cat"}"#));
+        assert!(prompt.contains(r#"{"role":"user","content":"what is this?\n[image]"}"#));
+        // the request that is forwarded keeps its messages
+        assert_eq!(serde_json::to_string(&conversation).unwrap(), original);
+
+        // each normalization is applied on its own
+        let normalization = ContentNormalization {
+            trim: true,
+            ..Default::default()
+        };
+        assert_eq!(normalization.normalize("  A  b\n "), "A  b");
+        let normalization = ContentNormalization {
+            collapse_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(normalization.normalize("  A  b\n "), " A b ");
+        let normalization = ContentNormalization {
+            lowercase: true,
+            ..Default::default()
+        };
+        assert_eq!(normalization.normalize("  A  b\n "), "  a  b\n ");
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), usize::MAX);
+        let prompt = router.generate_request(&conversation, &None).messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+            .to_string();
+        assert!(prompt.contains("Please DRAW"));
+    }
+
+    #[test]
+    fn test_builder_validates_options() {
+        let err = RouterModelV1::builder(HashMap::new(), "test-model".to_string())
+            .prompt_template("routes: {routes}")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, RoutingModelError::InvalidPromptTemplate(_)));
+        let err = RouterModelV1::builder(HashMap::new(), "test-model".to_string())
+            .token_length_divisor(0)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, RoutingModelError::InvalidOption(_)));
+
+        let router = RouterModelV1::builder(HashMap::new(), "Arch-Router".to_string())
+            .prompt_template("routes: {routes}\nconversation: {conversation}")
+            .token_length_divisor(2)
+            .build()
+            .unwrap();
+        assert_eq!(router.token_length_divisor, 2);
+        assert_eq!(
+            router.prompt_template,
+            "routes: {routes}\nconversation: {conversation}"
+        );
+        // the length follows the routing model unless set
+        assert_eq!(
+            router.max_token_length,
+            default_max_token_length("Arch-Router")
+        );
+    }
+
+    #[test]
+    fn test_language_detection_selects_divisor() {
+        let llm_routes = HashMap::from([(
+            "gpt-4o".to_string(),
+            vec![RoutingPreference {
+                name: "code-generation".to_string(),
+                description: "generating new code snippets".to_string(),
+                ..Default::default()
+            }],
+        )]);
+        let instruction = "\n对话是中文的，路由名称请原样回答。";
+        let router = RouterModelV1::builder(llm_routes.clone(), "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .language_detection(true)
+            .localized_instruction(Script::Cjk, instruction)
+            .build()
+            .unwrap();
+        let cjk = vec![Message::new("请帮我写一个排序函数".to_string())];
+        let latin = vec![Message::new("please write a sort function".to_string())];
+        assert_eq!(router.detected_script(&cjk), Some(Script::Cjk));
+        assert_eq!(router.detected_script(&latin), Some(Script::Latin));
+        assert!(Script::Cjk.token_length_divisor() < Script::Latin.token_length_divisor());
+
+        // only the conversation and the instruction are counted as CJK, the template and the
+        // routes with the configured divisor
+        let (request, stats) = router.generate_request_with_stats(&cjk, &None);
+        let prompt = request.messages[0].content.as_ref().unwrap().to_string();
+        assert!(prompt.ends_with(instruction));
+        let content_len = "请帮我写一个排序函数".len() + instruction.len();
+        assert_eq!(
+            stats.estimated_tokens,
+            (prompt.len() - content_len) / TOKEN_LENGTH_DIVISOR
+                + content_len / Script::Cjk.token_length_divisor()
+        );
+
+        let (request, stats) = router.generate_request_with_stats(&latin, &None);
+        let prompt = request.messages[0].content.as_ref().unwrap().to_string();
+        assert!(!prompt.contains(instruction));
+        assert_eq!(stats.estimated_tokens, prompt.len() / TOKEN_LENGTH_DIVISOR);
+
+        // a detected Latin script keeps the configured divisor
+        let router = RouterModelV1::builder(llm_routes.clone(), "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .language_detection(true)
+            .token_length_divisor(5)
+            .build()
+            .unwrap();
+        let (request, stats) = router.generate_request_with_stats(&latin, &None);
+        let prompt = request.messages[0].content.as_ref().unwrap().to_string();
+        assert_eq!(stats.estimated_tokens, prompt.len() / 5);
+
+        // the configured divisor is used unless detection is on
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .token_length_divisor(5)
+            .build()
+            .unwrap();
+        assert_eq!(router.detected_script(&cjk), None);
+        let (request, stats) = router.generate_request_with_stats(&cjk, &None);
+        let prompt = request.messages[0].content.as_ref().unwrap().to_string();
+        assert_eq!(stats.estimated_tokens, prompt.len() / 5);
+    }
+
+    #[test]
+    fn test_default_max_token_length() {
+        assert_eq!(default_max_token_length("test-model"), MAX_TOKEN_LEN);
+        assert_eq!(
+            default_max_token_length("Arch-Router"),
+            32_768 - ROUTING_RESPONSE_TOKEN_RESERVE
+        );
+        assert_eq!(
+            default_max_token_length("katanemo/Arch-Router-1.5B"),
+            32_768 - ROUTING_RESPONSE_TOKEN_RESERVE
+        );
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+
+        let router = RouterModelV1::for_routing_model(HashMap::new(), "test-model".to_string());
+        assert_eq!(router.max_token_length, MAX_TOKEN_LEN);
+        let router = RouterModelV1::for_routing_model(HashMap::new(), "Arch-Router".to_string());
+        assert_eq!(
+            router.max_token_length,
+            32_768 - ROUTING_RESPONSE_TOKEN_RESERVE
+        );
+        // explicit lengths win over the model's window
+        let router = RouterModelV1::new(HashMap::new(), "Arch-Router".to_string(), 1000);
+        assert_eq!(router.max_token_length, 1000);
+    }
+
+    #[test]
+    fn test_parse_response_bare_route_name() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image", "aliases": ["drawing"]}
+            ],
+            "claude-3-7-sonnet": [
+              {"name": "route1", "description": "generating new code snippets"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), 2000);
+
+        let result = router.parse_response("route1", &None).unwrap().route;
+        assert_eq!(
+            result,
+            Some(("route1".to_string(), "claude-3-7-sonnet".to_string()))
+        );
+        let result = router
+            .parse_response("<think>code it is</think>\n Image generation \n", &None)
+            .unwrap()
+            .route;
+        assert_eq!(
+            result,
+            Some(("Image generation".to_string(), "gpt-4o".to_string()))
+        );
+        let result = router.parse_response("drawing", &None).unwrap().route;
         assert_eq!(
             result,
             Some(("Image generation".to_string(), "gpt-4o".to_string()))
         );
+        assert!(router
+            .parse_response_ranked("route1", &None)
+            .unwrap()
+            .contains(&("route1".to_string(), "claude-3-7-sonnet".to_string())));
+
+        // unknown names and names within prose are not routes
+        let result = router.parse_response("route2", &None).unwrap().route;
+        assert_eq!(result, None);
+        let result = router.parse_response("use route1", &None).unwrap().route;
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_parse_response_after_reasoning() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+
+        let router = RouterModelV1::new(llm_routes, "test-model".to_string(), 2000);
+        let expected = Some(("Image generation".to_string(), "gpt-4o".to_string()));
+
+        let input = r#"<think>
+The user wants a picture. It can't be {"route": "other"} since the image route matches.
+</think>
+{"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // closing tag without the opening one
+        let input = r#"the user's request {is} about images</think>{"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // plain reasoning prose before the answer
+        let input = r#"Let's see, {"route": "other"} would be wrong because the user's asking for a drawing.
+So the answer is:
+{"route": "Image generation"}"#;
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, expected);
+
+        // nothing but reasoning
+        let input = "<think>maybe {route}</think>I am not sure";
+        let result = router.parse_response(input, &None).unwrap().route;
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_parse_response_ranked() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ],
+            "claude-3-7-sonnet": [
+              {"name": "code-generation", "description": "generating code"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(2000)
+            .ranked_routes(true)
+            .build()
+            .unwrap();
+
+        // duplicates are removed preserving order
+        let input = r#"{"routes": ["code-generation", "Image generation", "code-generation"]}"#;
+        let result = router.parse_response_ranked(input, &None).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (
+                    "code-generation".to_string(),
+                    "claude-3-7-sonnet".to_string()
+                ),
+                ("Image generation".to_string(), "gpt-4o".to_string()),
+            ]
+        );
+
+        // single route responses still work with parse_response returning the top route
+        let result = router.parse_response(input, &None).unwrap();
+        assert_eq!(
+            result.route,
+            Some((
+                "code-generation".to_string(),
+                "claude-3-7-sonnet".to_string()
+            ))
+        );
+
+        let input = r#"{"route": "Image generation"}"#;
+        let result = router.parse_response_ranked(input, &None).unwrap();
+        assert_eq!(
+            result,
+            vec![("Image generation".to_string(), "gpt-4o".to_string())]
+        );
+
+        // empty ranking maps to no route
+        let input = r#"{"routes": []}"#;
+        let result = router.parse_response_ranked(input, &None).unwrap();
+        assert!(result.is_empty());
+        let result = router.parse_response(input, &None).unwrap();
+        assert_eq!(result.route, None);
+    }
+
+    #[test]
+    fn test_ranked_routes_prompt() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .ranked_routes(true)
+            .build()
+            .unwrap();
+
+        let conversation: Vec<Message> = vec![Message::new("hi".to_string())];
+        let req = router.generate_request(&conversation, &None);
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+
+        assert!(prompt.ends_with(ARCH_ROUTER_V1_RANKED_ROUTES_PROMPT));
+    }
+
+    #[test]
+    fn test_confidence_prompt() {
+        let routes_str = r#"
+          {
+            "gpt-4o": [
+              {"name": "Image generation", "description": "generating image"}
+            ]
+        }
+        "#;
+        let llm_routes =
+            serde_json::from_str::<HashMap<String, Vec<RoutingPreference>>>(routes_str).unwrap();
+        let router = RouterModelV1::builder(llm_routes, "test-model".to_string())
+            .max_token_length(usize::MAX)
+            .ranked_routes(true)
+            .confidence(true)
+            .build()
+            .unwrap();
+
+        let conversation: Vec<Message> = vec![Message::new("hi".to_string())];
+        let req = router.generate_request(&conversation, &None);
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+
+        assert!(prompt.ends_with(&format!(
+            "{}{}",
+            ARCH_ROUTER_V1_RANKED_ROUTES_PROMPT, ARCH_ROUTER_V1_CONFIDENCE_PROMPT
+        )));
+
+        let result = router
+            .parse_response(
+                r#"{"routes": ["Image generation"], "confidence": 0.4}"#,
+                &None,
+            )
+            .unwrap();
+        assert_eq!(result.route_name(), Some("Image generation"));
+        assert_eq!(result.confidence, Some(0.4));
     }
 }
@@ -0,0 +1,355 @@
+use common::{configuration::ModelUsagePreference, consts::USER_ROLE};
+use hermesllm::providers::openai::types::{ChatCompletionsRequest, ContentType, Message};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::router_model::{RouteDecision, RouterModel, RoutingModelError};
+use super::router_model_v1::{
+    parse_llm_router_response, ranked_route_names, trim_conversation, LlmRouterResponse,
+    TOKEN_LENGTH_DIVISOR,
+};
+
+pub const ARCH_ROUTER_V2_SYSTEM_PROMPT: &str = r#"
+You are a helpful assistant designed to find the best suited route.
+You are provided with route definitions within <routes></routes> XML tags. Each route has a name, a description and example user requests that belong to the route:
+<routes>
+{routes}
+</routes>
+
+<conversation>
+{conversation}
+</conversation>
+
+Your task is to decide which route is best suit with user intent on the conversation in <conversation></conversation> XML tags.  Follow the instruction:
+1. If the latest intent from user is irrelevant or user intent is full filled, response with other route {"route": "other"}.
+2. You must analyze the route descriptions and examples and find the best match route for user latest intent.
+3. You only response the name of the route that best matches the user's request, use the exact name in the <routes></routes>.
+
+Based on your analysis, provide your response in the following JSON formats if you decide to match any route:
+{"route": "route_name"}
+"#;
+
+pub type Result<T> = std::result::Result<T, RoutingModelError>;
+
+/// Typed route definition for [`RouterModelV2`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteSpec {
+    pub name: String,
+    pub description: String,
+    /// Example user requests for the route, rendered as few-shot examples.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+    /// Model that serves the route, never shown to the routing model.
+    #[serde(skip_serializing)]
+    pub model: String,
+}
+
+pub struct RouterModelV2 {
+    routes: Vec<RouteSpec>,
+    routes_str: String,
+    routing_model: String,
+    max_token_length: usize,
+}
+
+impl RouterModelV2 {
+    pub fn new(routes: Vec<RouteSpec>, routing_model: String, max_token_length: usize) -> Self {
+        let routes_str = render_routes(&routes);
+        RouterModelV2 {
+            routes,
+            routes_str,
+            routing_model,
+            max_token_length,
+        }
+    }
+
+    fn resolve_routes(
+        &self,
+        router_response: &LlmRouterResponse,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<(String, String)> {
+        ranked_route_names(router_response)
+            .into_iter()
+            .filter_map(|route| {
+                let model = match usage_preferences {
+                    Some(usage_preferences) => usage_preferences
+                        .iter()
+                        .find(|pref| pref.routing_preferences.iter().any(|p| p.name == route))
+                        .map(|pref| pref.model.clone()),
+                    None => self
+                        .routes
+                        .iter()
+                        .find(|spec| spec.name == route)
+                        .map(|spec| spec.model.clone()),
+                };
+                if model.is_none() {
+                    warn!("RouterModelV2: no model found for route: {}", route);
+                }
+                model.map(|model| (route, model))
+            })
+            .collect()
+    }
+}
+
+/// Renders the routes in the order they were configured so that the prompt is deterministic.
+fn render_routes(routes: &[RouteSpec]) -> String {
+    serde_json::to_string(routes).unwrap_or_else(|_| "[]".to_string())
+}
+
+fn usage_preferences_to_route_specs(usage_preferences: &[ModelUsagePreference]) -> Vec<RouteSpec> {
+    usage_preferences
+        .iter()
+        .flat_map(|pref| {
+            pref.routing_preferences
+                .iter()
+                .map(|routing_pref| RouteSpec {
+                    name: routing_pref.name.clone(),
+                    description: routing_pref.description.clone(),
+                    examples: vec![],
+                    model: pref.model.clone(),
+                })
+        })
+        .collect()
+}
+
+impl RouterModel for RouterModelV2 {
+    fn generate_request(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> ChatCompletionsRequest {
+        // If preferences are passed in request then we use them otherwise we use the configured routes.
+        let routes_str = match usage_preferences {
+            Some(usage_preferences) => {
+                render_routes(&usage_preferences_to_route_specs(usage_preferences))
+            }
+            None => self.routes_str.clone(),
+        };
+
+        let selected_conversation_list = self.select_conversation(messages, usage_preferences);
+
+        let router_message = ARCH_ROUTER_V2_SYSTEM_PROMPT
+            .replace("{routes}", &routes_str)
+            .replace(
+                "{conversation}",
+                &serde_json::to_string(&selected_conversation_list).unwrap_or_default(),
+            );
+
+        ChatCompletionsRequest {
+            model: self.routing_model.clone(),
+            messages: vec![Message {
+                content: Some(ContentType::Text(router_message)),
+                role: USER_ROLE.to_string(),
+                ..Default::default()
+            }],
+            temperature: Some(0.01),
+            ..Default::default()
+        }
+    }
+
+    fn select_conversation(
+        &self,
+        messages: &[Message],
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<Message> {
+        let routes_len = match usage_preferences {
+            Some(usage_preferences) => {
+                render_routes(&usage_preferences_to_route_specs(usage_preferences)).len()
+            }
+            None => self.routes_str.len(),
+        };
+
+        // route examples can be large so they count toward the token budget
+        let base_token_count =
+            (ARCH_ROUTER_V2_SYSTEM_PROMPT.len() + routes_len) / TOKEN_LENGTH_DIVISOR;
+        trim_conversation(
+            messages,
+            self.max_token_length,
+            base_token_count,
+            false,
+            None,
+            false,
+            |text| text.len() / TOKEN_LENGTH_DIVISOR,
+        )
+    }
+
+    fn parse_response(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<RouteDecision> {
+        if content.is_empty() {
+            return Ok(RouteDecision::default());
+        }
+        let router_response = match parse_llm_router_response(content)? {
+            Some(router_response) => router_response,
+            None => return Ok(RouteDecision::default()),
+        };
+
+        Ok(RouteDecision {
+            route: self
+                .resolve_routes(&router_response, usage_preferences)
+                .into_iter()
+                .next(),
+            confidence: router_response.confidence,
+        })
+    }
+
+    fn parse_response_ranked(
+        &self,
+        content: &str,
+        usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Result<Vec<(String, String)>> {
+        if content.is_empty() {
+            return Ok(vec![]);
+        }
+        let router_response = match parse_llm_router_response(content)? {
+            Some(router_response) => router_response,
+            None => return Ok(vec![]),
+        };
+
+        Ok(self.resolve_routes(&router_response, usage_preferences))
+    }
+
+    fn answered_routes(
+        &self,
+        content: &str,
+        _usage_preferences: &Option<Vec<ModelUsagePreference>>,
+    ) -> Vec<String> {
+        match parse_llm_router_response(content) {
+            Ok(Some(router_response)) => ranked_route_names(&router_response),
+            _ => vec![],
+        }
+    }
+
+    fn get_model_name(&self) -> String {
+        self.routing_model.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::configuration::RoutingPreference;
+    use pretty_assertions::assert_eq;
+
+    fn route_specs() -> Vec<RouteSpec> {
+        vec![
+            RouteSpec {
+                name: "code-generation".to_string(),
+                description: "generating new code snippets".to_string(),
+                examples: vec![
+                    "write a function that reverses a string".to_string(),
+                    "create a python script to rename files".to_string(),
+                ],
+                model: "claude-3-7-sonnet".to_string(),
+            },
+            RouteSpec {
+                name: "image-generation".to_string(),
+                description: "generating image".to_string(),
+                examples: vec![],
+                model: "gpt-4o".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_system_prompt_format() {
+        let expected_prompt = r#"
+You are a helpful assistant designed to find the best suited route.
+You are provided with route definitions within <routes></routes> XML tags. Each route has a name, a description and example user requests that belong to the route:
+<routes>
+[{"name":"code-generation","description":"generating new code snippets","examples":["write a function that reverses a string","create a python script to rename files"]},{"name":"image-generation","description":"generating image"}]
+</routes>
+
+<conversation>
+[{"role":"user","content":"hi"},{"role":"assistant","content":"Hello! How can I assist you today?"},{"role":"user","content":"write me a function to sort a list"}]
+</conversation>
+
+Your task is to decide which route is best suit with user intent on the conversation in <conversation></conversation> XML tags.  Follow the instruction:
+1. If the latest intent from user is irrelevant or user intent is full filled, response with other route {"route": "other"}.
+2. You must analyze the route descriptions and examples and find the best match route for user latest intent.
+3. You only response the name of the route that best matches the user's request, use the exact name in the <routes></routes>.
+
+Based on your analysis, provide your response in the following JSON formats if you decide to match any route:
+{"route": "route_name"}
+"#;
+        let router = RouterModelV2::new(route_specs(), "test-model".to_string(), usize::MAX);
+
+        let conversation_str = r#"
+                    [
+                        {
+                            "role": "user",
+                            "content": "hi"
+                        },
+                        {
+                            "role": "assistant",
+                            "content": "Hello! How can I assist you today?"
+                        },
+                        {
+                            "role": "user",
+                            "content": "write me a function to sort a list"
+                        }
+                    ]
+        "#;
+        let conversation: Vec<Message> = serde_json::from_str(conversation_str).unwrap();
+
+        let req = router.generate_request(&conversation, &None);
+
+        let prompt = req.messages[0].content.as_ref().unwrap();
+
+        assert_eq!(expected_prompt, prompt.to_string());
+        assert_eq!(req.model, "test-model");
+    }
+
+    #[test]
+    fn test_usage_preferences_override_routes() {
+        let router = RouterModelV2::new(route_specs(), "test-model".to_string(), usize::MAX);
+
+        let usage_preferences = Some(vec![ModelUsagePreference {
+            model: "gpt-4o-mini".to_string(),
+            routing_preferences: vec![RoutingPreference {
+                name: "summarization".to_string(),
+                description: "summarizing documents".to_string(),
+                ..Default::default()
+            }],
+        }]);
+        let conversation = vec![Message::new("summarize this".to_string())];
+
+        let req = router.generate_request(&conversation, &usage_preferences);
+        let prompt = req.messages[0].content.as_ref().unwrap().to_string();
+        assert!(
+            prompt.contains(r#"[{"name":"summarization","description":"summarizing documents"}]"#)
+        );
+
+        let result = router
+            .parse_response(r#"{"route": "summarization"}"#, &usage_preferences)
+            .unwrap();
+        assert_eq!(
+            result.route,
+            Some(("summarization".to_string(), "gpt-4o-mini".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let router = RouterModelV2::new(route_specs(), "test-model".to_string(), usize::MAX);
+
+        let result = router
+            .parse_response(r#"{"route": "image-generation"}"#, &None)
+            .unwrap();
+        assert_eq!(
+            result.route,
+            Some(("image-generation".to_string(), "gpt-4o".to_string()))
+        );
+
+        let result = router
+            .parse_response(r#"{"route": "other"}"#, &None)
+            .unwrap();
+        assert_eq!(result.route, None);
+
+        let result = router
+            .parse_response(r#"{"route": "unknown"}"#, &None)
+            .unwrap();
+        assert_eq!(result.route, None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use hermesllm::providers::openai::types::ChatCompletionsRequest;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::handlers::request_log::redacted_request;
+
+use super::router_model::RouteDecision;
+
+/// What the routing model was asked and answered for a request, with the route chosen from its
+/// answer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RoutingLogRecord {
+    pub routing_model: String,
+    /// Request sent to the routing model, with message content cut when redacting.
+    pub prompt: String,
+    /// Content of the routing model's answer.
+    pub response: String,
+    pub route: Option<String>,
+    pub model: Option<String>,
+    pub confidence: Option<f32>,
+}
+
+/// Destination of the routing log records.
+pub trait RoutingLogSink: Send + Sync {
+    fn record(&self, record: &RoutingLogRecord);
+}
+
+/// Callbacks are sinks, e.g. to hand records over to a collector of their own.
+impl<F> RoutingLogSink for F
+where
+    F: Fn(&RoutingLogRecord) + Send + Sync,
+{
+    fn record(&self, record: &RoutingLogRecord) {
+        self(record)
+    }
+}
+
+/// Appends records to a file, one JSON object per line.
+pub struct FileRoutingLogSink {
+    file: Mutex<File>,
+}
+
+impl FileRoutingLogSink {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileRoutingLogSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl RoutingLogSink for FileRoutingLogSink {
+    fn record(&self, record: &RoutingLogRecord) {
+        let mut line = serde_json::to_vec(record).unwrap();
+        line.push(b'\n');
+        if let Err(err) = self.file.lock().unwrap().write_all(&line) {
+            warn!("failed to write routing log record: {}", err);
+        }
+    }
+}
+
+/// Records the routing calls of a sampled share of the requests to a sink.
+#[derive(Clone)]
+pub struct RoutingLog {
+    sink: Arc<dyn RoutingLogSink>,
+    sample_rate: f64,
+    content_max_chars: Option<usize>,
+}
+
+impl RoutingLog {
+    /// `sample_rate` is the share of the routing calls recorded, between 0 and 1.
+    pub fn new(sink: Arc<dyn RoutingLogSink>, sample_rate: f64) -> Self {
+        RoutingLog {
+            sink,
+            sample_rate,
+            content_max_chars: None,
+        }
+    }
+
+    /// Cuts the message content of recorded prompts to `content_max_chars` characters, prompts
+    /// are recorded whole when not set.
+    pub fn with_content_max_chars(mut self, content_max_chars: Option<usize>) -> Self {
+        self.content_max_chars = content_max_chars;
+        self
+    }
+
+    /// Whether the current routing call is recorded.
+    pub fn sampled(&self) -> bool {
+        rand::random::<f64>() < self.sample_rate
+    }
+
+    pub fn record(
+        &self,
+        routing_model: &str,
+        router_request: &ChatCompletionsRequest,
+        response: &str,
+        route_decision: &RouteDecision,
+    ) {
+        let prompt = match self.content_max_chars {
+            Some(max_chars) => redacted_request(router_request, max_chars),
+            None => serde_json::to_string(router_request).unwrap_or_default(),
+        };
+        let (route, model) = match &route_decision.route {
+            Some((route, model)) => (Some(route.clone()), Some(model.clone())),
+            None => (None, None),
+        };
+        self.sink.record(&RoutingLogRecord {
+            routing_model: routing_model.to_string(),
+            prompt,
+            response: response.to_string(),
+            route,
+            model,
+            confidence: route_decision.confidence,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_sink_appends_lines() {
+        let path =
+            std::env::temp_dir().join(format!("archgw-routing-log-{}.jsonl", std::process::id()));
+        let sink = FileRoutingLogSink::open(&path).unwrap();
+        let record = RoutingLogRecord {
+            routing_model: "Arch-Router".to_string(),
+            prompt: "{}".to_string(),
+            response: r#"{"route": "support"}"#.to_string(),
+            route: Some("support".to_string()),
+            model: Some("gpt-4o-mini".to_string()),
+            confidence: None,
+        };
+        sink.record(&record);
+        sink.record(&record);
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["route"], "support");
+        assert_eq!(lines[1]["response"], r#"{"route": "support"}"#);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use hermesllm::providers::openai::types::{ChatCompletionsRequest, Message};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Stable key of a request or a conversation, the first 128 bits of the SHA-256 of its
+/// normalized form. Unlike `DefaultHasher` the key is the same in every process and release, so
+/// it may be stored or shared between gateways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CacheKey(u128);
+
+impl CacheKey {
+    fn of(normalized: &Value) -> Self {
+        // maps of serde_json values are sorted by key, the encoding doesn't depend on the
+        // order the fields were sent in
+        let digest = Sha256::digest(normalized.to_string().as_bytes());
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        CacheKey(u128::from_be_bytes(bytes))
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    /// The first 64 bits of the key.
+    pub fn as_u64(&self) -> u64 {
+        (self.0 >> 64) as u64
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// Key of what the upstream answers on: the model, the messages as keyed by
+/// `normalize_and_hash_messages`, the sampling parameters, the stop sequences, the tools, the
+/// response format and the documents. How the answer is delivered, `stream` and `stream_options`, and the
+/// `metadata` of the caller are left out.
+///
+/// The stop sequences are a set, their order and duplicates don't change the key. Neither does
+/// the order of the fields of objects, e.g. of a tool's parameters or a response schema.
+pub fn normalize_and_hash(request: &ChatCompletionsRequest) -> CacheKey {
+    let mut stop = request.stop.clone();
+    if let Some(stop) = stop.as_mut() {
+        stop.sort();
+        stop.dedup();
+    }
+    let normalized = ChatCompletionsRequest {
+        messages: Vec::new(),
+        stream: None,
+        stream_options: None,
+        metadata: None,
+        stop,
+        ..request.clone()
+    };
+    let mut normalized = serde_json::to_value(normalized).unwrap_or_default();
+    if let Some(fields) = normalized.as_object_mut() {
+        fields.insert(
+            "messages".to_string(),
+            normalize_messages(&request.messages),
+        );
+    }
+    CacheKey::of(&normalized)
+}
+
+/// Key of a conversation: the role, content, tool calls and tool call id of each message, in
+/// order. The content is keyed as sent, whitespace included. The arguments of tool calls are
+/// keyed by their JSON value, their formatting and field order don't change the key.
+pub fn normalize_and_hash_messages(messages: &[Message]) -> CacheKey {
+    CacheKey::of(&normalize_messages(messages))
+}
+
+fn normalize_messages(messages: &[Message]) -> Value {
+    Value::Array(messages.iter().map(normalize_message).collect())
+}
+
+fn normalize_message(message: &Message) -> Value {
+    let mut normalized = serde_json::to_value(message).unwrap_or_default();
+    let tool_calls = normalized
+        .get_mut("tool_calls")
+        .and_then(Value::as_array_mut);
+    for tool_call in tool_calls.into_iter().flatten() {
+        let arguments = tool_call.pointer_mut("/function/arguments");
+        if let Some(arguments) = arguments {
+            let parsed = arguments
+                .as_str()
+                .and_then(|encoded| serde_json::from_str::<Value>(encoded).ok());
+            if let Some(parsed) = parsed {
+                *arguments = parsed;
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hermesllm::providers::openai::types::{FunctionCall, ToolCall, ToolType};
+
+    fn request(body: &str) -> ChatCompletionsRequest {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn test_field_order_does_not_change_the_key() {
+        let key = normalize_and_hash(&request(
+            r#"{"model": "gpt-4o", "temperature": 0, "stop": ["\n", "END"],
+                "messages": [{"role": "user", "content": "classify this"}],
+                "tools": [{"type": "function", "function": {"name": "classify",
+                    "parameters": {"type": "object", "properties": {"label": {"type": "string"}}}}}]}"#,
+        ));
+        let reordered = request(
+            r#"{"tools": [{"function": {"parameters": {"properties": {"label": {"type": "string"}},
+                    "type": "object"}, "name": "classify"}, "type": "function"}],
+                "messages": [{"content": "classify this", "role": "user"}],
+                "stop": ["END", "\n", "END"], "temperature": 0, "model": "gpt-4o"}"#,
+        );
+        assert_eq!(normalize_and_hash(&reordered), key);
+
+        // delivery and caller metadata are left out
+        let streamed = request(
+            r#"{"model": "gpt-4o", "temperature": 0, "stop": ["\n", "END"], "stream": true,
+                "stream_options": {"include_usage": true}, "metadata": {"team": "search"},
+                "messages": [{"role": "user", "content": "classify this"}],
+                "tools": [{"type": "function", "function": {"name": "classify",
+                    "parameters": {"type": "object", "properties": {"label": {"type": "string"}}}}}]}"#,
+        );
+        assert_eq!(normalize_and_hash(&streamed), key);
+    }
+
+    #[test]
+    fn test_content_and_parameters_change_the_key() {
+        let base = request(
+            r#"{"model": "gpt-4o", "messages": [{"role": "user", "content": "classify this"}]}"#,
+        );
+        let key = normalize_and_hash(&base);
+
+        let content = request(
+            r#"{"model": "gpt-4o", "messages": [{"role": "user", "content": "classify that"}]}"#,
+        );
+        assert_ne!(normalize_and_hash(&content), key);
+        let role = request(
+            r#"{"model": "gpt-4o", "messages": [{"role": "system", "content": "classify this"}]}"#,
+        );
+        assert_ne!(normalize_and_hash(&role), key);
+        let model = ChatCompletionsRequest {
+            model: "claude-3-7-sonnet".to_string(),
+            ..base.clone()
+        };
+        assert_ne!(normalize_and_hash(&model), key);
+        let max_tokens = ChatCompletionsRequest {
+            max_tokens: Some(10),
+            ..base.clone()
+        };
+        assert_ne!(normalize_and_hash(&max_tokens), key);
+    }
+
+    #[test]
+    fn test_message_keys() {
+        let tool_call = |arguments: &str| Message {
+            role: "assistant".to_string(),
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".to_string(),
+                tool_type: ToolType::Function,
+                function: FunctionCall {
+                    name: "get_weather".to_string(),
+                    arguments: arguments.to_string(),
+                },
+            }]),
+            ..Default::default()
+        };
+        let messages = vec![
+            Message::new("what's the weather in Paris?".to_string()),
+            tool_call(r#"{"city": "Paris", "unit": "celsius"}"#),
+        ];
+        let key = normalize_and_hash_messages(&messages);
+
+        // the arguments are keyed by their value
+        let reformatted = vec![
+            Message::new("what's the weather in Paris?".to_string()),
+            tool_call(r#"{"unit":"celsius","city":"Paris"}"#),
+        ];
+        assert_eq!(normalize_and_hash_messages(&reformatted), key);
+
+        let other_city = vec![
+            Message::new("what's the weather in Paris?".to_string()),
+            tool_call(r#"{"city": "Lyon", "unit": "celsius"}"#),
+        ];
+        assert_ne!(normalize_and_hash_messages(&other_city), key);
+        let other_question = vec![
+            Message::new("what's the weather in Lyon?".to_string()),
+            tool_call(r#"{"city": "Paris", "unit": "celsius"}"#),
+        ];
+        assert_ne!(normalize_and_hash_messages(&other_question), key);
+        // the order of the messages counts
+        let reversed: Vec<Message> = messages.iter().rev().cloned().collect();
+        assert_ne!(normalize_and_hash_messages(&reversed), key);
+    }
+
+    #[test]
+    fn test_key_is_stable() {
+        let key = normalize_and_hash_messages(&[Message::new("hello".to_string())]);
+        // the key must not change between releases, stored keys would no longer match
+        assert_eq!(key.to_string().len(), 32);
+        assert_eq!(key.as_u64(), (key.as_u128() >> 64) as u64);
+        let digest = Sha256::digest(br#"[{"content":"hello","role":"user"}]"#);
+        assert_eq!(key.to_string(), hex::encode(&digest[..16]));
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use common::configuration::CircuitBreaker;
+use common::consts::{
+    DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS, DEFAULT_CIRCUIT_BREAKER_FAILURE_RATIO,
+    DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS, DEFAULT_CIRCUIT_BREAKER_WINDOW_SIZE,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerPolicy {
+    pub failure_ratio: f64,
+    pub min_requests: usize,
+    pub window_size: usize,
+    pub cooldown: Duration,
+}
+
+impl CircuitBreakerPolicy {
+    pub fn from_config(circuit_breaker: &CircuitBreaker) -> Self {
+        let window_size = circuit_breaker
+            .window_size
+            .unwrap_or(DEFAULT_CIRCUIT_BREAKER_WINDOW_SIZE)
+            .max(1) as usize;
+        CircuitBreakerPolicy {
+            failure_ratio: circuit_breaker
+                .failure_ratio
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_FAILURE_RATIO),
+            min_requests: (circuit_breaker
+                .min_requests
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS)
+                .max(1) as usize)
+                .min(window_size),
+            window_size,
+            cooldown: Duration::from_millis(
+                circuit_breaker
+                    .cooldown_ms
+                    .unwrap_or(DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are sent, their outcomes are counted.
+    Closed,
+    /// Requests are rejected until the cooldown passed.
+    Open { until: Instant },
+    /// A single probe request was let through, its outcome closes or reopens the circuit.
+    HalfOpen { since: Instant },
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: CircuitState,
+    /// Outcomes of the most recent requests, `true` for failures.
+    outcomes: VecDeque<bool>,
+}
+
+/// Rejection of a request to a provider whose circuit is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+/// Circuit breaker per provider. A provider whose share of failed requests in the window
+/// reaches the failure ratio is not called until the cooldown passed, then a single probe
+/// request decides whether it is called again. Without a policy every request is let through.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    policy: Option<CircuitBreakerPolicy>,
+    breakers: Mutex<HashMap<String, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn from_config(circuit_breaker: Option<&CircuitBreaker>) -> Self {
+        CircuitBreakers {
+            policy: circuit_breaker.map(CircuitBreakerPolicy::from_config),
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request may be sent to `provider`. Every allowed request has to be followed
+    /// by a call to `record` with its outcome.
+    pub fn allow(&self, provider: &str) -> Result<(), CircuitOpen> {
+        self.allow_at(provider, Instant::now())
+    }
+
+    pub fn record(&self, provider: &str, success: bool) {
+        self.record_at(provider, success, Instant::now());
+    }
+
+    pub fn state(&self, provider: &str) -> CircuitState {
+        self.breakers
+            .lock()
+            .unwrap()
+            .get(provider)
+            .map_or(CircuitState::Closed, |breaker| breaker.state)
+    }
+
+    fn allow_at(&self, provider: &str, now: Instant) -> Result<(), CircuitOpen> {
+        let policy = match self.policy.as_ref() {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = match breakers.get_mut(provider) {
+            Some(breaker) => breaker,
+            None => return Ok(()),
+        };
+
+        match breaker.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open { until } if now < until => Err(CircuitOpen {
+                retry_after: until - now,
+            }),
+            // a probe that never reported back does not keep the circuit half open forever
+            CircuitState::HalfOpen { since } if now < since + policy.cooldown => Err(CircuitOpen {
+                retry_after: since + policy.cooldown - now,
+            }),
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => {
+                breaker.state = CircuitState::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    fn record_at(&self, provider: &str, success: bool, now: Instant) {
+        let policy = match self.policy.as_ref() {
+            Some(policy) => policy,
+            None => return,
+        };
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers
+            .entry(provider.to_string())
+            .or_insert_with(|| Breaker {
+                state: CircuitState::Closed,
+                outcomes: VecDeque::with_capacity(policy.window_size),
+            });
+
+        match breaker.state {
+            CircuitState::HalfOpen { .. } if success => {
+                breaker.state = CircuitState::Closed;
+                breaker.outcomes.clear();
+            }
+            CircuitState::HalfOpen { .. } => {
+                breaker.state = CircuitState::Open {
+                    until: now + policy.cooldown,
+                };
+            }
+            // responses of requests sent before the circuit opened
+            CircuitState::Open { .. } => {}
+            CircuitState::Closed => {
+                if breaker.outcomes.len() == policy.window_size {
+                    breaker.outcomes.pop_front();
+                }
+                breaker.outcomes.push_back(!success);
+
+                let failures = breaker.outcomes.iter().filter(|failed| **failed).count();
+                if breaker.outcomes.len() >= policy.min_requests
+                    && failures as f64 >= policy.failure_ratio * breaker.outcomes.len() as f64
+                {
+                    breaker.state = CircuitState::Open {
+                        until: now + policy.cooldown,
+                    };
+                    breaker.outcomes.clear();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit_breakers() -> CircuitBreakers {
+        CircuitBreakers::from_config(Some(&CircuitBreaker {
+            failure_ratio: Some(0.5),
+            min_requests: Some(4),
+            window_size: Some(4),
+            cooldown_ms: Some(1000),
+        }))
+    }
+
+    #[test]
+    fn test_opens_on_failure_ratio() {
+        let breakers = circuit_breakers();
+        let now = Instant::now();
+
+        breakers.record_at("gpt-4o", true, now);
+        breakers.record_at("gpt-4o", false, now);
+        breakers.record_at("gpt-4o", true, now);
+        assert_eq!(breakers.state("gpt-4o"), CircuitState::Closed);
+
+        // two failures out of the last four requests
+        breakers.record_at("gpt-4o", false, now);
+        assert_eq!(
+            breakers.state("gpt-4o"),
+            CircuitState::Open {
+                until: now + Duration::from_secs(1)
+            }
+        );
+        let open = breakers.allow_at("gpt-4o", now).unwrap_err();
+        assert_eq!(open.retry_after, Duration::from_secs(1));
+
+        // other providers are not affected
+        assert!(breakers.allow_at("claude-3-7-sonnet", now).is_ok());
+    }
+
+    #[test]
+    fn test_half_open_probe() {
+        let breakers = circuit_breakers();
+        let now = Instant::now();
+        for _ in 0..4 {
+            breakers.record_at("gpt-4o", false, now);
+        }
+        assert!(breakers.allow_at("gpt-4o", now).is_err());
+
+        // after the cooldown a single probe is let through
+        let probe = now + Duration::from_secs(1);
+        assert!(breakers.allow_at("gpt-4o", probe).is_ok());
+        assert_eq!(
+            breakers.state("gpt-4o"),
+            CircuitState::HalfOpen { since: probe }
+        );
+        assert!(breakers.allow_at("gpt-4o", probe).is_err());
+
+        // a failed probe opens the circuit again
+        breakers.record_at("gpt-4o", false, probe);
+        assert!(breakers.allow_at("gpt-4o", probe).is_err());
+
+        // a successful probe closes it
+        let probe = probe + Duration::from_secs(1);
+        assert!(breakers.allow_at("gpt-4o", probe).is_ok());
+        breakers.record_at("gpt-4o", true, probe);
+        assert_eq!(breakers.state("gpt-4o"), CircuitState::Closed);
+        assert!(breakers.allow_at("gpt-4o", probe).is_ok());
+    }
+
+    #[test]
+    fn test_disabled_without_config() {
+        let breakers = CircuitBreakers::from_config(None);
+        let now = Instant::now();
+        for _ in 0..100 {
+            breakers.record_at("gpt-4o", false, now);
+        }
+        assert!(breakers.allow_at("gpt-4o", now).is_ok());
+        assert_eq!(breakers.state("gpt-4o"), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use common::configuration::Compression;
+use common::consts::DEFAULT_COMPRESSION_MIN_BYTES;
+use hyper::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING};
+use thiserror::Error;
+
+/// Content codings archgw encodes and decodes bodies with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error("unsupported content encoding: {0}")]
+    Unsupported(String),
+    #[error("decompressed body exceeds {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("invalid {encoding} body: {source}")]
+    Invalid {
+        encoding: &'static str,
+        source: std::io::Error,
+    },
+}
+
+/// Encoding of a request body from its `Content-Encoding`, None for identity bodies.
+pub fn request_encoding(headers: &HeaderMap) -> Result<Option<Encoding>, CompressionError> {
+    let content_encoding = match headers.get(CONTENT_ENCODING) {
+        Some(content_encoding) => content_encoding.to_str().unwrap_or_default().trim(),
+        None => return Ok(None),
+    };
+    if content_encoding.is_empty() || content_encoding.eq_ignore_ascii_case("identity") {
+        return Ok(None);
+    }
+    Encoding::from_name(content_encoding)
+        .map(Some)
+        .ok_or_else(|| CompressionError::Unsupported(content_encoding.to_string()))
+}
+
+/// Decodes `body`, failing once the decoded body grows past `limit` bytes so that a small
+/// compressed body can't expand without bounds.
+pub fn decompress(
+    encoding: Encoding,
+    body: &[u8],
+    limit: usize,
+) -> Result<Bytes, CompressionError> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        Encoding::Gzip => Box::new(flate2::read::GzDecoder::new(body)),
+        Encoding::Brotli => Box::new(brotli::Decompressor::new(body, 4096)),
+    };
+    let mut decompressed = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|source| CompressionError::Invalid {
+            encoding: encoding.name(),
+            source,
+        })?;
+    if decompressed.len() > limit {
+        return Err(CompressionError::TooLarge { limit });
+    }
+    Ok(Bytes::from(decompressed))
+}
+
+pub fn compress(encoding: Encoding, body: &[u8]) -> std::io::Result<Bytes> {
+    let compressed = match encoding {
+        Encoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        Encoding::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                encoder.write_all(body)?;
+            }
+            compressed
+        }
+    };
+    Ok(Bytes::from(compressed))
+}
+
+/// Compression of the non streaming responses sent to clients, negotiated with their
+/// `Accept-Encoding`.
+#[derive(Debug, Clone)]
+pub struct ResponseCompression {
+    enabled: bool,
+    min_bytes: usize,
+}
+
+impl Default for ResponseCompression {
+    fn default() -> Self {
+        ResponseCompression::from_config(None)
+    }
+}
+
+impl ResponseCompression {
+    pub fn from_config(compression: Option<&Compression>) -> Self {
+        ResponseCompression {
+            enabled: compression
+                .and_then(|compression| compression.enabled)
+                .unwrap_or(true),
+            min_bytes: compression
+                .and_then(|compression| compression.min_bytes)
+                .unwrap_or(DEFAULT_COMPRESSION_MIN_BYTES),
+        }
+    }
+
+    /// The encoding the client prefers among those supported, brotli on a tie.
+    pub fn negotiate(&self, headers: &HeaderMap) -> Option<Encoding> {
+        if !self.enabled {
+            return None;
+        }
+        let mut best: Option<(Encoding, f32)> = None;
+        for accept_encoding in headers.get_all(ACCEPT_ENCODING) {
+            let accept_encoding = accept_encoding.to_str().unwrap_or_default();
+            for coding in accept_encoding.split(',') {
+                let mut parts = coding.split(';');
+                let name = parts.next().unwrap_or_default().trim();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                let encoding = match name {
+                    "*" => Some(Encoding::Gzip),
+                    name => Encoding::from_name(name),
+                };
+                let encoding = match encoding {
+                    Some(encoding) if quality > 0.0 => encoding,
+                    _ => continue,
+                };
+                let better = match best {
+                    None => true,
+                    Some((best_encoding, best_quality)) => {
+                        quality > best_quality
+                            || (quality == best_quality
+                                && encoding == Encoding::Brotli
+                                && best_encoding != Encoding::Brotli)
+                    }
+                };
+                if better {
+                    best = Some((encoding, quality));
+                }
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Whether a body of `len` bytes is worth compressing.
+    pub fn compresses(&self, len: usize) -> bool {
+        len >= self.min_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn accepting(accept_encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let compression = ResponseCompression::default();
+        assert_eq!(compression.negotiate(&HeaderMap::new()), None);
+        assert_eq!(
+            compression.negotiate(&accepting("gzip")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            compression.negotiate(&accepting("gzip, deflate, br")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            compression.negotiate(&accepting("br;q=0.5, gzip")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(compression.negotiate(&accepting("gzip;q=0, deflate")), None);
+        assert_eq!(compression.negotiate(&accepting("identity")), None);
+
+        let disabled = ResponseCompression::from_config(Some(&Compression {
+            enabled: Some(false),
+            min_bytes: None,
+        }));
+        assert_eq!(disabled.negotiate(&accepting("gzip")), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let body = r#"{"messages": [{"role": "user", "content": "hello"}]}"#.repeat(10);
+        for encoding in [Encoding::Gzip, Encoding::Brotli] {
+            let compressed = compress(encoding, body.as_bytes()).unwrap();
+            assert!(compressed.len() < body.len());
+            assert_eq!(
+                decompress(encoding, &compressed, body.len()).unwrap(),
+                body.as_bytes()
+            );
+            assert!(matches!(
+                decompress(encoding, &compressed, body.len() - 1),
+                Err(CompressionError::TooLarge { .. })
+            ));
+        }
+        assert!(matches!(
+            decompress(Encoding::Gzip, b"not gzip", 100),
+            Err(CompressionError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_request_encoding() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_encoding(&headers).unwrap(), None);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("GZIP"));
+        assert_eq!(request_encoding(&headers).unwrap(), Some(Encoding::Gzip));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+        assert!(matches!(
+            request_encoding(&headers),
+            Err(CompressionError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::configuration::Concurrency;
+use common::consts::DEFAULT_CONCURRENCY_QUEUE_TIMEOUT_MS;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Slots held by a request to a provider, released when dropped.
+#[derive(Debug)]
+pub struct UpstreamPermit {
+    _provider: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+/// Rejection of a request that could not get a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcurrencyLimited {
+    /// As many requests as allowed are waiting already.
+    QueueFull,
+    /// No slot became free within the queue timeout.
+    QueueTimeout,
+}
+
+/// Decrements the number of queued requests when a waiting request is done waiting.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Limits the requests in flight to the providers, in total and per provider. A request that
+/// finds no free slot waits in a bounded queue, or is rejected when the queue is full. Without
+/// limits every request gets a slot right away.
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimiter {
+    global: Option<Arc<Semaphore>>,
+    max_in_flight_per_provider: Option<usize>,
+    providers: Mutex<HashMap<String, Arc<Semaphore>>>,
+    max_queued: usize,
+    queued: AtomicUsize,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimiter {
+    pub fn from_config(concurrency: Option<&Concurrency>) -> Self {
+        let concurrency = match concurrency {
+            Some(concurrency) => concurrency,
+            None => return ConcurrencyLimiter::default(),
+        };
+        ConcurrencyLimiter {
+            global: concurrency
+                .max_in_flight
+                .map(|max_in_flight| Arc::new(Semaphore::new(max_in_flight.max(1)))),
+            max_in_flight_per_provider: concurrency
+                .max_in_flight_per_provider
+                .map(|max_in_flight| max_in_flight.max(1)),
+            providers: Mutex::new(HashMap::new()),
+            max_queued: concurrency.max_queued.unwrap_or(0),
+            queued: AtomicUsize::new(0),
+            queue_timeout: Duration::from_millis(
+                concurrency
+                    .queue_timeout_ms
+                    .unwrap_or(DEFAULT_CONCURRENCY_QUEUE_TIMEOUT_MS),
+            ),
+        }
+    }
+
+    /// Takes a slot for a request to `provider`, waiting in the queue when none is free. The
+    /// slot is held until the returned permit is dropped.
+    pub async fn acquire(&self, provider: &str) -> Result<UpstreamPermit, ConcurrencyLimited> {
+        let provider_semaphore = self.provider_semaphore(provider);
+        if let Some(permit) = self.try_acquire(provider_semaphore.as_ref()) {
+            return Ok(permit);
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(ConcurrencyLimited::QueueFull);
+        }
+        let _queue_slot = QueueSlot(&self.queued);
+
+        // the provider slot is taken first, always in the same order so waiting requests do
+        // not hold a global slot another provider could use
+        let acquire = async {
+            let provider = match provider_semaphore {
+                Some(semaphore) => Some(semaphore.acquire_owned().await.ok()?),
+                None => None,
+            };
+            let global = match self.global.as_ref() {
+                Some(semaphore) => Some(Arc::clone(semaphore).acquire_owned().await.ok()?),
+                None => None,
+            };
+            Some(UpstreamPermit {
+                _provider: provider,
+                _global: global,
+            })
+        };
+        match tokio::time::timeout(self.queue_timeout, acquire).await {
+            Ok(Some(permit)) => Ok(permit),
+            // the semaphores are never closed
+            Ok(None) | Err(_) => Err(ConcurrencyLimited::QueueTimeout),
+        }
+    }
+
+    fn provider_semaphore(&self, provider: &str) -> Option<Arc<Semaphore>> {
+        let max_in_flight = self.max_in_flight_per_provider?;
+        let mut providers = self.providers.lock().unwrap();
+        let semaphore = providers
+            .entry(provider.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(max_in_flight)));
+        Some(Arc::clone(semaphore))
+    }
+
+    fn try_acquire(&self, provider_semaphore: Option<&Arc<Semaphore>>) -> Option<UpstreamPermit> {
+        let provider = match provider_semaphore {
+            Some(semaphore) => Some(Arc::clone(semaphore).try_acquire_owned().ok()?),
+            None => None,
+        };
+        let global = match self.global.as_ref() {
+            Some(semaphore) => Some(Arc::clone(semaphore).try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(UpstreamPermit {
+            _provider: provider,
+            _global: global,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(
+        max_in_flight: Option<usize>,
+        max_in_flight_per_provider: Option<usize>,
+        max_queued: usize,
+    ) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::from_config(Some(&Concurrency {
+            max_in_flight,
+            max_in_flight_per_provider,
+            max_queued: Some(max_queued),
+            queue_timeout_ms: Some(50),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_request_over_limit_rejected_without_queue() {
+        let limiter = limiter(Some(2), None, 0);
+        let _first = limiter.acquire("gpt-4o").await.unwrap();
+        let second = limiter.acquire("claude-3-7-sonnet").await.unwrap();
+        assert_eq!(
+            limiter.acquire("gpt-4o").await.unwrap_err(),
+            ConcurrencyLimited::QueueFull
+        );
+
+        // a released slot is taken by the next request
+        drop(second);
+        assert!(limiter.acquire("gpt-4o").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_over_limit_queues() {
+        let limiter = Arc::new(limiter(Some(1), None, 1));
+        let first = limiter.acquire("gpt-4o").await.unwrap();
+
+        let queued = {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move { limiter.acquire("gpt-4o").await.map(|_| ()) })
+        };
+        while limiter.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        // the queue holds a single request
+        assert_eq!(
+            limiter.acquire("gpt-4o").await.unwrap_err(),
+            ConcurrencyLimited::QueueFull
+        );
+
+        drop(first);
+        assert!(queued.await.unwrap().is_ok());
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+
+        // nobody releases the slot in time
+        let _held = limiter.acquire("gpt-4o").await.unwrap();
+        assert_eq!(
+            limiter.acquire("gpt-4o").await.unwrap_err(),
+            ConcurrencyLimited::QueueTimeout
+        );
+    }
+
+    #[tokio::test]
+    async fn test_per_provider_limit() {
+        let limiter = limiter(None, Some(1), 0);
+        let _gpt = limiter.acquire("gpt-4o").await.unwrap();
+        assert!(limiter.acquire("gpt-4o").await.is_err());
+        // other providers have their own slots
+        assert!(limiter.acquire("claude-3-7-sonnet").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_without_config() {
+        let limiter = ConcurrencyLimiter::from_config(None);
+        let permits: Vec<_> =
+            futures::future::join_all((0..100).map(|_| limiter.acquire("gpt-4o")))
+                .await
+                .into_iter()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(permits.len(), 100);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use common::configuration::{Configuration, LlmProvider, LlmProviderType};
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use tracing::warn;
+
+const REDACTED_ACCESS_KEY: &str = "[redacted]";
+
+/// Access keys of the configured providers by provider name, together with the header each
+/// provider expects them in. Keys are resolved once at startup and never logged.
+#[derive(Default)]
+pub struct ProviderCredentials {
+    auth_headers: HashMap<String, (HeaderName, HeaderValue)>,
+}
+
+impl ProviderCredentials {
+    pub fn from_providers(providers: &[LlmProvider]) -> Self {
+        let mut auth_headers = HashMap::new();
+        for provider in providers {
+            // the api key env of a groq provider is read when no access key is configured
+            let access_key = provider
+                .access_key
+                .as_deref()
+                .and_then(resolve_access_key)
+                .or_else(|| {
+                    let api_key_env = provider.groq_provider()?.api_key_env?;
+                    read_access_key_env(&api_key_env)
+                });
+            let access_key = match access_key {
+                Some(access_key) => access_key,
+                None => continue,
+            };
+            let (header_name, header_value) = match auth_header(provider, &access_key) {
+                Some(auth_header) => auth_header,
+                None => continue,
+            };
+            let mut header_value = match HeaderValue::from_str(&header_value) {
+                Ok(header_value) => header_value,
+                Err(_) => {
+                    warn!(
+                        "access key of provider {} is not a valid header value",
+                        provider.name
+                    );
+                    continue;
+                }
+            };
+            header_value.set_sensitive(true);
+            auth_headers.insert(provider.name.clone(), (header_name, header_value));
+        }
+        ProviderCredentials { auth_headers }
+    }
+
+    /// Sets the auth header of the provider, overwriting whatever the client sent. Returns
+    /// false when no access key is configured for the provider.
+    pub fn apply(&self, provider_name: &str, headers: &mut HeaderMap) -> bool {
+        match self.auth_headers.get(provider_name) {
+            Some((header_name, header_value)) => {
+                headers.insert(header_name.clone(), header_value.clone());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for ProviderCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderCredentials")
+            .field("providers", &self.auth_headers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Access keys are usually rendered into the config, `$NAME` and `${NAME}` references that were
+/// left in are read from the environment.
+fn resolve_access_key(access_key: &str) -> Option<String> {
+    let variable = access_key
+        .strip_prefix("${")
+        .and_then(|name| name.strip_suffix('}'))
+        .or_else(|| access_key.strip_prefix('$'));
+    match variable {
+        Some(variable) => read_access_key_env(variable),
+        None if access_key.is_empty() => None,
+        None => Some(access_key.to_string()),
+    }
+}
+
+fn read_access_key_env(variable: &str) -> Option<String> {
+    match std::env::var(variable) {
+        Ok(access_key) if !access_key.is_empty() => Some(access_key),
+        _ => {
+            warn!("access key environment variable {} is not set", variable);
+            None
+        }
+    }
+}
+
+fn auth_header(provider: &LlmProvider, access_key: &str) -> Option<(HeaderName, String)> {
+    if let Some(compatible_provider) = provider.openai_compatible_provider() {
+        return compatible_provider
+            .auth_header(access_key)
+            .map(|(header_name, header_value)| {
+                (HeaderName::from_static(header_name), header_value)
+            });
+    }
+
+    Some(match provider.provider_interface {
+        LlmProviderType::Claude => (HeaderName::from_static("x-api-key"), access_key.to_string()),
+        LlmProviderType::Gemini => (
+            HeaderName::from_static("x-goog-api-key"),
+            access_key.to_string(),
+        ),
+        // bedrock requests are signed with the aws credentials instead
+        LlmProviderType::Bedrock => return None,
+        LlmProviderType::AzureOpenAI => {
+            (HeaderName::from_static("api-key"), access_key.to_string())
+        }
+        LlmProviderType::Arch
+        | LlmProviderType::Cohere
+        | LlmProviderType::Deepseek
+        | LlmProviderType::Groq
+        | LlmProviderType::Mistral
+        | LlmProviderType::Ollama
+        | LlmProviderType::OpenAI => (header::AUTHORIZATION, format!("Bearer {}", access_key)),
+    })
+}
+
+/// Copy of the configuration that is safe to log.
+pub fn redacted(config: &Configuration) -> Configuration {
+    let mut config = config.clone();
+    for provider in config.llm_providers.iter_mut() {
+        if provider.access_key.is_some() {
+            provider.access_key = Some(REDACTED_ACCESS_KEY.to_string());
+        }
+    }
+    if let Some(admin) = config.admin.as_mut() {
+        admin.token = REDACTED_ACCESS_KEY.to_string();
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn providers() -> Vec<LlmProvider> {
+        serde_yaml::from_str(
+            r#"
+- name: gpt-4o
+  provider_interface: openai
+  access_key: openai-key
+- name: claude-3-7-sonnet
+  provider_interface: claude
+  access_key: claude-key
+- name: gemini-2.0-flash
+  provider_interface: gemini
+  access_key: $BRIGHTSTAFF_TEST_GEMINI_KEY
+- name: mistral-large
+  provider_interface: mistral
+  access_key: ${BRIGHTSTAFF_TEST_UNSET_KEY}
+- name: azure-gpt-4o
+  provider_interface: openai
+  access_key: azure-key
+  openai_compatible:
+    base_url: https://example.openai.azure.com/openai/v1
+    auth_header: api_key
+- name: azure-o1
+  provider_interface: azure_openai
+  access_key: azure-o1-key
+  azure_openai:
+    endpoint: https://example.openai.azure.com
+- name: local-llama
+  provider_interface: openai
+- name: llama-3.3-70b
+  provider_interface: groq
+  groq:
+    api_key_env: BRIGHTSTAFF_TEST_GROQ_KEY
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_auth_header_per_provider() {
+        std::env::set_var("BRIGHTSTAFF_TEST_GEMINI_KEY", "gemini-key");
+        std::env::set_var("BRIGHTSTAFF_TEST_GROQ_KEY", "groq-key");
+        let credentials = ProviderCredentials::from_providers(&providers());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer client-key"),
+        );
+        assert!(credentials.apply("gpt-4o", &mut headers));
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer openai-key"
+        );
+
+        let mut headers = HeaderMap::new();
+        assert!(credentials.apply("claude-3-7-sonnet", &mut headers));
+        assert_eq!(headers.get("x-api-key").unwrap(), "claude-key");
+        assert!(headers.get(header::AUTHORIZATION).is_none());
+
+        let mut headers = HeaderMap::new();
+        assert!(credentials.apply("gemini-2.0-flash", &mut headers));
+        assert_eq!(headers.get("x-goog-api-key").unwrap(), "gemini-key");
+
+        let mut headers = HeaderMap::new();
+        assert!(credentials.apply("azure-gpt-4o", &mut headers));
+        assert_eq!(headers.get("api-key").unwrap(), "azure-key");
+
+        let mut headers = HeaderMap::new();
+        assert!(credentials.apply("azure-o1", &mut headers));
+        assert_eq!(headers.get("api-key").unwrap(), "azure-o1-key");
+
+        let mut headers = HeaderMap::new();
+        assert!(credentials.apply("llama-3.3-70b", &mut headers));
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer groq-key"
+        );
+
+        // unset environment variables and providers without a key get no header
+        let mut headers = HeaderMap::new();
+        assert!(!credentials.apply("mistral-large", &mut headers));
+        assert!(!credentials.apply("local-llama", &mut headers));
+        assert!(!credentials.apply("unknown", &mut headers));
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_keys_are_not_logged() {
+        let credentials = ProviderCredentials::from_providers(&providers());
+        let debug = format!("{:?}", credentials);
+        assert!(debug.contains("gpt-4o"));
+        assert!(!debug.contains("openai-key"));
+
+        let mut headers = HeaderMap::new();
+        credentials.apply("gpt-4o", &mut headers);
+        assert!(!format!("{:?}", headers).contains("openai-key"));
+    }
+}
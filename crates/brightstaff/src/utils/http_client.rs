@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use common::configuration::{Upstream, UpstreamTls};
+use common::consts::DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum HttpClientError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("client_cert_path and client_key_path must be set together")]
+    IncompleteClientIdentity,
+    #[error(transparent)]
+    Build(#[from] reqwest::Error),
+}
+
+/// Builds the client used for all upstream calls. The client keeps a connection pool
+/// internally and is cheap to clone, so a single instance should be shared by all requests.
+/// Only connecting is timed out by the client, a total timeout would also cut off long running
+/// streams, request timeouts are applied per request instead.
+pub fn build_http_client(upstream: Option<&Upstream>) -> Result<reqwest::Client, HttpClientError> {
+    let connect_timeout_ms = upstream
+        .and_then(|upstream| upstream.connect_timeout_ms)
+        .unwrap_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS);
+    let mut builder =
+        reqwest::Client::builder().connect_timeout(Duration::from_millis(connect_timeout_ms));
+
+    if let Some(upstream) = upstream {
+        if let Some(pool_max_idle_per_host) = upstream.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(pool_max_idle_per_host);
+        }
+        if let Some(pool_idle_timeout_ms) = upstream.pool_idle_timeout_ms {
+            builder = builder.pool_idle_timeout(Duration::from_millis(pool_idle_timeout_ms));
+        }
+        if let Some(compression) = upstream.compression {
+            builder = builder.gzip(compression).brotli(compression);
+        }
+        if let Some(tls) = upstream.tls.as_ref() {
+            builder = with_tls(builder, tls)?;
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+fn with_tls(
+    mut builder: reqwest::ClientBuilder,
+    tls: &UpstreamTls,
+) -> Result<reqwest::ClientBuilder, HttpClientError> {
+    if let Some(ca_cert_path) = tls.ca_cert_path.as_deref() {
+        for certificate in reqwest::Certificate::from_pem_bundle(&read(ca_cert_path)?)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (
+        tls.client_cert_path.as_deref(),
+        tls.client_key_path.as_deref(),
+    ) {
+        (Some(client_cert_path), Some(client_key_path)) => {
+            let identity = reqwest::Identity::from_pkcs8_pem(
+                &read(client_cert_path)?,
+                &read(client_key_path)?,
+            )?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err(HttpClientError::IncompleteClientIdentity),
+    }
+    if tls.insecure_skip_verify.unwrap_or(false) {
+        warn!("upstream certificates are not verified, insecure_skip_verify is set");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+fn read(path: &str) -> Result<Vec<u8>, HttpClientError> {
+    std::fs::read(path).map_err(|source| HttpClientError::Read {
+        path: path.to_string(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBiTCCAS+gAwIBAgIUZu2aJCmDVTKSbXJ0meSz3OZ+x84wCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOYXJjaGd3IHRlc3QgY2EwIBcNMjYxMDE0MTU1MzAxWhgPMjEy
+NjA5MjAxNTUzMDFaMBkxFzAVBgNVBAMMDmFyY2hndyB0ZXN0IGNhMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEgv04kYxad/wsHtaxk08yYEoLeauRONdU6jHXHI7L
+ASpbQ1voD3NuKU9DdIk5HdAALWKbqDlEHxZP32L6uklt1qNTMFEwHQYDVR0OBBYE
+FJNgeTMEBfCm31yuteZOIiY9dEkrMB8GA1UdIwQYMBaAFJNgeTMEBfCm31yuteZO
+IiY9dEkrMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIhALwmM+eV
+WW+1pCXnCPKkwNpt7OB+ppf1G/UZ7t9FRSNDAiBn4qVZgMUyqec6boIFVpTqW/qU
+kLlSR+KCl80D4fReoA==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_build_http_client() {
+        assert!(build_http_client(None).is_ok());
+
+        let upstream = Upstream {
+            pool_max_idle_per_host: Some(8),
+            pool_idle_timeout_ms: Some(30_000),
+            connect_timeout_ms: Some(5_000),
+            ..Default::default()
+        };
+        assert!(build_http_client(Some(&upstream)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_is_not_a_read_timeout() {
+        let upstream = Upstream {
+            connect_timeout_ms: Some(200),
+            ..Default::default()
+        };
+        let http_client = build_http_client(Some(&upstream)).unwrap();
+
+        // nothing answers on a non routable address, connecting fails after the connect timeout
+        let start = std::time::Instant::now();
+        let err = http_client
+            .get("http://10.255.255.1:81/")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_connect() || err.is_timeout(), "{:?}", err);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // an upstream that connects but is slow to answer is waited for
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+        });
+        let response = http_client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_build_http_client_with_tls() {
+        let ca_cert_path =
+            std::env::temp_dir().join(format!("archgw-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_cert_path, CA_CERT).unwrap();
+        let tls = |tls: UpstreamTls| Upstream {
+            tls: Some(tls),
+            ..Default::default()
+        };
+
+        let upstream = tls(UpstreamTls {
+            ca_cert_path: Some(ca_cert_path.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        assert!(build_http_client(Some(&upstream)).is_ok());
+        std::fs::remove_file(&ca_cert_path).unwrap();
+
+        let upstream = tls(UpstreamTls {
+            ca_cert_path: Some("/nonexistent/ca.pem".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            build_http_client(Some(&upstream)),
+            Err(HttpClientError::Read { path, .. }) if path == "/nonexistent/ca.pem"
+        ));
+
+        let upstream = tls(UpstreamTls {
+            client_cert_path: Some("/etc/archgw/client.pem".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            build_http_client(Some(&upstream)),
+            Err(HttpClientError::IncompleteClientIdentity)
+        ));
+
+        let upstream = tls(UpstreamTls {
+            insecure_skip_verify: Some(true),
+            ..Default::default()
+        });
+        assert!(build_http_client(Some(&upstream)).is_ok());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use common::configuration::Idempotency;
+use common::consts::{DEFAULT_IDEMPOTENCY_MAX_ENTRIES, DEFAULT_IDEMPOTENCY_TTL_MS};
+use hyper::header::HeaderMap;
+use hyper::StatusCode;
+use sha2::{Digest, Sha256};
+
+/// Response stored for an idempotency key, replayed as it was sent the first time.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Debug)]
+enum Entry {
+    InProgress {
+        fingerprint: String,
+    },
+    Done {
+        fingerprint: String,
+        response: StoredResponse,
+        expires: Instant,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    keys: HashMap<String, Entry>,
+    /// Keys with a stored response in insertion order, the front is evicted first.
+    order: VecDeque<String>,
+}
+
+/// What to do with a request carrying an idempotency key.
+#[derive(Debug)]
+pub enum IdempotencyCheck<'a> {
+    /// First request with the key, its response is stored through the guard.
+    Execute(IdempotencyGuard<'a>),
+    /// The key was used before, the stored response is sent again.
+    Replay(StoredResponse),
+    /// A request with the key is still being served.
+    InProgress,
+    /// The key was used for a different request.
+    Mismatch,
+}
+
+/// Responses of non streaming requests by the `Idempotency-Key` they were sent with, so that a
+/// client retrying after a network error gets the original response instead of a second
+/// completion. Keys are held until they expire.
+#[derive(Debug)]
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl Default for IdempotencyKeys {
+    fn default() -> Self {
+        IdempotencyKeys::from_config(None)
+    }
+}
+
+impl IdempotencyKeys {
+    pub fn from_config(idempotency: Option<&Idempotency>) -> Self {
+        IdempotencyKeys {
+            ttl: Duration::from_millis(
+                idempotency
+                    .and_then(|idempotency| idempotency.ttl_ms)
+                    .unwrap_or(DEFAULT_IDEMPOTENCY_TTL_MS),
+            ),
+            max_entries: idempotency
+                .and_then(|idempotency| idempotency.max_entries)
+                .unwrap_or(DEFAULT_IDEMPOTENCY_MAX_ENTRIES)
+                .max(1),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Claims `key` for the request with body `request`, unless it was used before.
+    pub fn check(&self, key: &str, request: &[u8]) -> IdempotencyCheck<'_> {
+        self.check_at(key, request, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, request: &[u8], now: Instant) -> IdempotencyCheck<'_> {
+        let fingerprint = hex::encode(Sha256::digest(request));
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        match entries.keys.get(key) {
+            Some(Entry::InProgress { fingerprint: used }) if *used == fingerprint => {
+                return IdempotencyCheck::InProgress
+            }
+            Some(Entry::Done {
+                fingerprint: used,
+                response,
+                expires,
+            }) if now < *expires => {
+                return if *used == fingerprint {
+                    IdempotencyCheck::Replay(response.clone())
+                } else {
+                    IdempotencyCheck::Mismatch
+                };
+            }
+            Some(Entry::InProgress { .. }) => return IdempotencyCheck::Mismatch,
+            // expired
+            Some(Entry::Done { .. }) => {
+                entries.order.retain(|stored_key| stored_key != key);
+            }
+            None => {}
+        }
+        entries
+            .keys
+            .insert(key.to_string(), Entry::InProgress { fingerprint });
+        IdempotencyCheck::Execute(IdempotencyGuard {
+            keys: self,
+            key: Some(key.to_string()),
+        })
+    }
+
+    fn complete(&self, key: String, response: StoredResponse, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        let fingerprint = match entries.keys.remove(&key) {
+            Some(Entry::InProgress { fingerprint }) => fingerprint,
+            _ => return,
+        };
+        entries.keys.insert(
+            key.clone(),
+            Entry::Done {
+                fingerprint,
+                response,
+                expires: now + self.ttl,
+            },
+        );
+        entries.order.push_back(key);
+        while entries.order.len() > self.max_entries {
+            match entries.order.pop_front() {
+                Some(oldest) => {
+                    entries.keys.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn release(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if matches!(entries.keys.get(key), Some(Entry::InProgress { .. })) {
+            entries.keys.remove(key);
+        }
+    }
+}
+
+/// Claim of an idempotency key by the request being served. Dropping it without storing a
+/// response frees the key, so that the client may retry a request that failed.
+#[derive(Debug)]
+pub struct IdempotencyGuard<'a> {
+    keys: &'a IdempotencyKeys,
+    key: Option<String>,
+}
+
+impl IdempotencyGuard<'_> {
+    /// Stores the response of the request, replayed for the key until it expires.
+    pub fn complete(self, response: StoredResponse) {
+        self.complete_at(response, Instant::now());
+    }
+
+    fn complete_at(mut self, response: StoredResponse, now: Instant) {
+        if let Some(key) = self.key.take() {
+            self.keys.complete(key, response, now);
+        }
+    }
+}
+
+impl Drop for IdempotencyGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.keys.release(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> IdempotencyKeys {
+        IdempotencyKeys::from_config(Some(&Idempotency {
+            ttl_ms: Some(1000),
+            max_entries: Some(2),
+        }))
+    }
+
+    fn response(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from(body),
+        }
+    }
+
+    fn execute<'a>(keys: &'a IdempotencyKeys, key: &str, now: Instant) -> IdempotencyGuard<'a> {
+        match keys.check_at(key, b"request", now) {
+            IdempotencyCheck::Execute(guard) => guard,
+            other => panic!("expected the request to execute, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let keys = keys();
+        let now = Instant::now();
+
+        let guard = execute(&keys, "a", now);
+        // the first request is still being served
+        assert!(matches!(
+            keys.check_at("a", b"request", now),
+            IdempotencyCheck::InProgress
+        ));
+        guard.complete_at(response("first"), now);
+
+        match keys.check_at("a", b"request", now) {
+            IdempotencyCheck::Replay(stored) => assert_eq!(stored.body, "first"),
+            other => panic!("expected a replay, got {:?}", other),
+        }
+        assert!(matches!(
+            keys.check_at("a", b"another request", now),
+            IdempotencyCheck::Mismatch
+        ));
+    }
+
+    #[test]
+    fn test_key_expiry() {
+        let keys = keys();
+        let now = Instant::now();
+        execute(&keys, "a", now).complete_at(response("first"), now);
+
+        // once expired the key runs a new request
+        let later = now + Duration::from_secs(1);
+        assert!(matches!(
+            keys.check_at("a", b"request", later),
+            IdempotencyCheck::Execute(_)
+        ));
+
+        // the oldest key is evicted once the store is full
+        for key in ["b", "c", "d"] {
+            execute(&keys, key, now).complete_at(response(""), now);
+        }
+        assert!(matches!(
+            keys.check_at("b", b"request", now),
+            IdempotencyCheck::Execute(_)
+        ));
+    }
+
+    #[test]
+    fn test_failed_request_frees_key() {
+        let keys = keys();
+        let now = Instant::now();
+        drop(execute(&keys, "a", now));
+        assert!(matches!(
+            keys.check_at("a", b"request", now),
+            IdempotencyCheck::Execute(_)
+        ));
+    }
+}
@@ -1 +1,19 @@
+pub mod cache_key;
+pub mod circuit_breaker;
+pub mod compression;
+pub mod concurrency;
+pub mod credentials;
+pub mod http_client;
+pub mod idempotency;
+pub mod model_allowlist;
+pub mod normalize;
+pub mod provider_headers;
+pub mod rate_limit;
+pub mod request_id;
+pub mod response_cache;
+pub mod retry;
+pub mod shutdown;
 pub mod tracing;
+pub mod transform;
+pub mod upstream_endpoints;
+pub mod usage;
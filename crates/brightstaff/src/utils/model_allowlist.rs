@@ -0,0 +1,107 @@
+use std::collections::{HashMap, HashSet};
+
+use common::configuration::Configuration;
+
+/// Models clients may be served. `routing.allowed_models` applies to every request, the
+/// `allowed_models` of a routing preference further restrict the models its route may resolve
+/// to, e.g. through usage preferences sent with the request. Without any list every model is
+/// allowed.
+#[derive(Debug, Default)]
+pub struct ModelAllowlist {
+    allowed_models: Option<HashSet<String>>,
+    route_allowed_models: HashMap<String, HashSet<String>>,
+}
+
+impl ModelAllowlist {
+    pub fn from_config(config: &Configuration) -> Self {
+        let allowed_models = config
+            .routing
+            .as_ref()
+            .and_then(|routing| routing.allowed_models.as_ref())
+            .map(|models| models.iter().cloned().collect());
+
+        let route_allowed_models = config
+            .llm_providers
+            .iter()
+            .flat_map(|provider| provider.routing_preferences.iter().flatten())
+            .filter_map(|pref| {
+                pref.allowed_models
+                    .as_ref()
+                    .map(|models| (pref.name.clone(), models.iter().cloned().collect()))
+            })
+            .collect();
+
+        ModelAllowlist {
+            allowed_models,
+            route_allowed_models,
+        }
+    }
+
+    /// Whether `model` may serve a request, `route` is the route that resolved to it if any.
+    pub fn is_allowed(&self, route: Option<&str>, model: &str) -> bool {
+        if let Some(allowed_models) = self.allowed_models.as_ref() {
+            if !allowed_models.contains(model) {
+                return false;
+            }
+        }
+        match route.and_then(|route| self.route_allowed_models.get(route)) {
+            Some(route_allowed_models) => route_allowed_models.contains(model),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const PROVIDERS: &str = r#"
+version: v0.1
+llm_providers:
+  - name: gpt-4o
+    provider_interface: openai
+    routing_preferences:
+      - name: image-generation
+        description: generating image
+  - name: claude-3-7-sonnet
+    provider_interface: claude
+    routing_preferences:
+      - name: code-generation
+        description: generating new code snippets
+        allowed_models: ["claude-3-7-sonnet", "gpt-4o"]
+"#;
+
+    #[test]
+    fn test_no_allowlist() {
+        let allowlist = ModelAllowlist::from_config(&config(PROVIDERS));
+        assert!(allowlist.is_allowed(None, "o1-pro"));
+        assert!(allowlist.is_allowed(Some("image-generation"), "o1-pro"));
+    }
+
+    #[test]
+    fn test_global_allowlist() {
+        let allowlist = ModelAllowlist::from_config(&config(&format!(
+            "{}routing:\n  allowed_models: [\"gpt-4o\", \"claude-3-7-sonnet\"]\n",
+            PROVIDERS
+        )));
+        assert!(allowlist.is_allowed(None, "gpt-4o"));
+        assert!(allowlist.is_allowed(Some("image-generation"), "gpt-4o"));
+        assert!(!allowlist.is_allowed(None, "o1-pro"));
+        assert!(!allowlist.is_allowed(Some("image-generation"), "o1-pro"));
+    }
+
+    #[test]
+    fn test_route_allowlist() {
+        let allowlist = ModelAllowlist::from_config(&config(PROVIDERS));
+        assert!(allowlist.is_allowed(Some("code-generation"), "claude-3-7-sonnet"));
+        assert!(allowlist.is_allowed(Some("code-generation"), "gpt-4o"));
+        assert!(!allowlist.is_allowed(Some("code-generation"), "gpt-4o-mini"));
+        // other routes and requests without a route are not restricted
+        assert!(allowlist.is_allowed(Some("image-generation"), "gpt-4o-mini"));
+        assert!(allowlist.is_allowed(None, "gpt-4o-mini"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde_json::Value;
+
+use crate::utils::request_id::new_request_id;
+
+/// Fills in the fields of a chat completions response that OpenAI compatible backends leave
+/// out but the OpenAI SDKs require: `id`, `object`, `created`, `model` and the `index` of each
+/// choice. `model` is the model the request was sent with. Fields that are present are kept
+/// and bodies that are not chat completions responses are returned as they are.
+pub fn normalize_response(body: Bytes, model: &str) -> Bytes {
+    let mut response: Value = match serde_json::from_slice(&body) {
+        Ok(response) => response,
+        Err(_) => return body,
+    };
+    let fields = match response.as_object_mut() {
+        Some(fields) if fields.get("choices").is_some_and(Value::is_array) => fields,
+        _ => return body,
+    };
+
+    let mut changed = false;
+    let mut fill = |name: &str, fields: &mut serde_json::Map<String, Value>, value: Value| {
+        if fields.get(name).is_none_or(Value::is_null) {
+            fields.insert(name.to_string(), value);
+            changed = true;
+        }
+    };
+    fill(
+        "id",
+        fields,
+        Value::from(format!("chatcmpl-{}", new_request_id())),
+    );
+    fill("object", fields, Value::from("chat.completion"));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    fill("created", fields, Value::from(now));
+    fill("model", fields, Value::from(model));
+    if let Some(choices) = fields.get_mut("choices").and_then(Value::as_array_mut) {
+        for (index, choice) in choices.iter_mut().enumerate() {
+            if let Some(choice) = choice.as_object_mut() {
+                fill("index", choice, Value::from(index));
+            }
+        }
+    }
+
+    if !changed {
+        return body;
+    }
+    serde_json::to_vec(&response)
+        .map(Bytes::from)
+        .unwrap_or(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimal_response_normalized() {
+        let body = Bytes::from(
+            r#"{"choices": [{"message": {"role": "assistant", "content": "hi"}, "finish_reason": "stop"}], "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}}"#,
+        );
+
+        let normalized: Value =
+            serde_json::from_slice(&normalize_response(body, "llama-3.3-70b")).unwrap();
+        assert!(normalized["id"].as_str().unwrap().starts_with("chatcmpl-"));
+        assert_eq!(normalized["object"], "chat.completion");
+        assert!(normalized["created"].as_u64().unwrap() > 0);
+        assert_eq!(normalized["model"], "llama-3.3-70b");
+        assert_eq!(normalized["choices"][0]["index"], 0);
+        assert_eq!(normalized["choices"][0]["message"]["content"], "hi");
+        assert_eq!(normalized["usage"]["total_tokens"], 2);
+        // the typed response parses now
+        serde_json::from_value::<hermesllm::providers::openai::types::ChatCompletionsResponse>(
+            normalized,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_complete_response_unchanged() {
+        let body = Bytes::from(
+            r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 1, "model": "gpt-4o", "choices": [{"index": 0, "message": {"role": "assistant", "content": "hi"}}]}"#,
+        );
+        assert_eq!(normalize_response(body.clone(), "other"), body);
+
+        let error = Bytes::from(r#"{"error": {"message": "boom"}}"#);
+        assert_eq!(normalize_response(error.clone(), "gpt-4o"), error);
+    }
+}
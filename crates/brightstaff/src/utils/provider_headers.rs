@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use common::configuration::LlmProvider;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use tracing::warn;
+
+/// Static headers of the configured providers by provider name, e.g. the `anthropic-version`
+/// or `HTTP-Referer` some providers expect with every request.
+#[derive(Debug, Default)]
+pub struct ProviderHeaders {
+    headers: HashMap<String, HeaderMap>,
+}
+
+impl ProviderHeaders {
+    pub fn from_providers(providers: &[LlmProvider]) -> Self {
+        let mut headers = HashMap::new();
+        for provider in providers {
+            let configured = match provider.headers.as_ref() {
+                Some(configured) if !configured.is_empty() => configured,
+                _ => continue,
+            };
+            let mut provider_headers = HeaderMap::new();
+            for (name, value) in configured {
+                match (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(value),
+                ) {
+                    (Ok(name), Ok(value)) => {
+                        provider_headers.insert(name, value);
+                    }
+                    _ => warn!(
+                        "header {} of provider {} is not a valid header, it is not sent",
+                        name, provider.name
+                    ),
+                }
+            }
+            headers.insert(provider.name.clone(), provider_headers);
+        }
+        ProviderHeaders { headers }
+    }
+
+    /// Sets the headers of the provider, overwriting those the client sent with the same name.
+    pub fn apply(&self, provider_name: &str, headers: &mut HeaderMap) {
+        if let Some(provider_headers) = self.headers.get(provider_name) {
+            for (name, value) in provider_headers {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let providers: Vec<LlmProvider> = serde_yaml::from_str(
+            r#"
+- name: openrouter
+  provider_interface: openai
+  headers:
+    HTTP-Referer: https://example.com
+    X-Title: archgw
+    "bad header": value
+- name: gpt-4o
+  provider_interface: openai
+"#,
+        )
+        .unwrap();
+        let provider_headers = ProviderHeaders::from_providers(&providers);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-title", HeaderValue::from_static("client"));
+        provider_headers.apply("openrouter", &mut headers);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("http-referer").unwrap(), "https://example.com");
+        assert_eq!(headers.get("x-title").unwrap(), "archgw");
+
+        let mut headers = HeaderMap::new();
+        provider_headers.apply("gpt-4o", &mut headers);
+        provider_headers.apply("unknown", &mut headers);
+        assert!(headers.is_empty());
+    }
+}
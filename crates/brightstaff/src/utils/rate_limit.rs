@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use common::configuration::{Configuration, RouteRateLimit};
+use hyper::header::HeaderMap;
+
+/// Bucket holding up to `capacity` tokens that refills at `capacity` tokens per minute. The
+/// available tokens may go negative when more is consumed than was available, e.g. by the
+/// usage of a response, which then delays the next request until the debt is paid back.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        TokenBucket {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until a whole token is available again, zero when one is available now.
+    fn wait_time(&self) -> Duration {
+        if self.available >= 1.0 || self.capacity <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.available) * 60.0 / self.capacity)
+    }
+
+    fn consume(&mut self, tokens: f64) {
+        self.available -= tokens;
+    }
+}
+
+#[derive(Debug)]
+struct ClientBuckets {
+    requests: TokenBucket,
+    tokens: Option<TokenBucket>,
+}
+
+#[derive(Debug)]
+struct RouteLimiter {
+    limit: RouteRateLimit,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+}
+
+/// Per route rate limits from the `rate_limit` of the routing preferences. Requests take a
+/// token from the request bucket of their route, the usage reported in the response is taken
+/// from the token bucket afterwards.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    routes: HashMap<String, RouteLimiter>,
+}
+
+/// Time after which the client may retry, sent as `Retry-After`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Whole seconds to wait, rounded up so that the retry is not rejected again.
+    pub fn retry_after_secs(&self) -> u64 {
+        let secs = self.retry_after.as_secs();
+        if self.retry_after.subsec_nanos() > 0 {
+            secs + 1
+        } else {
+            secs.max(1)
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &Configuration) -> Self {
+        let routes = config
+            .llm_providers
+            .iter()
+            .flat_map(|provider| provider.routing_preferences.iter().flatten())
+            .filter_map(|pref| {
+                pref.rate_limit.as_ref().map(|limit| {
+                    (
+                        pref.name.clone(),
+                        RouteLimiter {
+                            limit: limit.clone(),
+                            clients: Mutex::new(HashMap::new()),
+                        },
+                    )
+                })
+            })
+            .collect();
+
+        RateLimiter { routes }
+    }
+
+    /// Key of the buckets a request of `route` is counted against, the value of the client
+    /// header of the route if it has one. `None` when the route is not limited.
+    pub fn client_key(&self, route: &str, headers: &HeaderMap) -> Option<String> {
+        let route_limiter = self.routes.get(route)?;
+        let client = route_limiter
+            .limit
+            .client_header
+            .as_ref()
+            .and_then(|name| headers.get(name.as_str()))
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        Some(client.to_string())
+    }
+
+    /// Takes a request from the buckets of `route` and `client`, fails with the time to wait
+    /// when the requests or the tokens of the route are used up.
+    pub fn check(&self, route: &str, client: &str) -> Result<(), RateLimited> {
+        self.check_at(route, client, Instant::now())
+    }
+
+    /// Takes the tokens reported in the usage of a response from the token bucket of `route`.
+    pub fn record_tokens(&self, route: &str, client: &str, tokens: usize) {
+        self.record_tokens_at(route, client, tokens, Instant::now());
+    }
+
+    fn check_at(&self, route: &str, client: &str, now: Instant) -> Result<(), RateLimited> {
+        let route_limiter = match self.routes.get(route) {
+            Some(route_limiter) => route_limiter,
+            None => return Ok(()),
+        };
+        let mut clients = route_limiter.clients.lock().unwrap();
+        let buckets = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientBuckets {
+                requests: TokenBucket::new(route_limiter.limit.requests_per_minute, now),
+                tokens: route_limiter
+                    .limit
+                    .tokens_per_minute
+                    .map(|tokens_per_minute| TokenBucket::new(tokens_per_minute, now)),
+            });
+
+        buckets.requests.refill(now);
+        let mut retry_after = buckets.requests.wait_time();
+        if let Some(tokens) = buckets.tokens.as_mut() {
+            tokens.refill(now);
+            retry_after = retry_after.max(tokens.wait_time());
+        }
+        if !retry_after.is_zero() {
+            return Err(RateLimited { retry_after });
+        }
+
+        buckets.requests.consume(1.0);
+        Ok(())
+    }
+
+    fn record_tokens_at(&self, route: &str, client: &str, tokens: usize, now: Instant) {
+        let route_limiter = match self.routes.get(route) {
+            Some(route_limiter) => route_limiter,
+            None => return,
+        };
+        let mut clients = route_limiter.clients.lock().unwrap();
+        if let Some(bucket) = clients
+            .get_mut(client)
+            .and_then(|buckets| buckets.tokens.as_mut())
+        {
+            bucket.refill(now);
+            bucket.consume(tokens as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limiter(rate_limit: &str) -> RateLimiter {
+        let config: Configuration = serde_yaml::from_str(&format!(
+            r#"
+version: v0.1
+llm_providers:
+  - name: gpt-4o
+    provider_interface: openai
+    routing_preferences:
+      - name: image-generation
+        description: generating image
+      - name: code-generation
+        description: generating new code snippets
+        rate_limit: {}
+"#,
+            rate_limit
+        ))
+        .unwrap();
+        RateLimiter::from_config(&config)
+    }
+
+    #[test]
+    fn test_nth_request_rejected() {
+        let limiter = rate_limiter("{requests_per_minute: 3}");
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("code-generation", "", now).is_ok());
+        }
+        let limited = limiter.check_at("code-generation", "", now).unwrap_err();
+        assert_eq!(limited.retry_after, Duration::from_secs(20));
+        assert_eq!(limited.retry_after_secs(), 20);
+
+        // routes without a limit are not counted
+        for _ in 0..10 {
+            assert!(limiter.check_at("image-generation", "", now).is_ok());
+        }
+
+        // one request was refilled once the wait time passed
+        let later = now + Duration::from_secs(20);
+        assert!(limiter.check_at("code-generation", "", later).is_ok());
+        assert!(limiter.check_at("code-generation", "", later).is_err());
+    }
+
+    #[test]
+    fn test_clients_limited_separately() {
+        let limiter = rate_limiter("{requests_per_minute: 1, client_header: x-client-id}");
+        let now = Instant::now();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-id", "team-a".parse().unwrap());
+        let team_a = limiter.client_key("code-generation", &headers).unwrap();
+        assert_eq!(team_a, "team-a");
+        assert_eq!(
+            limiter.client_key("code-generation", &HeaderMap::new()),
+            Some(String::new())
+        );
+        assert_eq!(limiter.client_key("image-generation", &headers), None);
+
+        assert!(limiter.check_at("code-generation", &team_a, now).is_ok());
+        assert!(limiter.check_at("code-generation", &team_a, now).is_err());
+        assert!(limiter.check_at("code-generation", "team-b", now).is_ok());
+    }
+
+    #[test]
+    fn test_tokens_per_minute() {
+        let limiter = rate_limiter("{requests_per_minute: 100, tokens_per_minute: 600}");
+        let now = Instant::now();
+
+        assert!(limiter.check_at("code-generation", "", now).is_ok());
+        // the response used more tokens than the route has per minute
+        limiter.record_tokens_at("code-generation", "", 900, now);
+        let limited = limiter.check_at("code-generation", "", now).unwrap_err();
+        // 301 tokens are missing, refilled at 10 per second
+        assert_eq!(limited.retry_after_secs(), 31);
+
+        let later = now + Duration::from_secs(31);
+        assert!(limiter.check_at("code-generation", "", later).is_ok());
+    }
+}
@@ -0,0 +1,58 @@
+use common::consts::REQUEST_ID_HEADER;
+use hyper::header::{HeaderMap, HeaderValue};
+
+/// Longest incoming request id that is kept, longer ones are replaced by a generated id.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id correlating the logs of a request across archgw and the provider: the `x-request-id`
+/// sent by the client, or a new UUID when it sent none or an unusable one.
+pub fn request_id(headers: &HeaderMap) -> HeaderValue {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .filter(|value| {
+            let len = value.as_bytes().len();
+            len > 0 && len <= MAX_REQUEST_ID_LEN && value.to_str().is_ok()
+        })
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_str(&new_request_id()).unwrap())
+}
+
+/// Random UUID in its hyphenated form, version 4.
+pub fn new_request_id() -> String {
+    let bits = rand::random::<u128>();
+    // set the version to 4 and the variant to RFC 4122
+    let bits = (bits & !(0xf << 76)) | (0x4 << 76);
+    let bits = (bits & !(0x3 << 62)) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("req-1234"));
+        assert_eq!(request_id(&headers), "req-1234");
+
+        // missing and empty ids are generated
+        let generated = request_id(&HeaderMap::new());
+        let generated = generated.to_str().unwrap();
+        assert_eq!(generated.len(), 36);
+        assert_eq!(&generated[14..15], "4");
+        assert!(matches!(&generated[19..20], "8" | "9" | "a" | "b"));
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static(""));
+        assert_ne!(request_id(&headers), "");
+
+        assert_ne!(new_request_id(), new_request_id());
+    }
+}
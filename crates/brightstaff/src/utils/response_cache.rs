@@ -0,0 +1,207 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use common::configuration::ResponseCache;
+use common::consts::{DEFAULT_RESPONSE_CACHE_MAX_ENTRIES, DEFAULT_RESPONSE_CACHE_TTL_MS};
+use hermesllm::providers::openai::types::ChatCompletionsRequest;
+
+use crate::utils::cache_key::normalize_and_hash;
+
+#[derive(Debug)]
+struct CachedResponse {
+    body: Bytes,
+    expires: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    responses: HashMap<String, CachedResponse>,
+    /// Keys in insertion order, the front is evicted first.
+    order: VecDeque<String>,
+}
+
+/// Bodies of successful non streaming responses to deterministic requests, served again for
+/// identical requests until they expire. Without a config nothing is cached.
+#[derive(Debug, Default)]
+pub struct CompletionCache {
+    ttl: Duration,
+    max_entries: usize,
+    enabled: bool,
+    entries: Mutex<Entries>,
+}
+
+impl CompletionCache {
+    pub fn from_config(response_cache: Option<&ResponseCache>) -> Self {
+        let response_cache = match response_cache {
+            Some(response_cache) => response_cache,
+            None => return CompletionCache::default(),
+        };
+        CompletionCache {
+            ttl: Duration::from_millis(
+                response_cache
+                    .ttl_ms
+                    .unwrap_or(DEFAULT_RESPONSE_CACHE_TTL_MS),
+            ),
+            max_entries: response_cache
+                .max_entries
+                .unwrap_or(DEFAULT_RESPONSE_CACHE_MAX_ENTRIES)
+                .max(1),
+            enabled: true,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Key of the request when its response may be cached: a non streaming request with
+    /// temperature 0 and a single choice, sent to `model`. The key covers the model, the
+    /// messages and the sampling parameters, see `normalize_and_hash`.
+    pub fn key(&self, model: &str, request: &ChatCompletionsRequest) -> Option<String> {
+        if !self.enabled || !is_deterministic(request) {
+            return None;
+        }
+        let request = ChatCompletionsRequest {
+            model: model.to_string(),
+            ..request.clone()
+        };
+        Some(normalize_and_hash(&request).to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.get_at(key, Instant::now())
+    }
+
+    pub fn insert(&self, key: String, body: Bytes) {
+        self.insert_at(key, body, Instant::now());
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.responses.get(key) {
+            Some(cached) if now < cached.expires => Some(cached.body.clone()),
+            Some(_) => {
+                entries.responses.remove(key);
+                entries.order.retain(|cached_key| cached_key != key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert_at(&self, key: String, body: Bytes, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let cached = CachedResponse {
+            body,
+            expires: now + self.ttl,
+        };
+        if entries.responses.insert(key.clone(), cached).is_some() {
+            entries.order.retain(|cached_key| *cached_key != key);
+        }
+        entries.order.push_back(key);
+        while entries.responses.len() > self.max_entries {
+            match entries.order.pop_front() {
+                Some(oldest) => {
+                    entries.responses.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+fn is_deterministic(request: &ChatCompletionsRequest) -> bool {
+    !request.stream.unwrap_or(false)
+        && request.temperature == Some(0.0)
+        && request.n.unwrap_or(1) == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hermesllm::providers::openai::types::Message;
+
+    fn cache(max_entries: usize) -> CompletionCache {
+        CompletionCache::from_config(Some(&ResponseCache {
+            ttl_ms: Some(1000),
+            max_entries: Some(max_entries),
+        }))
+    }
+
+    fn request(content: &str) -> ChatCompletionsRequest {
+        ChatCompletionsRequest {
+            model: "none".to_string(),
+            messages: vec![Message::new(content.to_string())],
+            temperature: Some(0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_only_deterministic_requests_are_cached() {
+        let cache = cache(10);
+        assert!(cache.key("gpt-4o", &request("classify this")).is_some());
+
+        let streaming = ChatCompletionsRequest {
+            stream: Some(true),
+            ..request("classify this")
+        };
+        assert_eq!(cache.key("gpt-4o", &streaming), None);
+        let sampled = ChatCompletionsRequest {
+            temperature: Some(0.7),
+            ..request("classify this")
+        };
+        assert_eq!(cache.key("gpt-4o", &sampled), None);
+        let unset = ChatCompletionsRequest {
+            temperature: None,
+            ..request("classify this")
+        };
+        assert_eq!(cache.key("gpt-4o", &unset), None);
+
+        assert_eq!(
+            CompletionCache::from_config(None).key("gpt-4o", &request("classify this")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_key_covers_model_messages_and_sampling() {
+        let cache = cache(10);
+        let key = cache.key("gpt-4o", &request("classify this")).unwrap();
+
+        assert_eq!(cache.key("gpt-4o", &request("classify this")).unwrap(), key);
+        assert_ne!(
+            cache
+                .key("claude-3-7-sonnet", &request("classify this"))
+                .unwrap(),
+            key
+        );
+        assert_ne!(cache.key("gpt-4o", &request("classify that")).unwrap(), key);
+        let max_tokens = ChatCompletionsRequest {
+            max_tokens: Some(10),
+            ..request("classify this")
+        };
+        assert_ne!(cache.key("gpt-4o", &max_tokens).unwrap(), key);
+    }
+
+    #[test]
+    fn test_hit_miss_and_expiry() {
+        let cache = cache(2);
+        let now = Instant::now();
+        assert_eq!(cache.get_at("a", now), None);
+
+        cache.insert_at("a".to_string(), Bytes::from("response a"), now);
+        assert_eq!(cache.get_at("a", now), Some(Bytes::from("response a")));
+        assert_eq!(cache.get_at("a", now + Duration::from_secs(1)), None);
+
+        // the oldest response is evicted once the cache is full
+        cache.insert_at("b".to_string(), Bytes::from("response b"), now);
+        cache.insert_at("c".to_string(), Bytes::from("response c"), now);
+        cache.insert_at("d".to_string(), Bytes::from("response d"), now);
+        assert_eq!(cache.get_at("b", now), None);
+        assert!(cache.get_at("c", now).is_some());
+        assert!(cache.get_at("d", now).is_some());
+    }
+}
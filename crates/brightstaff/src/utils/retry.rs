@@ -0,0 +1,347 @@
+use std::time::{Duration, SystemTime};
+
+use common::configuration::Retry;
+use common::consts::{
+    DEFAULT_RETRYABLE_STATUS_CODES, DEFAULT_RETRY_BASE_DELAY_MS, DEFAULT_RETRY_MAX_ATTEMPTS,
+    DEFAULT_RETRY_MAX_DELAY_MS, DEFAULT_RETRY_MAX_RETRY_AFTER_MS,
+};
+use hyper::{header, StatusCode};
+use rand::Rng;
+use tracing::warn;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retryable_status_codes: Vec<u16>,
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Without a retry section in the config every request is sent exactly once.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(DEFAULT_RETRY_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_RETRY_MAX_DELAY_MS),
+            retryable_status_codes: DEFAULT_RETRYABLE_STATUS_CODES.to_vec(),
+            max_retry_after: Duration::from_millis(DEFAULT_RETRY_MAX_RETRY_AFTER_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(retry: Option<&Retry>) -> Self {
+        let retry = match retry {
+            Some(retry) => retry,
+            None => return RetryPolicy::default(),
+        };
+
+        RetryPolicy {
+            max_attempts: retry
+                .max_attempts
+                .unwrap_or(DEFAULT_RETRY_MAX_ATTEMPTS)
+                .max(1),
+            base_delay: Duration::from_millis(
+                retry.base_delay_ms.unwrap_or(DEFAULT_RETRY_BASE_DELAY_MS),
+            ),
+            max_delay: Duration::from_millis(
+                retry.max_delay_ms.unwrap_or(DEFAULT_RETRY_MAX_DELAY_MS),
+            ),
+            retryable_status_codes: retry
+                .retryable_status_codes
+                .clone()
+                .unwrap_or_else(|| DEFAULT_RETRYABLE_STATUS_CODES.to_vec()),
+            max_retry_after: Duration::from_millis(
+                retry
+                    .max_retry_after_ms
+                    .unwrap_or(DEFAULT_RETRY_MAX_RETRY_AFTER_MS),
+            ),
+        }
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    pub fn is_retryable_error(&self, err: &reqwest::Error) -> bool {
+        err.is_connect()
+    }
+
+    /// Exponential backoff with full jitter, `attempt` starts at 1 for the first retry.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+        if capped.is_zero() {
+            return capped;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=capped)
+    }
+
+    /// Delay before the next attempt, a `Retry-After` sent by the upstream takes precedence
+    /// over the backoff but is capped at `max_retry_after`.
+    fn delay(&self, attempt: u32, response: Option<&reqwest::Response>) -> Duration {
+        match response.and_then(retry_after) {
+            Some(retry_after) => retry_after.min(self.max_retry_after),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Parses a `Retry-After` header value given either as delay seconds or as an HTTP-date.
+/// Dates in the past result in a zero delay.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+/// Sends the request built by `build_request`, retrying on connection errors and retryable
+/// status codes. A new request is built for every attempt. Retries only happen before a
+/// response has been handed back to the caller, so a stream that started forwarding bytes to
+/// the client is never replayed. When the attempts are exhausted the last response or error is
+/// returned unchanged.
+pub async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    build_request: F,
+) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    send_with_retry_observed(policy, build_request, |_| {}).await
+}
+
+/// Same as [`send_with_retry`], `on_retry` is called with the attempt number before every
+/// retry.
+pub async fn send_with_retry_observed<F, R>(
+    policy: &RetryPolicy,
+    build_request: F,
+    on_retry: R,
+) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+    R: Fn(u32),
+{
+    let mut attempt = 1;
+    loop {
+        let result = build_request().send().await;
+        if attempt >= policy.max_attempts {
+            return result;
+        }
+
+        match &result {
+            Ok(response) if policy.is_retryable_status(response.status()) => {
+                warn!(
+                    "upstream responded with {}, retrying (attempt {} of {})",
+                    response.status(),
+                    attempt,
+                    policy.max_attempts
+                );
+            }
+            Err(err) if policy.is_retryable_error(err) => {
+                warn!(
+                    "upstream request failed: {}, retrying (attempt {} of {})",
+                    err, attempt, policy.max_attempts
+                );
+            }
+            _ => return result,
+        }
+
+        on_retry(attempt);
+        tokio::time::sleep(policy.delay(attempt, result.as_ref().ok())).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// Starts a server that answers with `statuses` in order and repeats the last one, 429
+    /// responses carry `Retry-After: 0`.
+    async fn start_mock_server(statuses: Vec<u16>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+
+        let server_hits = Arc::clone(&hits);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let hits = Arc::clone(&server_hits);
+                let statuses = statuses.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |_req| {
+                        let hit = hits.fetch_add(1, Ordering::SeqCst);
+                        let status = statuses[hit.min(statuses.len() - 1)];
+                        async move {
+                            let mut response = Response::new(Full::new(Bytes::from("{}")));
+                            *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                            if status == 429 {
+                                response
+                                    .headers_mut()
+                                    .insert(header::RETRY_AFTER, "0".parse().unwrap());
+                            }
+                            Ok::<_, Infallible>(response)
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (addr, hits)
+    }
+
+    fn test_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_503_then_200() {
+        let (addr, hits) = start_mock_server(vec![503, 200]).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/chat/completions", addr);
+
+        let response = send_with_retry(&test_policy(3), || client.post(&url).body("{}"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_exhausted_returns_last_response() {
+        let (addr, hits) = start_mock_server(vec![503]).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/chat/completions", addr);
+
+        let response = send_with_retry(&test_policy(3), || client.post(&url).body("{}"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_non_retryable_status_is_not_retried() {
+        let (addr, hits) = start_mock_server(vec![400, 200]).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/chat/completions", addr);
+
+        let response = send_with_retry(&test_policy(3), || client.post(&url).body("{}"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_exhausted_propagates_429() {
+        let (addr, hits) = start_mock_server(vec![429]).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/chat/completions", addr);
+
+        let policy = RetryPolicy {
+            // the backoff would make the test hang if Retry-After were ignored
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+            ..test_policy(2)
+        };
+        let response = send_with_retry(&policy, || client.post(&url).body("{}"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "0");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-1", now), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // dates in the past mean the request can be retried right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            ..Default::default()
+        };
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= Duration::from_millis(1000));
+        }
+        assert!(policy.backoff(1) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        assert_eq!(RetryPolicy::from_config(None).max_attempts, 1);
+
+        let retry = Retry {
+            max_attempts: Some(5),
+            retryable_status_codes: Some(vec![503]),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::from_config(Some(&retry));
+        assert_eq!(policy.max_attempts, 5);
+        assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+    }
+}
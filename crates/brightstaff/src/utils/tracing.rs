@@ -1,29 +1,350 @@
+use std::fmt;
 use std::sync::OnceLock;
 
+use common::configuration::{LogFormat, Tracing};
+use common::consts::{BAGGAGE_HEADER, TRACE_PARENT_HEADER, TRACE_STATE_HEADER};
+use hyper::header::HeaderMap;
 use opentelemetry::global;
+use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
 use opentelemetry_stdout::SpanExporter;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
 static INIT_LOGGER: OnceLock<SdkTracerProvider> = OnceLock::new();
 
-pub fn init_tracer() -> &'static SdkTracerProvider {
+/// How logs are written and where spans are exported, from the `tracing` section of the config.
+#[derive(Debug, Clone, Default)]
+pub struct TracerOptions {
+    /// Log filter used when `RUST_LOG` is not set, `info` when neither is.
+    pub log_level: Option<String>,
+    pub log_format: LogFormat,
+    /// OTLP gRPC collector spans are sent to, spans are written to stdout without one.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl TracerOptions {
+    pub fn from_config(tracing: Option<&Tracing>) -> Self {
+        TracerOptions {
+            log_level: tracing.and_then(|tracing| tracing.log_level.clone()),
+            log_format: tracing
+                .and_then(|tracing| tracing.log_format)
+                .unwrap_or_default(),
+            otlp_endpoint: tracing.and_then(|tracing| tracing.otlp_endpoint.clone()),
+        }
+    }
+}
+
+/// Installs the logger and the tracer provider. Only the first call does, later calls return
+/// the provider installed by the first one and ignore their options.
+pub fn init_tracer(options: &TracerOptions) -> &'static SdkTracerProvider {
     INIT_LOGGER.get_or_init(|| {
         global::set_text_map_propagator(TraceContextPropagator::new());
-        // Install stdout exporter pipeline to be able to retrieve the collected spans.
-        // For the demonstration, use `Sampler::AlwaysOn` sampler to sample all traces.
-        let provider = SdkTracerProvider::builder()
-            .with_simple_exporter(SpanExporter::default())
-            .build();
 
-        global::set_tracer_provider(provider.clone());
+        let filter = env_filter(options.log_level.as_deref());
+        let installed = match options.log_format {
+            LogFormat::Pretty => tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .finish()
+                .try_init(),
+            LogFormat::Json => json_subscriber(filter, std::io::stdout).try_init(),
+        };
+        if let Err(err) = installed {
+            eprintln!("logger already installed: {}", err);
+        }
 
-        tracing_subscriber::fmt()
-            .with_env_filter(
-                EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-            )
-            .init();
+        let provider = match options.otlp_endpoint.as_ref() {
+            Some(endpoint) => match opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()
+            {
+                Ok(exporter) => {
+                    tracing::info!("exporting spans to {}", endpoint);
+                    SdkTracerProvider::builder()
+                        .with_batch_exporter(exporter)
+                        .build()
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to build otlp exporter for {}, writing spans to stdout: {}",
+                        endpoint,
+                        err
+                    );
+                    stdout_provider()
+                }
+            },
+            None => stdout_provider(),
+        };
+        global::set_tracer_provider(provider.clone());
 
         provider
     })
 }
+
+fn stdout_provider() -> SdkTracerProvider {
+    SdkTracerProvider::builder()
+        .with_simple_exporter(SpanExporter::default())
+        .build()
+}
+
+/// `RUST_LOG` when set, otherwise the configured level.
+fn env_filter(log_level: Option<&str>) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        let log_level = log_level.unwrap_or("info");
+        EnvFilter::try_new(log_level).unwrap_or_else(|err| {
+            eprintln!("invalid log level {}, using info: {}", log_level, err);
+            EnvFilter::new("info")
+        })
+    })
+}
+
+fn json_subscriber<W>(filter: EnvFilter, writer: W) -> impl Subscriber + Send + Sync + 'static
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(false)
+        .fmt_fields(JsonFields)
+        .event_format(JsonFormat)
+        .finish()
+}
+
+/// Writes each event as one json object with its timestamp, level, target, fields and the
+/// spans it was recorded in.
+struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let mut fields = span
+                    .extensions()
+                    .get::<FormattedFields<JsonFields>>()
+                    .and_then(|formatted| serde_json::from_str(formatted).ok())
+                    .unwrap_or_else(Map::new);
+                fields.insert("name".to_string(), span.name().into());
+                spans.push(Value::Object(fields));
+            }
+        }
+
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), timestamp.into());
+        line.insert("level".to_string(), metadata.level().to_string().into());
+        line.insert("target".to_string(), metadata.target().into());
+        line.insert("fields".to_string(), Value::Object(fields));
+        if let Some(span) = spans.last() {
+            line.insert("span".to_string(), span.clone());
+        }
+        line.insert("spans".to_string(), Value::Array(spans));
+
+        let line = serde_json::to_string(&line).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Stores the fields of spans as json objects, so that `JsonFormat` can add them to events.
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        let json = serde_json::to_string(&map).map_err(|_| fmt::Error)?;
+        writer.write_str(&json)
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut map: Map<String, Value> = serde_json::from_str(current).unwrap_or_default();
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = serde_json::to_string(&map).map_err(|_| fmt::Error)?;
+        Ok(())
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// W3C trace context headers propagated on every hop so that upstream calls join the trace.
+pub const TRACE_CONTEXT_HEADERS: [&str; 3] =
+    [TRACE_PARENT_HEADER, TRACE_STATE_HEADER, BAGGAGE_HEADER];
+
+/// Collects the trace context headers from `headers`. Header names are matched case
+/// insensitively and repeated `tracestate`/`baggage` entries are kept.
+pub fn trace_context_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut trace_context = HeaderMap::new();
+    for name in TRACE_CONTEXT_HEADERS {
+        for value in headers.get_all(name) {
+            trace_context.append(name, value.clone());
+        }
+    }
+    trace_context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{HeaderName, HeaderValue};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_trace_context_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_bytes(b"TraceParent").unwrap(),
+            HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+        );
+        headers.insert(
+            HeaderName::from_bytes(b"TRACESTATE").unwrap(),
+            HeaderValue::from_static("congo=t61rcWkgMzE"),
+        );
+        headers.insert(
+            HeaderName::from_bytes(b"Baggage").unwrap(),
+            HeaderValue::from_static("userId=alice"),
+        );
+        headers.insert("x-request-id", HeaderValue::from_static("1234"));
+
+        let trace_context = trace_context_headers(&headers);
+
+        assert_eq!(trace_context.len(), 3);
+        assert_eq!(
+            trace_context.get(TRACE_PARENT_HEADER).unwrap(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+        assert_eq!(
+            trace_context.get(TRACE_STATE_HEADER).unwrap(),
+            "congo=t61rcWkgMzE"
+        );
+        assert_eq!(trace_context.get(BAGGAGE_HEADER).unwrap(), "userId=alice");
+        assert!(trace_context.get("x-request-id").is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_format() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = json_subscriber(EnvFilter::new("debug"), move || writer.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("chat_completion", request_id = "req-1");
+            let _entered = span.enter();
+            span.record("request_id", "req-2");
+            tracing::info!(model = "gpt-4o", "routing \"done\"");
+            tracing::debug!(attempt = 2, retry = true, "retrying");
+            tracing::trace!("filtered out");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "routing \"done\"");
+        assert_eq!(lines[0]["fields"]["model"], "gpt-4o");
+        assert_eq!(lines[0]["span"]["name"], "chat_completion");
+        assert_eq!(lines[0]["span"]["request_id"], "req-2");
+        assert_eq!(lines[0]["spans"].as_array().unwrap().len(), 1);
+        assert!(lines[0]["timestamp"].is_string());
+
+        assert_eq!(lines[1]["level"], "DEBUG");
+        assert_eq!(lines[1]["fields"]["attempt"], 2);
+        assert_eq!(lines[1]["fields"]["retry"], true);
+    }
+
+    #[test]
+    fn test_options_from_config() {
+        let options = TracerOptions::from_config(None);
+        assert_eq!(options.log_format, LogFormat::Pretty);
+        assert!(options.otlp_endpoint.is_none());
+
+        let tracing = Tracing {
+            log_level: Some("debug".to_string()),
+            log_format: Some(LogFormat::Json),
+            otlp_endpoint: Some("http://localhost:4317".to_string()),
+            ..Default::default()
+        };
+        let options = TracerOptions::from_config(Some(&tracing));
+        assert_eq!(options.log_level.as_deref(), Some("debug"));
+        assert_eq!(options.log_format, LogFormat::Json);
+        assert_eq!(
+            options.otlp_endpoint.as_deref(),
+            Some("http://localhost:4317")
+        );
+    }
+}
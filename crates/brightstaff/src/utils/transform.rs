@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hermesllm::providers::openai::types::{ChatCompletionsRequest, ChatCompletionsResponse};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Hooks that change requests before they are forwarded to the upstream and responses before
+/// they are returned to the client, e.g. to inject a system prompt or redact content. Only
+/// successful non streaming responses are passed to `on_response`.
+pub trait Transform: Send + Sync {
+    fn on_request(&self, _request: &mut ChatCompletionsRequest) {}
+
+    fn on_response(&self, _response: &mut ChatCompletionsResponse) {}
+}
+
+/// Transforms registered on the app state, run in the order they were added.
+#[derive(Clone, Default)]
+pub struct Transforms {
+    transforms: Vec<Arc<dyn Transform>>,
+}
+
+impl Transforms {
+    pub fn with_transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Runs the request hooks on `request` and writes the fields they changed into `body`, the
+    /// json the request was parsed from, so that fields the typed request does not know are
+    /// still forwarded. Returns whether the body changed.
+    pub fn on_request(&self, request: &mut ChatCompletionsRequest, body: &mut Value) -> bool {
+        if self.transforms.is_empty() {
+            return false;
+        }
+        let before = serde_json::to_value(&*request).unwrap_or_default();
+        for transform in &self.transforms {
+            transform.on_request(request);
+        }
+        let after = serde_json::to_value(&*request).unwrap_or_default();
+        merge_changes(body, &before, &after)
+    }
+
+    /// Runs the response hooks on the body of a chat completions response. Bodies that are not
+    /// chat completions responses are returned as they are.
+    pub fn on_response(&self, body: Bytes) -> Bytes {
+        if self.transforms.is_empty() {
+            return body;
+        }
+        let mut raw: Value = match serde_json::from_slice(&body) {
+            Ok(raw) => raw,
+            Err(_) => return body,
+        };
+        let mut response = match ChatCompletionsResponse::deserialize(&raw) {
+            Ok(response) => response,
+            Err(_) => return body,
+        };
+        let before = serde_json::to_value(&response).unwrap_or_default();
+        for transform in &self.transforms {
+            transform.on_response(&mut response);
+        }
+        let after = serde_json::to_value(&response).unwrap_or_default();
+        if !merge_changes(&mut raw, &before, &after) {
+            return body;
+        }
+        serde_json::to_vec(&raw).map(Bytes::from).unwrap_or(body)
+    }
+}
+
+/// Applies the top level fields that differ between `before` and `after` to `raw`, fields
+/// removed by a transform are removed from `raw` as well.
+fn merge_changes(raw: &mut Value, before: &Value, after: &Value) -> bool {
+    let (raw, before, after) = match (raw.as_object_mut(), before.as_object(), after.as_object()) {
+        (Some(raw), Some(before), Some(after)) => (raw, before, after),
+        _ => return false,
+    };
+    let mut changed = false;
+    for key in before.keys() {
+        if !after.contains_key(key) {
+            raw.remove(key);
+            changed = true;
+        }
+    }
+    for (key, value) in after {
+        if before.get(key) != Some(value) {
+            raw.insert(key.clone(), value.clone());
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hermesllm::providers::openai::types::ContentType;
+
+    struct StripTools;
+
+    impl Transform for StripTools {
+        fn on_request(&self, request: &mut ChatCompletionsRequest) {
+            request.tools = None;
+        }
+    }
+
+    struct Disclaimer;
+
+    impl Transform for Disclaimer {
+        fn on_response(&self, response: &mut ChatCompletionsResponse) {
+            for choice in response.choices.iter_mut() {
+                if let Some(ContentType::Text(content)) = choice.message.content.as_mut() {
+                    content.push_str(" (generated)");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_request_changes_keep_unknown_fields() {
+        let mut body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "hi"}],
+            "tools": [{"type": "function"}],
+            "response_format": {"type": "json_object"},
+        });
+        let mut request = ChatCompletionsRequest::deserialize(&body).unwrap();
+
+        assert!(!Transforms::default().on_request(&mut request, &mut body));
+        assert!(Transforms::default()
+            .with_transform(StripTools)
+            .on_request(&mut request, &mut body));
+        assert!(request.tools.is_none());
+        assert!(body.get("tools").is_none());
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert_eq!(body["messages"][0]["content"], "hi");
+    }
+
+    #[test]
+    fn test_response_hook() {
+        let body = Bytes::from(
+            r#"{"id": "1", "object": "chat.completion", "created": 0, "model": "gpt-4o", "choices": [{"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"}]}"#,
+        );
+        let transformed = Transforms::default()
+            .with_transform(Disclaimer)
+            .on_response(body.clone());
+        let transformed: Value = serde_json::from_slice(&transformed).unwrap();
+        assert_eq!(
+            transformed["choices"][0]["message"]["content"],
+            "hello (generated)"
+        );
+        // fields the typed response does not know are kept
+        assert_eq!(transformed["model"], "gpt-4o");
+
+        // other bodies are passed through
+        let error = Bytes::from(r#"{"error": {"message": "boom"}}"#);
+        assert_eq!(
+            Transforms::default()
+                .with_transform(Disclaimer)
+                .on_response(error.clone()),
+            error
+        );
+    }
+}
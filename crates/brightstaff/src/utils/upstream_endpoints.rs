@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use common::configuration::LlmProvider;
+use hermesllm::providers::azure_openai::types::AzureOpenAiProvider;
+use hermesllm::providers::bedrock::types::BedrockProvider;
+use hermesllm::providers::cohere::types::CohereProvider;
+use hermesllm::providers::groq::types::GroqProvider;
+use hermesllm::providers::ollama::types::OllamaProvider;
+use hermesllm::providers::openai::compatible::OpenAiCompatibleProvider;
+
+/// Where the requests of a provider are sent, and so which url and body format they get.
+#[derive(Debug, Clone)]
+pub enum UpstreamEndpoint {
+    /// The llm gateway, which picks the provider from the hint header.
+    Gateway,
+    OpenAiCompatible(OpenAiCompatibleProvider),
+    AzureOpenAi(AzureOpenAiProvider),
+    Bedrock(BedrockProvider),
+    Ollama(OllamaProvider),
+    Cohere(CohereProvider),
+    Groq(GroqProvider),
+}
+
+impl UpstreamEndpoint {
+    /// An openai compatible block wins over the provider interface, providers without a
+    /// backend of their own go through the llm gateway.
+    pub fn for_provider(provider: &LlmProvider) -> Self {
+        if let Some(compatible_provider) = provider.openai_compatible_provider() {
+            UpstreamEndpoint::OpenAiCompatible(compatible_provider)
+        } else if let Some(azure_openai_provider) = provider.azure_openai_provider() {
+            UpstreamEndpoint::AzureOpenAi(azure_openai_provider)
+        } else if let Some(bedrock_provider) = provider.bedrock_provider() {
+            UpstreamEndpoint::Bedrock(bedrock_provider)
+        } else if let Some(ollama_provider) = provider.ollama_provider() {
+            UpstreamEndpoint::Ollama(ollama_provider)
+        } else if let Some(cohere_provider) = provider.cohere_provider() {
+            UpstreamEndpoint::Cohere(cohere_provider)
+        } else if let Some(groq_provider) = provider.groq_provider() {
+            UpstreamEndpoint::Groq(groq_provider)
+        } else {
+            UpstreamEndpoint::Gateway
+        }
+    }
+}
+
+/// Upstream endpoint of every configured provider, resolved once at startup. A route is sent
+/// to the endpoint of the provider it resolved to, which the request may change through its
+/// usage preferences or a route override.
+#[derive(Debug, Default)]
+pub struct UpstreamEndpoints {
+    endpoints: HashMap<String, UpstreamEndpoint>,
+}
+
+impl UpstreamEndpoints {
+    pub fn from_providers(providers: &[LlmProvider]) -> Self {
+        UpstreamEndpoints {
+            endpoints: providers
+                .iter()
+                .map(|provider| {
+                    (
+                        provider.name.clone(),
+                        UpstreamEndpoint::for_provider(provider),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Endpoint of the provider, the llm gateway for providers that are not configured.
+    pub fn get(&self, provider_name: &str) -> &UpstreamEndpoint {
+        self.endpoints
+            .get(provider_name)
+            .unwrap_or(&UpstreamEndpoint::Gateway)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_per_provider() {
+        let providers: Vec<LlmProvider> = serde_yaml::from_str(
+            r#"
+- name: gpt-4o
+  provider_interface: openai
+- name: mistral-large
+  provider_interface: mistral
+  openai_compatible:
+    base_url: https://api.mistral.ai/v1
+- name: azure-o1
+  provider_interface: azure_openai
+  azure_openai:
+    endpoint: https://example.openai.azure.com
+- name: claude-bedrock
+  provider_interface: bedrock
+- name: llama3.2
+  provider_interface: ollama
+- name: command-r-plus
+  provider_interface: cohere
+- name: llama-3.3-70b
+  provider_interface: groq
+  groq:
+    models: [llama-3.3-70b-versatile]
+- name: llama-3.1-8b
+  provider_interface: groq
+"#,
+        )
+        .unwrap();
+        let endpoints = UpstreamEndpoints::from_providers(&providers);
+
+        assert!(matches!(endpoints.get("gpt-4o"), UpstreamEndpoint::Gateway));
+        assert!(matches!(
+            endpoints.get("mistral-large"),
+            UpstreamEndpoint::OpenAiCompatible(_)
+        ));
+        assert!(matches!(
+            endpoints.get("azure-o1"),
+            UpstreamEndpoint::AzureOpenAi(_)
+        ));
+        assert!(matches!(
+            endpoints.get("claude-bedrock"),
+            UpstreamEndpoint::Bedrock(_)
+        ));
+        assert!(matches!(
+            endpoints.get("llama3.2"),
+            UpstreamEndpoint::Ollama(_)
+        ));
+        assert!(matches!(
+            endpoints.get("command-r-plus"),
+            UpstreamEndpoint::Cohere(_)
+        ));
+        assert!(matches!(
+            endpoints.get("llama-3.3-70b"),
+            UpstreamEndpoint::Groq(_)
+        ));
+        // groq without its block still goes through the llm gateway
+        assert!(matches!(
+            endpoints.get("llama-3.1-8b"),
+            UpstreamEndpoint::Gateway
+        ));
+        assert!(matches!(
+            endpoints.get("unknown"),
+            UpstreamEndpoint::Gateway
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use hermesllm::providers::openai::types::Usage;
+use serde::Deserialize;
+
+/// Any response body or stream chunk that may report the token usage, or an error.
+#[derive(Debug, Deserialize)]
+struct UsageReport {
+    usage: Option<Usage>,
+    error: Option<serde_json::Value>,
+}
+
+/// Picks up the token usage reported by the upstream while its response is forwarded. Streams
+/// are scanned line by line for the chunk carrying the usage, which openai sends last when
+/// `stream_options.include_usage` is set. Non streaming bodies are buffered and parsed at the
+/// end. Error events sent by the upstream after a successful status are picked up as well.
+pub struct UsageTracker {
+    is_streaming: bool,
+    buffer: Vec<u8>,
+    usage: Option<Usage>,
+    error: Option<String>,
+}
+
+impl UsageTracker {
+    pub fn new(is_streaming: bool) -> Self {
+        UsageTracker {
+            is_streaming,
+            buffer: Vec::new(),
+            usage: None,
+            error: None,
+        }
+    }
+
+    /// Message of the first error event in the stream.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        if !self.is_streaming {
+            return;
+        }
+        while let Some(position) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=position).collect();
+            self.process_line(&line);
+        }
+    }
+
+    pub fn finish(mut self) -> Option<Usage> {
+        let remaining = std::mem::take(&mut self.buffer);
+        if self.is_streaming {
+            self.process_line(&remaining);
+        } else if let Ok(report) = serde_json::from_slice::<UsageReport>(&remaining) {
+            self.usage = report.usage;
+        }
+        self.usage
+    }
+
+    fn process_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let data = match line.trim().strip_prefix("data:") {
+            Some(data) => data.trim(),
+            None => return,
+        };
+        let report = match serde_json::from_str::<UsageReport>(data) {
+            Ok(report) => report,
+            Err(_) => return,
+        };
+        if let Some(usage) = report.usage {
+            self.usage = Some(usage);
+        }
+        if let (Some(error), None) = (report.error, self.error.as_ref()) {
+            self.error = Some(
+                error
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| error.to_string()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streaming_usage_split_across_chunks() {
+        let stream = concat!(
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}],\"usage\":null}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":1,\"total_tokens\":10}}\n\n",
+            "data: [DONE]\n\n"
+        );
+
+        for fragment_size in [1, 16, stream.len()] {
+            let mut tracker = UsageTracker::new(true);
+            for fragment in stream.as_bytes().chunks(fragment_size) {
+                tracker.push(fragment);
+            }
+            let usage = tracker.finish().unwrap();
+            assert_eq!(usage.prompt_tokens, 9);
+            assert_eq!(usage.completion_tokens, 1);
+            assert_eq!(usage.total_tokens, 10);
+        }
+    }
+
+    #[test]
+    fn test_streaming_without_usage() {
+        let mut tracker = UsageTracker::new(true);
+        tracker.push(b"data: {\"choices\":[],\"usage\":null}\n\ndata: [DONE]\n\n");
+        assert!(tracker.finish().is_none());
+    }
+
+    #[test]
+    fn test_streaming_error() {
+        let stream = concat!(
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}],\"usage\":null}\n\n",
+            "data: {\"error\":{\"message\":\"Rate limit reached\",\"type\":\"requests\",\"param\":null,\"code\":\"rate_limit_exceeded\"}}\n\n",
+        );
+        let mut tracker = UsageTracker::new(true);
+        for fragment in stream.as_bytes().chunks(9) {
+            tracker.push(fragment);
+        }
+        assert_eq!(tracker.error(), Some("Rate limit reached"));
+        assert!(tracker.finish().is_none());
+
+        let mut tracker = UsageTracker::new(true);
+        tracker.push(b"data: {\"choices\":[],\"usage\":null}\n\ndata: [DONE]\n\n");
+        assert!(tracker.error().is_none());
+    }
+
+    #[test]
+    fn test_non_streaming_usage() {
+        let body = r#"{"id":"chatcmpl-1","object":"chat.completion","created":1,"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42}}"#;
+        let mut tracker = UsageTracker::new(false);
+        for fragment in body.as_bytes().chunks(7) {
+            tracker.push(fragment);
+        }
+        assert_eq!(tracker.finish().unwrap().total_tokens, 42);
+    }
+}
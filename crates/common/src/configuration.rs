@@ -1,3 +1,10 @@
+use hermesllm::providers::azure_openai::types::AzureOpenAiProvider;
+use hermesllm::providers::bedrock::types::{BedrockProvider, DEFAULT_REGION};
+use hermesllm::providers::cohere::types::{CohereProvider, DEFAULT_BASE_URL as COHERE_BASE_URL};
+use hermesllm::providers::gemini::types::GeminiApi;
+use hermesllm::providers::groq::types::{GroqProvider, DEFAULT_BASE_URL as GROQ_BASE_URL};
+use hermesllm::providers::ollama::types::{OllamaProvider, DEFAULT_BASE_URL};
+use hermesllm::providers::openai::compatible::{AuthHeaderStyle, OpenAiCompatibleProvider};
 use hermesllm::providers::openai::types::{ModelDetail, ModelObject, Models};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -11,6 +18,79 @@ use crate::api::open_ai::{
 pub struct Routing {
     pub llm_provider: Option<String>,
     pub model: Option<String>,
+    pub default_route: Option<String>,
+    pub embedding: Option<EmbeddingRouting>,
+    /// Timeout of the call to the routing model.
+    pub timeout_ms: Option<u64>,
+    /// Use the default route instead of failing the request when the routing model times out.
+    pub fallback_on_timeout: Option<bool>,
+    /// Models requests may be served by, checked after routing. All models when not set.
+    pub allowed_models: Option<Vec<String>>,
+    /// Stream the answer of the routing model and stop reading once its JSON is complete.
+    pub stream: Option<bool>,
+    /// Decisions with a lower confidence go to the candidate route with the highest priority,
+    /// then the lowest cost tier. Decisions are taken as they are when not set.
+    pub tie_break_confidence: Option<f32>,
+    /// Decisions of the routing model with a lower confidence are dropped for the default route,
+    /// routes may set their own. Decisions without a confidence are accepted.
+    pub min_confidence: Option<f32>,
+    /// Moderation check of the latest user message before routing.
+    pub guard: Option<RoutingGuard>,
+    /// Records of the routing model calls for a sample of the requests, nothing is recorded
+    /// when not set.
+    pub log: Option<RoutingLog>,
+    /// Resolution of parent routes the routing model answers with, e.g. `billing` for the
+    /// routes `billing.refund` and `billing.status`. Only configured routes are accepted when
+    /// not set.
+    pub route_hierarchy: Option<RouteHierarchy>,
+}
+
+/// Routes named hierarchically, a parent route is a prefix of its children's names.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteHierarchy {
+    /// Separator between a parent and its children, `.` when not set.
+    pub separator: Option<String>,
+    /// Child route each parent resolves to.
+    pub default_children: Option<HashMap<String, String>>,
+    /// Resolve a parent without a default child to its only child, parents with several
+    /// children are ambiguous and fail the request.
+    pub prefix_match: Option<bool>,
+}
+
+/// Sample of the routing model's prompts, answers and chosen routes, e.g. to improve route
+/// descriptions. The prompts carry the conversations of the requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingLog {
+    /// File the records are appended to, one JSON object per line.
+    pub path: String,
+    /// Share of the routing model calls recorded, between 0 and 1.
+    pub sample_rate: f64,
+    /// Message content of the recorded prompts is cut to this many characters, prompts are
+    /// recorded whole when not set.
+    pub content_max_chars: Option<usize>,
+}
+
+/// Prompt guard endpoint checking the latest user message for jailbreaks and toxic content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingGuard {
+    /// Endpoint taking a `PromptGuardRequest`, e.g. `http://localhost:12000/guard`.
+    pub url: String,
+    /// Minimum probability for a message to be blocked.
+    pub threshold: Option<f64>,
+    /// Route blocked requests are sent to instead of being rejected.
+    pub route: Option<String>,
+    /// Reject requests when the guard fails, they are let through when not set.
+    pub fail_closed: Option<bool>,
+}
+
+/// Routes on embedding similarity instead of asking the routing model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRouting {
+    /// OpenAI compatible embeddings endpoint, e.g. `http://localhost:12001/v1/embeddings`.
+    pub url: String,
+    pub model: String,
+    /// Minimum cosine similarity for a route to be selected.
+    pub threshold: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +107,131 @@ pub struct Configuration {
     pub tracing: Option<Tracing>,
     pub mode: Option<GatewayMode>,
     pub routing: Option<Routing>,
+    pub upstream: Option<Upstream>,
+    pub health: Option<Health>,
+    /// Caches the responses of deterministic requests, disabled when not set.
+    pub response_cache: Option<ResponseCache>,
+    /// Replays of requests sent again with the same `Idempotency-Key` header.
+    pub idempotency: Option<Idempotency>,
+    /// Compression of the non streaming responses sent to clients.
+    pub compression: Option<Compression>,
+    /// Admin endpoints, e.g. the reload of the routes. Disabled when not set.
+    pub admin: Option<Admin>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Admin {
+    /// Token admin requests carry as `Authorization: Bearer <token>`.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResponseCache {
+    /// Time a cached response is served for.
+    pub ttl_ms: Option<u64>,
+    /// Responses held at most, the oldest is evicted first.
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Compression {
+    /// Compress responses for clients that send `Accept-Encoding`, defaults to true.
+    pub enabled: Option<bool>,
+    /// Smaller responses are sent as they are.
+    pub min_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Idempotency {
+    /// Time the response of a key is replayed for.
+    pub ttl_ms: Option<u64>,
+    /// Keys held at most, the oldest is evicted first.
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Health {
+    /// Probe the upstream endpoints on readiness checks, disabled for air-gapped setups.
+    pub probe_upstreams: Option<bool>,
+    pub probe_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Upstream {
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout_ms: Option<u64>,
+    /// Timeout of opening a connection to an upstream, so that unreachable providers fail
+    /// fast.
+    pub connect_timeout_ms: Option<u64>,
+    /// Timeout of a non streaming request, reading the whole response included.
+    pub timeout_ms: Option<u64>,
+    /// Longest wait for the response of a stream and between its chunks, generation may take
+    /// long but the stream as a whole is not timed out. Takes precedence over
+    /// `stream_idle_timeout_ms`.
+    pub read_timeout_ms: Option<u64>,
+    pub stream_idle_timeout_ms: Option<u64>,
+    /// Interval of the keep-alive comments sent on streams until the first chunk arrives, 0
+    /// disables them.
+    pub stream_keep_alive_ms: Option<u64>,
+    /// Chunks buffered per response between the upstream and the client.
+    pub stream_buffer_chunks: Option<usize>,
+    /// Ask upstreams for gzip or brotli responses, decoded before they are processed. Defaults
+    /// to true.
+    pub compression: Option<bool>,
+    pub retry: Option<Retry>,
+    /// Stops calling a provider that keeps failing, disabled when not set.
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Caps the requests sent to the providers at the same time, unbounded when not set.
+    pub concurrency: Option<Concurrency>,
+    pub tls: Option<UpstreamTls>,
+}
+
+/// TLS settings of the upstream client, e.g. for providers behind a private CA or requiring
+/// mutual TLS.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct UpstreamTls {
+    /// PEM bundle of root certificates trusted in addition to the system ones.
+    pub ca_cert_path: Option<String>,
+    /// PEM certificate presented to upstreams requiring mutual TLS, set with `client_key_path`.
+    pub client_cert_path: Option<String>,
+    /// PKCS#8 PEM private key of the client certificate.
+    pub client_key_path: Option<String>,
+    /// Accepts any upstream certificate. Only meant for tests against self-signed upstreams,
+    /// never for production traffic.
+    pub insecure_skip_verify: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Concurrency {
+    /// Requests in flight to all providers together.
+    pub max_in_flight: Option<usize>,
+    /// Requests in flight to a single provider.
+    pub max_in_flight_per_provider: Option<usize>,
+    /// Requests waiting for a free slot, requests beyond it are rejected. None wait when not set.
+    pub max_queued: Option<usize>,
+    /// Time a queued request waits for a free slot before it is rejected.
+    pub queue_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CircuitBreaker {
+    /// Share of failed requests in the window that opens the circuit, e.g. `0.5`.
+    pub failure_ratio: Option<f64>,
+    /// Requests in the window before the failure ratio is considered.
+    pub min_requests: Option<u32>,
+    /// Number of most recent requests the failure ratio is computed over.
+    pub window_size: Option<u32>,
+    /// Time an open circuit rejects requests before a probe request is let through.
+    pub cooldown_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Retry {
+    pub max_attempts: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub retryable_status_codes: Option<Vec<u16>>,
+    pub max_retry_after_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -34,12 +239,35 @@ pub struct Overrides {
     pub prompt_target_intent_matching_threshold: Option<f64>,
     pub optimize_context_window: Option<bool>,
     pub use_agent_orchestrator: Option<bool>,
+    pub max_request_body_bytes: Option<usize>,
+    /// Log the request body with message content cut to `log_content_max_chars`, by default
+    /// only request metadata is logged.
+    pub log_request_content: Option<bool>,
+    pub log_content_max_chars: Option<usize>,
+    /// How long shutdown waits for in-flight requests and streams before closing them.
+    pub shutdown_drain_timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Tracing {
     pub sampling_rate: Option<f64>,
     pub trace_arch_internal: Option<bool>,
+    /// Log filter like `info,brightstaff=debug`, `RUST_LOG` takes precedence when set.
+    pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    /// OTLP gRPC collector spans are exported to, e.g. `http://localhost:4317`.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    #[serde(rename = "pretty")]
+    Pretty,
+    /// One JSON object per line, for log aggregation.
+    #[serde(rename = "json")]
+    Json,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
@@ -161,6 +389,14 @@ pub enum LlmProviderType {
     OpenAI,
     #[serde(rename = "gemini")]
     Gemini,
+    #[serde(rename = "azure_openai")]
+    AzureOpenAI,
+    #[serde(rename = "bedrock")]
+    Bedrock,
+    #[serde(rename = "ollama")]
+    Ollama,
+    #[serde(rename = "cohere")]
+    Cohere,
 }
 
 impl Display for LlmProviderType {
@@ -173,6 +409,10 @@ impl Display for LlmProviderType {
             LlmProviderType::Gemini => write!(f, "gemini"),
             LlmProviderType::Mistral => write!(f, "mistral"),
             LlmProviderType::OpenAI => write!(f, "openai"),
+            LlmProviderType::AzureOpenAI => write!(f, "azure_openai"),
+            LlmProviderType::Bedrock => write!(f, "bedrock"),
+            LlmProviderType::Ollama => write!(f, "ollama"),
+            LlmProviderType::Cohere => write!(f, "cohere"),
         }
     }
 }
@@ -183,10 +423,71 @@ pub struct ModelUsagePreference {
     pub routing_preferences: Vec<RoutingPreference>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct RoutingPreference {
     pub name: String,
     pub description: String,
+    /// Other names of the route, shown to the routing model. A selected alias resolves to this
+    /// route and its provider.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+    /// Keywords that select the route without asking the routing model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keywords: Option<Vec<String>>,
+    /// Regular expressions that select the route without asking the routing model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patterns: Option<Vec<String>>,
+    /// Models the route may resolve to, e.g. through usage preferences sent with the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_models: Option<Vec<String>>,
+    /// Limits requests served by the route, checked before the upstream call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RouteRateLimit>,
+    /// Preference among candidate routes of a low confidence decision, higher wins. 0 when not
+    /// set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Relative cost of the route, among candidates of equal priority the lowest wins. Routes
+    /// without a cost tier come last.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_tier: Option<u32>,
+    /// Minimum confidence of a decision for the route, replaces the one of the routing config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_confidence: Option<f32>,
+    /// Providers sharing the traffic of the route in proportion to their weights, e.g. to
+    /// evaluate a new provider on a share of the requests. The route is served by the provider
+    /// it is configured on when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split: Option<Vec<ProviderWeight>>,
+    /// Providers tried in order when the provider of the route can't serve a request: it
+    /// fails with a server error or a timeout, its circuit is open or it does not support the
+    /// request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallbacks: Option<Vec<String>>,
+    /// Stop sequences added to the `stop` of every request served by the route, e.g. to end
+    /// answers that start playing another role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+/// Share of the traffic of a route sent to a provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderWeight {
+    /// Name of the provider in `llm_providers`.
+    pub provider: String,
+    pub weight: u32,
+}
+
+/// Token bucket limits of a route, the buckets refill continuously over a minute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteRateLimit {
+    pub requests_per_minute: u32,
+    /// Tokens per minute as reported in the upstream usage, requests are rejected once the
+    /// tokens of earlier responses used up the bucket.
+    pub tokens_per_minute: Option<u32>,
+    /// Header identifying the client, e.g. `x-client-id`. Each of its values gets its own
+    /// buckets, requests without it share one.
+    pub client_header: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -203,6 +504,158 @@ pub struct LlmProvider {
     pub rate_limits: Option<LlmRatelimit>,
     pub usage: Option<String>,
     pub routing_preferences: Option<Vec<RoutingPreference>>,
+    /// Only used by the gemini provider interface, defaults to the Generative Language API.
+    pub gemini_api: Option<GeminiApi>,
+    /// Sends requests straight to an OpenAI compatible backend instead of through the llm
+    /// gateway.
+    pub openai_compatible: Option<OpenAiCompatible>,
+    /// Only used by the azure_openai provider interface.
+    pub azure_openai: Option<AzureOpenAi>,
+    /// Only used by the bedrock provider interface.
+    pub bedrock: Option<Bedrock>,
+    /// Only used by the ollama provider interface.
+    pub ollama: Option<Ollama>,
+    /// Only used by the cohere provider interface.
+    pub cohere: Option<Cohere>,
+    /// Sends requests of the groq provider interface straight to groq instead of through the
+    /// llm gateway.
+    pub groq: Option<Groq>,
+    /// Fills in the fields of successful non streaming responses the OpenAI SDKs require, for
+    /// compatible backends that leave them out.
+    pub normalize_response: Option<bool>,
+    /// Static headers sent with every request to the provider, e.g. `anthropic-version`. The
+    /// auth header of the access key takes precedence.
+    pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAiCompatible {
+    /// Base url including the api version, e.g. `https://api.together.xyz/v1`.
+    pub base_url: String,
+    pub auth_header: Option<AuthHeaderStyle>,
+    pub model_map: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AzureOpenAi {
+    /// Resource endpoint, e.g. `https://my-resource.openai.azure.com`.
+    pub endpoint: String,
+    pub api_version: Option<String>,
+    /// Model name to the name of the deployment serving it, models that are not listed are
+    /// assumed to be deployed under their own name.
+    pub deployments: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Bedrock {
+    /// Defaults to `us-east-1`.
+    pub region: Option<String>,
+    /// Model name to the Bedrock model id or inference profile serving it, e.g.
+    /// `anthropic.claude-3-5-sonnet-20240620-v1:0`.
+    pub model_ids: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Ollama {
+    /// Defaults to `http://localhost:11434`.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Cohere {
+    /// Defaults to `https://api.cohere.com`.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Groq {
+    /// Defaults to `https://api.groq.com/openai/v1`.
+    pub base_url: Option<String>,
+    /// Environment variable the api key is read from when no access key is configured.
+    pub api_key_env: Option<String>,
+    /// Model ids requests may be sent with, e.g. `llama-3.3-70b-versatile`. Any model when not
+    /// set.
+    pub models: Option<Vec<String>>,
+}
+
+impl LlmProvider {
+    pub fn openai_compatible_provider(&self) -> Option<OpenAiCompatibleProvider> {
+        let openai_compatible = self.openai_compatible.as_ref()?;
+        Some(
+            OpenAiCompatibleProvider::new(openai_compatible.base_url.clone())
+                .with_auth_header_style(openai_compatible.auth_header.clone().unwrap_or_default())
+                .with_model_map(openai_compatible.model_map.clone().unwrap_or_default())
+                .with_default_model(self.model.clone()),
+        )
+    }
+
+    pub fn azure_openai_provider(&self) -> Option<AzureOpenAiProvider> {
+        if self.provider_interface != LlmProviderType::AzureOpenAI {
+            return None;
+        }
+        let azure_openai = self.azure_openai.as_ref()?;
+        let mut provider = AzureOpenAiProvider::new(azure_openai.endpoint.clone())
+            .with_deployments(azure_openai.deployments.clone().unwrap_or_default())
+            .with_default_model(self.model.clone());
+        if let Some(api_version) = azure_openai.api_version.as_ref() {
+            provider = provider.with_api_version(api_version.clone());
+        }
+        Some(provider)
+    }
+
+    /// Requests are signed with the AWS credentials of the environment, no access key is used.
+    pub fn bedrock_provider(&self) -> Option<BedrockProvider> {
+        if self.provider_interface != LlmProviderType::Bedrock {
+            return None;
+        }
+        let bedrock = self.bedrock.clone().unwrap_or_default();
+        Some(
+            BedrockProvider::new(bedrock.region.unwrap_or_else(|| DEFAULT_REGION.to_string()))
+                .with_model_ids(bedrock.model_ids.unwrap_or_default())
+                .with_default_model(self.model.clone()),
+        )
+    }
+
+    pub fn ollama_provider(&self) -> Option<OllamaProvider> {
+        if self.provider_interface != LlmProviderType::Ollama {
+            return None;
+        }
+        let base_url = self
+            .ollama
+            .as_ref()
+            .and_then(|ollama| ollama.base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Some(OllamaProvider::new(base_url).with_default_model(self.model.clone()))
+    }
+
+    pub fn cohere_provider(&self) -> Option<CohereProvider> {
+        if self.provider_interface != LlmProviderType::Cohere {
+            return None;
+        }
+        let base_url = self
+            .cohere
+            .as_ref()
+            .and_then(|cohere| cohere.base_url.clone())
+            .unwrap_or_else(|| COHERE_BASE_URL.to_string());
+        Some(CohereProvider::new(base_url).with_default_model(self.model.clone()))
+    }
+
+    pub fn groq_provider(&self) -> Option<GroqProvider> {
+        if self.provider_interface != LlmProviderType::Groq {
+            return None;
+        }
+        let groq = self.groq.as_ref()?;
+        Some(
+            GroqProvider::new(
+                groq.base_url
+                    .clone()
+                    .unwrap_or_else(|| GROQ_BASE_URL.to_string()),
+            )
+            .with_api_key_env(groq.api_key_env.clone())
+            .with_models(groq.models.clone().unwrap_or_default())
+            .with_default_model(self.model.clone()),
+        )
+    }
 }
 
 pub trait IntoModels {
@@ -210,16 +663,36 @@ pub trait IntoModels {
 }
 
 impl IntoModels for Vec<LlmProvider> {
+    /// One entry per model name in the order they are configured, a name configured more than
+    /// once is listed once with the routes of all its entries.
     fn into_models(self) -> Models {
-        let data = self
-            .iter()
-            .map(|provider| ModelDetail {
-                id: provider.name.clone(),
-                object: "model".to_string(),
-                created: 0,
-                owned_by: "system".to_string(),
-            })
-            .collect();
+        let mut data: Vec<ModelDetail> = vec![];
+        for provider in &self {
+            let routes = provider
+                .routing_preferences
+                .iter()
+                .flatten()
+                .map(|pref| pref.name.clone());
+
+            let model = match data.iter_mut().find(|model| model.id == provider.name) {
+                Some(model) => model,
+                None => {
+                    data.push(ModelDetail {
+                        id: provider.name.clone(),
+                        object: "model".to_string(),
+                        created: 0,
+                        owned_by: "system".to_string(),
+                        routes: vec![],
+                    });
+                    data.last_mut().unwrap()
+                }
+            };
+            for route in routes {
+                if !model.routes.contains(&route) {
+                    model.routes.push(route);
+                }
+            }
+        }
 
         Models {
             object: ModelObject::List,
@@ -242,6 +715,15 @@ impl Default for LlmProvider {
             rate_limits: None,
             usage: None,
             routing_preferences: None,
+            gemini_api: None,
+            openai_compatible: None,
+            azure_openai: None,
+            bedrock: None,
+            ollama: None,
+            cohere: None,
+            groq: None,
+            normalize_response: None,
+            headers: None,
         }
     }
 }
@@ -351,6 +833,8 @@ mod test {
 
     use crate::{api::open_ai::ToolType, configuration::GuardType};
 
+    use super::{IntoModels, LlmProvider};
+
     #[test]
     fn test_deserialize_configuration() {
         let ref_config = fs::read_to_string(
@@ -483,4 +967,99 @@ mod test {
             crate::api::open_ai::ParameterType::Bool
         );
     }
+
+    #[test]
+    fn test_into_models() {
+        let providers: Vec<LlmProvider> = serde_yaml::from_str(
+            r#"
+- name: gpt-4o
+  provider_interface: openai
+  routing_preferences:
+    - name: image-generation
+      description: generating image
+- name: claude-3-7-sonnet
+  provider_interface: claude
+  routing_preferences:
+    - name: code-generation
+      description: generating new code snippets
+- name: gpt-4o
+  provider_interface: openai
+  routing_preferences:
+    - name: summarization
+      description: summarizing documents
+    - name: image-generation
+      description: generating image
+- name: mistral-large
+  provider_interface: mistral
+"#,
+        )
+        .unwrap();
+
+        let models = serde_json::to_value(providers.into_models()).unwrap();
+        assert_eq!(
+            models,
+            serde_json::json!({
+                "object": "list",
+                "data": [
+                    {
+                        "id": "gpt-4o",
+                        "object": "model",
+                        "created": 0,
+                        "owned_by": "system",
+                        "routes": ["image-generation", "summarization"]
+                    },
+                    {
+                        "id": "claude-3-7-sonnet",
+                        "object": "model",
+                        "created": 0,
+                        "owned_by": "system",
+                        "routes": ["code-generation"]
+                    },
+                    {
+                        "id": "mistral-large",
+                        "object": "model",
+                        "created": 0,
+                        "owned_by": "system"
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_azure_openai_provider() {
+        let providers: Vec<LlmProvider> = serde_yaml::from_str(
+            r#"
+- name: azure-gpt-4o
+  provider_interface: azure_openai
+  model: gpt-4o
+  azure_openai:
+    endpoint: https://my-resource.openai.azure.com
+    api_version: 2025-01-01-preview
+    deployments:
+      gpt-4o: prod-gpt-4o
+- name: azure-default
+  provider_interface: azure_openai
+  azure_openai:
+    endpoint: https://my-resource.openai.azure.com
+- name: gpt-4o
+  provider_interface: openai
+"#,
+        )
+        .unwrap();
+
+        let provider = providers[0].azure_openai_provider().unwrap();
+        assert_eq!(
+            provider.chat_completions_url("azure-gpt-4o"),
+            "https://my-resource.openai.azure.com/openai/deployments/prod-gpt-4o/chat/completions?api-version=2025-01-01-preview"
+        );
+
+        let provider = providers[1].azure_openai_provider().unwrap();
+        assert_eq!(
+            provider.chat_completions_url("gpt-4o-mini"),
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o-mini/chat/completions?api-version=2024-10-21"
+        );
+
+        assert!(providers[2].azure_openai_provider().is_none());
+    }
 }
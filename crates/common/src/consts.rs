@@ -7,10 +7,46 @@ pub const ARCH_FC_REQUEST_TIMEOUT_MS: u64 = 30000; // 30 seconds
 pub const DEFAULT_TARGET_REQUEST_TIMEOUT_MS: u64 = 30000; // 30 seconds
 pub const API_REQUEST_TIMEOUT_MS: u64 = 30000; // 30 seconds
 pub const MODEL_SERVER_REQUEST_TIMEOUT_MS: u64 = 30000; // 30 seconds
+pub const DEFAULT_UPSTREAM_TIMEOUT_MS: u64 = 120000; // 2 minutes
+pub const DEFAULT_UPSTREAM_STREAM_IDLE_TIMEOUT_MS: u64 = 120000; // 2 minutes
+pub const DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS: u64 = 5000; // 5 seconds
+pub const DEFAULT_MAX_REQUEST_BODY_BYTES: usize = 1024 * 1024; // 1MB
+pub const DEFAULT_LOG_CONTENT_MAX_CHARS: usize = 50;
+pub const DEFAULT_HEALTH_PROBE_TIMEOUT_MS: u64 = 2000;
+pub const DEFAULT_STREAM_BUFFER_CHUNKS: usize = 16;
+pub const DEFAULT_STREAM_KEEP_ALIVE_MS: u64 = 15000; // 15 seconds
+pub const DEFAULT_COMPRESSION_MIN_BYTES: usize = 1024;
+pub const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_MS: u64 = 30000; // 30 seconds
+pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 100;
+pub const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 5000; // 5 seconds
+pub const DEFAULT_RETRY_MAX_RETRY_AFTER_MS: u64 = 30000; // 30 seconds
+pub const DEFAULT_RETRYABLE_STATUS_CODES: [u16; 5] = [429, 500, 502, 503, 504];
+pub const DEFAULT_CIRCUIT_BREAKER_FAILURE_RATIO: f64 = 0.5;
+pub const DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS: u32 = 10;
+pub const DEFAULT_CIRCUIT_BREAKER_WINDOW_SIZE: u32 = 20;
+pub const DEFAULT_CIRCUIT_BREAKER_COOLDOWN_MS: u64 = 30000; // 30 seconds
+pub const DEFAULT_CONCURRENCY_QUEUE_TIMEOUT_MS: u64 = 10000; // 10 seconds
+pub const DEFAULT_RESPONSE_CACHE_TTL_MS: u64 = 300000; // 5 minutes
+pub const DEFAULT_RESPONSE_CACHE_MAX_ENTRIES: usize = 1000;
+pub const DEFAULT_IDEMPOTENCY_TTL_MS: u64 = 3600000; // 1 hour
+pub const DEFAULT_IDEMPOTENCY_MAX_ENTRIES: usize = 10000;
+pub const DEFAULT_EMBEDDING_ROUTING_THRESHOLD: f32 = 0.5;
+pub const DEFAULT_ROUTING_TIMEOUT_MS: u64 = 10000; // 10 seconds
+pub const ROUTING_MAX_ATTEMPTS: u32 = 2;
+pub const DEFAULT_ROUTING_BATCH_CONCURRENCY: usize = 8;
+pub const DEFAULT_GUARD_THRESHOLD: f64 = 0.5;
 pub const MODEL_SERVER_NAME: &str = "model_server";
 pub const ARCH_ROUTING_HEADER: &str = "x-arch-llm-provider";
 pub const MESSAGES_KEY: &str = "messages";
 pub const ARCH_PROVIDER_HINT_HEADER: &str = "x-arch-llm-provider-hint";
+pub const ARCH_ROUTE_OVERRIDE_HEADER: &str = "x-arch-route-override";
+pub const ARCH_SELECTED_ROUTE_HEADER: &str = "x-arch-selected-route";
+pub const ARCH_SELECTED_MODEL_HEADER: &str = "x-arch-selected-model";
+pub const ARCH_CACHE_HEADER: &str = "x-arch-cache";
+pub const ARCH_BUFFER_STREAM_HEADER: &str = "x-arch-buffer-stream";
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
 pub const CHAT_COMPLETIONS_PATH: &str = "/v1/chat/completions";
 pub const HEALTHZ_PATH: &str = "/healthz";
 pub const X_ARCH_STATE_HEADER: &str = "x-arch-state";
@@ -20,6 +56,8 @@ pub const X_ARCH_FC_MODEL_RESPONSE: &str = "x-arch-fc-model-response";
 pub const ARCH_FC_MODEL_NAME: &str = "Arch-Function";
 pub const REQUEST_ID_HEADER: &str = "x-request-id";
 pub const TRACE_PARENT_HEADER: &str = "traceparent";
+pub const TRACE_STATE_HEADER: &str = "tracestate";
+pub const BAGGAGE_HEADER: &str = "baggage";
 pub const ARCH_INTERNAL_CLUSTER_NAME: &str = "arch_internal";
 pub const ARCH_UPSTREAM_HOST_HEADER: &str = "x-arch-upstream";
 pub const ARCH_MODEL_PREFIX: &str = "Arch";
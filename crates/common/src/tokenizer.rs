@@ -1,15 +1,38 @@
 use log::debug;
 
-#[allow(dead_code)]
-pub fn token_count(model_name: &str, text: &str) -> Result<usize, String> {
-    debug!("getting token count model={}", model_name);
+/// Counts tokens for a piece of text. Used by components that need to budget
+/// prompt size without hard-coding a character based heuristic.
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+/// Tokenizer backed by tiktoken. Models that tiktoken does not know about are
+/// tokenized with the gpt-4o encoding, o200k_base, which is a reasonable approximation.
+pub struct TiktokenTokenizer {
+    bpe: tiktoken_rs::CoreBPE,
+}
+
+impl TiktokenTokenizer {
+    pub fn new(model_name: &str) -> Result<Self, String> {
+        let bpe = tiktoken_rs::get_bpe_from_model(tiktoken_model_name(model_name))
+            .map_err(|e| e.to_string())?;
+        Ok(TiktokenTokenizer { bpe })
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+fn tiktoken_model_name(model_name: &str) -> &str {
     //HACK: add support for tokenizing mistral and other models
     //filed issue https://github.com/katanemo/arch/issues/222
-
-    let updated_model = match model_name.starts_with("gpt-4") {
+    match model_name.starts_with("gpt-4") {
         false => {
             debug!(
-                "tiktoken_rs: unsupported model: {}, using gpt-4 to compute token count",
+                "tiktoken_rs: unsupported model: {}, using gpt-4o (o200k_base) to compute token count",
                 model_name
             );
             "gpt-4o"
@@ -21,10 +44,16 @@ pub fn token_count(model_name: &str, text: &str) -> Result<usize, String> {
                 model_name
             }
         }
-    };
+    }
+}
+
+#[allow(dead_code)]
+pub fn token_count(model_name: &str, text: &str) -> Result<usize, String> {
+    debug!("getting token count model={}", model_name);
 
     // Consideration: is it more expensive to instantiate the BPE object every time, or to contend the singleton?
-    let bpe = tiktoken_rs::get_bpe_from_model(updated_model).map_err(|e| e.to_string())?;
+    let bpe = tiktoken_rs::get_bpe_from_model(tiktoken_model_name(model_name))
+        .map_err(|e| e.to_string())?;
     Ok(bpe.encode_ordinary(text).len())
 }
 
@@ -41,4 +70,11 @@ mod test {
             token_count(model_name, text).expect("correct tokenization")
         );
     }
+
+    #[test]
+    fn tiktoken_tokenizer_count() {
+        let tokenizer = TiktokenTokenizer::new("gpt-3.5-turbo").expect("tokenizer");
+        let text = "How many tokens does this sentence have?";
+        assert_eq!(8, tokenizer.count(text));
+    }
 }
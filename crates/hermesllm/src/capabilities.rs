@@ -0,0 +1,506 @@
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ContentType, MultiPartContentType, ResponseFormat,
+};
+use crate::Provider;
+
+/// Sampling parameters of a chat completions request a model may not accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingParam {
+    Temperature,
+    TopP,
+    N,
+    MaxTokens,
+    Stop,
+    PresencePenalty,
+    FrequencyPenalty,
+}
+
+impl SamplingParam {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplingParam::Temperature => "temperature",
+            SamplingParam::TopP => "top_p",
+            SamplingParam::N => "n",
+            SamplingParam::MaxTokens => "max_tokens",
+            SamplingParam::Stop => "stop",
+            SamplingParam::PresencePenalty => "presence_penalty",
+            SamplingParam::FrequencyPenalty => "frequency_penalty",
+        }
+    }
+}
+
+const ALL_SAMPLING_PARAMS: &[SamplingParam] = &[
+    SamplingParam::Temperature,
+    SamplingParam::TopP,
+    SamplingParam::N,
+    SamplingParam::MaxTokens,
+    SamplingParam::Stop,
+    SamplingParam::PresencePenalty,
+    SamplingParam::FrequencyPenalty,
+];
+
+/// Everything but `n`, for providers that return a single choice.
+const SINGLE_CHOICE_SAMPLING_PARAMS: &[SamplingParam] = &[
+    SamplingParam::Temperature,
+    SamplingParam::TopP,
+    SamplingParam::MaxTokens,
+    SamplingParam::Stop,
+    SamplingParam::PresencePenalty,
+    SamplingParam::FrequencyPenalty,
+];
+
+/// Reasoning models of openai reject the sampling parameters they fix themselves.
+const REASONING_SAMPLING_PARAMS: &[SamplingParam] = &[SamplingParam::N, SamplingParam::MaxTokens];
+
+/// How a provider honors the `response_format` of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredOutput {
+    /// Neither JSON mode nor schemas.
+    Unsupported,
+    /// `json_object` only.
+    JsonMode,
+    /// `json_object` and `json_schema`.
+    JsonSchema,
+    /// `json_object` and `json_schema`, by forcing a call of a tool taking the schema. The
+    /// tools of the request can't be called at the same time, and the answer is not streamed.
+    ForcedTool,
+}
+
+/// What a provider, or one of its models, supports beyond the common subset of the chat
+/// completions api. Requests using anything else are rejected or stripped before they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    pub supports_streaming: bool,
+    pub supports_tools: bool,
+    /// Several choices for one request, `n > 1`.
+    pub supports_n: bool,
+    /// Image parts in messages.
+    pub supports_vision: bool,
+    /// Context window in tokens, None when it depends on a model that is not known.
+    pub max_context: Option<u32>,
+    pub sampling_params: &'static [SamplingParam],
+    pub structured_output: StructuredOutput,
+    /// Most stop sequences a request may set, None when the provider sets no limit.
+    pub max_stop_sequences: Option<usize>,
+    /// Documents to ground the answer on, which the chat completions api does not have.
+    pub supports_documents: bool,
+}
+
+impl ProviderCapabilities {
+    /// Capabilities of `provider` for `model`, models without an entry of their own get those
+    /// of the provider. A `provider/` prefix of the model id is ignored.
+    pub fn lookup(provider: &Provider, model: &str) -> Self {
+        let model = model.rsplit('/').next().unwrap_or(model);
+        match provider {
+            Provider::OpenAI | Provider::AzureOpenAI => openai(model),
+            Provider::Groq => groq(model),
+            Provider::Mistral => ProviderCapabilities::default(),
+            Provider::Gemini => ProviderCapabilities {
+                max_stop_sequences: Some(5),
+                ..Default::default()
+            },
+            // text only models
+            Provider::Arch => ProviderCapabilities {
+                supports_n: false,
+                supports_vision: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                structured_output: StructuredOutput::Unsupported,
+                ..Default::default()
+            },
+            Provider::Deepseek => ProviderCapabilities {
+                supports_n: false,
+                supports_vision: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                structured_output: StructuredOutput::JsonMode,
+                ..Default::default()
+            },
+            Provider::Github | Provider::Ollama => ProviderCapabilities {
+                supports_n: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                ..Default::default()
+            },
+            Provider::Claude => ProviderCapabilities {
+                supports_n: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                structured_output: StructuredOutput::ForcedTool,
+                ..Default::default()
+            },
+            Provider::Cohere => ProviderCapabilities {
+                supports_n: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                max_stop_sequences: Some(5),
+                supports_documents: true,
+                ..Default::default()
+            },
+            // the converse translation does not force tool calls
+            Provider::Bedrock => ProviderCapabilities {
+                supports_n: false,
+                sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+                structured_output: StructuredOutput::Unsupported,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn supports_param(&self, param: SamplingParam) -> bool {
+        self.sampling_params.contains(&param)
+    }
+
+    /// The features `request` uses that are not supported, empty when it can be sent.
+    pub fn unsupported(&self, request: &ChatCompletionsRequest) -> Vec<String> {
+        let mut unsupported = Vec::new();
+        if request.stream.unwrap_or(false) && !self.supports_streaming {
+            unsupported.push("streaming".to_string());
+        }
+        if has_tools(request) && !self.supports_tools {
+            unsupported.push("tools".to_string());
+        }
+        if let Some(n) = request.n.filter(|n| *n > 1) {
+            if !self.supports_n {
+                unsupported.push(format!("n = {}, only one choice can be requested", n));
+            }
+        }
+        if !self.supports_vision && has_images(request) {
+            unsupported.push("image content".to_string());
+        }
+        if !self.supports_documents
+            && request
+                .documents
+                .as_ref()
+                .is_some_and(|documents| !documents.is_empty())
+        {
+            unsupported.push("documents".to_string());
+        }
+        if let Some(response_format) = request
+            .response_format
+            .as_ref()
+            .filter(|response_format| **response_format != ResponseFormat::Text)
+        {
+            match self.structured_output {
+                StructuredOutput::Unsupported => {
+                    unsupported.push(format!("response_format {}", response_format.name()))
+                }
+                StructuredOutput::JsonMode
+                    if matches!(response_format, ResponseFormat::JsonSchema { .. }) =>
+                {
+                    unsupported.push(format!("response_format {}", response_format.name()))
+                }
+                StructuredOutput::ForcedTool if has_tools(request) => unsupported.push(format!(
+                    "response_format {} together with tools",
+                    response_format.name()
+                )),
+                StructuredOutput::ForcedTool if request.stream.unwrap_or(false) => unsupported
+                    .push(format!(
+                        "response_format {} with streaming",
+                        response_format.name()
+                    )),
+                _ => {}
+            }
+        }
+        unsupported
+    }
+
+    /// Removes the sampling parameters the model would reject, returns whether any was set.
+    pub fn strip_unsupported_params(&self, request: &mut ChatCompletionsRequest) -> bool {
+        let mut stripped = false;
+        let mut strip = |param: SamplingParam, set: bool| {
+            let unsupported = set && !self.supports_param(param);
+            stripped |= unsupported;
+            unsupported
+        };
+        if strip(SamplingParam::Temperature, request.temperature.is_some()) {
+            request.temperature = None;
+        }
+        if strip(SamplingParam::TopP, request.top_p.is_some()) {
+            request.top_p = None;
+        }
+        if strip(SamplingParam::N, request.n.is_some()) {
+            request.n = None;
+        }
+        if strip(SamplingParam::MaxTokens, request.max_tokens.is_some()) {
+            request.max_tokens = None;
+        }
+        if strip(SamplingParam::Stop, request.stop.is_some()) {
+            request.stop = None;
+        }
+        if strip(
+            SamplingParam::PresencePenalty,
+            request.presence_penalty.is_some(),
+        ) {
+            request.presence_penalty = None;
+        }
+        if strip(
+            SamplingParam::FrequencyPenalty,
+            request.frequency_penalty.is_some(),
+        ) {
+            request.frequency_penalty = None;
+        }
+        stripped
+    }
+}
+
+impl Default for ProviderCapabilities {
+    /// Everything a chat completions request can ask for.
+    fn default() -> Self {
+        ProviderCapabilities {
+            supports_streaming: true,
+            supports_tools: true,
+            supports_n: true,
+            supports_vision: true,
+            max_context: None,
+            sampling_params: ALL_SAMPLING_PARAMS,
+            structured_output: StructuredOutput::JsonSchema,
+            max_stop_sequences: None,
+            supports_documents: false,
+        }
+    }
+}
+
+fn has_tools(request: &ChatCompletionsRequest) -> bool {
+    request
+        .tools
+        .as_ref()
+        .is_some_and(|tools| !tools.is_empty())
+}
+
+fn has_images(request: &ChatCompletionsRequest) -> bool {
+    request
+        .messages
+        .iter()
+        .any(|message| match &message.content {
+            Some(ContentType::MultiPart(parts)) => parts
+                .iter()
+                .any(|part| part.content_type == MultiPartContentType::ImageUrl),
+            _ => false,
+        })
+}
+
+fn openai(model: &str) -> ProviderCapabilities {
+    let (supports_vision, max_context) = match model {
+        model if model.starts_with("gpt-4.1") => (true, Some(1_047_576)),
+        model if model.starts_with("gpt-4o") || model.starts_with("gpt-4-turbo") => {
+            (true, Some(128_000))
+        }
+        model if model.starts_with("gpt-4") => (false, Some(8_192)),
+        model if model.starts_with("gpt-3.5-turbo") => (false, Some(16_385)),
+        model if model.starts_with("o1-mini") => (false, Some(128_000)),
+        model if model.starts_with("o3-mini") => (false, Some(200_000)),
+        model
+            if ["o1", "o3", "o4"]
+                .iter()
+                .any(|prefix| model.starts_with(prefix)) =>
+        {
+            (true, Some(200_000))
+        }
+        _ => (true, None),
+    };
+    let reasoning = ["o1", "o3", "o4"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix));
+    // schemas came with gpt-4o, older models only have json mode or neither
+    let structured_output = match model {
+        model
+            if model.starts_with("o1-mini") || model == "gpt-4" || model.starts_with("gpt-4-0") =>
+        {
+            StructuredOutput::Unsupported
+        }
+        model if model.starts_with("gpt-4-turbo") || model.starts_with("gpt-3.5-turbo") => {
+            StructuredOutput::JsonMode
+        }
+        _ => StructuredOutput::JsonSchema,
+    };
+    ProviderCapabilities {
+        supports_vision,
+        max_context,
+        sampling_params: if reasoning {
+            REASONING_SAMPLING_PARAMS
+        } else {
+            ALL_SAMPLING_PARAMS
+        },
+        structured_output,
+        max_stop_sequences: Some(4),
+        ..Default::default()
+    }
+}
+
+fn groq(model: &str) -> ProviderCapabilities {
+    let (supports_vision, max_context) = match model {
+        model if model.starts_with("llama-4") => (true, Some(131_072)),
+        "llama-3.3-70b-versatile" | "llama-3.1-8b-instant" => (false, Some(131_072)),
+        "llama3-70b-8192" | "llama3-8b-8192" | "gemma2-9b-it" => (false, Some(8_192)),
+        _ => (true, None),
+    };
+    ProviderCapabilities {
+        supports_n: false,
+        supports_vision,
+        max_context,
+        sampling_params: SINGLE_CHOICE_SAMPLING_PARAMS,
+        structured_output: StructuredOutput::JsonMode,
+        max_stop_sequences: Some(4),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> ChatCompletionsRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_openai_lookup() {
+        let gpt_4o = ProviderCapabilities::lookup(&Provider::OpenAI, "gpt-4o-mini");
+        assert!(gpt_4o.supports_n && gpt_4o.supports_vision && gpt_4o.supports_tools);
+        assert_eq!(gpt_4o.max_context, Some(128_000));
+        assert!(gpt_4o.supports_param(SamplingParam::Temperature));
+        assert_eq!(gpt_4o.max_stop_sequences, Some(4));
+
+        let gpt_35 = ProviderCapabilities::lookup(&Provider::OpenAI, "openai/gpt-3.5-turbo");
+        assert!(!gpt_35.supports_vision);
+        assert_eq!(gpt_35.max_context, Some(16_385));
+
+        let o3 = ProviderCapabilities::lookup(&Provider::OpenAI, "o3");
+        assert!(!o3.supports_param(SamplingParam::Temperature));
+        assert!(o3.supports_param(SamplingParam::MaxTokens));
+
+        // unknown models get the capabilities of the provider
+        let unknown = ProviderCapabilities::lookup(&Provider::AzureOpenAI, "my-deployment");
+        assert_eq!(
+            unknown,
+            ProviderCapabilities {
+                max_stop_sequences: Some(4),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_groq_lookup() {
+        let llama = ProviderCapabilities::lookup(&Provider::Groq, "llama-3.3-70b-versatile");
+        assert!(llama.supports_streaming && llama.supports_tools);
+        assert!(!llama.supports_n && !llama.supports_vision);
+        assert!(!llama.supports_param(SamplingParam::N));
+        assert_eq!(llama.max_context, Some(131_072));
+
+        let scout = ProviderCapabilities::lookup(
+            &Provider::Groq,
+            "meta-llama/llama-4-scout-17b-16e-instruct",
+        );
+        assert!(scout.supports_vision);
+
+        assert_eq!(
+            ProviderCapabilities::lookup(&Provider::Groq, "new-model").max_context,
+            None
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let request = request(
+            r#"{"model": "llama-3.3-70b-versatile", "n": 2, "stream": true, "tools": [{"type": "function"}], "messages": [
+                {"role": "user", "content": [{"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}]}
+            ]}"#,
+        );
+        let groq = ProviderCapabilities::lookup(&Provider::Groq, &request.model);
+        assert_eq!(
+            groq.unsupported(&request),
+            vec![
+                "n = 2, only one choice can be requested".to_string(),
+                "image content".to_string()
+            ]
+        );
+        assert!(ProviderCapabilities::lookup(&Provider::OpenAI, "gpt-4o")
+            .unsupported(&request)
+            .is_empty());
+        assert_eq!(
+            ProviderCapabilities::lookup(&Provider::Deepseek, "deepseek-chat")
+                .unsupported(&request),
+            vec![
+                "n = 2, only one choice can be requested".to_string(),
+                "image content".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_documents() {
+        let request = request(
+            r#"{"model": "command-r-plus", "messages": [{"role": "user", "content": "what is the refund window?"}],
+                "documents": [{"data": {"text": "Refunds within 30 days."}}]}"#,
+        );
+        assert!(
+            ProviderCapabilities::lookup(&Provider::Cohere, &request.model)
+                .unsupported(&request)
+                .is_empty()
+        );
+        assert_eq!(
+            ProviderCapabilities::lookup(&Provider::OpenAI, "gpt-4o").unsupported(&request),
+            vec!["documents".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_response_format() {
+        let json_schema = request(
+            r#"{"model": "llama-3.3-70b-versatile", "messages": [{"role": "user", "content": "hi"}],
+                "response_format": {"type": "json_schema", "json_schema": {"name": "answer", "schema": {"type": "object"}}}}"#,
+        );
+        let json_object = ChatCompletionsRequest {
+            response_format: Some(ResponseFormat::JsonObject),
+            ..json_schema.clone()
+        };
+        let unsupported = |provider: Provider, model: &str, request: &ChatCompletionsRequest| {
+            ProviderCapabilities::lookup(&provider, model).unsupported(request)
+        };
+
+        assert!(unsupported(Provider::Gemini, "gemini-2.0-flash", &json_schema).is_empty());
+        assert!(unsupported(Provider::OpenAI, "gpt-4o", &json_schema).is_empty());
+        assert!(unsupported(Provider::Groq, &json_object.model, &json_object).is_empty());
+        assert_eq!(
+            unsupported(Provider::Groq, &json_schema.model, &json_schema),
+            vec!["response_format json_schema".to_string()]
+        );
+        assert_eq!(
+            unsupported(Provider::OpenAI, "gpt-4", &json_object),
+            vec!["response_format json_object".to_string()]
+        );
+        assert_eq!(
+            unsupported(Provider::Bedrock, "anthropic.claude-3-haiku", &json_object),
+            vec!["response_format json_object".to_string()]
+        );
+
+        // claude answers through a forced tool call, the request can't bring tools of its own
+        assert!(unsupported(Provider::Claude, "claude-3-7-sonnet", &json_schema).is_empty());
+        let with_tools = ChatCompletionsRequest {
+            tools: Some(vec![serde_json::json!({"type": "function"})]),
+            ..json_schema
+        };
+        assert_eq!(
+            unsupported(Provider::Claude, "claude-3-7-sonnet", &with_tools),
+            vec!["response_format json_schema together with tools".to_string()]
+        );
+        let streaming = ChatCompletionsRequest {
+            stream: Some(true),
+            ..json_object
+        };
+        assert_eq!(
+            unsupported(Provider::Claude, "claude-3-7-sonnet", &streaming),
+            vec!["response_format json_object with streaming".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_strip_unsupported_params() {
+        let mut request = request(
+            r#"{"model": "o3", "temperature": 0.2, "top_p": 0.9, "max_tokens": 100, "messages": [{"role": "user", "content": "hi"}]}"#,
+        );
+        let o3 = ProviderCapabilities::lookup(&Provider::OpenAI, &request.model);
+        assert!(o3.strip_unsupported_params(&mut request));
+        assert_eq!(request.temperature, None);
+        assert_eq!(request.top_p, None);
+        assert_eq!(request.max_tokens, Some(100));
+        assert!(!o3.strip_unsupported_params(&mut request));
+    }
+}
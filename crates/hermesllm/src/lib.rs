@@ -1,10 +1,13 @@
 //! hermesllm: A library for translating LLM API requests and responses
-//! between Mistral, Grok, Gemini, and OpenAI-compliant formats.
+//! between Mistral, Grok, Gemini, Cohere, and OpenAI-compliant formats.
 
 use std::fmt::Display;
 
+pub mod capabilities;
 pub mod providers;
 
+pub use capabilities::ProviderCapabilities;
+
 pub enum Provider {
     Arch,
     Mistral,
@@ -14,6 +17,10 @@ pub enum Provider {
     OpenAI,
     Claude,
     Github,
+    AzureOpenAI,
+    Bedrock,
+    Ollama,
+    Cohere,
 }
 
 impl From<&str> for Provider {
@@ -27,11 +34,22 @@ impl From<&str> for Provider {
             "openai" => Provider::OpenAI,
             "claude" => Provider::Claude,
             "github" => Provider::Github,
+            "azure_openai" => Provider::AzureOpenAI,
+            "bedrock" => Provider::Bedrock,
+            "ollama" => Provider::Ollama,
+            "cohere" => Provider::Cohere,
             _ => panic!("Unknown provider: {}", value),
         }
     }
 }
 
+impl Provider {
+    /// What the provider supports for `model`, see [`ProviderCapabilities::lookup`].
+    pub fn capabilities(&self, model: &str) -> ProviderCapabilities {
+        ProviderCapabilities::lookup(self, model)
+    }
+}
+
 impl Display for Provider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -43,6 +61,10 @@ impl Display for Provider {
             Provider::OpenAI => write!(f, "OpenAI"),
             Provider::Claude => write!(f, "Claude"),
             Provider::Github => write!(f, "Github"),
+            Provider::AzureOpenAI => write!(f, "AzureOpenAI"),
+            Provider::Bedrock => write!(f, "Bedrock"),
+            Provider::Ollama => write!(f, "Ollama"),
+            Provider::Cohere => write!(f, "Cohere"),
         }
     }
 }
@@ -50,6 +72,17 @@ impl Display for Provider {
 #[cfg(test)]
 mod tests {
     use crate::providers::openai::types::{ChatCompletionsRequest, Message};
+    use crate::Provider;
+
+    #[test]
+    fn test_capabilities() {
+        assert!(Provider::OpenAI.capabilities("gpt-4o").supports_n);
+        assert!(
+            !Provider::from("claude")
+                .capabilities("claude-3-7-sonnet")
+                .supports_n
+        );
+    }
 
     #[test]
     fn openai_builder() {
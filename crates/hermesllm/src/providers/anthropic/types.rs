@@ -0,0 +1,1050 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ChatCompletionsResponse, Choice, ContentType, FunctionCall, ImageUrl,
+    Message, MultiPartContent, MultiPartContentType, ResponseFormat, ToolCall, ToolType, Usage,
+};
+
+/// Anthropic requires `max_tokens`, this is used when the OpenAI request does not set it.
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Tool the model is made to call for a `response_format`, anthropic has no JSON mode. The
+/// input of the call is the answer.
+pub const RESPONSE_FORMAT_TOOL: &str = "json_response";
+
+#[derive(Debug, Error)]
+pub enum AnthropicError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("chat completions response has no choices")]
+    MissingChoices,
+}
+
+type Result<T> = std::result::Result<T, AnthropicError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AnthropicRole {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: Option<String>,
+    pub data: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum ContentBlock {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image")]
+    Image { source: ImageSource },
+    #[serde(rename = "tool_use")]
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    #[serde(rename = "tool_result")]
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AnthropicMessage {
+    pub role: AnthropicRole,
+    pub content: Vec<ContentBlock>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicRequest {
+    pub model: String,
+    pub messages: Vec<AnthropicMessage>,
+    pub system: Option<String>,
+    pub max_tokens: u32,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub stream: Option<bool>,
+    pub tools: Option<Vec<AnthropicTool>>,
+    pub tool_choice: Option<AnthropicToolChoice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicToolChoice {
+    Auto,
+    Any,
+    Tool { name: String },
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AnthropicTool {
+    pub name: String,
+    pub description: Option<String>,
+    pub input_schema: Value,
+}
+
+impl TryFrom<&[u8]> for AnthropicRequest {
+    type Error = AnthropicError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(AnthropicError::from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: usize,
+    #[serde(default)]
+    pub output_tokens: usize,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub response_type: String,
+    pub role: AnthropicRole,
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+    pub usage: AnthropicUsage,
+}
+
+impl TryFrom<&[u8]> for AnthropicResponse {
+    type Error = AnthropicError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(AnthropicError::from)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnthropicStreamMessage {
+    pub id: String,
+    pub model: String,
+    pub usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ContentDelta {
+    #[serde(rename = "text_delta")]
+    TextDelta { text: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageDelta {
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+}
+
+/// Events of an anthropic messages stream, the `data` of each server sent event.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum AnthropicStreamEvent {
+    #[serde(rename = "message_start")]
+    MessageStart { message: AnthropicStreamMessage },
+    #[serde(rename = "content_block_delta")]
+    ContentBlockDelta { index: u32, delta: ContentDelta },
+    #[serde(rename = "message_delta")]
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<AnthropicUsage>,
+    },
+    #[serde(rename = "message_stop")]
+    MessageStop,
+    #[serde(rename = "error")]
+    Error { error: serde_json::Value },
+    /// `ping`, `content_block_start` and `content_block_stop` carry nothing to translate.
+    #[serde(other)]
+    Other,
+}
+
+fn content_to_blocks(content: &ContentType) -> Vec<ContentBlock> {
+    match content {
+        ContentType::Text(text) => vec![ContentBlock::Text { text: text.clone() }],
+        ContentType::MultiPart(parts) => parts
+            .iter()
+            .filter_map(|part| match part.content_type {
+                MultiPartContentType::Text => {
+                    part.text.clone().map(|text| ContentBlock::Text { text })
+                }
+                MultiPartContentType::ImageUrl => {
+                    part.image_url
+                        .as_ref()
+                        .map(|image_url| ContentBlock::Image {
+                            source: image_source_from_url(&image_url.url),
+                        })
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Data urls (`data:image/png;base64,...`) become base64 sources, anything else is passed as a
+/// url source.
+fn image_source_from_url(url: &str) -> ImageSource {
+    if let Some((media_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return ImageSource {
+            source_type: "base64".to_string(),
+            media_type: Some(media_type.to_string()),
+            data: Some(data.to_string()),
+            url: None,
+        };
+    }
+
+    ImageSource {
+        source_type: "url".to_string(),
+        media_type: None,
+        data: None,
+        url: Some(url.to_string()),
+    }
+}
+
+fn image_source_to_url(source: &ImageSource) -> Option<String> {
+    match (source.data.as_ref(), source.url.as_ref()) {
+        (Some(data), _) => Some(format!(
+            "data:{};base64,{}",
+            source.media_type.as_deref().unwrap_or("image/png"),
+            data
+        )),
+        (None, Some(url)) => Some(url.clone()),
+        (None, None) => None,
+    }
+}
+
+fn blocks_to_content(blocks: &[ContentBlock]) -> ContentType {
+    match blocks {
+        [ContentBlock::Text { text }] => ContentType::Text(text.clone()),
+        blocks => ContentType::MultiPart(
+            blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(MultiPartContent {
+                        text: Some(text.clone()),
+                        image_url: None,
+                        content_type: MultiPartContentType::Text,
+                    }),
+                    ContentBlock::Image { source } => {
+                        image_source_to_url(source).map(|url| MultiPartContent {
+                            text: None,
+                            image_url: Some(ImageUrl { url }),
+                            content_type: MultiPartContentType::ImageUrl,
+                        })
+                    }
+                    ContentBlock::ToolUse { .. } | ContentBlock::ToolResult { .. } => None,
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn blocks_to_text(blocks: &[ContentBlock]) -> String {
+    blocks
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("")
+}
+
+/// Content and tool calls of an assistant message. Anthropic rejects empty text blocks, which
+/// OpenAI clients commonly send alongside tool calls.
+fn assistant_message_to_blocks(message: &Message) -> Vec<ContentBlock> {
+    let mut blocks: Vec<ContentBlock> = message
+        .content
+        .as_ref()
+        .map(content_to_blocks)
+        .unwrap_or_default();
+    blocks.retain(|block| !matches!(block, ContentBlock::Text { text } if text.is_empty()));
+    blocks.extend(message.tool_calls.iter().flatten().map(|tool_call| {
+        ContentBlock::ToolUse {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            // anthropic expects an object, arguments that are not valid json are dropped
+            input: serde_json::from_str(&tool_call.function.arguments)
+                .unwrap_or_else(|_| Value::Object(Default::default())),
+        }
+    }));
+    blocks
+}
+
+fn blocks_to_tool_calls(blocks: &[ContentBlock]) -> Option<Vec<ToolCall>> {
+    let tool_calls: Vec<ToolCall> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse { id, name, input } => Some(ToolCall {
+                id: id.clone(),
+                tool_type: ToolType::Function,
+                function: FunctionCall {
+                    name: name.clone(),
+                    arguments: input.to_string(),
+                },
+            }),
+            _ => None,
+        })
+        .collect();
+    if tool_calls.is_empty() {
+        None
+    } else {
+        Some(tool_calls)
+    }
+}
+
+/// OpenAI tools are `{"type": "function", "function": {"name", "description", "parameters"}}`.
+fn tool_from_openai(tool: &Value) -> Option<AnthropicTool> {
+    let function = tool.get("function")?;
+    Some(AnthropicTool {
+        name: function.get("name")?.as_str()?.to_string(),
+        description: function
+            .get("description")
+            .and_then(|description| description.as_str())
+            .map(|description| description.to_string()),
+        input_schema: function
+            .get("parameters")
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+    })
+}
+
+/// The tool answering a JSON `response_format`, None for text.
+fn response_format_tool(response_format: ResponseFormat) -> Option<AnthropicTool> {
+    let any_object = || serde_json::json!({ "type": "object" });
+    match response_format {
+        ResponseFormat::Text => None,
+        ResponseFormat::JsonObject => Some(AnthropicTool {
+            name: RESPONSE_FORMAT_TOOL.to_string(),
+            description: Some("Respond with a JSON object.".to_string()),
+            input_schema: any_object(),
+        }),
+        ResponseFormat::JsonSchema { json_schema } => Some(AnthropicTool {
+            name: RESPONSE_FORMAT_TOOL.to_string(),
+            description: Some(json_schema.description.unwrap_or_else(|| {
+                format!("Respond with a JSON object, the {}.", json_schema.name)
+            })),
+            input_schema: json_schema.schema.unwrap_or_else(any_object),
+        }),
+    }
+}
+
+fn tool_to_openai(tool: &AnthropicTool) -> Value {
+    let mut function = serde_json::json!({
+        "name": tool.name,
+        "parameters": tool.input_schema,
+    });
+    if let Some(description) = &tool.description {
+        function["description"] = Value::String(description.clone());
+    }
+    serde_json::json!({ "type": "function", "function": function })
+}
+
+pub(crate) fn stop_reason_to_finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => "stop",
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        other => other,
+    }
+    .to_string()
+}
+
+fn finish_reason_to_stop_reason(finish_reason: &str) -> String {
+    match finish_reason {
+        "stop" => "end_turn",
+        "length" => "max_tokens",
+        "tool_calls" => "tool_use",
+        other => other,
+    }
+    .to_string()
+}
+
+impl From<ChatCompletionsRequest> for AnthropicRequest {
+    /// System (and developer) messages move to the top level `system` field, every other role
+    /// that is not `assistant` is sent as a user turn. Tool calls become `tool_use` blocks and
+    /// `tool` messages `tool_result` blocks. A JSON `response_format` forces a call of the
+    /// [`RESPONSE_FORMAT_TOOL`].
+    fn from(request: ChatCompletionsRequest) -> Self {
+        let mut system_prompts = Vec::new();
+        let mut messages: Vec<AnthropicMessage> = Vec::new();
+        for message in request.messages {
+            match (message.role.as_str(), message.tool_call_id.as_ref()) {
+                ("system" | "developer", _) => {
+                    if let Some(content) = message.content.as_ref() {
+                        system_prompts.push(content.to_string());
+                    }
+                }
+                ("assistant", _) => {
+                    let content = assistant_message_to_blocks(&message);
+                    if !content.is_empty() {
+                        messages.push(AnthropicMessage {
+                            role: AnthropicRole::Assistant,
+                            content,
+                        });
+                    }
+                }
+                ("tool", Some(tool_call_id)) => {
+                    let block = ContentBlock::ToolResult {
+                        tool_use_id: tool_call_id.clone(),
+                        content: message
+                            .content
+                            .as_ref()
+                            .map(|content| content.to_string())
+                            .unwrap_or_default(),
+                    };
+                    // results of parallel tool calls are sent in a single user turn
+                    match messages.last_mut() {
+                        Some(last)
+                            if last.role == AnthropicRole::User
+                                && last.content.iter().all(|block| {
+                                    matches!(block, ContentBlock::ToolResult { .. })
+                                }) =>
+                        {
+                            last.content.push(block)
+                        }
+                        _ => messages.push(AnthropicMessage {
+                            role: AnthropicRole::User,
+                            content: vec![block],
+                        }),
+                    }
+                }
+                _ => {
+                    if let Some(content) = message.content.as_ref() {
+                        messages.push(AnthropicMessage {
+                            role: AnthropicRole::User,
+                            content: content_to_blocks(content),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut tools: Option<Vec<AnthropicTool>> = request
+            .tools
+            .as_ref()
+            .map(|tools| tools.iter().filter_map(tool_from_openai).collect());
+        let tool_choice = match request.response_format.and_then(response_format_tool) {
+            Some(tool) => {
+                tools.get_or_insert_with(Vec::new).push(tool);
+                Some(AnthropicToolChoice::Tool {
+                    name: RESPONSE_FORMAT_TOOL.to_string(),
+                })
+            }
+            None => None,
+        };
+
+        AnthropicRequest {
+            model: request.model,
+            messages,
+            system: if system_prompts.is_empty() {
+                None
+            } else {
+                Some(system_prompts.join("\n"))
+            },
+            max_tokens: request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: request.temperature,
+            top_p: request.top_p,
+            stop_sequences: request.stop,
+            stream: request.stream,
+            tools,
+            tool_choice,
+        }
+    }
+}
+
+impl From<AnthropicRequest> for ChatCompletionsRequest {
+    fn from(request: AnthropicRequest) -> Self {
+        let mut messages = Vec::new();
+        if let Some(system) = request.system {
+            messages.push(Message {
+                role: "system".to_string(),
+                content: Some(ContentType::Text(system)),
+                ..Default::default()
+            });
+        }
+        for message in request.messages {
+            let (tool_blocks, content_blocks): (Vec<ContentBlock>, Vec<ContentBlock>) =
+                message.content.into_iter().partition(|block| {
+                    matches!(
+                        block,
+                        ContentBlock::ToolUse { .. } | ContentBlock::ToolResult { .. }
+                    )
+                });
+            match message.role {
+                AnthropicRole::Assistant => messages.push(Message {
+                    role: "assistant".to_string(),
+                    content: if content_blocks.is_empty() {
+                        None
+                    } else {
+                        Some(blocks_to_content(&content_blocks))
+                    },
+                    tool_calls: blocks_to_tool_calls(&tool_blocks),
+                    ..Default::default()
+                }),
+                AnthropicRole::User => {
+                    // every tool result is a separate tool message in openai
+                    for block in tool_blocks {
+                        if let ContentBlock::ToolResult {
+                            tool_use_id,
+                            content,
+                        } = block
+                        {
+                            messages.push(Message {
+                                role: "tool".to_string(),
+                                content: Some(ContentType::Text(content)),
+                                tool_call_id: Some(tool_use_id),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    if !content_blocks.is_empty() {
+                        messages.push(Message {
+                            role: "user".to_string(),
+                            content: Some(blocks_to_content(&content_blocks)),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+
+        ChatCompletionsRequest {
+            model: request.model,
+            messages,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            max_tokens: Some(request.max_tokens),
+            stream: request.stream,
+            stop: request.stop_sequences,
+            tools: request
+                .tools
+                .as_ref()
+                .map(|tools| tools.iter().map(tool_to_openai).collect()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<AnthropicResponse> for ChatCompletionsResponse {
+    /// The input of a [`RESPONSE_FORMAT_TOOL`] call is the content of the answer.
+    fn from(mut response: AnthropicResponse) -> Self {
+        let response_format_answer = response.content.iter().find_map(|block| match block {
+            ContentBlock::ToolUse { name, input, .. } if name == RESPONSE_FORMAT_TOOL => {
+                Some(input.to_string())
+            }
+            _ => None,
+        });
+        if let Some(answer) = response_format_answer {
+            response.content = vec![ContentBlock::Text { text: answer }];
+            if response.stop_reason.as_deref() == Some("tool_use") {
+                response.stop_reason = Some("end_turn".to_string());
+            }
+        }
+
+        let text = blocks_to_text(&response.content);
+        let tool_calls = blocks_to_tool_calls(&response.content);
+        ChatCompletionsResponse {
+            id: response.id,
+            object: "chat.completion".to_string(),
+            // anthropic does not report a creation time
+            created: 0,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: "assistant".to_string(),
+                    // openai leaves the content empty when the model only calls tools
+                    content: if text.is_empty() && tool_calls.is_some() {
+                        None
+                    } else {
+                        Some(ContentType::Text(text))
+                    },
+                    tool_calls,
+                    ..Default::default()
+                },
+                finish_reason: response
+                    .stop_reason
+                    .as_deref()
+                    .map(stop_reason_to_finish_reason),
+            }],
+            usage: Some(Usage {
+                prompt_tokens: response.usage.input_tokens,
+                completion_tokens: response.usage.output_tokens,
+                total_tokens: response.usage.input_tokens + response.usage.output_tokens,
+            }),
+        }
+    }
+}
+
+impl TryFrom<ChatCompletionsResponse> for AnthropicResponse {
+    type Error = AnthropicError;
+
+    /// Only the first choice is kept as anthropic responses carry a single message.
+    fn try_from(response: ChatCompletionsResponse) -> Result<Self> {
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(AnthropicError::MissingChoices)?;
+        let usage = response.usage.unwrap_or(Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        });
+
+        Ok(AnthropicResponse {
+            id: response.id,
+            response_type: "message".to_string(),
+            role: AnthropicRole::Assistant,
+            model: String::new(),
+            content: assistant_message_to_blocks(&choice.message),
+            stop_reason: choice
+                .finish_reason
+                .as_deref()
+                .map(finish_reason_to_stop_reason),
+            stop_sequence: None,
+            usage: AnthropicUsage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_role_mapping() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "claude-3-7-sonnet-latest",
+          "messages": [
+            { "role": "system", "content": "You are a helpful assistant." },
+            { "role": "user", "content": "hi" },
+            { "role": "assistant", "content": "Hello! How can I help?" },
+            { "role": "tool", "content": "{\"temperature\": 20}" },
+            { "role": "user", "content": "what is the weather in seattle" }
+          ],
+          "temperature": 0.5
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let anthropic_request = AnthropicRequest::from(chat_completions_request);
+
+        assert_eq!(
+            anthropic_request.system,
+            Some("You are a helpful assistant.".to_string())
+        );
+        assert_eq!(anthropic_request.max_tokens, DEFAULT_MAX_TOKENS);
+        assert_eq!(anthropic_request.temperature, Some(0.5));
+
+        let roles: Vec<AnthropicRole> = anthropic_request
+            .messages
+            .iter()
+            .map(|message| message.role.clone())
+            .collect();
+        assert_eq!(
+            roles,
+            vec![
+                AnthropicRole::User,
+                AnthropicRole::Assistant,
+                AnthropicRole::User,
+                AnthropicRole::User,
+            ]
+        );
+        assert_eq!(
+            anthropic_request.messages[0].content,
+            vec![ContentBlock::Text {
+                text: "hi".to_string()
+            }]
+        );
+
+        let json = serde_json::to_value(&anthropic_request).unwrap();
+        assert_eq!(json["messages"][0]["content"][0]["type"], "text");
+        assert_eq!(json["messages"][1]["role"], "assistant");
+        assert!(json.get("stop_sequences").is_none());
+    }
+
+    #[test]
+    fn test_request_image_content() {
+        let chat_completions_request = ChatCompletionsRequest {
+            model: "claude-3-7-sonnet-latest".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: Some(ContentType::MultiPart(vec![
+                    MultiPartContent {
+                        text: Some("describe this photo pls".to_string()),
+                        image_url: None,
+                        content_type: MultiPartContentType::Text,
+                    },
+                    MultiPartContent {
+                        text: None,
+                        image_url: Some(ImageUrl {
+                            url: "data:image/jpeg;base64,/9j/4AAQ==".to_string(),
+                        }),
+                        content_type: MultiPartContentType::ImageUrl,
+                    },
+                ])),
+                ..Default::default()
+            }],
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+
+        let anthropic_request = AnthropicRequest::from(chat_completions_request);
+        assert_eq!(anthropic_request.max_tokens, 100);
+        assert_eq!(
+            anthropic_request.messages[0].content[1],
+            ContentBlock::Image {
+                source: ImageSource {
+                    source_type: "base64".to_string(),
+                    media_type: Some("image/jpeg".to_string()),
+                    data: Some("/9j/4AAQ==".to_string()),
+                    url: None,
+                }
+            }
+        );
+
+        // and back again
+        let chat_completions_request = ChatCompletionsRequest::from(anthropic_request);
+        match chat_completions_request.messages[0]
+            .content
+            .as_ref()
+            .unwrap()
+        {
+            ContentType::MultiPart(parts) => {
+                assert_eq!(parts.len(), 2);
+                assert_eq!(
+                    parts[1].image_url,
+                    Some(ImageUrl {
+                        url: "data:image/jpeg;base64,/9j/4AAQ==".to_string()
+                    })
+                );
+            }
+            ContentType::Text(_) => panic!("Expected MultiPart content"),
+        }
+    }
+
+    #[test]
+    fn test_anthropic_request_to_chat_completions() {
+        const ANTHROPIC_REQUEST: &str = r#"
+        {
+          "model": "claude-3-7-sonnet-latest",
+          "system": "You are a helpful assistant.",
+          "max_tokens": 1024,
+          "messages": [
+            { "role": "user", "content": [{ "type": "text", "text": "hi" }] },
+            { "role": "assistant", "content": [{ "type": "text", "text": "Hello!" }] }
+          ]
+        }
+        "#;
+
+        let anthropic_request = AnthropicRequest::try_from(ANTHROPIC_REQUEST.as_bytes()).unwrap();
+        let chat_completions_request = ChatCompletionsRequest::from(anthropic_request);
+
+        let roles: Vec<&str> = chat_completions_request
+            .messages
+            .iter()
+            .map(|message| message.role.as_str())
+            .collect();
+        assert_eq!(roles, vec!["system", "user", "assistant"]);
+        assert_eq!(
+            chat_completions_request.messages[0].content,
+            Some(ContentType::Text(
+                "You are a helpful assistant.".to_string()
+            ))
+        );
+        assert_eq!(
+            chat_completions_request.messages[1].content,
+            Some(ContentType::Text("hi".to_string()))
+        );
+        assert_eq!(chat_completions_request.max_tokens, Some(1024));
+    }
+
+    #[test]
+    fn test_response_translation() {
+        const ANTHROPIC_RESPONSE: &str = r#"
+        {
+          "id": "msg_01DZDMxYSgq8aPQxMQoBv6Kb",
+          "type": "message",
+          "role": "assistant",
+          "model": "claude-3-7-sonnet-latest",
+          "content": [{ "type": "text", "text": "Hello! How can I assist you today?" }],
+          "stop_reason": "end_turn",
+          "stop_sequence": null,
+          "usage": { "input_tokens": 10, "output_tokens": 12 }
+        }
+        "#;
+
+        let anthropic_response =
+            AnthropicResponse::try_from(ANTHROPIC_RESPONSE.as_bytes()).unwrap();
+        let chat_completions_response = ChatCompletionsResponse::from(anthropic_response);
+
+        assert_eq!(chat_completions_response.id, "msg_01DZDMxYSgq8aPQxMQoBv6Kb");
+        assert_eq!(chat_completions_response.choices.len(), 1);
+        let choice = &chat_completions_response.choices[0];
+        assert_eq!(choice.message.role, "assistant");
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text(
+                "Hello! How can I assist you today?".to_string()
+            ))
+        );
+        assert_eq!(choice.finish_reason, Some("stop".to_string()));
+        let usage = chat_completions_response.usage.as_ref().unwrap();
+        assert_eq!(usage.total_tokens, 22);
+
+        let anthropic_response = AnthropicResponse::try_from(chat_completions_response).unwrap();
+        assert_eq!(anthropic_response.role, AnthropicRole::Assistant);
+        assert_eq!(anthropic_response.stop_reason, Some("end_turn".to_string()));
+        assert_eq!(anthropic_response.usage.output_tokens, 12);
+    }
+
+    #[test]
+    fn test_response_without_choices() {
+        let chat_completions_response = ChatCompletionsResponse {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            choices: vec![],
+            usage: None,
+        };
+        assert!(matches!(
+            AnthropicResponse::try_from(chat_completions_response),
+            Err(AnthropicError::MissingChoices)
+        ));
+    }
+
+    const TOOL_CALL_REQUEST: &str = r#"
+    {
+      "model": "claude-3-7-sonnet-latest",
+      "messages": [
+        { "role": "user", "content": "what is the weather in seattle and portland" },
+        {
+          "role": "assistant",
+          "content": "",
+          "tool_calls": [
+            {
+              "id": "call_1",
+              "type": "function",
+              "function": { "name": "get_weather", "arguments": "{\"location\": \"seattle\"}" }
+            },
+            {
+              "id": "call_2",
+              "type": "function",
+              "function": { "name": "get_weather", "arguments": "{\"location\": \"portland\"}" }
+            }
+          ]
+        },
+        { "role": "tool", "tool_call_id": "call_1", "content": "{\"temperature\": 20}" },
+        { "role": "tool", "tool_call_id": "call_2", "content": "{\"temperature\": 25}" }
+      ],
+      "tools": [
+        {
+          "type": "function",
+          "function": {
+            "name": "get_weather",
+            "description": "Get the current weather for a location",
+            "parameters": {
+              "type": "object",
+              "properties": { "location": { "type": "string" } },
+              "required": ["location"]
+            }
+          }
+        }
+      ]
+    }
+    "#;
+
+    #[test]
+    fn test_tool_call_request_translation() {
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(TOOL_CALL_REQUEST).unwrap();
+        let anthropic_request = AnthropicRequest::from(chat_completions_request);
+
+        let tools = anthropic_request.tools.as_ref().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "get_weather");
+        assert_eq!(tools[0].input_schema["required"][0], "location");
+
+        assert_eq!(anthropic_request.messages.len(), 3);
+        // the empty text content is dropped
+        assert_eq!(
+            anthropic_request.messages[1].content[0],
+            ContentBlock::ToolUse {
+                id: "call_1".to_string(),
+                name: "get_weather".to_string(),
+                input: serde_json::json!({ "location": "seattle" }),
+            }
+        );
+        // both tool results are sent in a single user turn
+        assert_eq!(anthropic_request.messages[2].role, AnthropicRole::User);
+        assert_eq!(
+            anthropic_request.messages[2].content,
+            vec![
+                ContentBlock::ToolResult {
+                    tool_use_id: "call_1".to_string(),
+                    content: "{\"temperature\": 20}".to_string(),
+                },
+                ContentBlock::ToolResult {
+                    tool_use_id: "call_2".to_string(),
+                    content: "{\"temperature\": 25}".to_string(),
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&anthropic_request).unwrap();
+        assert_eq!(json["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(json["messages"][2]["content"][0]["type"], "tool_result");
+
+        // and back again
+        let chat_completions_request = ChatCompletionsRequest::from(anthropic_request);
+        let roles: Vec<&str> = chat_completions_request
+            .messages
+            .iter()
+            .map(|message| message.role.as_str())
+            .collect();
+        assert_eq!(roles, vec!["user", "assistant", "tool", "tool"]);
+        let assistant_message = &chat_completions_request.messages[1];
+        assert!(assistant_message.content.is_none());
+        let tool_calls = assistant_message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[1].id, "call_2");
+        assert_eq!(
+            serde_json::from_str::<Value>(&tool_calls[1].function.arguments).unwrap(),
+            serde_json::json!({ "location": "portland" })
+        );
+        assert_eq!(
+            chat_completions_request.messages[3].tool_call_id,
+            Some("call_2".to_string())
+        );
+        let tools = chat_completions_request.tools.as_ref().unwrap();
+        assert_eq!(tools[0]["function"]["name"], "get_weather");
+    }
+
+    #[test]
+    fn test_tool_call_response_round_trip() {
+        const ANTHROPIC_RESPONSE: &str = r#"
+        {
+          "id": "msg_01Aq9w938a90dw8q",
+          "type": "message",
+          "role": "assistant",
+          "model": "claude-3-7-sonnet-latest",
+          "content": [
+            { "type": "text", "text": "Let me check the weather." },
+            {
+              "type": "tool_use",
+              "id": "toolu_01A09q90qw90lq917835lq9",
+              "name": "get_weather",
+              "input": { "location": "seattle" }
+            }
+          ],
+          "stop_reason": "tool_use",
+          "stop_sequence": null,
+          "usage": { "input_tokens": 10, "output_tokens": 12 }
+        }
+        "#;
+
+        let anthropic_response =
+            AnthropicResponse::try_from(ANTHROPIC_RESPONSE.as_bytes()).unwrap();
+        let chat_completions_response = ChatCompletionsResponse::from(anthropic_response);
+
+        let choice = &chat_completions_response.choices[0];
+        assert_eq!(choice.finish_reason, Some("tool_calls".to_string()));
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text("Let me check the weather.".to_string()))
+        );
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "toolu_01A09q90qw90lq917835lq9");
+        assert_eq!(tool_calls[0].tool_type, ToolType::Function);
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+
+        let json = serde_json::to_value(&chat_completions_response).unwrap();
+        assert_eq!(
+            json["choices"][0]["message"]["tool_calls"][0]["type"],
+            "function"
+        );
+        assert!(json["choices"][0]["message"].get("tool_call_id").is_none());
+
+        let anthropic_response = AnthropicResponse::try_from(chat_completions_response).unwrap();
+        assert_eq!(anthropic_response.stop_reason, Some("tool_use".to_string()));
+        assert_eq!(
+            anthropic_response.content[1],
+            ContentBlock::ToolUse {
+                id: "toolu_01A09q90qw90lq917835lq9".to_string(),
+                name: "get_weather".to_string(),
+                input: serde_json::json!({ "location": "seattle" }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_response_format_forces_tool() {
+        let chat_completions_request: ChatCompletionsRequest = serde_json::from_str(
+            r#"{"model": "claude-3-7-sonnet-latest", "messages": [{"role": "user", "content": "list three colors"}],
+                "response_format": {"type": "json_schema", "json_schema": {"name": "colors", "schema": {
+                    "type": "object", "properties": {"colors": {"type": "array", "items": {"type": "string"}}}
+                }}}}"#,
+        )
+        .unwrap();
+        let anthropic_request = AnthropicRequest::from(chat_completions_request);
+        let json = serde_json::to_value(&anthropic_request).unwrap();
+        assert_eq!(
+            json["tool_choice"],
+            serde_json::json!({"type": "tool", "name": RESPONSE_FORMAT_TOOL})
+        );
+        assert_eq!(json["tools"][0]["name"], RESPONSE_FORMAT_TOOL);
+        assert_eq!(
+            json["tools"][0]["input_schema"]["properties"]["colors"]["type"],
+            "array"
+        );
+
+        // the input of the forced call is the answer
+        let anthropic_response = AnthropicResponse::try_from(
+            r#"{"id": "msg_1", "type": "message", "role": "assistant", "model": "claude-3-7-sonnet-latest",
+                "content": [{"type": "tool_use", "id": "toolu_1", "name": "json_response", "input": {"colors": ["red", "green", "blue"]}}],
+                "stop_reason": "tool_use", "usage": {"input_tokens": 10, "output_tokens": 12}}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let choice = ChatCompletionsResponse::from(anthropic_response).choices[0].clone();
+        assert_eq!(choice.finish_reason, Some("stop".to_string()));
+        assert_eq!(choice.message.tool_calls, None);
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text(
+                r#"{"colors":["red","green","blue"]}"#.to_string()
+            ))
+        );
+    }
+}
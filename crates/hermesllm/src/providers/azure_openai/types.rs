@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+/// Api version sent when none is configured.
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI serves the OpenAI chat completions protocol, but models are addressed by the
+/// name of their deployment in the url, the api version is a query parameter and the access
+/// key goes in an `api-key` header.
+#[derive(Debug, Clone)]
+pub struct AzureOpenAiProvider {
+    /// Resource endpoint, e.g. `https://my-resource.openai.azure.com`.
+    pub endpoint: String,
+    pub api_version: String,
+    /// Model name to the name of the deployment serving it.
+    pub deployments: HashMap<String, String>,
+    /// Model used when the requested model has no deployment.
+    pub default_model: Option<String>,
+}
+
+impl AzureOpenAiProvider {
+    pub fn new(endpoint: String) -> Self {
+        AzureOpenAiProvider {
+            endpoint,
+            api_version: DEFAULT_API_VERSION.to_string(),
+            deployments: HashMap::new(),
+            default_model: None,
+        }
+    }
+
+    pub fn with_api_version(mut self, api_version: String) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn with_deployments(mut self, deployments: HashMap<String, String>) -> Self {
+        self.deployments = deployments;
+        self
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    /// Deployment serving `model`. Models without a deployment fall back to the default model,
+    /// a model that is not mapped at all is assumed to be deployed under its own name.
+    pub fn deployment(&self, model: &str) -> String {
+        let model = match self.deployments.get(model) {
+            Some(deployment) => return deployment.clone(),
+            None => self.default_model.as_deref().unwrap_or(model),
+        };
+        self.deployments
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    pub fn chat_completions_path(&self, model: &str) -> String {
+        format!(
+            "/openai/deployments/{}/chat/completions?api-version={}",
+            self.deployment(model),
+            self.api_version
+        )
+    }
+
+    pub fn chat_completions_url(&self, model: &str) -> String {
+        format!(
+            "{}{}",
+            self.endpoint.trim_end_matches('/'),
+            self.chat_completions_path(model)
+        )
+    }
+
+    /// Header name and value carrying the access key.
+    pub fn auth_header(&self, access_key: &str) -> (&'static str, String) {
+        ("api-key", access_key.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> AzureOpenAiProvider {
+        AzureOpenAiProvider::new("https://my-resource.openai.azure.com/".to_string())
+            .with_deployments(HashMap::from([(
+                "gpt-4o".to_string(),
+                "prod-gpt-4o".to_string(),
+            )]))
+    }
+
+    #[test]
+    fn test_chat_completions_url() {
+        let provider = provider();
+        assert_eq!(
+            provider.chat_completions_url("gpt-4o"),
+            "https://my-resource.openai.azure.com/openai/deployments/prod-gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+
+        let provider = provider.with_api_version("2025-01-01-preview".to_string());
+        assert_eq!(
+            provider.chat_completions_path("gpt-4o"),
+            "/openai/deployments/prod-gpt-4o/chat/completions?api-version=2025-01-01-preview"
+        );
+        assert_eq!(
+            provider.auth_header("secret"),
+            ("api-key", "secret".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deployment_fallback() {
+        let provider = provider();
+        // unmapped models are deployed under their own name
+        assert_eq!(provider.deployment("gpt-4o-mini"), "gpt-4o-mini");
+
+        let provider = provider.with_default_model(Some("gpt-4o".to_string()));
+        assert_eq!(provider.deployment("gpt-4o-mini"), "prod-gpt-4o");
+        assert_eq!(provider.deployment("gpt-4o"), "prod-gpt-4o");
+    }
+}
@@ -0,0 +1,2 @@
+pub mod sigv4;
+pub mod types;
@@ -0,0 +1,397 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// AWS credentials used to sign requests.
+#[derive(Clone, PartialEq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Set for temporary credentials, e.g. from an assumed role.
+    pub session_token: Option<String>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish()
+    }
+}
+
+impl Credentials {
+    pub fn new(access_key_id: String, secret_access_key: String) -> Self {
+        Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: None,
+        }
+    }
+
+    pub fn with_session_token(mut self, session_token: Option<String>) -> Self {
+        self.session_token = session_token;
+        self
+    }
+
+    /// Resolves credentials the way the AWS SDKs do for static credentials, first from the
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment
+    /// variables, then from the `AWS_PROFILE` (or `default`) profile of the shared credentials
+    /// file.
+    pub fn from_default_chain() -> Option<Self> {
+        Self::from_env().or_else(Self::from_shared_credentials_file)
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let access_key_id = non_empty_env("AWS_ACCESS_KEY_ID")?;
+        let secret_access_key = non_empty_env("AWS_SECRET_ACCESS_KEY")?;
+        Some(
+            Credentials::new(access_key_id, secret_access_key)
+                .with_session_token(non_empty_env("AWS_SESSION_TOKEN")),
+        )
+    }
+
+    /// Reads `AWS_SHARED_CREDENTIALS_FILE`, defaulting to `~/.aws/credentials`.
+    pub fn from_shared_credentials_file() -> Option<Self> {
+        let path = non_empty_env("AWS_SHARED_CREDENTIALS_FILE")
+            .or_else(|| non_empty_env("HOME").map(|home| format!("{}/.aws/credentials", home)))?;
+        let contents = std::fs::read_to_string(path).ok()?;
+        let profile = non_empty_env("AWS_PROFILE").unwrap_or_else(|| "default".to_string());
+        Self::from_profile(&contents, &profile)
+    }
+
+    /// Credentials of `profile` in the ini formatted shared credentials file.
+    pub fn from_profile(contents: &str, profile: &str) -> Option<Self> {
+        let mut in_profile = false;
+        let mut access_key_id = None;
+        let mut secret_access_key = None;
+        let mut session_token = None;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_profile = section.trim() == profile;
+                continue;
+            }
+            if !in_profile {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim().to_string();
+                match key.trim() {
+                    "aws_access_key_id" => access_key_id = Some(value),
+                    "aws_secret_access_key" => secret_access_key = Some(value),
+                    "aws_session_token" => session_token = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        Some(Credentials::new(access_key_id?, secret_access_key?).with_session_token(session_token))
+    }
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// What and where a request is signed for.
+#[derive(Debug)]
+pub struct SigningParams<'a> {
+    pub credentials: &'a Credentials,
+    pub region: &'a str,
+    pub service: &'a str,
+    /// Time of the request formatted as `YYYYMMDDTHHMMSSZ`, see [`amz_date`].
+    pub amz_date: &'a str,
+}
+
+/// Signs a request with AWS Signature Version 4. `path` and `query` are as sent on the wire,
+/// `headers` are the headers to sign besides `host`. Returns the headers to add to the request,
+/// `x-amz-date`, `x-amz-security-token` for temporary credentials and `authorization`.
+pub fn sign(
+    method: &str,
+    host: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    params: &SigningParams,
+) -> Vec<(&'static str, String)> {
+    let mut signing_headers = vec![("x-amz-date", params.amz_date.to_string())];
+    if let Some(session_token) = params.credentials.session_token.as_ref() {
+        signing_headers.push(("x-amz-security-token", session_token.clone()));
+    }
+
+    let mut canonical_headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), canonical_header_value(value)))
+        .chain(std::iter::once(("host".to_string(), host.to_string())))
+        .chain(
+            signing_headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        )
+        .collect();
+    canonical_headers.sort();
+    let signed_headers = canonical_headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        canonical_uri(path),
+        canonical_query(query),
+        canonical_headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect::<String>(),
+        signed_headers,
+        hex::encode(Sha256::digest(body))
+    );
+
+    let date = &params.amz_date[..8.min(params.amz_date.len())];
+    let scope = format!("{}/{}/{}/aws4_request", date, params.region, params.service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        params.amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = [date, params.region, params.service, "aws4_request"]
+        .iter()
+        .fold(
+            format!("AWS4{}", params.credentials.secret_access_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, params.credentials.access_key_id, scope, signed_headers, signature
+    );
+    signing_headers.push(("authorization", authorization));
+    signing_headers
+}
+
+/// Formats a time the way SigV4 expects it, e.g. `20150830T123600Z`.
+pub fn amz_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Gregorian date of a day count since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Every path segment is encoded once more, except for S3 which Bedrock is not.
+fn canonical_uri(path: &str) -> String {
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(uri_encode)
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (uri_encode(name), uri_encode(value))
+        })
+        .collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn canonical_header_value(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Percent encodes everything but the unreserved characters of RFC 3986.
+pub fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        let digest = Sha256::digest(key);
+        block[..digest.len()].copy_from_slice(&digest);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+    inner.update(message);
+    let inner = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(inner);
+    outer.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // credentials of the AWS SigV4 test suite
+    fn credentials() -> Credentials {
+        Credentials::new(
+            "AKIDEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_get_vanilla() {
+        let credentials = credentials();
+        let params = SigningParams {
+            credentials: &credentials,
+            region: "us-east-1",
+            service: "service",
+            amz_date: "20150830T123600Z",
+        };
+        let headers = sign("GET", "example.amazonaws.com", "/", "", &[], b"", &params);
+        assert_eq!(
+            headers,
+            vec![
+                ("x-amz-date", "20150830T123600Z".to_string()),
+                (
+                    "authorization",
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session_token_is_signed() {
+        let credentials = credentials().with_session_token(Some("session".to_string()));
+        let params = SigningParams {
+            credentials: &credentials,
+            region: "us-east-1",
+            service: "bedrock",
+            amz_date: "20150830T123600Z",
+        };
+        let headers = sign(
+            "POST",
+            "bedrock-runtime.us-east-1.amazonaws.com",
+            "/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/converse",
+            "",
+            &[("Content-Type", "application/json")],
+            b"{}",
+            &params,
+        );
+        assert_eq!(headers[1], ("x-amz-security-token", "session".to_string()));
+        assert!(headers[2]
+            .1
+            .contains("SignedHeaders=content-type;host;x-amz-date;x-amz-security-token,"));
+    }
+
+    #[test]
+    fn test_canonical_uri() {
+        assert_eq!(canonical_uri("/"), "/");
+        assert_eq!(
+            canonical_uri("/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/converse"),
+            "/model/anthropic.claude-3-5-sonnet-20240620-v1%253A0/converse"
+        );
+        assert_eq!(canonical_query("b=2&a=1 2"), "a=1%202&b=2");
+    }
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1440938160)),
+            "20150830T123600Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1709251199)),
+            "20240229T235959Z"
+        );
+    }
+
+    #[test]
+    fn test_credentials_from_profile() {
+        const CREDENTIALS: &str = r#"
+[default]
+aws_access_key_id = AKIDDEFAULT
+aws_secret_access_key = default-secret
+
+[bedrock]
+aws_access_key_id=AKIDBEDROCK
+aws_secret_access_key=bedrock-secret
+aws_session_token=bedrock-token
+"#;
+        let credentials = Credentials::from_profile(CREDENTIALS, "default").unwrap();
+        assert_eq!(credentials.access_key_id, "AKIDDEFAULT");
+        assert_eq!(credentials.session_token, None);
+
+        let credentials = Credentials::from_profile(CREDENTIALS, "bedrock").unwrap();
+        assert_eq!(credentials.secret_access_key, "bedrock-secret");
+        assert_eq!(credentials.session_token, Some("bedrock-token".to_string()));
+        assert!(!format!("{:?}", credentials).contains("bedrock-secret"));
+
+        assert!(Credentials::from_profile(CREDENTIALS, "missing").is_none());
+    }
+}
@@ -0,0 +1,663 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use thiserror::Error;
+
+use crate::providers::bedrock::sigv4::{self, Credentials, SigningParams};
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ChatCompletionsResponse, Choice, ContentType, FunctionCall, Message,
+    MultiPartContentType, ToolCall, ToolType, Usage,
+};
+
+/// Bedrock requires a region, this is used when none is configured.
+pub const DEFAULT_REGION: &str = "us-east-1";
+
+const SERVICE: &str = "bedrock";
+
+#[derive(Debug, Error)]
+pub enum BedrockError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, BedrockError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ConverseRole {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageSource {
+    /// Base64 encoded image.
+    pub bytes: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImageBlock {
+    /// `png`, `jpeg`, `gif` or `webp`.
+    pub format: String,
+    pub source: ImageSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolUseBlock {
+    pub tool_use_id: String,
+    pub name: String,
+    pub input: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolResultBlock {
+    pub tool_use_id: String,
+    pub content: Vec<ToolResultContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ToolResultContent {
+    Text(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentBlock {
+    Text(String),
+    Image(ImageBlock),
+    ToolUse(ToolUseBlock),
+    ToolResult(ToolResultBlock),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConverseMessage {
+    pub role: ConverseRole,
+    pub content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SystemContentBlock {
+    pub text: String,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceConfiguration {
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolInputSchema {
+    pub json: Value,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolSpecification {
+    pub name: String,
+    pub description: Option<String>,
+    pub input_schema: ToolInputSchema,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub tool_spec: ToolSpecification,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolConfiguration {
+    pub tools: Vec<Tool>,
+}
+
+/// Body of the Bedrock Converse API, which takes the same shape for every model family
+/// (Anthropic, Llama, Mistral, ...). The model is part of the url.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseRequest {
+    pub messages: Vec<ConverseMessage>,
+    pub system: Option<Vec<SystemContentBlock>>,
+    pub inference_config: Option<InferenceConfiguration>,
+    pub tool_config: Option<ToolConfiguration>,
+}
+
+impl ConverseRequest {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(BedrockError::from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConverseOutput {
+    pub message: ConverseMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseUsage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseResponse {
+    pub output: ConverseOutput,
+    pub stop_reason: Option<String>,
+    pub usage: ConverseUsage,
+}
+
+impl TryFrom<&[u8]> for ConverseResponse {
+    type Error = BedrockError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(BedrockError::from)
+    }
+}
+
+/// Data urls, `data:image/png;base64,...`, are sent inline. Bedrock does not fetch images, other
+/// urls are dropped.
+fn image_block_from_url(url: &str) -> Option<ImageBlock> {
+    let (media_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    Some(ImageBlock {
+        format: media_type.strip_prefix("image/")?.to_string(),
+        source: ImageSource {
+            bytes: data.to_string(),
+        },
+    })
+}
+
+fn content_to_blocks(content: &ContentType) -> Vec<ContentBlock> {
+    match content {
+        ContentType::Text(text) => vec![ContentBlock::Text(text.clone())],
+        ContentType::MultiPart(parts) => parts
+            .iter()
+            .filter_map(|part| match part.content_type {
+                MultiPartContentType::Text => part.text.clone().map(ContentBlock::Text),
+                MultiPartContentType::ImageUrl => part
+                    .image_url
+                    .as_ref()
+                    .and_then(|image_url| image_block_from_url(&image_url.url))
+                    .map(ContentBlock::Image),
+            })
+            .collect(),
+    }
+}
+
+/// Content and tool calls of an assistant message. Bedrock rejects empty text blocks.
+fn assistant_message_to_blocks(message: &Message) -> Vec<ContentBlock> {
+    let mut blocks: Vec<ContentBlock> = message
+        .content
+        .as_ref()
+        .map(content_to_blocks)
+        .unwrap_or_default();
+    blocks.retain(|block| !matches!(block, ContentBlock::Text(text) if text.is_empty()));
+    blocks.extend(message.tool_calls.iter().flatten().map(|tool_call| {
+        ContentBlock::ToolUse(ToolUseBlock {
+            tool_use_id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            // bedrock expects an object, arguments that are not valid json are dropped
+            input: serde_json::from_str(&tool_call.function.arguments)
+                .unwrap_or_else(|_| Value::Object(Default::default())),
+        })
+    }));
+    blocks
+}
+
+/// OpenAI tools are `{"type": "function", "function": {"name", "description", "parameters"}}`.
+fn tool_from_openai(tool: &Value) -> Option<Tool> {
+    let function = tool.get("function")?;
+    Some(Tool {
+        tool_spec: ToolSpecification {
+            name: function.get("name")?.as_str()?.to_string(),
+            description: function
+                .get("description")
+                .and_then(|description| description.as_str())
+                .map(|description| description.to_string()),
+            input_schema: ToolInputSchema {
+                json: function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            },
+        },
+    })
+}
+
+fn stop_reason_to_finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => "stop",
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        "guardrail_intervened" | "content_filtered" => "content_filter",
+        other => other,
+    }
+    .to_string()
+}
+
+impl From<ChatCompletionsRequest> for ConverseRequest {
+    /// System (and developer) messages move to the top level `system` field, every other role
+    /// that is not `assistant` is sent as a user turn. Converse requires user and assistant
+    /// turns to alternate, consecutive messages of the same role are merged into one turn.
+    fn from(request: ChatCompletionsRequest) -> Self {
+        let mut system = Vec::new();
+        let mut messages: Vec<ConverseMessage> = Vec::new();
+        for message in request.messages {
+            let (role, content) = match (message.role.as_str(), message.tool_call_id.as_ref()) {
+                ("system" | "developer", _) => {
+                    if let Some(content) = message.content.as_ref() {
+                        system.push(SystemContentBlock {
+                            text: content.to_string(),
+                        });
+                    }
+                    continue;
+                }
+                ("assistant", _) => (
+                    ConverseRole::Assistant,
+                    assistant_message_to_blocks(&message),
+                ),
+                ("tool", Some(tool_call_id)) => (
+                    ConverseRole::User,
+                    vec![ContentBlock::ToolResult(ToolResultBlock {
+                        tool_use_id: tool_call_id.clone(),
+                        content: vec![ToolResultContent::Text(
+                            message
+                                .content
+                                .as_ref()
+                                .map(|content| content.to_string())
+                                .unwrap_or_default(),
+                        )],
+                    })],
+                ),
+                _ => (
+                    ConverseRole::User,
+                    message
+                        .content
+                        .as_ref()
+                        .map(content_to_blocks)
+                        .unwrap_or_default(),
+                ),
+            };
+            if content.is_empty() {
+                continue;
+            }
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(ConverseMessage { role, content }),
+            }
+        }
+
+        let inference_config = InferenceConfiguration {
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            stop_sequences: request.stop,
+        };
+
+        ConverseRequest {
+            messages,
+            system: if system.is_empty() {
+                None
+            } else {
+                Some(system)
+            },
+            inference_config: if inference_config == InferenceConfiguration::default() {
+                None
+            } else {
+                Some(inference_config)
+            },
+            tool_config: request
+                .tools
+                .as_ref()
+                .map(|tools| {
+                    tools
+                        .iter()
+                        .filter_map(tool_from_openai)
+                        .collect::<Vec<_>>()
+                })
+                .filter(|tools| !tools.is_empty())
+                .map(|tools| ToolConfiguration { tools }),
+        }
+    }
+}
+
+impl From<ConverseResponse> for ChatCompletionsResponse {
+    fn from(response: ConverseResponse) -> Self {
+        let content = response.output.message.content;
+        let text = content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<&str>>()
+            .join("");
+        let tool_calls: Vec<ToolCall> = content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse(tool_use) => Some(ToolCall {
+                    id: tool_use.tool_use_id.clone(),
+                    tool_type: ToolType::Function,
+                    function: FunctionCall {
+                        name: tool_use.name.clone(),
+                        arguments: tool_use.input.to_string(),
+                    },
+                }),
+                _ => None,
+            })
+            .collect();
+        let tool_calls = if tool_calls.is_empty() {
+            None
+        } else {
+            Some(tool_calls)
+        };
+
+        ChatCompletionsResponse {
+            // bedrock reports neither an id nor a creation time in the body
+            id: String::new(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: "assistant".to_string(),
+                    // openai leaves the content empty when the model only calls tools
+                    content: if text.is_empty() && tool_calls.is_some() {
+                        None
+                    } else {
+                        Some(ContentType::Text(text))
+                    },
+                    tool_calls,
+                    ..Default::default()
+                },
+                finish_reason: response
+                    .stop_reason
+                    .as_deref()
+                    .map(stop_reason_to_finish_reason),
+            }],
+            usage: Some(Usage {
+                prompt_tokens: response.usage.input_tokens,
+                completion_tokens: response.usage.output_tokens,
+                total_tokens: response.usage.total_tokens,
+            }),
+        }
+    }
+}
+
+/// A Bedrock runtime endpoint of one region.
+#[derive(Debug, Clone)]
+pub struct BedrockProvider {
+    pub region: String,
+    /// Model name to the Bedrock model id (or inference profile) serving it.
+    pub model_ids: HashMap<String, String>,
+    /// Model used when the requested model has no model id.
+    pub default_model: Option<String>,
+}
+
+impl BedrockProvider {
+    pub fn new(region: String) -> Self {
+        BedrockProvider {
+            region,
+            model_ids: HashMap::new(),
+            default_model: None,
+        }
+    }
+
+    pub fn with_model_ids(mut self, model_ids: HashMap<String, String>) -> Self {
+        self.model_ids = model_ids;
+        self
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    pub fn host(&self) -> String {
+        format!("bedrock-runtime.{}.amazonaws.com", self.region)
+    }
+
+    /// Model id serving `model`, resolved like deployments of azure openai.
+    pub fn model_id(&self, model: &str) -> String {
+        let model = match self.model_ids.get(model) {
+            Some(model_id) => return model_id.clone(),
+            None => self.default_model.as_deref().unwrap_or(model),
+        };
+        self.model_ids
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    pub fn converse_path(&self, model: &str) -> String {
+        format!(
+            "/model/{}/converse",
+            sigv4::uri_encode(&self.model_id(model))
+        )
+    }
+
+    pub fn converse_url(&self, model: &str) -> String {
+        format!("https://{}{}", self.host(), self.converse_path(model))
+    }
+
+    /// Headers that sign a Converse request for `model` with `body`, `amz_date` is the time of
+    /// the request, see [`sigv4::amz_date`].
+    pub fn sign_converse(
+        &self,
+        model: &str,
+        body: &[u8],
+        credentials: &Credentials,
+        amz_date: &str,
+    ) -> Vec<(&'static str, String)> {
+        sigv4::sign(
+            "POST",
+            &self.host(),
+            &self.converse_path(model),
+            "",
+            &[("content-type", "application/json")],
+            body,
+            &SigningParams {
+                credentials,
+                region: &self.region,
+                service: SERVICE,
+                amz_date,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converse_request() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "claude-3-5-sonnet",
+          "messages": [
+            { "role": "system", "content": "You are a helpful assistant." },
+            { "role": "user", "content": "what is the weather in seattle" },
+            {
+              "role": "assistant",
+              "content": "",
+              "tool_calls": [
+                {
+                  "id": "call_1",
+                  "type": "function",
+                  "function": { "name": "get_weather", "arguments": "{\"city\": \"seattle\"}" }
+                }
+              ]
+            },
+            { "role": "tool", "tool_call_id": "call_1", "content": "{\"temperature\": 20}" },
+            { "role": "user", "content": "and in celsius?" }
+          ],
+          "temperature": 0.5,
+          "max_tokens": 256,
+          "stop": ["\n\n"],
+          "tools": [
+            {
+              "type": "function",
+              "function": {
+                "name": "get_weather",
+                "description": "current weather of a city",
+                "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+              }
+            }
+          ]
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let converse_request = ConverseRequest::from(chat_completions_request);
+        let body: Value = serde_json::from_slice(&converse_request.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "messages": [
+                    {
+                        "role": "user",
+                        "content": [{ "text": "what is the weather in seattle" }]
+                    },
+                    {
+                        "role": "assistant",
+                        "content": [
+                            {
+                                "toolUse": {
+                                    "toolUseId": "call_1",
+                                    "name": "get_weather",
+                                    "input": { "city": "seattle" }
+                                }
+                            }
+                        ]
+                    },
+                    {
+                        "role": "user",
+                        "content": [
+                            {
+                                "toolResult": {
+                                    "toolUseId": "call_1",
+                                    "content": [{ "text": "{\"temperature\": 20}" }]
+                                }
+                            },
+                            { "text": "and in celsius?" }
+                        ]
+                    }
+                ],
+                "system": [{ "text": "You are a helpful assistant." }],
+                "inferenceConfig": {
+                    "maxTokens": 256,
+                    "temperature": 0.5,
+                    "stopSequences": ["\n\n"]
+                },
+                "toolConfig": {
+                    "tools": [
+                        {
+                            "toolSpec": {
+                                "name": "get_weather",
+                                "description": "current weather of a city",
+                                "inputSchema": {
+                                    "json": {
+                                        "type": "object",
+                                        "properties": { "city": { "type": "string" } }
+                                    }
+                                }
+                            }
+                        }
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_converse_request_without_options() {
+        let request = ChatCompletionsRequest {
+            model: "llama3-70b".to_string(),
+            messages: vec![Message::new("hi".to_string())],
+            ..Default::default()
+        };
+        let body = serde_json::to_value(ConverseRequest::from(request)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "messages": [{ "role": "user", "content": [{ "text": "hi" }] }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_converse_response() {
+        const CONVERSE_RESPONSE: &str = r#"
+        {
+          "output": {
+            "message": {
+              "role": "assistant",
+              "content": [
+                { "text": "Let me check." },
+                { "toolUse": { "toolUseId": "tooluse_1", "name": "get_weather", "input": { "city": "seattle" } } }
+              ]
+            }
+          },
+          "stopReason": "tool_use",
+          "usage": { "inputTokens": 30, "outputTokens": 12, "totalTokens": 42 },
+          "metrics": { "latencyMs": 812 }
+        }
+        "#;
+
+        let converse_response = ConverseResponse::try_from(CONVERSE_RESPONSE.as_bytes()).unwrap();
+        let response = ChatCompletionsResponse::from(converse_response);
+
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text("Let me check.".to_string()))
+        );
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "tooluse_1");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"city":"seattle"}"#);
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 30);
+        assert_eq!(usage.completion_tokens, 12);
+        assert_eq!(usage.total_tokens, 42);
+    }
+
+    #[test]
+    fn test_converse_url() {
+        let provider = BedrockProvider::new("us-west-2".to_string())
+            .with_model_ids(HashMap::from([(
+                "claude-3-5-sonnet".to_string(),
+                "anthropic.claude-3-5-sonnet-20240620-v1:0".to_string(),
+            )]))
+            .with_default_model(Some("claude-3-5-sonnet".to_string()));
+
+        assert_eq!(
+            provider.converse_url("bedrock-claude"),
+            "https://bedrock-runtime.us-west-2.amazonaws.com/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/converse"
+        );
+
+        let credentials = Credentials::new("AKIDEXAMPLE".to_string(), "secret".to_string());
+        let headers =
+            provider.sign_converse("bedrock-claude", b"{}", &credentials, "20150830T123600Z");
+        assert_eq!(headers[0], ("x-amz-date", "20150830T123600Z".to_string()));
+        assert!(headers[1].1.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-west-2/bedrock/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature="
+        ));
+    }
+}
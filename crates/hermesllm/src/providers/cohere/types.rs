@@ -0,0 +1,554 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use thiserror::Error;
+
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ChatCompletionsResponse, Choice, ContentType, Message, ResponseFormat,
+    ToolCall, Usage,
+};
+
+/// Cohere's api, the chat endpoint is under `/v2/chat`.
+pub const DEFAULT_BASE_URL: &str = "https://api.cohere.com";
+
+#[derive(Debug, Error)]
+pub enum CohereError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, CohereError>;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct CohereMessage {
+    pub role: String,
+    /// Text, or text and image parts in the OpenAI format.
+    pub content: Option<ContentType>,
+    /// Text the model wrote before calling tools, the content of the assistant message in
+    /// OpenAI.
+    pub tool_plan: Option<String>,
+    /// Same format as OpenAI tool calls.
+    pub tool_calls: Option<Vec<ToolCall>>,
+    pub tool_call_id: Option<String>,
+}
+
+/// Cohere asks for JSON with `json_object`, the schema is optional.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CohereResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    pub json_schema: Option<Value>,
+}
+
+/// Body of cohere's `/v2/chat`.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereChatRequest {
+    pub model: String,
+    pub messages: Vec<CohereMessage>,
+    pub stream: Option<bool>,
+    pub temperature: Option<f32>,
+    /// Nucleus sampling, `top_p` in OpenAI.
+    pub p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Same format as OpenAI tools.
+    pub tools: Option<Vec<Value>>,
+    /// Texts or objects the answer is grounded on, cited in the response.
+    pub documents: Option<Vec<Value>>,
+    pub response_format: Option<CohereResponseFormat>,
+}
+
+impl CohereChatRequest {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(CohereError::from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CohereContentBlock {
+    Text {
+        text: String,
+    },
+    /// e.g. the thinking of reasoning models, which is not part of the answer.
+    #[serde(other)]
+    Other,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereResponseMessage {
+    #[serde(default)]
+    pub role: String,
+    pub content: Option<Vec<CohereContentBlock>>,
+    pub tool_plan: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Spans of the answer grounded on the documents, OpenAI has no place for them.
+    pub citations: Option<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereTokens {
+    #[serde(default)]
+    pub input_tokens: usize,
+    #[serde(default)]
+    pub output_tokens: usize,
+}
+
+/// `tokens` counts what the model saw and generated, `billed_units` what was charged.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereUsage {
+    pub billed_units: Option<CohereTokens>,
+    pub tokens: Option<CohereTokens>,
+}
+
+impl From<&CohereUsage> for Usage {
+    fn from(usage: &CohereUsage) -> Self {
+        let tokens = usage
+            .tokens
+            .as_ref()
+            .or(usage.billed_units.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        Usage {
+            prompt_tokens: tokens.input_tokens,
+            completion_tokens: tokens.output_tokens,
+            total_tokens: tokens.input_tokens + tokens.output_tokens,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereChatResponse {
+    #[serde(default)]
+    pub id: String,
+    pub finish_reason: Option<String>,
+    pub message: Option<CohereResponseMessage>,
+    pub usage: Option<CohereUsage>,
+}
+
+impl TryFrom<&[u8]> for CohereChatResponse {
+    type Error = CohereError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(CohereError::from)
+    }
+}
+
+/// Delta of a stream event, only the parts that are translated.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereStreamDelta {
+    pub message: Option<CohereStreamMessage>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<CohereUsage>,
+    /// Set on the `message-end` event of a stream that failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereStreamMessage {
+    pub content: Option<CohereStreamContent>,
+    pub tool_plan: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CohereStreamContent {
+    pub text: Option<String>,
+}
+
+/// Server sent events of a `/v2/chat` stream, the type is repeated in the data.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum CohereStreamEvent {
+    MessageStart {
+        id: Option<String>,
+    },
+    ContentDelta {
+        delta: CohereStreamDelta,
+    },
+    ToolPlanDelta {
+        delta: CohereStreamDelta,
+    },
+    MessageEnd {
+        delta: CohereStreamDelta,
+    },
+    /// Content, tool call and citation boundaries, and tool call deltas.
+    #[serde(other)]
+    Other,
+}
+
+pub(crate) fn finish_reason_to_openai(finish_reason: &str) -> String {
+    match finish_reason {
+        "COMPLETE" | "STOP_SEQUENCE" => "stop".to_string(),
+        "MAX_TOKENS" => "length".to_string(),
+        "TOOL_CALL" => "tool_calls".to_string(),
+        other => other.to_lowercase(),
+    }
+}
+
+/// Developer messages are system messages for cohere. The content of assistant messages that
+/// call tools is their tool plan.
+fn message_from_openai(message: Message) -> CohereMessage {
+    let role = if message.role == "developer" {
+        "system".to_string()
+    } else {
+        message.role
+    };
+    let calls_tools = message
+        .tool_calls
+        .as_ref()
+        .is_some_and(|tool_calls| !tool_calls.is_empty());
+    let (content, tool_plan) = match message.content {
+        Some(content) if calls_tools => {
+            let text = content.to_string();
+            (None, Some(text).filter(|text| !text.is_empty()))
+        }
+        content => (content, None),
+    };
+    CohereMessage {
+        role,
+        content,
+        tool_plan,
+        tool_calls: message.tool_calls,
+        tool_call_id: message.tool_call_id,
+    }
+}
+
+impl From<ChatCompletionsRequest> for CohereChatRequest {
+    fn from(request: ChatCompletionsRequest) -> Self {
+        CohereChatRequest {
+            model: request.model,
+            messages: request
+                .messages
+                .into_iter()
+                .map(message_from_openai)
+                .collect(),
+            stream: request.stream,
+            temperature: request.temperature,
+            p: request.top_p,
+            max_tokens: request.max_tokens,
+            stop_sequences: request.stop,
+            presence_penalty: request.presence_penalty,
+            frequency_penalty: request.frequency_penalty,
+            tools: request.tools,
+            documents: request.documents,
+            response_format: match request.response_format {
+                Some(ResponseFormat::JsonObject) => Some(CohereResponseFormat {
+                    format_type: "json_object".to_string(),
+                    json_schema: None,
+                }),
+                Some(ResponseFormat::JsonSchema { json_schema }) => Some(CohereResponseFormat {
+                    format_type: "json_object".to_string(),
+                    json_schema: json_schema.schema,
+                }),
+                Some(ResponseFormat::Text) | None => None,
+            },
+        }
+    }
+}
+
+impl From<CohereChatResponse> for ChatCompletionsResponse {
+    fn from(response: CohereChatResponse) -> Self {
+        let message = response.message.unwrap_or_default();
+        let text: String = message
+            .content
+            .iter()
+            .flatten()
+            .filter_map(|block| match block {
+                CohereContentBlock::Text { text } => Some(text.as_str()),
+                CohereContentBlock::Other => None,
+            })
+            .collect();
+        let tool_calls = message
+            .tool_calls
+            .filter(|tool_calls| !tool_calls.is_empty());
+        // the tool plan stands in for the content of the message calling tools
+        let content = match message.tool_plan {
+            Some(tool_plan) if text.is_empty() && tool_calls.is_some() => Some(tool_plan),
+            _ if text.is_empty() && tool_calls.is_some() => None,
+            _ => Some(text),
+        };
+
+        ChatCompletionsResponse {
+            id: response.id,
+            object: "chat.completion".to_string(),
+            // cohere does not report a creation time
+            created: 0,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: "assistant".to_string(),
+                    content: content.map(ContentType::Text),
+                    tool_calls,
+                    ..Default::default()
+                },
+                finish_reason: response
+                    .finish_reason
+                    .as_deref()
+                    .map(finish_reason_to_openai),
+            }],
+            usage: response.usage.as_ref().map(Usage::from),
+        }
+    }
+}
+
+/// Cohere's api at `base_url`.
+#[derive(Debug, Clone)]
+pub struct CohereProvider {
+    pub base_url: String,
+    /// Model sent instead of the requested one, e.g. `command-r-plus`.
+    pub default_model: Option<String>,
+}
+
+impl CohereProvider {
+    pub fn new(base_url: String) -> Self {
+        CohereProvider {
+            base_url,
+            default_model: None,
+        }
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    pub fn chat_url(&self) -> String {
+        format!("{}/v2/chat", self.base_url.trim_end_matches('/'))
+    }
+
+    pub fn chat_request(&self, request: ChatCompletionsRequest) -> CohereChatRequest {
+        let mut chat_request = CohereChatRequest::from(request);
+        if let Some(default_model) = self.default_model.as_ref() {
+            chat_request.model = default_model.clone();
+        }
+        chat_request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "command",
+          "messages": [
+            { "role": "system", "content": "Answer from the documents." },
+            { "role": "user", "content": "what is the refund window?" },
+            {
+              "role": "assistant",
+              "content": "I will look up the policy.",
+              "tool_calls": [
+                {
+                  "id": "call_1",
+                  "type": "function",
+                  "function": { "name": "search_policies", "arguments": "{\"query\": \"refunds\"}" }
+                }
+              ]
+            },
+            { "role": "tool", "tool_call_id": "call_1", "content": "30 days" }
+          ],
+          "documents": [{ "id": "policy", "data": { "text": "Refunds within 30 days." } }],
+          "tools": [{ "type": "function", "function": { "name": "search_policies", "parameters": { "type": "object" } } }],
+          "temperature": 0.3,
+          "top_p": 0.9,
+          "max_tokens": 256,
+          "stop": ["END"],
+          "stream_options": { "include_usage": true }
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let provider = CohereProvider::new(DEFAULT_BASE_URL.to_string())
+            .with_default_model(Some("command-r-plus".to_string()));
+        let chat_request = provider.chat_request(chat_completions_request);
+        let body: Value = serde_json::from_slice(&chat_request.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(provider.chat_url(), "https://api.cohere.com/v2/chat");
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "command-r-plus",
+                "messages": [
+                    { "role": "system", "content": "Answer from the documents." },
+                    { "role": "user", "content": "what is the refund window?" },
+                    {
+                        "role": "assistant",
+                        "tool_plan": "I will look up the policy.",
+                        "tool_calls": [
+                            {
+                                "id": "call_1",
+                                "type": "function",
+                                "function": { "name": "search_policies", "arguments": "{\"query\": \"refunds\"}" }
+                            }
+                        ]
+                    },
+                    { "role": "tool", "tool_call_id": "call_1", "content": "30 days" }
+                ],
+                "temperature": 0.3,
+                "p": 0.9,
+                "max_tokens": 256,
+                "stop_sequences": ["END"],
+                "tools": [{ "type": "function", "function": { "name": "search_policies", "parameters": { "type": "object" } } }],
+                "documents": [{ "id": "policy", "data": { "text": "Refunds within 30 days." } }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_role_mapping() {
+        let message = |role: &str| Message {
+            role: role.to_string(),
+            ..Message::new("hi".to_string())
+        };
+        let chat_request = CohereChatRequest::from(ChatCompletionsRequest {
+            model: "command-r".to_string(),
+            messages: vec![
+                message("developer"),
+                message("system"),
+                message("user"),
+                message("assistant"),
+                message("tool"),
+            ],
+            ..Default::default()
+        });
+        let roles: Vec<&str> = chat_request
+            .messages
+            .iter()
+            .map(|message| message.role.as_str())
+            .collect();
+        assert_eq!(roles, ["system", "system", "user", "assistant", "tool"]);
+        // without tool calls the content of assistant messages is kept
+        assert_eq!(
+            chat_request.messages[3].content,
+            Some(ContentType::Text("hi".to_string()))
+        );
+        assert_eq!(chat_request.messages[3].tool_plan, None);
+    }
+
+    #[test]
+    fn test_response_format() {
+        let chat_request = |response_format: Option<ResponseFormat>| {
+            CohereChatRequest::from(ChatCompletionsRequest {
+                model: "command-r".to_string(),
+                messages: vec![Message::new("list three colors".to_string())],
+                response_format,
+                ..Default::default()
+            })
+            .response_format
+        };
+        let schema =
+            serde_json::json!({"type": "object", "properties": {"colors": {"type": "array"}}});
+        let json_schema = serde_json::from_value(serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {"name": "colors", "schema": schema}
+        }))
+        .unwrap();
+        assert_eq!(
+            chat_request(Some(json_schema)),
+            Some(CohereResponseFormat {
+                format_type: "json_object".to_string(),
+                json_schema: Some(schema),
+            })
+        );
+        assert_eq!(
+            chat_request(Some(ResponseFormat::JsonObject)),
+            Some(CohereResponseFormat {
+                format_type: "json_object".to_string(),
+                json_schema: None,
+            })
+        );
+        assert_eq!(chat_request(Some(ResponseFormat::Text)), None);
+    }
+
+    #[test]
+    fn test_chat_response() {
+        const COHERE_RESPONSE: &str = r#"
+        {
+          "id": "c14c80c3-18eb-4519-9460-6c92edd8cfb4",
+          "finish_reason": "COMPLETE",
+          "message": {
+            "role": "assistant",
+            "content": [{ "type": "text", "text": "Refunds are accepted within 30 days." }],
+            "citations": [{ "start": 28, "end": 35, "text": "30 days", "sources": [] }]
+          },
+          "usage": {
+            "billed_units": { "input_tokens": 20, "output_tokens": 9 },
+            "tokens": { "input_tokens": 214, "output_tokens": 9 }
+          }
+        }
+        "#;
+
+        let cohere_response = CohereChatResponse::try_from(COHERE_RESPONSE.as_bytes()).unwrap();
+        let response = ChatCompletionsResponse::from(cohere_response);
+
+        assert_eq!(response.id, "c14c80c3-18eb-4519-9460-6c92edd8cfb4");
+        assert_eq!(response.object, "chat.completion");
+        let choice = &response.choices[0];
+        assert_eq!(choice.message.role, "assistant");
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text(
+                "Refunds are accepted within 30 days.".to_string()
+            ))
+        );
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 214);
+        assert_eq!(usage.completion_tokens, 9);
+        assert_eq!(usage.total_tokens, 223);
+    }
+
+    #[test]
+    fn test_tool_call_response() {
+        const COHERE_RESPONSE: &str = r#"
+        {
+          "id": "5a50480a-cf52-46f0-af01-53d18539bd31",
+          "finish_reason": "TOOL_CALL",
+          "message": {
+            "role": "assistant",
+            "tool_plan": "I will look up the weather in Toronto.",
+            "tool_calls": [
+              {
+                "id": "get_weather_1byjy32y4hvq",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"location\":\"Toronto\"}" }
+              }
+            ]
+          }
+        }
+        "#;
+
+        let cohere_response = CohereChatResponse::try_from(COHERE_RESPONSE.as_bytes()).unwrap();
+        let response = ChatCompletionsResponse::from(cohere_response);
+
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text(
+                "I will look up the weather in Toronto.".to_string()
+            ))
+        );
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "get_weather_1byjy32y4hvq");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            tool_calls[0].function.arguments,
+            r#"{"location":"Toronto"}"#
+        );
+        assert!(response.usage.is_none());
+    }
+}
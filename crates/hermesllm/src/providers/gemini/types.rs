@@ -0,0 +1,831 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ChatCompletionsResponse, Choice, ContentType,
+    FunctionCall as OpenAIFunctionCall, Message, MultiPartContentType, ResponseFormat, ToolCall,
+    ToolType, Usage,
+};
+
+/// Keywords of JSON schema that gemini's `responseSchema`, a subset of the OpenAPI schema
+/// object, rejects.
+const UNSUPPORTED_SCHEMA_KEYWORDS: [&str; 3] = ["$schema", "additionalProperties", "strict"];
+
+#[derive(Debug, Error)]
+pub enum GeminiError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, GeminiError>;
+
+/// Which Google API serves the gemini models. The public Generative Language API and Vertex AI
+/// accept the same `generateContent` body but differ in host and path.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(tag = "type")]
+pub enum GeminiApi {
+    #[default]
+    #[serde(rename = "generative_language")]
+    GenerativeLanguage,
+    #[serde(rename = "vertex_ai")]
+    VertexAi {
+        project_id: String,
+        location: String,
+    },
+}
+
+impl GeminiApi {
+    pub fn host(&self) -> String {
+        match self {
+            GeminiApi::GenerativeLanguage => "generativelanguage.googleapis.com".to_string(),
+            GeminiApi::VertexAi { location, .. } => {
+                format!("{}-aiplatform.googleapis.com", location)
+            }
+        }
+    }
+
+    pub fn path(&self, model: &str, stream: bool) -> String {
+        let method = if stream {
+            "streamGenerateContent?alt=sse"
+        } else {
+            "generateContent"
+        };
+        match self {
+            GeminiApi::GenerativeLanguage => format!("/v1beta/models/{}:{}", model, method),
+            GeminiApi::VertexAi {
+                project_id,
+                location,
+            } => format!(
+                "/v1/projects/{}/locations/{}/publishers/google/models/{}:{}",
+                project_id, location, model, method
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum GeminiRole {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "model")]
+    Model,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    pub mime_type: Option<String>,
+    pub file_uri: String,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionCall {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionResponse {
+    pub id: Option<String>,
+    pub name: String,
+    pub response: Value,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    pub text: Option<String>,
+    pub inline_data: Option<Blob>,
+    pub file_data: Option<FileData>,
+    pub function_call: Option<FunctionCall>,
+    pub function_response: Option<FunctionResponse>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Content {
+    /// Not set on `systemInstruction`.
+    pub role: Option<GeminiRole>,
+    pub parts: Vec<Part>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub candidate_count: Option<u32>,
+    pub max_output_tokens: Option<u32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// `application/json` for JSON answers.
+    pub response_mime_type: Option<String>,
+    /// Schema of a JSON answer, requires `response_mime_type` to be `application/json`.
+    pub response_schema: Option<Value>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: Option<String>,
+    pub parameters: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+/// Body of a `generateContent` request, the model and streaming mode are part of the path (see
+/// [`GeminiApi::path`]).
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiRequest {
+    pub contents: Vec<Content>,
+    pub system_instruction: Option<Content>,
+    pub generation_config: Option<GenerationConfig>,
+    pub tools: Option<Vec<GeminiTool>>,
+}
+
+impl TryFrom<&[u8]> for GeminiRequest {
+    type Error = GeminiError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(GeminiError::from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub content: Option<Content>,
+    #[serde(rename = "finishReason")]
+    pub finish_reason: Option<String>,
+    pub index: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: usize,
+    #[serde(default)]
+    pub candidates_token_count: usize,
+    #[serde(default)]
+    pub total_token_count: usize,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub usage_metadata: Option<UsageMetadata>,
+    pub model_version: Option<String>,
+    pub response_id: Option<String>,
+    /// Set instead of the candidates when the request failed, also mid-stream.
+    pub error: Option<GeminiApiError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiApiError {
+    pub code: Option<u16>,
+    #[serde(default)]
+    pub message: String,
+    /// e.g. `RESOURCE_EXHAUSTED`
+    pub status: Option<String>,
+}
+
+impl TryFrom<&[u8]> for GeminiResponse {
+    type Error = GeminiError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(GeminiError::from)
+    }
+}
+
+fn content_to_parts(content: &ContentType) -> Vec<Part> {
+    match content {
+        ContentType::Text(text) => vec![Part {
+            text: Some(text.clone()),
+            ..Default::default()
+        }],
+        ContentType::MultiPart(parts) => parts
+            .iter()
+            .filter_map(|part| match part.content_type {
+                MultiPartContentType::Text => part.text.clone().map(|text| Part {
+                    text: Some(text),
+                    ..Default::default()
+                }),
+                MultiPartContentType::ImageUrl => part
+                    .image_url
+                    .as_ref()
+                    .map(|image_url| part_from_url(&image_url.url)),
+            })
+            .collect(),
+    }
+}
+
+/// Data urls (`data:image/png;base64,...`) are sent inline, anything else as file data.
+fn part_from_url(url: &str) -> Part {
+    if let Some((mime_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return Part {
+            inline_data: Some(Blob {
+                mime_type: mime_type.to_string(),
+                data: data.to_string(),
+            }),
+            ..Default::default()
+        };
+    }
+
+    Part {
+        file_data: Some(FileData {
+            mime_type: None,
+            file_uri: url.to_string(),
+        }),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn parts_to_text(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .filter_map(|part| part.text.as_deref())
+        .collect::<Vec<&str>>()
+        .join("")
+}
+
+fn parts_to_tool_calls(parts: &[Part]) -> Option<Vec<ToolCall>> {
+    let tool_calls: Vec<ToolCall> = parts
+        .iter()
+        .filter_map(|part| part.function_call.as_ref())
+        .enumerate()
+        .map(|(position, function_call)| ToolCall {
+            // gemini only reports ids for some models
+            id: function_call
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("call_{}", position)),
+            tool_type: ToolType::Function,
+            function: OpenAIFunctionCall {
+                name: function_call.name.clone(),
+                arguments: function_call.args.to_string(),
+            },
+        })
+        .collect();
+    if tool_calls.is_empty() {
+        None
+    } else {
+        Some(tool_calls)
+    }
+}
+
+/// OpenAI tools are `{"type": "function", "function": {"name", "description", "parameters"}}`.
+fn function_declaration_from_openai(tool: &Value) -> Option<FunctionDeclaration> {
+    let function = tool.get("function")?;
+    Some(FunctionDeclaration {
+        name: function.get("name")?.as_str()?.to_string(),
+        description: function
+            .get("description")
+            .and_then(|description| description.as_str())
+            .map(|description| description.to_string()),
+        parameters: function.get("parameters").cloned(),
+    })
+}
+
+/// The OpenAI `response_format` as gemini's response mime type and schema.
+fn response_format_to_gemini(
+    response_format: Option<ResponseFormat>,
+) -> (Option<String>, Option<Value>) {
+    match response_format {
+        Some(ResponseFormat::JsonObject) => (Some("application/json".to_string()), None),
+        Some(ResponseFormat::JsonSchema { json_schema }) => (
+            Some("application/json".to_string()),
+            json_schema.schema.map(response_schema_from_json_schema),
+        ),
+        Some(ResponseFormat::Text) | None => (None, None),
+    }
+}
+
+fn response_schema_from_json_schema(mut schema: Value) -> Value {
+    match &mut schema {
+        Value::Object(object) => {
+            for keyword in UNSUPPORTED_SCHEMA_KEYWORDS {
+                object.remove(keyword);
+            }
+            for value in object.values_mut() {
+                *value = response_schema_from_json_schema(value.take());
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                *item = response_schema_from_json_schema(item.take());
+            }
+        }
+        _ => {}
+    }
+    schema
+}
+
+pub(crate) fn finish_reason_to_openai(finish_reason: &str) -> String {
+    match finish_reason {
+        "STOP" => "stop",
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => "content_filter",
+        other => other,
+    }
+    .to_string()
+}
+
+impl From<ChatCompletionsRequest> for GeminiRequest {
+    /// System (and developer) messages move to `systemInstruction`, `assistant` maps to `model`
+    /// and every other role is sent as `user`. Tool calls become `functionCall` parts and `tool`
+    /// messages `functionResponse` parts. Gemini rejects consecutive turns with the same role so
+    /// those are merged into a single content.
+    fn from(request: ChatCompletionsRequest) -> Self {
+        let mut system_parts = Vec::new();
+        let mut contents: Vec<Content> = Vec::new();
+        // function responses are matched by name, tool messages only carry the call id
+        let mut tool_call_names: HashMap<String, String> = HashMap::new();
+        for message in request.messages {
+            let content_parts = message
+                .content
+                .as_ref()
+                .map(content_to_parts)
+                .unwrap_or_default();
+            let (role, parts) = match (message.role.as_str(), message.tool_call_id.as_ref()) {
+                ("system" | "developer", _) => {
+                    system_parts.extend(content_parts);
+                    continue;
+                }
+                ("assistant", _) => {
+                    let mut parts: Vec<Part> = content_parts
+                        .into_iter()
+                        .filter(|part| !matches!(part.text.as_deref(), Some("")))
+                        .collect();
+                    for tool_call in message.tool_calls.iter().flatten() {
+                        tool_call_names
+                            .insert(tool_call.id.clone(), tool_call.function.name.clone());
+                        parts.push(Part {
+                            function_call: Some(FunctionCall {
+                                id: None,
+                                name: tool_call.function.name.clone(),
+                                args: serde_json::from_str(&tool_call.function.arguments)
+                                    .unwrap_or_else(|_| Value::Object(Default::default())),
+                            }),
+                            ..Default::default()
+                        });
+                    }
+                    (GeminiRole::Model, parts)
+                }
+                ("tool", Some(tool_call_id)) => {
+                    let text = message
+                        .content
+                        .as_ref()
+                        .map(|content| content.to_string())
+                        .unwrap_or_default();
+                    // gemini expects an object as the function response
+                    let response = match serde_json::from_str::<Value>(&text) {
+                        Ok(response @ Value::Object(_)) => response,
+                        _ => serde_json::json!({ "content": text }),
+                    };
+                    let name = tool_call_names
+                        .get(tool_call_id)
+                        .cloned()
+                        .unwrap_or_else(|| tool_call_id.clone());
+                    let part = Part {
+                        function_response: Some(FunctionResponse {
+                            id: None,
+                            name,
+                            response,
+                        }),
+                        ..Default::default()
+                    };
+                    (GeminiRole::User, vec![part])
+                }
+                _ => (GeminiRole::User, content_parts),
+            };
+            if parts.is_empty() {
+                continue;
+            }
+            match contents.last_mut() {
+                Some(last) if last.role.as_ref() == Some(&role) => last.parts.extend(parts),
+                _ => contents.push(Content {
+                    role: Some(role),
+                    parts,
+                }),
+            }
+        }
+
+        let (response_mime_type, response_schema) =
+            response_format_to_gemini(request.response_format);
+        let generation_config = GenerationConfig {
+            temperature: request.temperature,
+            top_p: request.top_p,
+            candidate_count: request.n,
+            max_output_tokens: request.max_tokens,
+            stop_sequences: request.stop,
+            presence_penalty: request.presence_penalty,
+            frequency_penalty: request.frequency_penalty,
+            response_mime_type,
+            response_schema,
+        };
+
+        GeminiRequest {
+            contents,
+            system_instruction: if system_parts.is_empty() {
+                None
+            } else {
+                Some(Content {
+                    role: None,
+                    parts: system_parts,
+                })
+            },
+            generation_config: if generation_config == GenerationConfig::default() {
+                None
+            } else {
+                Some(generation_config)
+            },
+            tools: request.tools.as_ref().map(|tools| {
+                vec![GeminiTool {
+                    function_declarations: tools
+                        .iter()
+                        .filter_map(function_declaration_from_openai)
+                        .collect(),
+                }]
+            }),
+        }
+    }
+}
+
+impl From<GeminiResponse> for ChatCompletionsResponse {
+    fn from(response: GeminiResponse) -> Self {
+        let choices = response
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(position, candidate)| {
+                let parts = candidate
+                    .content
+                    .as_ref()
+                    .map(|content| content.parts.as_slice())
+                    .unwrap_or_default();
+                let text = parts_to_text(parts);
+                let tool_calls = parts_to_tool_calls(parts);
+                // gemini finishes with STOP when calling functions
+                let finish_reason = match (&tool_calls, candidate.finish_reason.as_deref()) {
+                    (Some(_), Some("STOP")) => Some("tool_calls".to_string()),
+                    (_, finish_reason) => finish_reason.map(finish_reason_to_openai),
+                };
+                Choice {
+                    index: candidate.index.unwrap_or(position as u32),
+                    message: Message {
+                        role: "assistant".to_string(),
+                        content: if text.is_empty() && tool_calls.is_some() {
+                            None
+                        } else {
+                            Some(ContentType::Text(text))
+                        },
+                        tool_calls,
+                        ..Default::default()
+                    },
+                    finish_reason,
+                }
+            })
+            .collect();
+
+        ChatCompletionsResponse {
+            id: response.response_id.unwrap_or_default(),
+            object: "chat.completion".to_string(),
+            // gemini does not report a creation time
+            created: 0,
+            choices,
+            usage: response.usage_metadata.map(|usage| Usage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens: usage.candidates_token_count,
+                total_tokens: usage.total_token_count,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT_COMPLETIONS_REQUEST: &str = r#"
+    {
+      "model": "gemini-2.0-flash",
+      "messages": [
+        { "role": "system", "content": "You are a helpful assistant." },
+        { "role": "developer", "content": "Answer briefly." },
+        { "role": "user", "content": "hi" },
+        { "role": "assistant", "content": "Hello! How can I help?" },
+        { "role": "tool", "content": "{\"temperature\": 20}" },
+        { "role": "user", "content": "what is the weather in seattle" }
+      ],
+      "temperature": 0.5,
+      "max_tokens": 256
+    }
+    "#;
+
+    #[test]
+    fn test_request_role_mapping() {
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let gemini_request = GeminiRequest::from(chat_completions_request);
+
+        let roles: Vec<Option<GeminiRole>> = gemini_request
+            .contents
+            .iter()
+            .map(|content| content.role.clone())
+            .collect();
+        // the tool result and the following user turn are merged
+        assert_eq!(
+            roles,
+            vec![
+                Some(GeminiRole::User),
+                Some(GeminiRole::Model),
+                Some(GeminiRole::User)
+            ]
+        );
+        assert_eq!(gemini_request.contents[2].parts.len(), 2);
+        assert_eq!(
+            gemini_request.contents[2].parts[1].text,
+            Some("what is the weather in seattle".to_string())
+        );
+
+        let json = serde_json::to_value(&gemini_request).unwrap();
+        assert_eq!(json["contents"][1]["role"], "model");
+        assert_eq!(json["generationConfig"]["temperature"], 0.5);
+        assert_eq!(json["generationConfig"]["maxOutputTokens"], 256);
+        assert!(json["generationConfig"].get("topP").is_none());
+    }
+
+    #[test]
+    fn test_system_instruction_extraction() {
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let gemini_request = GeminiRequest::from(chat_completions_request);
+
+        let system_instruction = gemini_request.system_instruction.as_ref().unwrap();
+        assert_eq!(system_instruction.role, None);
+        let texts: Vec<&str> = system_instruction
+            .parts
+            .iter()
+            .filter_map(|part| part.text.as_deref())
+            .collect();
+        assert_eq!(
+            texts,
+            vec!["You are a helpful assistant.", "Answer briefly."]
+        );
+
+        let json = serde_json::to_value(&gemini_request).unwrap();
+        assert!(json["systemInstruction"].get("role").is_none());
+        assert_eq!(
+            json["systemInstruction"]["parts"][0]["text"],
+            "You are a helpful assistant."
+        );
+    }
+
+    #[test]
+    fn test_request_without_system_or_config() {
+        let chat_completions_request = ChatCompletionsRequest {
+            model: "gemini-2.0-flash".to_string(),
+            messages: vec![Message::new("hi".to_string())],
+            ..Default::default()
+        };
+        let gemini_request = GeminiRequest::from(chat_completions_request);
+
+        assert!(gemini_request.system_instruction.is_none());
+        assert!(gemini_request.generation_config.is_none());
+        assert_eq!(gemini_request.contents.len(), 1);
+    }
+
+    #[test]
+    fn test_response_format_translation() {
+        let request = |response_format: &str| {
+            GeminiRequest::from(
+                serde_json::from_str::<ChatCompletionsRequest>(&format!(
+                    r#"{{"model": "gemini-2.0-flash", "messages": [{{"role": "user", "content": "list three colors"}}], "response_format": {}}}"#,
+                    response_format
+                ))
+                .unwrap(),
+            )
+        };
+
+        let gemini_request = request(r#"{"type": "json_object"}"#);
+        let json = serde_json::to_value(&gemini_request).unwrap();
+        assert_eq!(
+            json["generationConfig"],
+            serde_json::json!({"responseMimeType": "application/json"})
+        );
+
+        let gemini_request = request(
+            r#"{"type": "json_schema", "json_schema": {"name": "colors", "strict": true, "schema": {
+                "type": "object",
+                "properties": {"colors": {"type": "array", "items": {"type": "string"}}},
+                "required": ["colors"],
+                "additionalProperties": false
+            }}}"#,
+        );
+        let json = serde_json::to_value(&gemini_request).unwrap();
+        assert_eq!(
+            json["generationConfig"],
+            serde_json::json!({
+                "responseMimeType": "application/json",
+                "responseSchema": {
+                    "type": "object",
+                    "properties": {"colors": {"type": "array", "items": {"type": "string"}}},
+                    "required": ["colors"]
+                }
+            })
+        );
+
+        // plain text needs no generation config
+        let gemini_request = request(r#"{"type": "text"}"#);
+        assert!(gemini_request.generation_config.is_none());
+    }
+
+    #[test]
+    fn test_response_translation() {
+        const GEMINI_RESPONSE: &str = r#"
+        {
+          "candidates": [
+            {
+              "content": {
+                "role": "model",
+                "parts": [{ "text": "Hello! " }, { "text": "How can I assist you today?" }]
+              },
+              "finishReason": "STOP",
+              "index": 0
+            }
+          ],
+          "usageMetadata": {
+            "promptTokenCount": 10,
+            "candidatesTokenCount": 12,
+            "totalTokenCount": 22
+          },
+          "modelVersion": "gemini-2.0-flash",
+          "responseId": "mclIaI3sLqGbz7IPm6ulmQ4"
+        }
+        "#;
+
+        let gemini_response = GeminiResponse::try_from(GEMINI_RESPONSE.as_bytes()).unwrap();
+        let chat_completions_response = ChatCompletionsResponse::from(gemini_response);
+
+        assert_eq!(chat_completions_response.id, "mclIaI3sLqGbz7IPm6ulmQ4");
+        assert_eq!(chat_completions_response.choices.len(), 1);
+        let choice = &chat_completions_response.choices[0];
+        assert_eq!(choice.message.role, "assistant");
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text(
+                "Hello! How can I assist you today?".to_string()
+            ))
+        );
+        assert_eq!(choice.finish_reason, Some("stop".to_string()));
+        let usage = chat_completions_response.usage.as_ref().unwrap();
+        assert_eq!(usage.prompt_tokens, 10);
+        assert_eq!(usage.completion_tokens, 12);
+        assert_eq!(usage.total_tokens, 22);
+    }
+
+    #[test]
+    fn test_api_paths() {
+        let api: GeminiApi = serde_json::from_str(r#"{"type": "generative_language"}"#).unwrap();
+        assert_eq!(api, GeminiApi::GenerativeLanguage);
+        assert_eq!(api.host(), "generativelanguage.googleapis.com");
+        assert_eq!(
+            api.path("gemini-2.0-flash", false),
+            "/v1beta/models/gemini-2.0-flash:generateContent"
+        );
+
+        let api: GeminiApi = serde_json::from_str(
+            r#"{"type": "vertex_ai", "project_id": "my-project", "location": "us-central1"}"#,
+        )
+        .unwrap();
+        assert_eq!(api.host(), "us-central1-aiplatform.googleapis.com");
+        assert_eq!(
+            api.path("gemini-2.0-flash", true),
+            "/v1/projects/my-project/locations/us-central1/publishers/google/models/gemini-2.0-flash:streamGenerateContent?alt=sse"
+        );
+    }
+
+    #[test]
+    fn test_tool_call_translation() {
+        const TOOL_CALL_REQUEST: &str = r#"
+        {
+          "model": "gemini-2.0-flash",
+          "messages": [
+            { "role": "user", "content": "what is the weather in seattle" },
+            {
+              "role": "assistant",
+              "content": null,
+              "tool_calls": [
+                {
+                  "id": "call_1",
+                  "type": "function",
+                  "function": { "name": "get_weather", "arguments": "{\"location\": \"seattle\"}" }
+                }
+              ]
+            },
+            { "role": "tool", "tool_call_id": "call_1", "content": "20 degrees" }
+          ],
+          "tools": [
+            {
+              "type": "function",
+              "function": {
+                "name": "get_weather",
+                "parameters": { "type": "object", "properties": { "location": { "type": "string" } } }
+              }
+            }
+          ]
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(TOOL_CALL_REQUEST).unwrap();
+        let gemini_request = GeminiRequest::from(chat_completions_request);
+
+        assert_eq!(gemini_request.contents.len(), 3);
+        assert_eq!(
+            gemini_request.contents[1].parts[0].function_call,
+            Some(FunctionCall {
+                id: None,
+                name: "get_weather".to_string(),
+                args: serde_json::json!({ "location": "seattle" }),
+            })
+        );
+        // the response is matched to the call by function name
+        assert_eq!(
+            gemini_request.contents[2].parts[0].function_response,
+            Some(FunctionResponse {
+                id: None,
+                name: "get_weather".to_string(),
+                response: serde_json::json!({ "content": "20 degrees" }),
+            })
+        );
+
+        let json = serde_json::to_value(&gemini_request).unwrap();
+        assert_eq!(
+            json["tools"][0]["functionDeclarations"][0]["name"],
+            "get_weather"
+        );
+        assert_eq!(
+            json["contents"][1]["parts"][0]["functionCall"]["name"],
+            "get_weather"
+        );
+        assert_eq!(json["contents"][2]["role"], "user");
+
+        const GEMINI_RESPONSE: &str = r#"
+        {
+          "candidates": [
+            {
+              "content": {
+                "role": "model",
+                "parts": [{ "functionCall": { "name": "get_weather", "args": { "location": "portland" } } }]
+              },
+              "finishReason": "STOP",
+              "index": 0
+            }
+          ]
+        }
+        "#;
+
+        let gemini_response = GeminiResponse::try_from(GEMINI_RESPONSE.as_bytes()).unwrap();
+        let chat_completions_response = ChatCompletionsResponse::from(gemini_response);
+        let choice = &chat_completions_response.choices[0];
+        assert_eq!(choice.finish_reason, Some("tool_calls".to_string()));
+        assert!(choice.message.content.is_none());
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "call_0");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            serde_json::from_str::<Value>(&tool_calls[0].function.arguments).unwrap(),
+            serde_json::json!({ "location": "portland" })
+        );
+    }
+}
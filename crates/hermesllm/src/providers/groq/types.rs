@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::providers::openai::types::ChatCompletionsRequest;
+use crate::{Provider, ProviderCapabilities};
+
+/// Groq accepts at most this many stop sequences.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Groq's OpenAI compatible API.
+pub const DEFAULT_BASE_URL: &str = "https://api.groq.com/openai/v1";
+
+#[derive(Debug, Error)]
+pub enum GroqError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("missing required field: {field}")]
+    MissingField { field: &'static str },
+    #[error("unsupported value for {field}: {reason}")]
+    UnsupportedValue { field: &'static str, reason: String },
+    #[error("model {model} is not supported")]
+    UnsupportedModel { model: String },
+}
+
+type Result<T> = std::result::Result<T, GroqError>;
+
+/// Chat completions request as accepted by Groq's OpenAI compatible API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroqRequest {
+    #[serde(flatten)]
+    pub request: ChatCompletionsRequest,
+}
+
+impl GroqRequest {
+    /// Drops the fields Groq rejects, `stream_options` and `metadata`, and fails on values it
+    /// can't serve instead of letting the upstream answer with a 400.
+    pub fn from_openai(mut request: ChatCompletionsRequest) -> Result<Self> {
+        if request.model.is_empty() {
+            return Err(GroqError::MissingField { field: "model" });
+        }
+        if request.messages.is_empty() {
+            return Err(GroqError::MissingField { field: "messages" });
+        }
+        let capabilities = ProviderCapabilities::lookup(&Provider::Groq, &request.model);
+        if let Some(n) = request.n {
+            if n != 1 && !capabilities.supports_n {
+                return Err(GroqError::UnsupportedValue {
+                    field: "n",
+                    reason: format!("only a single choice is supported, got {}", n),
+                });
+            }
+        }
+        if let Some(temperature) = request.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(GroqError::UnsupportedValue {
+                    field: "temperature",
+                    reason: format!("must be between 0 and 2, got {}", temperature),
+                });
+            }
+        }
+        if let Some(stop) = request.stop.as_ref() {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(GroqError::UnsupportedValue {
+                    field: "stop",
+                    reason: format!(
+                        "at most {} stop sequences are supported, got {}",
+                        MAX_STOP_SEQUENCES,
+                        stop.len()
+                    ),
+                });
+            }
+        }
+
+        request.stream_options = None;
+        request.metadata = None;
+
+        Ok(GroqRequest { request })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(GroqError::from)
+    }
+}
+
+/// Groq endpoint and the models served there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroqProvider {
+    pub base_url: String,
+    /// Environment variable holding the api key, e.g. `GROQ_API_KEY`.
+    pub api_key_env: Option<String>,
+    /// Model ids requests may be sent with, any model when empty.
+    pub models: Vec<String>,
+    /// Model sent instead of the requested one, e.g. `llama-3.3-70b-versatile`.
+    pub default_model: Option<String>,
+}
+
+impl GroqProvider {
+    pub fn new(base_url: String) -> Self {
+        GroqProvider {
+            base_url,
+            api_key_env: None,
+            models: vec![],
+            default_model: None,
+        }
+    }
+
+    pub fn with_api_key_env(mut self, api_key_env: Option<String>) -> Self {
+        self.api_key_env = api_key_env;
+        self
+    }
+
+    pub fn with_models(mut self, models: Vec<String>) -> Self {
+        self.models = models;
+        self
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    pub fn chat_completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+
+    pub fn supports_model(&self, model: &str) -> bool {
+        self.models.is_empty() || self.models.iter().any(|supported| supported == model)
+    }
+
+    /// Request for the configured model, fails for models that are not in `models`.
+    pub fn chat_request(&self, mut request: ChatCompletionsRequest) -> Result<GroqRequest> {
+        if let Some(default_model) = self.default_model.as_ref() {
+            request.model = default_model.clone();
+        }
+        if !self.supports_model(&request.model) {
+            return Err(GroqError::UnsupportedModel {
+                model: request.model,
+            });
+        }
+        GroqRequest::from_openai(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::types::Message;
+    use serde_json::Value;
+
+    #[test]
+    fn test_unsupported_fields_are_stripped() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "llama-3.3-70b-versatile",
+          "messages": [{ "role": "user", "content": "hi" }],
+          "stream": true,
+          "stream_options": { "include_usage": true },
+          "metadata": { "archgw_preference_config": "" }
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let groq_request = GroqRequest::from_openai(chat_completions_request).unwrap();
+        let body: Value = serde_json::from_slice(&groq_request.to_bytes().unwrap()).unwrap();
+
+        assert!(body.get("stream_options").is_none());
+        assert!(body.get("metadata").is_none());
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["model"], "llama-3.3-70b-versatile");
+    }
+
+    #[test]
+    fn test_supported_request_is_unchanged() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "llama-3.3-70b-versatile",
+          "messages": [
+            { "role": "system", "content": "You are a helpful assistant." },
+            { "role": "user", "content": "hi" }
+          ],
+          "temperature": 0.5,
+          "top_p": 0.9,
+          "n": 1,
+          "max_tokens": 100,
+          "stop": ["\n\n"],
+          "presence_penalty": 0.1,
+          "frequency_penalty": 0.2
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let groq_request = GroqRequest::from_openai(chat_completions_request).unwrap();
+        let body: Value = serde_json::from_slice(&groq_request.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::from_str::<Value>(CHAT_COMPLETIONS_REQUEST).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_incompatible_values_are_rejected() {
+        let request = ChatCompletionsRequest {
+            model: "llama-3.3-70b-versatile".to_string(),
+            messages: vec![Message::new("hi".to_string())],
+            n: Some(2),
+            ..Default::default()
+        };
+        let err = GroqRequest::from_openai(request.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            GroqError::UnsupportedValue { field: "n", .. }
+        ));
+
+        let err = GroqRequest::from_openai(ChatCompletionsRequest {
+            n: None,
+            temperature: Some(2.5),
+            ..request.clone()
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            GroqError::UnsupportedValue {
+                field: "temperature",
+                ..
+            }
+        ));
+
+        let err = GroqRequest::from_openai(ChatCompletionsRequest {
+            n: None,
+            messages: vec![],
+            ..request
+        })
+        .unwrap_err();
+        assert!(matches!(err, GroqError::MissingField { field: "messages" }));
+    }
+
+    #[test]
+    fn test_groq_provider() {
+        let provider = GroqProvider::new(format!("{}/", DEFAULT_BASE_URL)).with_models(vec![
+            "llama-3.3-70b-versatile".to_string(),
+            "llama-3.1-8b-instant".to_string(),
+        ]);
+        assert_eq!(
+            provider.chat_completions_url(),
+            "https://api.groq.com/openai/v1/chat/completions"
+        );
+
+        let request = ChatCompletionsRequest {
+            model: "llama-3.1-8b-instant".to_string(),
+            messages: vec![Message::new("hi".to_string())],
+            ..Default::default()
+        };
+        let groq_request = provider.chat_request(request.clone()).unwrap();
+        assert_eq!(groq_request.request.model, "llama-3.1-8b-instant");
+
+        let err = provider
+            .chat_request(ChatCompletionsRequest {
+                model: "mixtral-8x7b-32768".to_string(),
+                ..request.clone()
+            })
+            .unwrap_err();
+        assert!(
+            matches!(err, GroqError::UnsupportedModel { model } if model == "mixtral-8x7b-32768")
+        );
+
+        // the configured model replaces the requested one
+        let groq_request = provider
+            .with_default_model(Some("llama-3.3-70b-versatile".to_string()))
+            .chat_request(ChatCompletionsRequest {
+                model: "none".to_string(),
+                ..request
+            })
+            .unwrap();
+        assert_eq!(groq_request.request.model, "llama-3.3-70b-versatile");
+    }
+}
@@ -1 +1,9 @@
+pub mod anthropic;
+pub mod azure_openai;
+pub mod bedrock;
+pub mod cohere;
+pub mod gemini;
+pub mod groq;
+pub mod ollama;
 pub mod openai;
+pub mod streaming;
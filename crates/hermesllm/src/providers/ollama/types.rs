@@ -0,0 +1,469 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use thiserror::Error;
+
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, ChatCompletionsResponse, Choice, ContentType, FunctionCall, Message,
+    MultiPartContentType, ResponseFormat, ToolCall, ToolType, Usage,
+};
+
+/// Where a local ollama server listens by default.
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Debug, Error)]
+pub enum OllamaError {
+    #[error("json error: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, OllamaError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    /// Unlike OpenAI the arguments are a json object, not an encoded string.
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct OllamaMessage {
+    pub role: String,
+    /// Plain text, images are sent separately.
+    #[serde(default)]
+    pub content: String,
+    /// Base64 encoded images, without a data url prefix.
+    pub images: Option<Vec<String>>,
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+}
+
+/// Sampling parameters, which ollama takes in an `options` object rather than at the top level.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct OllamaOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Maximum number of tokens to generate, `max_tokens` in OpenAI.
+    pub num_predict: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+/// Body of ollama's `/api/chat`.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaChatRequest {
+    pub model: String,
+    pub messages: Vec<OllamaMessage>,
+    /// Ollama streams unless told otherwise, so this is always sent.
+    pub stream: bool,
+    pub options: Option<OllamaOptions>,
+    /// Same format as OpenAI tools.
+    pub tools: Option<Vec<Value>>,
+    /// `"json"` for any JSON answer or the JSON schema of the answer.
+    pub format: Option<Value>,
+}
+
+impl OllamaChatRequest {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(OllamaError::from)
+    }
+}
+
+/// A non streaming response, or one line of the newline delimited json stream. The last line
+/// has `done` set and carries the token counts.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaChatResponse {
+    #[serde(default)]
+    pub model: String,
+    pub created_at: Option<String>,
+    pub message: Option<OllamaMessage>,
+    #[serde(default)]
+    pub done: bool,
+    pub done_reason: Option<String>,
+    pub prompt_eval_count: Option<usize>,
+    pub eval_count: Option<usize>,
+    /// Set instead of everything else when the request failed.
+    pub error: Option<String>,
+}
+
+impl TryFrom<&[u8]> for OllamaChatResponse {
+    type Error = OllamaError;
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(OllamaError::from)
+    }
+}
+
+impl OllamaChatResponse {
+    pub fn usage(&self) -> Option<Usage> {
+        if !self.done {
+            return None;
+        }
+        let prompt_tokens = self.prompt_eval_count.unwrap_or_default();
+        let completion_tokens = self.eval_count.unwrap_or_default();
+        Some(Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        })
+    }
+
+    pub fn finish_reason(&self) -> Option<String> {
+        let calls_tools = self
+            .message
+            .as_ref()
+            .and_then(|message| message.tool_calls.as_ref())
+            .map_or(false, |tool_calls| !tool_calls.is_empty());
+        match self.done_reason.as_deref() {
+            _ if calls_tools => Some("tool_calls".to_string()),
+            Some(done_reason) => Some(done_reason.to_string()),
+            None if self.done => Some("stop".to_string()),
+            None => None,
+        }
+    }
+}
+
+/// Text parts are joined, images must be inline data urls as ollama does not fetch images.
+fn split_content(content: &ContentType) -> (String, Option<Vec<String>>) {
+    match content {
+        ContentType::Text(text) => (text.clone(), None),
+        ContentType::MultiPart(parts) => {
+            let images: Vec<String> = parts
+                .iter()
+                .filter(|part| part.content_type == MultiPartContentType::ImageUrl)
+                .filter_map(|part| part.image_url.as_ref())
+                .filter_map(|image_url| {
+                    image_url
+                        .url
+                        .strip_prefix("data:")
+                        .and_then(|url| url.split_once(";base64,"))
+                        .map(|(_, data)| data.to_string())
+                })
+                .collect();
+            (
+                content.to_string(),
+                if images.is_empty() {
+                    None
+                } else {
+                    Some(images)
+                },
+            )
+        }
+    }
+}
+
+fn message_from_openai(message: Message) -> OllamaMessage {
+    let (content, images) = message
+        .content
+        .as_ref()
+        .map(split_content)
+        .unwrap_or_default();
+    let tool_calls = message.tool_calls.map(|tool_calls| {
+        tool_calls
+            .into_iter()
+            .map(|tool_call| OllamaToolCall {
+                function: OllamaFunctionCall {
+                    name: tool_call.function.name,
+                    // ollama expects an object, arguments that are not valid json are dropped
+                    arguments: serde_json::from_str(&tool_call.function.arguments)
+                        .unwrap_or_else(|_| Value::Object(Default::default())),
+                },
+            })
+            .collect()
+    });
+    OllamaMessage {
+        // developer messages are system messages for ollama, tool results keep their role
+        role: if message.role == "developer" {
+            "system".to_string()
+        } else {
+            message.role
+        },
+        content,
+        images,
+        tool_calls,
+    }
+}
+
+impl From<ChatCompletionsRequest> for OllamaChatRequest {
+    fn from(request: ChatCompletionsRequest) -> Self {
+        let options = OllamaOptions {
+            temperature: request.temperature,
+            top_p: request.top_p,
+            num_predict: request.max_tokens,
+            stop: request.stop,
+            presence_penalty: request.presence_penalty,
+            frequency_penalty: request.frequency_penalty,
+        };
+        OllamaChatRequest {
+            model: request.model,
+            messages: request
+                .messages
+                .into_iter()
+                .map(message_from_openai)
+                .collect(),
+            stream: request.stream.unwrap_or(false),
+            options: if options == OllamaOptions::default() {
+                None
+            } else {
+                Some(options)
+            },
+            tools: request.tools,
+            format: match request.response_format {
+                Some(ResponseFormat::JsonObject) => Some(Value::String("json".to_string())),
+                Some(ResponseFormat::JsonSchema { json_schema }) => Some(
+                    json_schema
+                        .schema
+                        .unwrap_or_else(|| Value::String("json".to_string())),
+                ),
+                Some(ResponseFormat::Text) | None => None,
+            },
+        }
+    }
+}
+
+impl From<OllamaChatResponse> for ChatCompletionsResponse {
+    fn from(response: OllamaChatResponse) -> Self {
+        let usage = response.usage();
+        let finish_reason = response.finish_reason();
+        let message = response.message.unwrap_or_default();
+        // ollama does not identify tool calls, they are numbered in order
+        let tool_calls = message.tool_calls.map(|tool_calls| {
+            tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(index, tool_call)| ToolCall {
+                    id: format!("call_{}", index),
+                    tool_type: ToolType::Function,
+                    function: FunctionCall {
+                        name: tool_call.function.name,
+                        arguments: tool_call.function.arguments.to_string(),
+                    },
+                })
+                .collect::<Vec<ToolCall>>()
+        });
+
+        ChatCompletionsResponse {
+            // ollama reports neither an id nor a numeric creation time
+            id: String::new(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: "assistant".to_string(),
+                    // openai leaves the content empty when the model only calls tools
+                    content: if message.content.is_empty() && tool_calls.is_some() {
+                        None
+                    } else {
+                        Some(ContentType::Text(message.content))
+                    },
+                    tool_calls,
+                    ..Default::default()
+                },
+                finish_reason,
+            }],
+            usage,
+        }
+    }
+}
+
+/// An ollama server at `base_url`.
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    pub base_url: String,
+    /// Model sent instead of the requested one, e.g. `llama3.2`.
+    pub default_model: Option<String>,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: String) -> Self {
+        OllamaProvider {
+            base_url,
+            default_model: None,
+        }
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    pub fn chat_url(&self) -> String {
+        format!("{}/api/chat", self.base_url.trim_end_matches('/'))
+    }
+
+    pub fn chat_request(&self, request: ChatCompletionsRequest) -> OllamaChatRequest {
+        let mut chat_request = OllamaChatRequest::from(request);
+        if let Some(default_model) = self.default_model.as_ref() {
+            chat_request.model = default_model.clone();
+        }
+        chat_request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request() {
+        const CHAT_COMPLETIONS_REQUEST: &str = r#"
+        {
+          "model": "llama",
+          "messages": [
+            { "role": "system", "content": "You are a helpful assistant." },
+            {
+              "role": "user",
+              "content": [
+                { "type": "text", "text": "what is in this image?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } }
+              ]
+            },
+            {
+              "role": "assistant",
+              "content": "",
+              "tool_calls": [
+                {
+                  "id": "call_1",
+                  "type": "function",
+                  "function": { "name": "describe", "arguments": "{\"detail\": \"high\"}" }
+                }
+              ]
+            },
+            { "role": "tool", "tool_call_id": "call_1", "content": "a cat" }
+          ],
+          "temperature": 0.5,
+          "max_tokens": 128,
+          "stream_options": { "include_usage": true }
+        }
+        "#;
+
+        let chat_completions_request: ChatCompletionsRequest =
+            serde_json::from_str(CHAT_COMPLETIONS_REQUEST).unwrap();
+        let provider = OllamaProvider::new(DEFAULT_BASE_URL.to_string())
+            .with_default_model(Some("llama3.2".to_string()));
+        let chat_request = provider.chat_request(chat_completions_request);
+        let body: Value = serde_json::from_slice(&chat_request.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(provider.chat_url(), "http://localhost:11434/api/chat");
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "llama3.2",
+                "messages": [
+                    { "role": "system", "content": "You are a helpful assistant." },
+                    {
+                        "role": "user",
+                        "content": "what is in this image?",
+                        "images": ["iVBORw0KGgo="]
+                    },
+                    {
+                        "role": "assistant",
+                        "content": "",
+                        "tool_calls": [
+                            { "function": { "name": "describe", "arguments": { "detail": "high" } } }
+                        ]
+                    },
+                    { "role": "tool", "content": "a cat" }
+                ],
+                // ollama streams by default
+                "stream": false,
+                "options": { "temperature": 0.5, "num_predict": 128 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_response_format() {
+        let chat_request = |response_format: Option<ResponseFormat>| {
+            OllamaChatRequest::from(ChatCompletionsRequest {
+                model: "llama3.2".to_string(),
+                messages: vec![Message::new("list three colors".to_string())],
+                response_format,
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            chat_request(Some(ResponseFormat::JsonObject)).format,
+            Some(Value::String("json".to_string()))
+        );
+        let schema =
+            serde_json::json!({"type": "object", "properties": {"colors": {"type": "array"}}});
+        let json_schema = serde_json::from_value(serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {"name": "colors", "schema": schema}
+        }))
+        .unwrap();
+        assert_eq!(chat_request(Some(json_schema)).format, Some(schema));
+        assert_eq!(chat_request(Some(ResponseFormat::Text)).format, None);
+    }
+
+    #[test]
+    fn test_chat_response() {
+        const OLLAMA_RESPONSE: &str = r#"
+        {
+          "model": "llama3.2",
+          "created_at": "2025-06-10T08:15:42.123456Z",
+          "message": { "role": "assistant", "content": "Hello! How can I help?" },
+          "done": true,
+          "done_reason": "stop",
+          "total_duration": 4883583458,
+          "prompt_eval_count": 26,
+          "eval_count": 8
+        }
+        "#;
+
+        let ollama_response = OllamaChatResponse::try_from(OLLAMA_RESPONSE.as_bytes()).unwrap();
+        let response = ChatCompletionsResponse::from(ollama_response);
+
+        assert_eq!(response.object, "chat.completion");
+        let choice = &response.choices[0];
+        assert_eq!(choice.message.role, "assistant");
+        assert_eq!(
+            choice.message.content,
+            Some(ContentType::Text("Hello! How can I help?".to_string()))
+        );
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        let usage = response.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 26);
+        assert_eq!(usage.completion_tokens, 8);
+        assert_eq!(usage.total_tokens, 34);
+    }
+
+    #[test]
+    fn test_tool_call_response() {
+        const OLLAMA_RESPONSE: &str = r#"
+        {
+          "model": "llama3.2",
+          "message": {
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [{ "function": { "name": "get_weather", "arguments": { "city": "seattle" } } }]
+          },
+          "done": true,
+          "done_reason": "stop"
+        }
+        "#;
+
+        let ollama_response = OllamaChatResponse::try_from(OLLAMA_RESPONSE.as_bytes()).unwrap();
+        let response = ChatCompletionsResponse::from(ollama_response);
+
+        let choice = &response.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert!(choice.message.content.is_none());
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "call_0");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"city":"seattle"}"#);
+    }
+}
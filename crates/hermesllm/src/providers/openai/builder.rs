@@ -1,6 +1,8 @@
 use serde_json::Value;
 
-use crate::providers::openai::types::{ChatCompletionsRequest, Message, StreamOptions};
+use crate::providers::openai::types::{
+    ChatCompletionsRequest, Message, ResponseFormat, StreamOptions,
+};
 
 #[derive(Debug, Clone)]
 pub struct OpenAIRequestBuilder {
@@ -16,6 +18,7 @@ pub struct OpenAIRequestBuilder {
     frequency_penalty: Option<f32>,
     stream_options: Option<StreamOptions>,
     tools: Option<Vec<Value>>,
+    response_format: Option<ResponseFormat>,
 }
 
 impl OpenAIRequestBuilder {
@@ -33,6 +36,7 @@ impl OpenAIRequestBuilder {
             frequency_penalty: None,
             stream_options: None,
             tools: None,
+            response_format: None,
         }
     }
 
@@ -87,6 +91,11 @@ impl OpenAIRequestBuilder {
         self
     }
 
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
     pub fn build(self) -> Result<ChatCompletionsRequest, &'static str> {
         let request = ChatCompletionsRequest {
             model: self.model,
@@ -101,6 +110,8 @@ impl OpenAIRequestBuilder {
             frequency_penalty: self.frequency_penalty,
             stream_options: self.stream_options,
             tools: self.tools,
+            response_format: self.response_format,
+            documents: None,
             metadata: None,
         };
         Ok(request)
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How the access key is sent to an OpenAI compatible backend.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub enum AuthHeaderStyle {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    #[serde(rename = "bearer")]
+    Bearer,
+    /// `api-key: <key>`, as used by azure openai
+    #[serde(rename = "api_key")]
+    ApiKey,
+    /// `x-api-key: <key>`
+    #[serde(rename = "x_api_key")]
+    XApiKey,
+    /// The backend does not need authentication, e.g. a local vllm or lm studio server.
+    #[serde(rename = "none")]
+    None,
+}
+
+/// A backend that speaks the OpenAI chat completions protocol (Mistral, Together, vLLM,
+/// LM Studio, ...) at its own base url.
+#[derive(Debug, Clone, Default)]
+pub struct OpenAiCompatibleProvider {
+    /// Base url including the api version, e.g. `https://api.mistral.ai/v1`.
+    pub base_url: String,
+    pub auth_header_style: AuthHeaderStyle,
+    /// Requested model name to the name the backend knows the model by.
+    pub model_map: HashMap<String, String>,
+    /// Model sent when the requested model is not in the map.
+    pub default_model: Option<String>,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(base_url: String) -> Self {
+        OpenAiCompatibleProvider {
+            base_url,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_auth_header_style(mut self, auth_header_style: AuthHeaderStyle) -> Self {
+        self.auth_header_style = auth_header_style;
+        self
+    }
+
+    pub fn with_model_map(mut self, model_map: HashMap<String, String>) -> Self {
+        self.model_map = model_map;
+        self
+    }
+
+    pub fn with_default_model(mut self, default_model: Option<String>) -> Self {
+        self.default_model = default_model;
+        self
+    }
+
+    pub fn chat_completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+
+    /// Header name and value carrying the access key, `None` if the backend takes no key.
+    pub fn auth_header(&self, access_key: &str) -> Option<(&'static str, String)> {
+        match self.auth_header_style {
+            AuthHeaderStyle::Bearer => Some(("authorization", format!("Bearer {}", access_key))),
+            AuthHeaderStyle::ApiKey => Some(("api-key", access_key.to_string())),
+            AuthHeaderStyle::XApiKey => Some(("x-api-key", access_key.to_string())),
+            AuthHeaderStyle::None => None,
+        }
+    }
+
+    pub fn map_model(&self, model: &str) -> String {
+        self.model_map
+            .get(model)
+            .or(self.default_model.as_ref())
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    /// Rewrites the `model` of a chat completions request body. The request is kept as json so
+    /// fields the typed request does not model are still forwarded.
+    pub fn rewrite_request(&self, request: &mut Value) {
+        let model = request
+            .get("model")
+            .and_then(|model| model.as_str())
+            .unwrap_or_default();
+        let model = self.map_model(model);
+        if let Some(request) = request.as_object_mut() {
+            request.insert("model".to_string(), Value::String(model));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> OpenAiCompatibleProvider {
+        OpenAiCompatibleProvider::new("http://localhost:8000/v1/".to_string()).with_model_map(
+            HashMap::from([(
+                "llama".to_string(),
+                "meta-llama/Llama-3.1-8B-Instruct".to_string(),
+            )]),
+        )
+    }
+
+    #[test]
+    fn test_model_rewritten_from_map() {
+        let mut request = serde_json::json!({
+            "model": "llama",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "logprobs": true
+        });
+        provider().rewrite_request(&mut request);
+
+        assert_eq!(request["model"], "meta-llama/Llama-3.1-8B-Instruct");
+        // fields unknown to hermesllm are kept
+        assert_eq!(request["logprobs"], true);
+    }
+
+    #[test]
+    fn test_unmapped_model() {
+        let mut request = serde_json::json!({ "model": "mistral-large-latest", "messages": [] });
+        provider().rewrite_request(&mut request);
+        assert_eq!(request["model"], "mistral-large-latest");
+
+        let provider = provider().with_default_model(Some("qwen2.5".to_string()));
+        provider.rewrite_request(&mut request);
+        assert_eq!(request["model"], "qwen2.5");
+    }
+
+    #[test]
+    fn test_url_and_auth_header() {
+        let provider = provider();
+        assert_eq!(
+            provider.chat_completions_url(),
+            "http://localhost:8000/v1/chat/completions"
+        );
+        assert_eq!(
+            provider.auth_header("secret"),
+            Some(("authorization", "Bearer secret".to_string()))
+        );
+
+        let provider = provider.with_auth_header_style(AuthHeaderStyle::ApiKey);
+        assert_eq!(
+            provider.auth_header("secret"),
+            Some(("api-key", "secret".to_string()))
+        );
+        let provider = provider.with_auth_header_style(AuthHeaderStyle::None);
+        assert_eq!(provider.auth_header("secret"), None);
+    }
+}
@@ -8,6 +8,11 @@ use std::convert::TryFrom;
 use std::str;
 use thiserror::Error;
 
+use crate::providers::bedrock::types::{BedrockError, ConverseRequest};
+use crate::providers::cohere::types::{CohereChatRequest, CohereError};
+use crate::providers::groq::types::{GroqError, GroqRequest};
+use crate::providers::ollama::types::{OllamaChatRequest, OllamaError};
+use crate::providers::streaming::StreamAggregator;
 use crate::Provider;
 
 #[derive(Debug, Error)]
@@ -23,6 +28,16 @@ pub enum OpenAIError {
     },
     #[error("unsupported provider: {provider}")]
     UnsupportedProvider { provider: String },
+    #[error("invalid groq request: {0}")]
+    GroqError(#[from] GroqError),
+    #[error("bedrock error: {0}")]
+    BedrockError(#[from] BedrockError),
+    #[error("ollama error: {0}")]
+    OllamaError(#[from] OllamaError),
+    #[error("cohere error: {0}")]
+    CohereError(#[from] CohereError),
+    #[error("invalid request: {}", problems.join("; "))]
+    InvalidRequest { problems: Vec<String> },
 }
 
 type Result<T> = std::result::Result<T, OpenAIError>;
@@ -81,11 +96,48 @@ impl Display for ContentType {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ToolType {
+    #[serde(rename = "function")]
+    Function,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON encoded arguments, as generated by the model.
+    #[serde(deserialize_with = "deserialize_arguments")]
+    pub arguments: String,
+}
+
+/// Some clients send the arguments of earlier tool calls as a JSON object instead of an
+/// encoded string, those are encoded again.
+fn deserialize_arguments<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(arguments) => Ok(arguments),
+        arguments => Ok(arguments.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionCall,
+}
+
 #[skip_serializing_none]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Message {
     pub role: String,
     pub content: Option<ContentType>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Set on `tool` messages, the id of the tool call this message is the result of.
+    pub tool_call_id: Option<String>,
 }
 
 impl Message {
@@ -93,6 +145,7 @@ impl Message {
         Self {
             role: "user".to_string(),
             content: Some(ContentType::Text(content)),
+            ..Default::default()
         }
     }
 }
@@ -102,6 +155,35 @@ pub struct StreamOptions {
     pub include_usage: bool,
 }
 
+/// Format of the answer, `json_object` asks for any JSON object and `json_schema` for one
+/// matching the schema.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+impl ResponseFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResponseFormat::Text => "text",
+            ResponseFormat::JsonObject => "json_object",
+            ResponseFormat::JsonSchema { .. } => "json_schema",
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub description: Option<String>,
+    pub schema: Option<Value>,
+    pub strict: Option<bool>,
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ChatCompletionsRequest {
@@ -117,6 +199,10 @@ pub struct ChatCompletionsRequest {
     pub frequency_penalty: Option<f32>,
     pub stream_options: Option<StreamOptions>,
     pub tools: Option<Vec<Value>>,
+    pub response_format: Option<ResponseFormat>,
+    /// Documents the answer is grounded on, not part of the OpenAI api. Only sent to providers
+    /// that take documents, e.g. cohere.
+    pub documents: Option<Vec<Value>>,
     pub metadata: Option<HashMap<String, Value>>,
 }
 
@@ -137,6 +223,21 @@ pub struct ChatCompletionsResponse {
     pub usage: Option<Usage>,
 }
 
+impl ChatCompletionsResponse {
+    /// Merges the chunks of a stream into the response the request gets without streaming,
+    /// see `StreamAggregator`. None for a stream without chunks.
+    pub fn from_stream_chunks<I>(chunks: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = ChatCompletionStreamResponse>,
+    {
+        let mut aggregator = StreamAggregator::default();
+        for chunk in chunks {
+            aggregator.push(chunk);
+        }
+        aggregator.finish()
+    }
+}
+
 impl TryFrom<&[u8]> for ChatCompletionsResponse {
     type Error = OpenAIError;
     fn try_from(bytes: &[u8]) -> Result<Self> {
@@ -153,16 +254,70 @@ impl<'a> TryFrom<(&'a [u8], &'a Provider)> for ChatCompletionsResponse {
     }
 }
 
+/// Roles accepted in the messages of a request.
+pub const MESSAGE_ROLES: [&str; 6] = [
+    "system",
+    "developer",
+    "user",
+    "assistant",
+    "tool",
+    "function",
+];
+
 impl ChatCompletionsRequest {
+    /// Checks what deserializing does not, so that invalid requests are rejected with every
+    /// problem listed instead of with the first error of the provider.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.messages.is_empty() {
+            problems.push("messages must not be empty".to_string());
+        }
+        for (index, message) in self.messages.iter().enumerate() {
+            if !MESSAGE_ROLES.contains(&message.role.as_str()) {
+                problems.push(format!(
+                    "messages[{}]: invalid role '{}', expected one of: {}",
+                    index,
+                    message.role,
+                    MESSAGE_ROLES.join(", ")
+                ));
+            }
+            let has_tool_calls = message
+                .tool_calls
+                .as_ref()
+                .is_some_and(|tool_calls| !tool_calls.is_empty());
+            if message.content.is_none() && !has_tool_calls {
+                problems.push(format!(
+                    "messages[{}]: message has neither content nor tool_calls",
+                    index
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(OpenAIError::InvalidRequest { problems })
+        }
+    }
+
     pub fn to_bytes(&self, provider: Provider) -> Result<Vec<u8>> {
         match provider {
-            Provider::OpenAI
-            | Provider::Arch
+            // reasoning models reject the sampling parameters they fix themselves
+            Provider::OpenAI | Provider::AzureOpenAI => {
+                let mut request = self.clone();
+                provider
+                    .capabilities(&self.model)
+                    .strip_unsupported_params(&mut request);
+                serde_json::to_vec(&request).map_err(OpenAIError::from)
+            }
+            Provider::Arch
             | Provider::Deepseek
             | Provider::Mistral
-            | Provider::Groq
             | Provider::Gemini
             | Provider::Claude => serde_json::to_vec(self).map_err(OpenAIError::from),
+            Provider::Groq => Ok(GroqRequest::from_openai(self.clone())?.to_bytes()?),
+            Provider::Bedrock => Ok(ConverseRequest::from(self.clone()).to_bytes()?),
+            Provider::Ollama => Ok(OllamaChatRequest::from(self.clone()).to_bytes()?),
+            Provider::Cohere => Ok(CohereChatRequest::from(self.clone()).to_bytes()?),
             _ => Err(OpenAIError::UnsupportedProvider {
                 provider: provider.to_string(),
             }),
@@ -191,6 +346,27 @@ pub struct Usage {
 pub struct DeltaMessage {
     pub role: Option<String>,
     pub content: Option<ContentType>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// Part of a tool call sent in a stream chunk. The first part of a call carries its id, type
+/// and function name, the arguments follow in pieces across the next chunks.
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ToolCallDelta {
+    /// Position of the call among the tool calls of the message.
+    pub index: u32,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub tool_type: Option<ToolType>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -287,6 +463,9 @@ pub struct ModelDetail {
     pub object: String,
     pub created: usize,
     pub owned_by: String,
+    /// Routes served by the model, an archgw extension to the OpenAI format.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -305,6 +484,50 @@ pub struct Models {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_validate() {
+        let request: ChatCompletionsRequest =
+            serde_json::from_str(r#"{"model": "gpt-4o", "messages": []}"#).unwrap();
+        match request.validate() {
+            Err(OpenAIError::InvalidRequest { problems }) => {
+                assert_eq!(problems, vec!["messages must not be empty".to_string()])
+            }
+            other => panic!("expected an invalid request, got {:?}", other),
+        }
+
+        let request: ChatCompletionsRequest = serde_json::from_str(
+            r#"{"model": "gpt-4o", "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "robot", "content": "beep"},
+                {"role": "assistant", "content": null}
+            ]}"#,
+        )
+        .unwrap();
+        let err = request.validate().unwrap_err();
+        match &err {
+            OpenAIError::InvalidRequest { problems } => {
+                assert_eq!(problems.len(), 2);
+                assert!(problems[0].starts_with("messages[1]: invalid role 'robot'"));
+                assert_eq!(
+                    problems[1],
+                    "messages[2]: message has neither content nor tool_calls"
+                );
+            }
+            other => panic!("expected an invalid request, got {:?}", other),
+        }
+        assert!(err.to_string().starts_with("invalid request: messages[1]"));
+
+        let request: ChatCompletionsRequest = serde_json::from_str(
+            r#"{"model": "gpt-4o", "messages": [
+                {"role": "user", "content": "weather in Tokyo?"},
+                {"role": "assistant", "content": null, "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}]},
+                {"role": "tool", "content": "sunny", "tool_call_id": "call_1"}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(request.validate().is_ok());
+    }
+
     #[test]
     fn test_content_type_display() {
         let text_content = ContentType::Text("Hello, world!".to_string());
@@ -428,6 +651,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tool_call_arguments_object() {
+        let message: Message = serde_json::from_str(
+            r#"{"role": "assistant", "content": null, "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": {"location": "Tokyo"}}}]}"#,
+        )
+        .unwrap();
+        let tool_calls = message.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].function.arguments, r#"{"location":"Tokyo"}"#);
+    }
+
     #[test]
     fn test_sse_streaming() {
         let json_data = r#"data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-3.5-turbo","choices":[{"index":0,"delta":{"role":"assistant"},"finish_reason":null}]}
@@ -489,6 +722,38 @@ data: [DONE]"#;
         }
     }
 
+    #[test]
+    fn test_response_from_stream_chunks() {
+        let json_data = r#"data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant"},"finish_reason":null}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello, "},"finish_reason":null}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"how can I help?"},"finish_reason":null}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}
+data: {"id":"chatcmpl-123","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":6,"total_tokens":15}}
+data: [DONE]"#;
+
+        let chunks = SseChatCompletionIter::new(json_data.lines())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let response = ChatCompletionsResponse::from_stream_chunks(chunks).unwrap();
+        let body = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hello, how can I help?"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 6, "total_tokens": 15}
+            })
+        );
+
+        assert!(ChatCompletionsResponse::from_stream_chunks(Vec::new()).is_none());
+    }
+
     #[test]
     fn parse_chat_completions_request() {
         const CHAT_COMPLETIONS_REQUEST: &str = r#"
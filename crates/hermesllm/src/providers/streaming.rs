@@ -0,0 +1,815 @@
+use std::collections::VecDeque;
+use thiserror::Error;
+
+use crate::providers::anthropic::types::{
+    stop_reason_to_finish_reason, AnthropicStreamEvent, ContentDelta,
+};
+use crate::providers::cohere::types::{self as cohere, CohereStreamEvent};
+use crate::providers::gemini::types::{finish_reason_to_openai, parts_to_text, GeminiResponse};
+use crate::providers::ollama::types::OllamaChatResponse;
+use crate::providers::openai::types::{
+    ChatCompletionStreamResponse, ChatCompletionsResponse, Choice, ContentType, DeltaMessage,
+    FunctionCall, Message, StreamChoice, ToolCall, ToolType, Usage,
+};
+use crate::Provider;
+
+pub const SSE_DONE_FRAME: &str = "data: [DONE]\n\n";
+
+#[derive(Debug, Error)]
+pub enum StreamingError {
+    #[error("invalid streaming data: {source}, data: {data}")]
+    InvalidStreamingData {
+        source: serde_json::Error,
+        data: String,
+    },
+}
+
+type Result<T> = std::result::Result<T, StreamingError>;
+
+enum StreamFormat {
+    Anthropic,
+    Gemini,
+    /// Newline delimited json rather than server sent events.
+    Ollama,
+    Cohere,
+}
+
+/// Translates the server sent events of a provider stream into OpenAI `chat.completion.chunk`
+/// frames. Bytes are buffered until a complete line is received so events split across network
+/// chunks are only parsed once whole. Translated frames are returned by iterating the translator.
+pub struct SseStreamTranslator {
+    format: StreamFormat,
+    buffer: Vec<u8>,
+    frames: VecDeque<Result<String>>,
+    id: String,
+    model: String,
+    prompt_tokens: usize,
+    role_sent: bool,
+    done: bool,
+}
+
+impl SseStreamTranslator {
+    /// Returns `None` for providers that already stream OpenAI compatible chunks.
+    pub fn for_provider(provider: &Provider) -> Option<Self> {
+        let format = match provider {
+            Provider::Claude => StreamFormat::Anthropic,
+            Provider::Gemini => StreamFormat::Gemini,
+            Provider::Ollama => StreamFormat::Ollama,
+            Provider::Cohere => StreamFormat::Cohere,
+            _ => return None,
+        };
+
+        Some(SseStreamTranslator {
+            format,
+            buffer: Vec::new(),
+            frames: VecDeque::new(),
+            id: String::new(),
+            model: String::new(),
+            prompt_tokens: 0,
+            role_sent: false,
+            done: false,
+        })
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        while let Some(position) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=position).collect();
+            self.process_line(&String::from_utf8_lossy(&line));
+        }
+    }
+
+    /// Processes whatever is left in the buffer and terminates the stream with `data: [DONE]`
+    /// if the provider did not end it already.
+    pub fn finish(&mut self) {
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.process_line(&String::from_utf8_lossy(&line));
+        }
+        self.push_done();
+    }
+
+    fn process_line(&mut self, line: &str) {
+        let data = match (&self.format, line.trim_end().strip_prefix("data:")) {
+            (StreamFormat::Ollama, _) => line.trim(),
+            (_, Some(data)) => data.trim(),
+            // event names, comments and blank separator lines
+            (_, None) => return,
+        };
+        if data.is_empty() || self.done {
+            return;
+        }
+        if data == "[DONE]" {
+            self.push_done();
+            return;
+        }
+
+        let result = match self.format {
+            StreamFormat::Anthropic => serde_json::from_str::<AnthropicStreamEvent>(data)
+                .map(|event| self.process_anthropic_event(event)),
+            StreamFormat::Gemini => serde_json::from_str::<GeminiResponse>(data)
+                .map(|response| self.process_gemini_response(response)),
+            StreamFormat::Ollama => serde_json::from_str::<OllamaChatResponse>(data)
+                .map(|response| self.process_ollama_response(response)),
+            StreamFormat::Cohere => serde_json::from_str::<CohereStreamEvent>(data)
+                .map(|event| self.process_cohere_event(event)),
+        };
+        if let Err(source) = result {
+            self.frames
+                .push_back(Err(StreamingError::InvalidStreamingData {
+                    source,
+                    data: data.to_string(),
+                }));
+        }
+    }
+
+    fn process_anthropic_event(&mut self, event: AnthropicStreamEvent) {
+        match event {
+            AnthropicStreamEvent::MessageStart { message } => {
+                self.id = message.id;
+                self.model = message.model;
+                self.prompt_tokens = message.usage.map(|u| u.input_tokens).unwrap_or_default();
+                self.push_chunk(0, Some(String::new()), None, None);
+            }
+            AnthropicStreamEvent::ContentBlockDelta {
+                delta: ContentDelta::TextDelta { text },
+                ..
+            } => self.push_chunk(0, Some(text), None, None),
+            AnthropicStreamEvent::MessageDelta { delta, usage } => {
+                let completion_tokens = usage.map(|u| u.output_tokens).unwrap_or_default();
+                self.push_chunk(
+                    0,
+                    None,
+                    delta
+                        .stop_reason
+                        .as_deref()
+                        .map(stop_reason_to_finish_reason),
+                    Some(Usage {
+                        prompt_tokens: self.prompt_tokens,
+                        completion_tokens,
+                        total_tokens: self.prompt_tokens + completion_tokens,
+                    }),
+                );
+            }
+            AnthropicStreamEvent::MessageStop => self.push_done(),
+            AnthropicStreamEvent::Error { error } => {
+                let message = error
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                // e.g. overloaded_error or rate_limit_error
+                let error_type = error
+                    .get("type")
+                    .and_then(|error_type| error_type.as_str())
+                    .unwrap_or("api_error")
+                    .to_string();
+                self.push_error(message, error_type.clone(), Some(error_type));
+            }
+            AnthropicStreamEvent::ContentBlockDelta { .. } | AnthropicStreamEvent::Other => {}
+        }
+    }
+
+    fn process_gemini_response(&mut self, response: GeminiResponse) {
+        if let Some(error) = response.error {
+            let error_type = error
+                .status
+                .map(|status| status.to_lowercase())
+                .unwrap_or_else(|| "api_error".to_string());
+            self.push_error(
+                error.message,
+                error_type,
+                error.code.map(|code| code.to_string()),
+            );
+            return;
+        }
+        if let Some(id) = response.response_id {
+            self.id = id;
+        }
+        if let Some(model) = response.model_version {
+            self.model = model;
+        }
+        for (position, candidate) in response.candidates.iter().enumerate() {
+            let text = candidate
+                .content
+                .as_ref()
+                .map(|content| parts_to_text(&content.parts))
+                .unwrap_or_default();
+            let finish_reason = candidate
+                .finish_reason
+                .as_deref()
+                .map(finish_reason_to_openai);
+            // gemini repeats the usage on every chunk, only report it with the last one
+            let usage = match (&finish_reason, &response.usage_metadata) {
+                (Some(_), Some(usage)) => Some(Usage {
+                    prompt_tokens: usage.prompt_token_count,
+                    completion_tokens: usage.candidates_token_count,
+                    total_tokens: usage.total_token_count,
+                }),
+                _ => None,
+            };
+            self.push_chunk(
+                candidate.index.unwrap_or(position as u32),
+                Some(text),
+                finish_reason,
+                usage,
+            );
+        }
+    }
+
+    fn process_ollama_response(&mut self, mut response: OllamaChatResponse) {
+        if let Some(error) = response.error.take() {
+            self.push_error(error, "api_error".to_string(), None);
+            return;
+        }
+        let usage = response.usage();
+        let finish_reason = response.finish_reason();
+        self.model = response.model;
+        self.push_chunk(
+            0,
+            response.message.map(|message| message.content),
+            finish_reason,
+            usage,
+        );
+        // the last line is the end of the stream
+        if response.done {
+            self.push_done();
+        }
+    }
+
+    /// Text and tool plan deltas are sent as content, tool call deltas are not translated.
+    fn process_cohere_event(&mut self, event: CohereStreamEvent) {
+        match event {
+            CohereStreamEvent::MessageStart { id } => {
+                self.id = id.unwrap_or_default();
+                self.push_chunk(0, Some(String::new()), None, None);
+            }
+            CohereStreamEvent::ContentDelta { delta } => {
+                let text = delta
+                    .message
+                    .and_then(|message| message.content)
+                    .and_then(|content| content.text);
+                if let Some(text) = text {
+                    self.push_chunk(0, Some(text), None, None);
+                }
+            }
+            CohereStreamEvent::ToolPlanDelta { delta } => {
+                if let Some(tool_plan) = delta.message.and_then(|message| message.tool_plan) {
+                    self.push_chunk(0, Some(tool_plan), None, None);
+                }
+            }
+            CohereStreamEvent::MessageEnd { delta } => {
+                if let Some(error) = delta.error {
+                    self.push_error(error, "api_error".to_string(), None);
+                    return;
+                }
+                self.push_chunk(
+                    0,
+                    None,
+                    delta
+                        .finish_reason
+                        .as_deref()
+                        .map(cohere::finish_reason_to_openai),
+                    delta.usage.as_ref().map(Usage::from),
+                );
+                self.push_done();
+            }
+            CohereStreamEvent::Other => {}
+        }
+    }
+
+    fn push_chunk(
+        &mut self,
+        index: u32,
+        content: Option<String>,
+        finish_reason: Option<String>,
+        usage: Option<Usage>,
+    ) {
+        let role = if self.role_sent {
+            None
+        } else {
+            self.role_sent = true;
+            Some("assistant".to_string())
+        };
+        let chunk = ChatCompletionStreamResponse {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".to_string(),
+            // providers do not report a creation time
+            created: 0,
+            model: self.model.clone(),
+            choices: vec![StreamChoice {
+                index,
+                delta: DeltaMessage {
+                    role,
+                    content: content.map(ContentType::Text),
+                    tool_calls: None,
+                },
+                finish_reason,
+            }],
+            usage,
+        };
+        // serializing the chunk types cannot fail
+        let data = serde_json::to_string(&chunk).unwrap();
+        self.frames.push_back(Ok(format!("data: {}\n\n", data)));
+    }
+
+    /// Errors sent after the response headers can only be reported in-band, they are emitted
+    /// as an OpenAI error event and end the stream.
+    fn push_error(&mut self, message: String, error_type: String, code: Option<String>) {
+        let error = serde_json::json!({
+            "error": {
+                "message": message,
+                "type": error_type,
+                "param": null,
+                "code": code,
+            }
+        });
+        self.frames.push_back(Ok(format!("data: {}\n\n", error)));
+        self.push_done();
+    }
+
+    fn push_done(&mut self) {
+        if !self.done {
+            self.done = true;
+            self.frames.push_back(Ok(SSE_DONE_FRAME.to_string()));
+        }
+    }
+}
+
+impl Iterator for SseStreamTranslator {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames.pop_front()
+    }
+}
+
+/// Assembles the chunks of an OpenAI stream into the response the request gets without
+/// streaming. The content deltas of each choice are concatenated and its tool calls assembled
+/// from their parts, the arguments of a call being the concatenation of its pieces. The role
+/// and finish reason are the last ones sent and the usage is the one reported by the stream.
+#[derive(Debug, Default)]
+pub struct StreamAggregator {
+    response: Option<ChatCompletionsResponse>,
+    model: Option<String>,
+    /// Index in the chunks of each tool call of each choice of the response.
+    tool_call_indexes: Vec<Vec<u32>>,
+}
+
+impl StreamAggregator {
+    pub fn push(&mut self, chunk: ChatCompletionStreamResponse) {
+        if self.model.is_none() {
+            self.model = Some(chunk.model);
+        }
+        let response = self
+            .response
+            .get_or_insert_with(|| ChatCompletionsResponse {
+                id: chunk.id,
+                object: "chat.completion".to_string(),
+                created: chunk.created,
+                choices: Vec::new(),
+                usage: None,
+            });
+        if chunk.usage.is_some() {
+            response.usage = chunk.usage;
+        }
+
+        for stream_choice in chunk.choices {
+            let position = match response
+                .choices
+                .iter()
+                .position(|choice| choice.index == stream_choice.index)
+            {
+                Some(position) => position,
+                None => {
+                    response.choices.push(Choice {
+                        index: stream_choice.index,
+                        message: Message {
+                            role: "assistant".to_string(),
+                            ..Default::default()
+                        },
+                        finish_reason: None,
+                    });
+                    self.tool_call_indexes.push(Vec::new());
+                    response.choices.len() - 1
+                }
+            };
+            let choice = &mut response.choices[position];
+            let tool_call_indexes = &mut self.tool_call_indexes[position];
+
+            if let Some(role) = stream_choice.delta.role {
+                choice.message.role = role;
+            }
+            if let Some(delta) = stream_choice.delta.content {
+                let content = match choice.message.content.take() {
+                    Some(content) => format!("{}{}", content, delta),
+                    None => delta.to_string(),
+                };
+                choice.message.content = Some(ContentType::Text(content));
+            }
+            for tool_call_delta in stream_choice.delta.tool_calls.into_iter().flatten() {
+                let tool_calls = choice.message.tool_calls.get_or_insert_with(Vec::new);
+                let tool_call = match tool_call_indexes
+                    .iter()
+                    .position(|index| *index == tool_call_delta.index)
+                {
+                    Some(position) => &mut tool_calls[position],
+                    None => {
+                        tool_call_indexes.push(tool_call_delta.index);
+                        tool_calls.push(ToolCall {
+                            id: String::new(),
+                            tool_type: ToolType::Function,
+                            function: FunctionCall {
+                                name: String::new(),
+                                arguments: String::new(),
+                            },
+                        });
+                        tool_calls.last_mut().unwrap()
+                    }
+                };
+                if let Some(id) = tool_call_delta.id {
+                    tool_call.id = id;
+                }
+                if let Some(tool_type) = tool_call_delta.tool_type {
+                    tool_call.tool_type = tool_type;
+                }
+                if let Some(function) = tool_call_delta.function {
+                    // the name is sent whole with the first part, some backends repeat it
+                    if let Some(name) = function.name.filter(|_| tool_call.function.name.is_empty())
+                    {
+                        tool_call.function.name = name;
+                    }
+                    if let Some(arguments) = function.arguments {
+                        tool_call.function.arguments.push_str(&arguments);
+                    }
+                }
+            }
+            if stream_choice.finish_reason.is_some() {
+                choice.finish_reason = stream_choice.finish_reason;
+            }
+        }
+    }
+
+    /// Model of the first chunk, the response has no field for it.
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// The assembled response, None when no chunk was pushed.
+    pub fn finish(self) -> Option<ChatCompletionsResponse> {
+        let mut response = self.response?;
+        response.choices.sort_by_key(|choice| choice.index);
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::types::SseChatCompletionIter;
+
+    const ANTHROPIC_STREAM: &str = "event: message_start
+data: {\"type\": \"message_start\", \"message\": {\"id\": \"msg_1nZdL29xx5MUA1yADyHTEsnR8uuvGzszyY\", \"type\": \"message\", \"role\": \"assistant\", \"content\": [], \"model\": \"claude-3-7-sonnet-latest\", \"stop_reason\": null, \"stop_sequence\": null, \"usage\": {\"input_tokens\": 25, \"output_tokens\": 1}}}
+
+event: content_block_start
+data: {\"type\": \"content_block_start\", \"index\": 0, \"content_block\": {\"type\": \"text\", \"text\": \"\"}}
+
+event: ping
+data: {\"type\": \"ping\"}
+
+event: content_block_delta
+data: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \"Hello\"}}
+
+event: content_block_delta
+data: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \"!\"}}
+
+event: content_block_stop
+data: {\"type\": \"content_block_stop\", \"index\": 0}
+
+event: message_delta
+data: {\"type\": \"message_delta\", \"delta\": {\"stop_reason\": \"end_turn\", \"stop_sequence\":null}, \"usage\": {\"output_tokens\": 15}}
+
+event: message_stop
+data: {\"type\": \"message_stop\"}
+
+";
+
+    const GEMINI_STREAM: &str = "data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Hello\"}],\"role\": \"model\"},\"index\": 0}],\"usageMetadata\": {\"promptTokenCount\": 5,\"totalTokenCount\": 5},\"modelVersion\": \"gemini-2.0-flash\",\"responseId\": \"mclIaI3sLqGbz7IPm6ulmQ4\"}\r
+\r
+data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \" world\"}],\"role\": \"model\"},\"finishReason\": \"STOP\",\"index\": 0}],\"usageMetadata\": {\"promptTokenCount\": 5,\"candidatesTokenCount\": 2,\"totalTokenCount\": 7},\"modelVersion\": \"gemini-2.0-flash\",\"responseId\": \"mclIaI3sLqGbz7IPm6ulmQ4\"}\r
+\r
+";
+
+    const OLLAMA_STREAM: &str = "{\"model\":\"llama3.2\",\"created_at\":\"2025-06-10T08:15:42.1Z\",\"message\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"done\":false}
+{\"model\":\"llama3.2\",\"created_at\":\"2025-06-10T08:15:42.2Z\",\"message\":{\"role\":\"assistant\",\"content\":\" world\"},\"done\":false}
+{\"model\":\"llama3.2\",\"created_at\":\"2025-06-10T08:15:42.3Z\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":26,\"eval_count\":2}
+";
+
+    const COHERE_STREAM: &str = "event: message-start\r
+data: {\"id\":\"29f14a5a-11de-4cae-9800-25e4747408ea\",\"type\":\"message-start\",\"delta\":{\"message\":{\"role\":\"assistant\",\"content\":[],\"tool_plan\":\"\",\"tool_calls\":[],\"citations\":[]}}}\r
+\r
+event: content-start\r
+data: {\"type\":\"content-start\",\"index\":0,\"delta\":{\"message\":{\"content\":{\"type\":\"text\",\"text\":\"\"}}}}\r
+\r
+event: content-delta\r
+data: {\"type\":\"content-delta\",\"index\":0,\"delta\":{\"message\":{\"content\":{\"text\":\"Hello\"}}}}\r
+\r
+event: content-delta\r
+data: {\"type\":\"content-delta\",\"index\":0,\"delta\":{\"message\":{\"content\":{\"text\":\" world\"}}}}\r
+\r
+event: content-end\r
+data: {\"type\":\"content-end\",\"index\":0}\r
+\r
+event: message-end\r
+data: {\"type\":\"message-end\",\"delta\":{\"finish_reason\":\"COMPLETE\",\"usage\":{\"billed_units\":{\"input_tokens\":3,\"output_tokens\":2},\"tokens\":{\"input_tokens\":70,\"output_tokens\":2}}}}\r
+\r
+data: [DONE]\r
+\r
+";
+
+    /// Feeds the stream in fragments of `fragment_size` bytes so events are split mid json.
+    fn translate(provider: Provider, stream: &str, fragment_size: usize) -> Vec<String> {
+        let mut translator = SseStreamTranslator::for_provider(&provider).unwrap();
+        let mut frames = Vec::new();
+        for fragment in stream.as_bytes().chunks(fragment_size) {
+            translator.push(fragment);
+            frames.extend(translator.by_ref().map(|frame| frame.unwrap()));
+        }
+        translator.finish();
+        frames.extend(translator.map(|frame| frame.unwrap()));
+        frames
+    }
+
+    fn parse_chunks(frames: &[String]) -> Vec<ChatCompletionStreamResponse> {
+        let output = frames.concat();
+        SseChatCompletionIter::new(output.lines())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_anthropic_stream_translation() {
+        for fragment_size in [1, 7, 64, ANTHROPIC_STREAM.len()] {
+            let frames = translate(Provider::Claude, ANTHROPIC_STREAM, fragment_size);
+            assert_eq!(frames.len(), 5);
+            assert_eq!(frames.last().unwrap(), SSE_DONE_FRAME);
+
+            let chunks = parse_chunks(&frames);
+            assert_eq!(chunks.len(), 4);
+            assert!(chunks.iter().all(|c| c.object == "chat.completion.chunk"));
+            assert_eq!(chunks[0].id, "msg_1nZdL29xx5MUA1yADyHTEsnR8uuvGzszyY");
+            assert_eq!(chunks[0].model, "claude-3-7-sonnet-latest");
+            assert_eq!(
+                chunks[0].choices[0].delta.role,
+                Some("assistant".to_string())
+            );
+            assert!(chunks[1].choices[0].delta.role.is_none());
+
+            let content: String = chunks
+                .iter()
+                .filter_map(|c| c.choices[0].delta.content.as_ref())
+                .map(|c| c.to_string())
+                .collect();
+            assert_eq!(content, "Hello!");
+
+            let last = chunks.last().unwrap();
+            assert_eq!(last.choices[0].finish_reason, Some("stop".to_string()));
+            let usage = last.usage.as_ref().unwrap();
+            assert_eq!(usage.prompt_tokens, 25);
+            assert_eq!(usage.completion_tokens, 15);
+            assert_eq!(usage.total_tokens, 40);
+        }
+    }
+
+    #[test]
+    fn test_gemini_stream_translation() {
+        for fragment_size in [1, 13, GEMINI_STREAM.len()] {
+            let frames = translate(Provider::Gemini, GEMINI_STREAM, fragment_size);
+            // gemini does not terminate the stream, [DONE] is emitted on finish
+            assert_eq!(frames.len(), 3);
+            assert_eq!(frames.last().unwrap(), SSE_DONE_FRAME);
+
+            let chunks = parse_chunks(&frames);
+            assert_eq!(chunks[0].id, "mclIaI3sLqGbz7IPm6ulmQ4");
+            assert_eq!(chunks[0].model, "gemini-2.0-flash");
+            assert_eq!(
+                chunks[0].choices[0].delta.role,
+                Some("assistant".to_string())
+            );
+            assert!(chunks[0].usage.is_none());
+            assert_eq!(
+                chunks[1].choices[0].delta.content,
+                Some(ContentType::Text(" world".to_string()))
+            );
+            assert_eq!(chunks[1].choices[0].finish_reason, Some("stop".to_string()));
+            assert_eq!(chunks[1].usage.as_ref().unwrap().total_tokens, 7);
+        }
+    }
+
+    #[test]
+    fn test_ollama_stream_translation() {
+        for fragment_size in [1, 17, OLLAMA_STREAM.len()] {
+            let frames = translate(Provider::Ollama, OLLAMA_STREAM, fragment_size);
+            assert_eq!(frames.len(), 4);
+            assert_eq!(frames.last().unwrap(), SSE_DONE_FRAME);
+
+            let chunks = parse_chunks(&frames);
+            assert_eq!(chunks[0].model, "llama3.2");
+            assert_eq!(
+                chunks[0].choices[0].delta.role,
+                Some("assistant".to_string())
+            );
+            let content: String = chunks
+                .iter()
+                .filter_map(|c| c.choices[0].delta.content.as_ref())
+                .map(|c| c.to_string())
+                .collect();
+            assert_eq!(content, "Hello world");
+
+            let last = chunks.last().unwrap();
+            assert!(chunks[0].choices[0].finish_reason.is_none());
+            assert_eq!(last.choices[0].finish_reason, Some("stop".to_string()));
+            assert_eq!(last.usage.as_ref().unwrap().total_tokens, 28);
+        }
+
+        let frames = translate(
+            Provider::Ollama,
+            "{\"error\":\"model 'llama9' not found\"}\n",
+            5,
+        );
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].contains("model 'llama9' not found"));
+    }
+
+    #[test]
+    fn test_cohere_stream_translation() {
+        for fragment_size in [1, 23, COHERE_STREAM.len()] {
+            let frames = translate(Provider::Cohere, COHERE_STREAM, fragment_size);
+            assert_eq!(frames.len(), 5);
+            assert_eq!(frames.last().unwrap(), SSE_DONE_FRAME);
+
+            let chunks = parse_chunks(&frames);
+            assert_eq!(chunks[0].id, "29f14a5a-11de-4cae-9800-25e4747408ea");
+            assert_eq!(
+                chunks[0].choices[0].delta.role,
+                Some("assistant".to_string())
+            );
+            let content: String = chunks
+                .iter()
+                .filter_map(|c| c.choices[0].delta.content.as_ref())
+                .map(|c| c.to_string())
+                .collect();
+            assert_eq!(content, "Hello world");
+
+            let last = chunks.last().unwrap();
+            assert_eq!(last.choices[0].finish_reason, Some("stop".to_string()));
+            assert_eq!(last.usage.as_ref().unwrap().total_tokens, 72);
+        }
+
+        let frames = translate(
+            Provider::Cohere,
+            "data: {\"type\":\"message-end\",\"delta\":{\"error\":\"internal server error\"}}\n\n",
+            7,
+        );
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].contains("internal server error"));
+    }
+
+    #[test]
+    fn test_trailing_event_without_newline() {
+        let stream = GEMINI_STREAM.trim_end();
+        let frames = translate(Provider::Gemini, stream, 10);
+        assert_eq!(frames.len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_event_is_reported() {
+        let mut translator = SseStreamTranslator::for_provider(&Provider::Claude).unwrap();
+        translator.push(b"data: {\"type\": \"message_start\", \"message\": }\n\n");
+        translator.push(b"data: {\"type\": \"message_stop\"}\n\n");
+
+        let frames: Vec<Result<String>> = translator.collect();
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(
+            frames[0],
+            Err(StreamingError::InvalidStreamingData { .. })
+        ));
+        assert_eq!(frames[1].as_ref().unwrap(), SSE_DONE_FRAME);
+    }
+
+    #[test]
+    fn test_mid_stream_errors() {
+        let anthropic_stream = concat!(
+            "event: message_start\n",
+            "data: {\"type\": \"message_start\", \"message\": {\"id\": \"msg_1\", \"model\": \"claude-3-7-sonnet-latest\", \"usage\": {\"input_tokens\": 25, \"output_tokens\": 1}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \"Hello\"}}\n\n",
+            "event: error\n",
+            "data: {\"type\": \"error\", \"error\": {\"type\": \"overloaded_error\", \"message\": \"Overloaded\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\": \"content_block_delta\", \"index\": 0, \"delta\": {\"type\": \"text_delta\", \"text\": \"!\"}}\n\n",
+        );
+        let gemini_stream = concat!(
+            "data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Hello\"}],\"role\": \"model\"},\"index\": 0}]}\r\n\r\n",
+            "data: {\"error\": {\"code\": 429, \"message\": \"Resource has been exhausted\", \"status\": \"RESOURCE_EXHAUSTED\"}}\r\n\r\n",
+        );
+
+        for (provider, stream, frame_count, message, error_type, code) in [
+            (
+                Provider::Claude,
+                anthropic_stream,
+                4,
+                "Overloaded",
+                "overloaded_error",
+                "overloaded_error",
+            ),
+            (
+                Provider::Gemini,
+                gemini_stream,
+                3,
+                "Resource has been exhausted",
+                "resource_exhausted",
+                "429",
+            ),
+        ] {
+            let frames = translate(provider, stream, 11);
+            // the content before the error, the error and the end of the stream, nothing after
+            assert_eq!(frames.len(), frame_count);
+            assert_eq!(frames.last().unwrap(), SSE_DONE_FRAME);
+
+            let error = frames[frame_count - 2].strip_prefix("data: ").unwrap();
+            let error: serde_json::Value = serde_json::from_str(error.trim()).unwrap();
+            assert_eq!(error["error"]["message"], message);
+            assert_eq!(error["error"]["type"], error_type);
+            assert_eq!(error["error"]["code"], code);
+        }
+    }
+
+    #[test]
+    fn test_openai_compatible_providers_pass_through() {
+        assert!(SseStreamTranslator::for_provider(&Provider::OpenAI).is_none());
+        assert!(SseStreamTranslator::for_provider(&Provider::Arch).is_none());
+    }
+
+    fn aggregate(stream: &str) -> StreamAggregator {
+        let mut aggregator = StreamAggregator::default();
+        for chunk in SseChatCompletionIter::new(stream.lines()) {
+            aggregator.push(chunk.unwrap());
+        }
+        aggregator
+    }
+
+    #[test]
+    fn test_stream_aggregator_text() {
+        let stream = r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hel"},"finish_reason":null}]}
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"lo"},"finish_reason":"stop"}]}
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[],"usage":{"prompt_tokens":5,"completion_tokens":2,"total_tokens":7}}
+data: [DONE]"#;
+
+        let aggregator = aggregate(stream);
+        assert_eq!(aggregator.model(), Some("gpt-4o"));
+        let response = aggregator.finish().unwrap();
+        assert_eq!(response.id, "chatcmpl-1");
+        assert_eq!(response.object, "chat.completion");
+        assert_eq!(response.choices.len(), 1);
+        let choice = &response.choices[0];
+        assert_eq!(choice.message.role, "assistant");
+        assert_eq!(
+            choice.message.content.as_ref().unwrap().to_string(),
+            "Hello"
+        );
+        assert!(choice.message.tool_calls.is_none());
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.usage.unwrap().total_tokens, 7);
+
+        assert!(StreamAggregator::default().finish().is_none());
+    }
+
+    #[test]
+    fn test_stream_aggregator_tool_call_split_across_chunks() {
+        let stream = r#"data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}]}
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"loca"}}]},"finish_reason":null}]}
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"id":"call_2","type":"function","function":{"name":"get_time","arguments":"{}"}}]},"finish_reason":null}]}
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"tion\": \"Tok"}}]},"finish_reason":null}]}
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"yo\"}"}}]},"finish_reason":null}]}
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1700000000,"model":"gpt-4o","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}
+data: [DONE]"#;
+
+        let response = aggregate(stream).finish().unwrap();
+        let choice = &response.choices[0];
+        assert!(choice.message.content.is_none());
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"location": "Tokyo"}"#);
+        let arguments: serde_json::Value =
+            serde_json::from_str(&tool_calls[0].function.arguments).unwrap();
+        assert_eq!(arguments["location"], "Tokyo");
+        assert_eq!(tool_calls[1].id, "call_2");
+        assert_eq!(tool_calls[1].function.name, "get_time");
+        assert_eq!(tool_calls[1].function.arguments, "{}");
+    }
+}
@@ -109,6 +109,21 @@ impl StreamContext {
                     }
                 }
             }
+            LlmProviderType::AzureOpenAI => {
+                // the body is not available yet, the deployment is picked for the model of
+                // the provider
+                let llm_provider = self.llm_provider();
+                let azure_path = llm_provider.azure_openai_provider().map(|provider| {
+                    provider
+                        .chat_completions_path(llm_provider.model.as_deref().unwrap_or_default())
+                });
+                let path = self.get_http_request_header(":path");
+                if let Some(azure_path) = azure_path {
+                    if path.as_deref() == Some("/v1/chat/completions") {
+                        self.set_http_request_header(":path", Some(azure_path.as_str()));
+                    }
+                }
+            }
             _ => {}
         }
 
@@ -132,6 +147,12 @@ impl StreamContext {
                     ),
                 })?;
 
+        if self.llm_provider().provider_interface == LlmProviderType::AzureOpenAI {
+            self.set_http_request_header("api-key", Some(llm_provider_api_key_value));
+            self.set_http_request_header("Authorization", None);
+            return Ok(());
+        }
+
         let authorization_header_value = format!("Bearer {}", llm_provider_api_key_value);
 
         self.set_http_request_header("Authorization", Some(&authorization_header_value));